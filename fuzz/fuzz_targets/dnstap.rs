@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tarnish_dns::dnstap::{read_stream, DnstapMessage};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = DnstapMessage::decode(data);
+    let _ = read_stream(data);
+});
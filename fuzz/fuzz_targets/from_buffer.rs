@@ -0,0 +1,15 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tarnish_dns::buffer::PacketBuffer;
+use tarnish_dns::protocol::DnsPacket;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(mut buffer) = PacketBuffer::from_bytes(data) else {
+        return;
+    };
+    let _ = DnsPacket::from_buffer(&mut buffer);
+
+    let mut buffer = PacketBuffer::from_bytes(data).unwrap();
+    let _ = DnsPacket::from_buffer_lenient(&mut buffer);
+});
@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tarnish_dns::pcap::CaptureReader;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(reader) = CaptureReader::new(data) else {
+        return;
+    };
+    for message in reader {
+        let _ = message;
+    }
+});
@@ -0,0 +1,25 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tarnish_dns::buffer::PacketBuffer;
+use tarnish_dns::name::Name;
+
+// The first byte picks where in the message the name starts, so that
+// compression pointers have earlier data to point at.
+fuzz_target!(|data: &[u8]| {
+    let Some((&start, message)) = data.split_first() else {
+        return;
+    };
+    let Ok(mut buffer) = PacketBuffer::from_bytes(message) else {
+        return;
+    };
+    if buffer.seek(start as usize).is_err() {
+        return;
+    }
+
+    let mut name = Name::root();
+    if buffer.read_qname(&mut name).is_ok() {
+        assert!(name.wire_length() <= tarnish_dns::name::MAX_NAME_LENGTH);
+        assert!(buffer.position() <= message.len());
+    }
+});
@@ -0,0 +1,25 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tarnish_dns::buffer::PacketBuffer;
+use tarnish_dns::protocol::DnsPacket;
+
+// Anything we manage to parse must survive being written and parsed again.
+fuzz_target!(|data: &[u8]| {
+    let Ok(mut buffer) = PacketBuffer::from_bytes(data) else {
+        return;
+    };
+    let Ok(mut packet) = DnsPacket::from_buffer(&mut buffer) else {
+        return;
+    };
+
+    let mut written = PacketBuffer::new();
+    if packet.write(&mut written).is_err() {
+        // Uncompressed output may not fit where the compressed input did
+        return;
+    }
+
+    let mut reread = PacketBuffer::from_bytes(&written.buffer[..written.position]).unwrap();
+    let reparsed = DnsPacket::from_buffer(&mut reread).expect("written packet must parse");
+    assert_eq!(packet, reparsed);
+});
@@ -0,0 +1,66 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tarnish_dns::buffer::PacketBuffer;
+use tarnish_dns::protocol::{DnsClass, DnsPacket, DnsRecord, Opcode, QueryType, ResultCode};
+
+/// Whether every value in `packet` has a single wire representation, e.g. an
+/// `UNKNOWN` type numbered like a known one reads back as the known variant.
+fn is_canonical(packet: &DnsPacket) -> bool {
+    let opcode = packet.header.opcode.to_number();
+    let rcode = packet.header.rescode.to_number();
+    let known_type = |qtype: u16| !matches!(QueryType::from_number(qtype), QueryType::UNKNOWN(_));
+
+    opcode < 16
+        && Opcode::from_number(opcode) == packet.header.opcode
+        && rcode < 16
+        && ResultCode::from_number(rcode) == packet.header.rescode
+        && packet.questions.iter().all(|question| {
+            QueryType::from_number(question.qtype.to_number()) == question.qtype
+                && DnsClass::from_number(question.qclass.to_number()) == question.qclass
+        })
+        && packet
+            .answers
+            .iter()
+            .chain(&packet.authorities)
+            .chain(&packet.resources)
+            .all(|record| {
+                let class = record.class();
+                let update = matches!(class, DnsClass::ANY | DnsClass::NONE);
+                let canonical_class = match record {
+                    DnsRecord::OPT { .. } => true,
+                    _ => DnsClass::from_number(class.to_number()) == class,
+                };
+                let canonical_data = match record {
+                    // Empty UPDATE RDATA is the only form a known type can take
+                    DnsRecord::UNKNOWN { qtype, data, .. } => {
+                        !known_type(*qtype) || (update && data.is_empty() && *qtype != 41)
+                    }
+                    DnsRecord::TXT { data, .. } => !(update && data.is_empty()),
+                    DnsRecord::TSIG {
+                        time_signed, error, ..
+                    } => {
+                        *time_signed < 1 << 48
+                            && ResultCode::from_number(error.to_number()) == *error
+                    }
+                    _ => true,
+                };
+                canonical_class && canonical_data
+            })
+}
+
+fuzz_target!(|packet: DnsPacket| {
+    let mut packet = packet;
+    if !is_canonical(&packet) {
+        return;
+    }
+
+    let mut written = PacketBuffer::new();
+    if packet.write(&mut written).is_err() {
+        return;
+    }
+
+    let mut reread = PacketBuffer::from_bytes(&written.buffer[..written.position]).unwrap();
+    let reparsed = DnsPacket::from_buffer(&mut reread).expect("written packet must parse");
+    assert_eq!(packet, reparsed);
+});
@@ -0,0 +1,153 @@
+//! Access control lists of client networks, checked by the server before
+//! answering queries, zone transfers, dynamic updates and NOTIFY messages.
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use crate::DnsError;
+
+/// An address prefix, such as `192.0.2.0/24` or `2001:db8::/32`. A bare
+/// address stands for itself alone.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Network {
+    pub address: IpAddr,
+    pub prefix_length: u8,
+}
+
+impl Network {
+    pub fn new(address: IpAddr, prefix_length: u8) -> crate::Result<Network> {
+        let max_length = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix_length > max_length {
+            return Err(DnsError::InvalidNetwork(format!(
+                "{}/{}",
+                address, prefix_length
+            )));
+        }
+
+        Ok(Network {
+            address,
+            prefix_length,
+        })
+    }
+
+    /// Whether `address` is in the network. IPv4 addresses mapped into IPv6
+    /// are matched as IPv4 ones.
+    pub fn contains(&self, address: IpAddr) -> bool {
+        let address = match address {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(address),
+            address => address,
+        };
+
+        match (self.address, address) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_length as u32)
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(address) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_length as u32)
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(address) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Network {
+    type Err = DnsError;
+
+    fn from_str(text: &str) -> crate::Result<Network> {
+        let invalid = || DnsError::InvalidNetwork(text.to_string());
+
+        let (address, prefix_length) = match text.split_once('/') {
+            Some((address, length)) => (address, Some(length)),
+            None => (text, None),
+        };
+        let address: IpAddr = address.parse().map_err(|_| invalid())?;
+        let prefix_length = match (prefix_length, address) {
+            (Some(length), _) => length.parse().map_err(|_| invalid())?,
+            (None, IpAddr::V4(_)) => 32,
+            (None, IpAddr::V6(_)) => 128,
+        };
+
+        Network::new(address, prefix_length).map_err(|_| invalid())
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_length)
+    }
+}
+
+/// The networks allowed each kind of request. An empty list allows no
+/// client at all.
+///
+/// By default queries and NOTIFY messages are allowed from anywhere, while
+/// zone transfers and dynamic updates are only allowed from loopback
+/// addresses, so that a server nobody configured does not hand out or take
+/// in whole zones.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Acl {
+    pub query: Vec<Network>,
+    pub transfer: Vec<Network>,
+    pub update: Vec<Network>,
+    pub notify: Vec<Network>,
+}
+
+impl Acl {
+    pub fn new() -> Acl {
+        Acl::default()
+    }
+
+    /// Every IPv4 and IPv6 address.
+    pub fn any() -> Vec<Network> {
+        vec![
+            Network {
+                address: Ipv4Addr::UNSPECIFIED.into(),
+                prefix_length: 0,
+            },
+            Network {
+                address: Ipv6Addr::UNSPECIFIED.into(),
+                prefix_length: 0,
+            },
+        ]
+    }
+
+    /// The IPv4 and IPv6 loopback addresses.
+    pub fn loopback() -> Vec<Network> {
+        vec![
+            Network {
+                address: Ipv4Addr::new(127, 0, 0, 0).into(),
+                prefix_length: 8,
+            },
+            Network {
+                address: Ipv6Addr::LOCALHOST.into(),
+                prefix_length: 128,
+            },
+        ]
+    }
+
+    /// Whether `address` is in one of `networks`.
+    pub fn allows(networks: &[Network], address: IpAddr) -> bool {
+        networks.iter().any(|network| network.contains(address))
+    }
+}
+
+impl Default for Acl {
+    fn default() -> Acl {
+        Acl {
+            query: Acl::any(),
+            transfer: Acl::loopback(),
+            update: Acl::loopback(),
+            notify: Acl::any(),
+        }
+    }
+}
@@ -0,0 +1,328 @@
+use std::fmt::Write;
+use std::io::BufRead;
+use std::net::SocketAddr;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::client::DnsClient;
+use crate::name::Name;
+use crate::protocol::{
+    DnsClass, DnsPacket, DnsQuestion, DnsRecord, QueryType, ResultCode, EDNS_DNSSEC_OK,
+};
+
+/// Column names of the CSV output, in the order `BatchResult::to_csv` writes
+/// them.
+pub const CSV_HEADER: &str = "line,name,type,rcode,answers,latency_ms,error";
+
+/// One query read from a batch file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BatchQuery {
+    /// Line of the input the query was read from, counting from one.
+    pub line: usize,
+    pub name: String,
+    pub qtype: QueryType,
+}
+
+impl BatchQuery {
+    /// Reads a `name [type]` line, the fields separated by spaces or a
+    /// comma. Blank lines and `#` comments give `None`, the type defaults
+    /// to A.
+    pub fn parse(line: usize, text: &str) -> Option<Result<BatchQuery, String>> {
+        let text = text.split('#').next().unwrap_or_default();
+        let mut fields = text
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|field| !field.is_empty());
+
+        let name = fields.next()?.to_string();
+        let qtype = match fields.next() {
+            Some(qtype) => match qtype.parse() {
+                Ok(qtype) => qtype,
+                Err(error) => return Some(Err(format!("line {}: {}", line, error))),
+            },
+            None => QueryType::A,
+        };
+        if let Some(extra) = fields.next() {
+            return Some(Err(format!("line {}: unexpected `{}`", line, extra)));
+        }
+
+        Some(Ok(BatchQuery { line, name, qtype }))
+    }
+}
+
+/// The outcome of one query of a batch.
+#[derive(Clone, Debug)]
+pub struct BatchResult {
+    pub query: BatchQuery,
+    /// `None` when no response was received.
+    pub rcode: Option<ResultCode>,
+    pub answers: Vec<DnsRecord>,
+    /// Time until the response, or until giving up.
+    pub latency: Duration,
+    pub error: Option<String>,
+}
+
+impl BatchResult {
+    /// Writes the result as a single line JSON object.
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{");
+        write!(json, "\"line\":{},", self.query.line).ok();
+        write!(json, "\"name\":{},", json_string(&self.query.name)).ok();
+        write!(json, "\"type\":\"{}\",", self.query.qtype).ok();
+        match self.rcode {
+            Some(rcode) => write!(json, "\"rcode\":\"{}\",", rcode).ok(),
+            None => write!(json, "\"rcode\":null,").ok(),
+        };
+
+        json.push_str("\"answers\":[");
+        for (index, record) in self.answers.iter().enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+            write!(
+                json,
+                "{{\"name\":{},\"type\":\"{}\",\"ttl\":{},\"data\":{}}}",
+                json_string(&record.domain().to_string()),
+                record.qtype(),
+                record.ttl(),
+                json_string(&record.data_to_string())
+            )
+            .ok();
+        }
+        json.push_str("],");
+
+        write!(json, "\"latency_ms\":{:.3},", self.latency_ms()).ok();
+        match &self.error {
+            Some(error) => write!(json, "\"error\":{}", json_string(error)).ok(),
+            None => write!(json, "\"error\":null").ok(),
+        };
+        json.push('}');
+
+        json
+    }
+
+    /// Writes the result as a CSV row following `CSV_HEADER`, the answer
+    /// data joined with spaces.
+    pub fn to_csv(&self) -> String {
+        let answers: Vec<String> = self.answers.iter().map(DnsRecord::data_to_string).collect();
+
+        [
+            self.query.line.to_string(),
+            csv_field(&self.query.name),
+            self.query.qtype.to_string(),
+            self.rcode
+                .map(|rcode| rcode.to_string())
+                .unwrap_or_default(),
+            csv_field(&answers.join(" ")),
+            format!("{:.3}", self.latency_ms()),
+            csv_field(self.error.as_deref().unwrap_or_default()),
+        ]
+        .join(",")
+    }
+
+    fn latency_ms(&self) -> f64 {
+        self.latency.as_secs_f64() * 1000.0
+    }
+}
+
+pub(crate) fn json_string(text: &str) -> String {
+    let mut json = String::with_capacity(text.len() + 2);
+    json.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                write!(json, "\\u{:04x}", c as u32).ok();
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+
+    json
+}
+
+/// Quotes a CSV field when it holds a separator, a quote or a line break.
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// Resolves lists of names against one server, with a bounded number of
+/// queries in flight and an optional limit on the query rate.
+pub struct BatchRunner {
+    pub server: SocketAddr,
+    /// Most queries waiting for a response at the same time.
+    pub concurrency: usize,
+    /// Most queries sent per second, unlimited when `None`.
+    pub rate: Option<u32>,
+    pub timeout: Duration,
+    pub retries: usize,
+    pub tcp: bool,
+    pub recursion_desired: bool,
+    pub randomize_case: bool,
+    /// UDP payload size advertised in an EDNS OPT record, no OPT record is
+    /// sent when `None`.
+    pub edns: Option<u16>,
+    /// Sets the DNSSEC OK bit, which needs `edns`.
+    pub dnssec: bool,
+}
+
+impl BatchRunner {
+    pub fn new(server: SocketAddr) -> BatchRunner {
+        BatchRunner {
+            server,
+            concurrency: 16,
+            rate: None,
+            timeout: Duration::from_secs(5),
+            retries: 0,
+            tcp: false,
+            recursion_desired: true,
+            randomize_case: false,
+            edns: None,
+            dnssec: false,
+        }
+    }
+
+    /// Resolves the queries read from `input`, calling `on_result` for each
+    /// as it completes, in completion order. Lines that fail to parse are
+    /// passed to `on_error`, as is a failure to read `input`, which ends the
+    /// batch.
+    ///
+    /// The input is read as the queries go out, so that lists of any length
+    /// are resolved in constant memory.
+    pub fn run<R, F, E>(&self, input: R, mut on_result: F, mut on_error: E)
+    where
+        R: BufRead + Send,
+        F: FnMut(BatchResult),
+        E: FnMut(String),
+    {
+        let workers = self.concurrency.max(1);
+        let (query_sender, query_receiver) = mpsc::sync_channel::<BatchQuery>(workers);
+        let query_receiver = Mutex::new(query_receiver);
+        let (result_sender, result_receiver) = mpsc::channel();
+        let (error_sender, error_receiver) = mpsc::channel();
+
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                let result_sender = result_sender.clone();
+                let query_receiver = &query_receiver;
+                scope.spawn(move || {
+                    while let Some(query) = next_query(query_receiver) {
+                        if result_sender.send(self.resolve(query)).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(result_sender);
+
+            scope.spawn(move || {
+                let started = Instant::now();
+                let mut sent = 0u64;
+                for (index, text) in input.lines().enumerate() {
+                    let text = match text {
+                        Ok(text) => text,
+                        Err(error) => {
+                            error_sender
+                                .send(format!("line {}: {}", index + 1, error))
+                                .ok();
+                            break;
+                        }
+                    };
+                    let query = match BatchQuery::parse(index + 1, &text) {
+                        Some(Ok(query)) => query,
+                        Some(Err(error)) => {
+                            error_sender.send(error).ok();
+                            continue;
+                        }
+                        None => continue,
+                    };
+
+                    // Pace the queries evenly instead of sending in bursts
+                    if let Some(rate) = self.rate.filter(|rate| *rate > 0) {
+                        let due = started + Duration::from_secs_f64(sent as f64 / rate as f64);
+                        thread::sleep(due.saturating_duration_since(Instant::now()));
+                    }
+                    sent += 1;
+
+                    if query_sender.send(query).is_err() {
+                        break;
+                    }
+                }
+            });
+
+            for result in result_receiver {
+                for error in error_receiver.try_iter() {
+                    on_error(error);
+                }
+                on_result(result);
+            }
+            for error in error_receiver.try_iter() {
+                on_error(error);
+            }
+        });
+    }
+
+    /// Sends one query and waits for its response.
+    pub fn resolve(&self, query: BatchQuery) -> BatchResult {
+        let started = Instant::now();
+        let response = Name::from_unicode(&query.name).and_then(|name| {
+            let mut packet = DnsPacket::new();
+            packet.header.recursion_desired = self.recursion_desired;
+            let mut question = DnsQuestion::new(name, query.qtype);
+            question.qclass = DnsClass::IN;
+            packet.questions.push(question);
+            if let Some(udp_payload_size) = self.edns {
+                packet.resources.push(DnsRecord::OPT {
+                    udp_payload_size,
+                    extended_rcode: 0,
+                    version: 0,
+                    flags: if self.dnssec { EDNS_DNSSEC_OK } else { 0 },
+                    options: Vec::new(),
+                });
+            }
+
+            let mut client = DnsClient::new(self.server);
+            client.timeout = self.timeout;
+            client.retries = self.retries;
+            client.randomize_case = self.randomize_case;
+            client.tcp = self.tcp;
+            client.send(packet)
+        });
+
+        let latency = started.elapsed();
+        match response {
+            Ok(response) => BatchResult {
+                query,
+                rcode: Some(response.rcode()),
+                answers: response.answers,
+                latency,
+                error: None,
+            },
+            Err(error) => BatchResult {
+                query,
+                rcode: None,
+                answers: Vec::new(),
+                latency,
+                error: Some(error.to_string()),
+            },
+        }
+    }
+}
+
+fn next_query(receiver: &Mutex<Receiver<BatchQuery>>) -> Option<BatchQuery> {
+    receiver
+        .lock()
+        .unwrap_or_else(|error| error.into_inner())
+        .recv()
+        .ok()
+}
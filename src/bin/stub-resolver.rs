@@ -1,57 +1,569 @@
-use std::net::UdpSocket;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
 
-use tarnish_dns::buffer::PacketBuffer;
-use tarnish_dns::protocol::DnsPacket;
-use tarnish_dns::protocol::DnsQuestion;
-use tarnish_dns::protocol::QueryType;
+use tarnish_dns::batch::{BatchRunner, CSV_HEADER};
+use tarnish_dns::client::{DnsClient, Exchange};
+use tarnish_dns::name::Name;
+use tarnish_dns::protocol::{
+    DnsClass, DnsPacket, DnsQuestion, DnsRecord, QueryType, ResultCode, EDNS_DNSSEC_OK,
+};
+use tarnish_dns::trace::{TraceStep, Tracer};
 use tarnish_dns::DnsError;
 
-fn main() -> tarnish_dns::Result<()> {
-    let qname = "www.github.com";
-    let qtype = QueryType::MX;
-    let server = ("8.8.8.8", 53);
+const USAGE: &str = "\
+usage: stub-resolver [@server] [-p port] [name] [type] [class] [+option...]
+       stub-resolver [@server] [-p port] -f file [+option...]
 
-    let socket = UdpSocket::bind(("0.0.0.0", 5454))
-        .map_err(|error| DnsError::SocketBind { source: error })?;
+  @server        server to query, an address or a host name
+  -p port        server port (default 53)
+  -t type        query type, when it could be mistaken for a name
+  -c class       query class, when it could be mistaken for a name
+  -q name        query name, when it could be mistaken for a type or class
+  -f file        resolve the `name [type]` lines of a file, `-` for stdin,
+                 printing one result per line
 
-    let mut packet = DnsPacket::new();
+  +[no]tcp       query over TCP (default: UDP, TCP after truncation)
+  +[no]short     print only the answer data
+  +[no]recurse   ask the server to recurse (default: on)
+  +[no]dnssec    ask for DNSSEC records, implies +edns
+  +[no]edns      send an EDNS OPT record (default: on)
+  +bufsize=N     EDNS UDP payload size (default: 1232)
+  +time=N        seconds to wait for a response (default: 5)
+  +tries=N       attempts made before giving up (default: 3)
+  +retry=N       retries after the first attempt (default: 2)
+  +[no]0x20      randomize the case of the query name (default: off)
+  +[no]idnout    show internationalized names in Unicode
+  +[no]trace     follow the delegations from the root servers, ignoring
+                 @server, and show every step
 
-    packet.header.id = 42;
-    packet.header.questions = 1;
-    packet.header.recursion_desired = true;
-    packet
-        .questions
-        .push(DnsQuestion::new(qname.to_string(), qtype));
+batch options:
+  +format=F      json lines or csv (default: json)
+  +concurrency=N queries in flight at once (default: 16)
+  +rate=N        most queries sent per second (default: unlimited)";
+
+/// Default EDNS payload size, small enough to avoid IP fragmentation.
+const DEFAULT_BUFSIZE: u16 = 1232;
+
+/// Server used when none is given and none is found in resolv.conf.
+const FALLBACK_SERVER: [u8; 4] = [8, 8, 8, 8];
+
+/// Exit status when no response was received, as dig uses.
+const EXIT_NO_REPLY: u8 = 9;
+
+struct Options {
+    server: Option<String>,
+    port: u16,
+    qname: Option<String>,
+    qtype: Option<QueryType>,
+    qclass: Option<DnsClass>,
+    tcp: bool,
+    short: bool,
+    recurse: bool,
+    dnssec: bool,
+    edns: bool,
+    bufsize: u16,
+    timeout: Duration,
+    tries: usize,
+    randomize_case: bool,
+    unicode: bool,
+    trace: bool,
+    batch: Option<String>,
+    format: Format,
+    concurrency: usize,
+    rate: Option<u32>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Format {
+    Json,
+    Csv,
+}
+
+impl Options {
+    fn new() -> Options {
+        Options {
+            server: None,
+            port: 53,
+            qname: None,
+            qtype: None,
+            qclass: None,
+            tcp: false,
+            short: false,
+            recurse: true,
+            dnssec: false,
+            edns: true,
+            bufsize: DEFAULT_BUFSIZE,
+            timeout: Duration::from_secs(5),
+            tries: 3,
+            randomize_case: false,
+            unicode: false,
+            trace: false,
+            batch: None,
+            format: Format::Json,
+            concurrency: 16,
+            rate: None,
+        }
+    }
+
+    fn parse(arguments: &[String]) -> Result<Options, String> {
+        let mut options = Options::new();
+
+        let mut arguments = arguments.iter();
+        while let Some(argument) = arguments.next() {
+            let mut value = |flag: &str| {
+                arguments
+                    .next()
+                    .cloned()
+                    .ok_or_else(|| format!("missing value after {}", flag))
+            };
+
+            match argument.as_str() {
+                "-h" | "--help" => return Err(String::new()),
+                "-p" => {
+                    let port = value("-p")?;
+                    options.port = port
+                        .parse()
+                        .map_err(|_| format!("invalid port `{}`", port))?;
+                }
+                "-t" => options.qtype = Some(parse_mnemonic(&value("-t")?)?),
+                "-c" => options.qclass = Some(parse_mnemonic(&value("-c")?)?),
+                "-q" => options.qname = Some(value("-q")?),
+                "-f" => options.batch = Some(value("-f")?),
+                _ if argument.starts_with('@') => options.server = Some(argument[1..].to_string()),
+                _ if argument.starts_with('+') => options.set(&argument[1..])?,
+                _ if argument.starts_with('-') => {
+                    return Err(format!("unknown option `{}`", argument))
+                }
+                _ => options.positional(argument),
+            }
+        }
+
+        Ok(options)
+    }
+
+    /// Takes a bare argument as the type or class when it reads as one that
+    /// is not given yet, and as the name otherwise.
+    fn positional(&mut self, argument: &str) {
+        if self.qtype.is_none() {
+            if let Ok(qtype) = argument.parse() {
+                self.qtype = Some(qtype);
+                return;
+            }
+        }
+        if self.qclass.is_none() {
+            if let Ok(qclass) = argument.parse() {
+                self.qclass = Some(qclass);
+                return;
+            }
+        }
+
+        self.qname = Some(argument.to_string());
+    }
+
+    /// Applies a `+option`, `+nooption` or `+option=value` flag.
+    fn set(&mut self, flag: &str) -> Result<(), String> {
+        let (name, value) = match flag.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (flag, None),
+        };
+        let (name, enabled) = match name.strip_prefix("no") {
+            Some(name) => (name, false),
+            None => (name, true),
+        };
+        let number = |value: Option<&str>| -> Result<u64, String> {
+            value
+                .and_then(|value| value.parse().ok())
+                .ok_or_else(|| format!("+{} needs a number", name))
+        };
+
+        match name {
+            "tcp" | "vc" => self.tcp = enabled,
+            "short" => self.short = enabled,
+            "recurse" => self.recurse = enabled,
+            "dnssec" => {
+                self.dnssec = enabled;
+                self.edns |= enabled;
+            }
+            "edns" => self.edns = enabled,
+            "bufsize" => {
+                self.bufsize = number(value)?.min(u16::MAX as u64) as u16;
+                self.edns = true;
+            }
+            "time" => self.timeout = Duration::from_secs(number(value)?.max(1)),
+            "tries" => self.tries = number(value)?.max(1) as usize,
+            "retry" => self.tries = (number(value)? as usize).saturating_add(1),
+            "0x20" => self.randomize_case = enabled,
+            "idnout" => self.unicode = enabled,
+            "trace" => self.trace = enabled,
+            "format" => {
+                self.format = match value {
+                    Some("json") => Format::Json,
+                    Some("csv") => Format::Csv,
+                    _ => return Err("+format is either json or csv".to_string()),
+                }
+            }
+            "concurrency" => self.concurrency = number(value)?.max(1) as usize,
+            "rate" => self.rate = Some(number(value)?.min(u32::MAX as u64) as u32),
+            _ => return Err(format!("unknown option `+{}`", flag)),
+        }
+
+        Ok(())
+    }
+
+    /// The address of the server, from `@server` or the system resolver
+    /// configuration.
+    fn server_address(&self) -> Result<SocketAddr, String> {
+        let Some(server) = &self.server else {
+            let address = system_nameserver().unwrap_or(IpAddr::from(FALLBACK_SERVER));
+            return Ok(SocketAddr::new(address, self.port));
+        };
+
+        if let Ok(address) = server.parse::<IpAddr>() {
+            return Ok(SocketAddr::new(address, self.port));
+        }
+        (server.as_str(), self.port)
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addresses| addresses.next())
+            .ok_or_else(|| format!("couldn't get address for `{}`", server))
+    }
+
+    fn query(&self) -> Result<DnsPacket, String> {
+        // Without a name dig asks for the root servers
+        let (qname, default_type) = match &self.qname {
+            Some(qname) => (
+                // Users may type internationalized names, they go out as
+                // A-labels
+                Name::from_unicode(qname).map_err(|error| format!("`{}`: {}", qname, error))?,
+                QueryType::A,
+            ),
+            None => (Name::root(), QueryType::NS),
+        };
+
+        let mut packet = DnsPacket::new();
+        packet.header.recursion_desired = self.recurse;
+        let mut question = DnsQuestion::new(qname, self.qtype.unwrap_or(default_type));
+        question.qclass = self.qclass.unwrap_or(DnsClass::IN);
+        packet.questions.push(question);
+
+        if self.edns {
+            packet.resources.push(DnsRecord::OPT {
+                udp_payload_size: self.bufsize,
+                extended_rcode: 0,
+                version: 0,
+                flags: if self.dnssec { EDNS_DNSSEC_OK } else { 0 },
+                options: Vec::new(),
+            });
+        }
+
+        Ok(packet)
+    }
+}
+
+fn parse_mnemonic<T: std::str::FromStr<Err = DnsError>>(text: &str) -> Result<T, String> {
+    text.parse().map_err(|error: DnsError| error.to_string())
+}
+
+/// The first nameserver listed in `/etc/resolv.conf`.
+fn system_nameserver() -> Option<IpAddr> {
+    let contents = fs::read_to_string("/etc/resolv.conf").ok()?;
+
+    contents.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            Some("nameserver") => fields.next()?.parse().ok(),
+            _ => None,
+        }
+    })
+}
+
+fn main() -> ExitCode {
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    let options = match Options::parse(&arguments) {
+        Ok(options) => options,
+        // Asking for help is the only way to get here without an error
+        Err(error) if error.is_empty() => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(error) => {
+            eprintln!("stub-resolver: {}", error);
+            eprintln!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if let Some(path) = &options.batch {
+        return batch(&options, path);
+    }
+
+    let query = match options.query() {
+        Ok(query) => query,
+        Err(error) => {
+            eprintln!("stub-resolver: {}", error);
+            return ExitCode::FAILURE;
+        }
+    };
+    if options.trace {
+        return trace(&options, &arguments, &query.questions[0]);
+    }
+
+    let server = match options.server_address() {
+        Ok(server) => server,
+        Err(error) => {
+            eprintln!("stub-resolver: {}", error);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut client = DnsClient::new(server);
+    client.timeout = options.timeout;
+    client.retries = options.tries - 1;
+    client.randomize_case = options.randomize_case;
+    client.tcp = options.tcp;
+
+    match client.exchange(query) {
+        Ok(exchange) => {
+            print_exchange(&options, &arguments, server, &exchange);
+            ExitCode::SUCCESS
+        }
+        Err(DnsError::Timeout) => {
+            println!(";; connection timed out; no servers could be reached");
+            ExitCode::from(EXIT_NO_REPLY)
+        }
+        Err(error) => {
+            println!(";; communications error to {}: {}", server, error);
+            ExitCode::from(EXIT_NO_REPLY)
+        }
+    }
+}
+
+fn print_exchange(
+    options: &Options,
+    arguments: &[String],
+    server: SocketAddr,
+    exchange: &Exchange,
+) {
+    let response = &exchange.response;
+    if options.short {
+        for record in &response.answers {
+            println!("{}", record.data_to_string());
+        }
+        return;
+    }
 
-    let mut request_buffer = PacketBuffer::new();
-    packet.write(&mut request_buffer)?;
+    println!();
+    println!(
+        "; <<>> stub-resolver {} <<>> {}",
+        env!("CARGO_PKG_VERSION"),
+        arguments.join(" ")
+    );
+    println!(";; Got answer:");
+    if options.unicode {
+        print_unicode(response);
+    }
+    print!("{}", response);
+    println!();
+    println!(";; Query time: {} msec", exchange.query_time.as_millis());
+    println!(
+        ";; SERVER: {}#{}({}) ({})",
+        server.ip(),
+        server.port(),
+        server.ip(),
+        if exchange.tcp { "TCP" } else { "UDP" }
+    );
+    println!(";; MSG SIZE  rcvd: {}", exchange.message_size);
+}
 
-    socket
-        .send_to(&request_buffer.buffer[0..request_buffer.position], server)
-        .map_err(|source| DnsError::SocketIO { source })?;
+/// Resolves every query listed in the file at `path`, writing the results
+/// to standard output as they complete. Like a single query, the batch
+/// exits with `EXIT_NO_REPLY` when any query got no response, and fails
+/// when lines had to be skipped.
+fn batch(options: &Options, path: &str) -> ExitCode {
+    let server = match options.server_address() {
+        Ok(server) => server,
+        Err(error) => {
+            eprintln!("stub-resolver: {}", error);
+            return ExitCode::FAILURE;
+        }
+    };
+    let input: Box<dyn BufRead + Send> = match path {
+        "-" => Box::new(BufReader::new(io::stdin())),
+        _ => match File::open(path) {
+            Ok(file) => Box::new(BufReader::new(file)),
+            Err(error) => {
+                eprintln!("stub-resolver: skipping {}", error);
+                return ExitCode::FAILURE;
+            }
+        },
+    };
 
-    let mut response_buffer = PacketBuffer::new();
-    socket
-        .recv_from(&mut response_buffer.buffer)
-        .map_err(|source| DnsError::SocketIO { source })?;
+    let mut runner = BatchRunner::new(server);
+    runner.concurrency = options.concurrency;
+    runner.rate = options.rate.filter(|rate| *rate > 0);
+    runner.timeout = options.timeout;
+    runner.retries = options.tries - 1;
+    runner.tcp = options.tcp;
+    runner.recursion_desired = options.recurse;
+    runner.randomize_case = options.randomize_case;
+    runner.edns = options.edns.then_some(options.bufsize);
+    runner.dnssec = options.dnssec;
 
-    let response_packet = DnsPacket::from_buffer(&mut response_buffer)?;
+    let mut output = io::stdout().lock();
+    if options.format == Format::Csv {
+        writeln!(output, "{}", CSV_HEADER).ok();
+    }
 
-    // TODO: impl display for Packet?
-    println!("{:#?}", response_packet.header);
+    let started = Instant::now();
+    let mut count = 0;
+    let mut failed = 0;
+    let mut skipped = 0;
+    runner.run(
+        input,
+        |result| {
+            count += 1;
+            failed += result.error.is_some() as usize;
+            let line = match options.format {
+                Format::Json => result.to_json(),
+                Format::Csv => result.to_csv(),
+            };
+            writeln!(output, "{}", line).ok();
+        },
+        |error| {
+            skipped += 1;
+            eprintln!("stub-resolver: skipping {}", error);
+        },
+    );
 
-    for question in response_packet.questions {
-        println!("{:#?}", question);
+    eprintln!(
+        ";; {} queries, {} without response, {} lines skipped, in {:.1} s",
+        count,
+        failed,
+        skipped,
+        started.elapsed().as_secs_f64()
+    );
+    if failed > 0 {
+        ExitCode::from(EXIT_NO_REPLY)
+    } else if skipped > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
     }
-    for record in response_packet.answers {
-        println!("{:#?}", record);
+}
+
+/// Follows the delegations from the root servers down to the answer,
+/// printing what every server said along the way.
+fn trace(options: &Options, arguments: &[String], question: &DnsQuestion) -> ExitCode {
+    let mut tracer = Tracer::new();
+    tracer.timeout = options.timeout;
+    tracer.retries = options.tries - 1;
+    tracer.tcp = options.tcp;
+
+    if !options.short {
+        println!();
+        println!(
+            "; <<>> stub-resolver {} <<>> {}",
+            env!("CARGO_PKG_VERSION"),
+            arguments.join(" ")
+        );
     }
-    for record in response_packet.authorities {
-        println!("{:#?}", record);
+
+    let result = tracer.trace(question, |step| {
+        if !options.short {
+            print_step(step);
+        }
+    });
+    match result {
+        Ok(response) => {
+            if options.short {
+                for record in &response.answers {
+                    println!("{}", record.data_to_string());
+                }
+            }
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            println!(";; trace failed: {}", error);
+            ExitCode::from(EXIT_NO_REPLY)
+        }
     }
-    for record in response_packet.resources {
-        println!("{:#?}", record);
+}
+
+/// Prints the records a server returned, with its referral NS set and glue,
+/// followed by where and how fast they came from.
+fn print_step(step: &TraceStep) {
+    let source = format!(
+        "{}#{}({})",
+        step.server.ip(),
+        step.server.port(),
+        step.server_name
+    );
+    let exchange = match &step.result {
+        Ok(exchange) => exchange,
+        Err(error) => {
+            println!(
+                ";; no response from {} for zone {}: {}",
+                source, step.zone, error
+            );
+            return;
+        }
+    };
+
+    let response = &exchange.response;
+    let records = response
+        .answers
+        .iter()
+        .chain(&response.authorities)
+        .chain(&response.resources)
+        .filter(|record| !matches!(record, DnsRecord::OPT { .. }));
+    for record in records {
+        println!("{}", record);
     }
 
-    Ok(())
+    let rcode = match response.rcode() {
+        ResultCode::NOERROR => String::new(),
+        rcode => format!(", status {}", rcode),
+    };
+    println!(
+        ";; Received {} bytes from {} in {} ms (zone {}{})",
+        exchange.message_size,
+        source,
+        exchange.query_time.as_millis(),
+        step.zone,
+        rcode
+    );
+    println!();
+}
+
+/// Lists the Unicode form of every name in the response that differs from
+/// the A-label form shown in the sections.
+fn print_unicode(response: &DnsPacket) {
+    let names = response
+        .questions
+        .iter()
+        .map(|question| &question.name)
+        .chain(
+            response
+                .answers
+                .iter()
+                .chain(&response.authorities)
+                .chain(&response.resources)
+                .map(DnsRecord::domain),
+        );
+
+    let mut shown: Vec<&Name> = Vec::new();
+    for name in names {
+        if shown.contains(&name) {
+            continue;
+        }
+        shown.push(name);
+
+        let display = name.to_unicode();
+        if display != name.to_string() {
+            println!(";; IDN: {} is {}", name, display);
+        }
+    }
 }
@@ -0,0 +1,121 @@
+use std::net::{TcpListener, UdpSocket};
+use std::process::ExitCode;
+use std::sync::mpsc;
+use std::thread;
+
+use tarnish_dns::config::ServerConfig;
+use tarnish_dns::metrics::Metrics;
+use tarnish_dns::secondary::SecondaryManager;
+
+const USAGE: &str = "\
+usage: tarnish-server [--check-config] config.toml
+
+  --check-config  validate the configuration and exit, without serving";
+
+fn main() -> ExitCode {
+    let mut check = false;
+    let mut path = None;
+    for argument in std::env::args().skip(1) {
+        match argument.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return ExitCode::SUCCESS;
+            }
+            "--check-config" => check = true,
+            _ if argument.starts_with('-') => {
+                eprintln!("tarnish-server: unknown option `{}`", argument);
+                eprintln!("{}", USAGE);
+                return ExitCode::FAILURE;
+            }
+            _ if path.is_none() => path = Some(argument),
+            _ => {
+                eprintln!("tarnish-server: more than one configuration given");
+                eprintln!("{}", USAGE);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let Some(path) = path else {
+        eprintln!("tarnish-server: no configuration given");
+        eprintln!("{}", USAGE);
+        return ExitCode::FAILURE;
+    };
+
+    let config = match ServerConfig::load(&path).and_then(|config| {
+        config.check_zones()?;
+        Ok(config)
+    }) {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("tarnish-server: {}: {}", path, error);
+            return ExitCode::FAILURE;
+        }
+    };
+    if check {
+        println!("{}: configuration is valid", path);
+        return ExitCode::SUCCESS;
+    }
+
+    match serve(&config) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("tarnish-server: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Binds every listener and serves until one of them fails.
+fn serve(config: &ServerConfig) -> Result<(), String> {
+    let mut server = config.build_server().map_err(|error| error.to_string())?;
+
+    if let Some(address) = config.metrics {
+        let metrics = Metrics::new();
+        server.metrics = Some(metrics.clone());
+        let listener = TcpListener::bind(address)
+            .map_err(|error| format!("metrics on {}: {}", address, error))?;
+        thread::spawn(move || metrics.serve_http(&listener));
+    }
+
+    let secondary_zones = config.secondary_zones();
+    if !secondary_zones.is_empty() {
+        let manager = SecondaryManager::new(&mut server);
+        for zone in secondary_zones {
+            manager.add_zone(zone);
+        }
+        thread::spawn(move || manager.run());
+    }
+
+    // Bind everything before serving anything, so that a busy port stops
+    // the server right away
+    let mut sockets = Vec::new();
+    let mut listeners = Vec::new();
+    for address in &config.listen {
+        if config.udp {
+            let socket =
+                UdpSocket::bind(address).map_err(|error| format!("udp {}: {}", address, error))?;
+            sockets.push(socket);
+        }
+        if config.tcp {
+            let listener = TcpListener::bind(address)
+                .map_err(|error| format!("tcp {}: {}", address, error))?;
+            listeners.push(listener);
+        }
+    }
+    let (sender, failures) = mpsc::channel();
+    for socket in sockets {
+        let (server, sender) = (server.clone(), sender.clone());
+        thread::spawn(move || sender.send(server.serve_udp(&socket)));
+    }
+    for listener in listeners {
+        let (server, sender) = (server.clone(), sender.clone());
+        thread::spawn(move || sender.send(server.serve_tcp(&listener)));
+    }
+
+    // Listeners only return when they fail
+    if let Ok(Err(error)) = failures.recv() {
+        return Err(error.to_string());
+    }
+
+    Ok(())
+}
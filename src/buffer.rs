@@ -1,37 +1,85 @@
+use crate::name::Name;
+use crate::protocol::Location;
 use crate::DnsError;
 
+/// Largest message a plain UDP buffer holds, without EDNS.
+pub const UDP_MESSAGE_LENGTH: usize = 512;
+
+/// Largest message that fits behind the two byte TCP length prefix.
+pub const MAX_MESSAGE_LENGTH: usize = 65535;
+
 pub struct PacketBuffer {
-    pub buffer: [u8; 512],
+    pub buffer: Vec<u8>,
     pub position: usize,
+    /// Number of valid bytes when reading a received message, reads past it
+    /// fail as truncated.
+    pub length: usize,
+    /// Entry currently being parsed, reported in parse errors.
+    pub location: Location,
+    /// End of the RDATA currently being parsed. Sequential reads may not go
+    /// past it, while compression pointers may still target the whole message.
+    pub window_end: Option<usize>,
+}
+
+impl Default for PacketBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PacketBuffer {
+    /// Creates a buffer for a UDP message.
     pub fn new() -> PacketBuffer {
+        PacketBuffer::with_capacity(UDP_MESSAGE_LENGTH)
+    }
+
+    /// Creates a buffer for messages of up to `capacity` bytes, such as the
+    /// larger ones sent over TCP.
+    pub fn with_capacity(capacity: usize) -> PacketBuffer {
         PacketBuffer {
-            buffer: [0; 512],
+            buffer: vec![0; capacity],
             position: 0,
+            length: capacity,
+            location: Location::default(),
+            window_end: None,
         }
     }
 
+    /// Creates a buffer for reading a received message.
+    pub fn from_bytes(bytes: &[u8]) -> crate::Result<PacketBuffer> {
+        if bytes.len() > MAX_MESSAGE_LENGTH {
+            return Err(DnsError::BufferEnd);
+        }
+        let mut buffer = PacketBuffer::with_capacity(bytes.len().max(UDP_MESSAGE_LENGTH));
+        buffer.buffer[..bytes.len()].copy_from_slice(bytes);
+        buffer.length = bytes.len();
+
+        Ok(buffer)
+    }
+
     pub fn position(&self) -> usize {
         self.position
     }
 
     pub fn step(&mut self, steps: usize) -> crate::Result<()> {
-        self.position += steps;
-
-        Ok(())
+        self.seek(self.position + steps)
     }
 
+    /// Moves to `pos`, which may be at most the end of the message.
     pub fn seek(&mut self, pos: usize) -> crate::Result<()> {
+        self.check_window(pos)?;
+        if pos > self.length {
+            return Err(self.truncated(self.length));
+        }
         self.position = pos;
 
         Ok(())
     }
 
     pub fn read(&mut self) -> crate::Result<u8> {
-        if self.position >= 512 {
-            return Err(DnsError::BufferEnd);
+        self.check_window(self.position + 1)?;
+        if self.position >= self.length {
+            return Err(self.truncated(self.position));
         }
         let response = self.buffer[self.position];
         self.position += 1;
@@ -40,17 +88,17 @@ impl PacketBuffer {
     }
 
     pub fn get(&mut self, position: usize) -> crate::Result<u8> {
-        if position >= 512 {
-            return Err(DnsError::BufferEnd);
+        if position >= self.length {
+            return Err(self.truncated(position));
         }
         Ok(self.buffer[position])
     }
 
     pub fn get_range(&mut self, start: usize, length: usize) -> crate::Result<&[u8]> {
-        if start + length >= 512 {
-            return Err(DnsError::BufferEnd);
+        if start.saturating_add(length) > self.length {
+            return Err(self.truncated(self.length));
         }
-        Ok(&self.buffer[start..start + length as usize])
+        Ok(&self.buffer[start..start + length])
     }
 
     pub fn read_u16(&mut self) -> crate::Result<u16> {
@@ -63,16 +111,15 @@ impl PacketBuffer {
         let res = ((self.read()? as u32) << 24)
             | ((self.read()? as u32) << 16)
             | ((self.read()? as u32) << 8)
-            | ((self.read()? as u32) << 0);
+            | (self.read()? as u32);
 
         Ok(res)
     }
 
-    pub fn read_qname(&mut self, outstr: &mut String) -> crate::Result<()> {
+    pub fn read_qname(&mut self, outname: &mut Name) -> crate::Result<()> {
         let mut position = self.position();
         let mut jumped = false;
 
-        let mut delimiter = "";
         let max_jumps = 5;
         let mut jumps_performed = 0;
         loop {
@@ -81,6 +128,9 @@ impl PacketBuffer {
                 return Err(DnsError::MaxJumps(max_jumps));
             }
 
+            if !jumped {
+                self.check_window(position + 1)?;
+            }
             let length = self.get(position)?;
 
             // A two byte sequence, where the two highest bits of the first byte is
@@ -91,11 +141,22 @@ impl PacketBuffer {
                 // When a jump is performed, we only modify the shared buffer
                 // position once, and avoid making the change later on.
                 if !jumped {
+                    self.check_window(position + 2)?;
                     self.seek(position + 2)?;
                 }
 
                 let b2 = self.get(position + 1)? as u16;
                 let offset = (((length as u16) ^ 0xC0) << 8) | b2;
+
+                // Only pointers to prior occurrences are legal, which also
+                // rules out any loop
+                if offset as usize >= position {
+                    return Err(DnsError::ForwardPointer {
+                        offset: position,
+                        target: offset as usize,
+                        location: self.location,
+                    });
+                }
                 position = offset as usize;
                 jumped = true;
                 jumps_performed += 1;
@@ -109,12 +170,20 @@ impl PacketBuffer {
                 break;
             }
 
-            outstr.push_str(delimiter);
-
-            let str_buffer = self.get_range(position, length as usize)?;
-            outstr.push_str(&String::from_utf8_lossy(str_buffer).to_lowercase());
+            if !jumped {
+                self.check_window(position + length as usize)?;
+            }
 
-            delimiter = ".";
+            // Case is preserved as received, callers needing to match names
+            // must compare them case-insensitively.
+            let label = self.get_range(position, length as usize)?.to_vec();
+            outname.push_label(label).map_err(|error| match error {
+                DnsError::NameTooLong => DnsError::OversizedName {
+                    offset: position - 1,
+                    location: self.location,
+                },
+                error => error,
+            })?;
 
             position += length as usize;
         }
@@ -126,8 +195,27 @@ impl PacketBuffer {
         Ok(())
     }
 
+    /// Fails if sequentially reading up to `end` would leave the current
+    /// RDATA window.
+    fn check_window(&self, end: usize) -> crate::Result<()> {
+        match self.window_end {
+            Some(window_end) if end > window_end => Err(DnsError::RdataOverrun {
+                offset: window_end,
+                location: self.location,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn truncated(&self, offset: usize) -> DnsError {
+        DnsError::Truncated {
+            offset,
+            location: self.location,
+        }
+    }
+
     pub fn write(&mut self, value: u8) -> crate::Result<()> {
-        if self.position >= 512 {
+        if self.position >= self.buffer.len() {
             return Err(DnsError::BufferEnd);
         }
         self.buffer[self.position] = value;
@@ -152,20 +240,17 @@ impl PacketBuffer {
         self.write(((value >> 24) & 0xFF) as u8)?;
         self.write(((value >> 16) & 0xFF) as u8)?;
         self.write(((value >> 8) & 0xFF) as u8)?;
-        self.write(((value >> 0) & 0xFF) as u8)?;
+        self.write((value & 0xFF) as u8)?;
 
         Ok(())
     }
 
-    pub fn write_qname(&mut self, qname: &str) -> crate::Result<()> {
-        for label in qname.split('.') {
-            let length = label.len();
-            if length > 0x34 {
-                return Err(DnsError::LabelExceedsMaxLengthSize);
-            }
-
-            self.write_u8(length as u8)?;
-            for byte in label.as_bytes() {
+    /// Writes `qname` uncompressed, label and total length limits are
+    /// already guaranteed by `Name`.
+    pub fn write_qname(&mut self, qname: &Name) -> crate::Result<()> {
+        for label in qname.labels() {
+            self.write_u8(label.len() as u8)?;
+            for byte in label {
                 self.write_u8(*byte)?;
             }
         }
@@ -176,6 +261,9 @@ impl PacketBuffer {
     }
 
     pub fn set(&mut self, pos: usize, val: u8) -> crate::Result<()> {
+        if pos >= self.buffer.len() {
+            return Err(DnsError::BufferEnd);
+        }
         self.buffer[pos] = val;
 
         Ok(())
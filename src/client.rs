@@ -0,0 +1,476 @@
+use std::io::{ErrorKind, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::buffer::{PacketBuffer, MAX_MESSAGE_LENGTH, UDP_MESSAGE_LENGTH};
+use crate::dnstap::{Dnstap, DnstapMessage, MessageType};
+use crate::metrics::Metrics;
+use crate::name::Name;
+use crate::pcap::Transport;
+use crate::protocol::{DnsClass, DnsPacket, DnsQuestion, DnsRecord, QueryType};
+use crate::transfer::{read_tcp_message, stream_error};
+use crate::tsig::{unix_time, TsigKey, TsigSigner, TsigVerifier};
+use crate::DnsError;
+
+/// Number of attempts made to bind a randomly chosen source port before
+/// falling back to letting the operating system pick one.
+const BIND_ATTEMPTS: usize = 10;
+
+/// Lowest source port considered when choosing a random one, everything
+/// below is either privileged or commonly reserved.
+const MIN_SOURCE_PORT: u16 = 1024;
+
+/// A UDP client that hardens outgoing queries against off-path spoofing.
+///
+/// Every query uses an ID drawn from the operating system CSPRNG and is sent
+/// from a freshly bound, randomly chosen source port. Datagrams that do not
+/// come from the queried server, or whose ID or question do not match the
+/// query, are discarded and the client keeps waiting for the genuine answer.
+/// Should none arrive in time, the query fails with the error the last
+/// mismatched answer was rejected for rather than a plain `Timeout`.
+///
+/// When `randomize_case` is set the query name is sent with randomly mixed
+/// case (DNS 0x20 encoding) and the echoed question must match it exactly,
+/// adding up to one bit of entropy per letter in the name. An answer that
+/// only gets the case wrong is rejected as `CaseMismatch` like any other
+/// mismatch, so servers that fold the case of the question can not be
+/// queried with it.
+///
+/// When `tsig` is set requests are signed with the key, and responses are
+/// only accepted with a valid signature.
+///
+/// When `tcp` is set queries go over TCP from the start, instead of only
+/// after a truncated UDP response.
+///
+/// When `dnstap` is set queries and the responses accepted for them are
+/// logged there as RESOLVER_QUERY and RESOLVER_RESPONSE events, and when
+/// `metrics` is set the time every attempt takes is recorded there.
+pub struct DnsClient {
+    pub server: SocketAddr,
+    pub timeout: Duration,
+    /// Times a query is sent again after timing out.
+    pub retries: usize,
+    pub randomize_case: bool,
+    pub tsig: Option<TsigKey>,
+    pub tcp: bool,
+    pub dnstap: Option<Dnstap>,
+    pub metrics: Option<Metrics>,
+}
+
+/// A response along with how it was received.
+#[derive(Clone, Debug)]
+pub struct Exchange {
+    pub response: DnsPacket,
+    /// Size of the response on the wire.
+    pub message_size: usize,
+    /// Time from sending the successful attempt to receiving its response.
+    pub query_time: Duration,
+    /// Whether the response came over TCP.
+    pub tcp: bool,
+}
+
+impl DnsClient {
+    pub fn new(server: SocketAddr) -> DnsClient {
+        DnsClient {
+            server,
+            timeout: Duration::from_secs(5),
+            retries: 0,
+            randomize_case: false,
+            tsig: None,
+            tcp: false,
+            dnstap: None,
+            metrics: None,
+        }
+    }
+
+    pub fn query(&self, qname: &Name, qtype: QueryType) -> crate::Result<DnsPacket> {
+        self.query_class(qname, qtype, DnsClass::IN)
+    }
+
+    pub fn query_class(
+        &self,
+        qname: &Name,
+        qtype: QueryType,
+        qclass: DnsClass,
+    ) -> crate::Result<DnsPacket> {
+        let mut packet = DnsPacket::new();
+
+        let qname = if self.randomize_case {
+            randomize_case(qname)?
+        } else {
+            qname.clone()
+        };
+
+        packet.header.questions = 1;
+        packet.header.recursion_desired = true;
+        let mut question = DnsQuestion::new(qname, qtype);
+        question.qclass = qclass;
+        packet.questions.push(question);
+
+        self.send(packet)
+    }
+
+    /// Sends `packet` under a fresh random ID and waits for its response,
+    /// e.g. for UPDATE messages built with `UpdateBuilder`.
+    pub fn send(&self, packet: DnsPacket) -> crate::Result<DnsPacket> {
+        self.exchange(packet).map(|exchange| exchange.response)
+    }
+
+    /// Like `send`, also reporting the size of the response and how long it
+    /// took to arrive.
+    ///
+    /// Queries time out after `timeout` and are sent again up to `retries`
+    /// times. Truncated UDP responses are retried over TCP. Each attempt
+    /// draws a new ID and, with `tsig`, is signed anew.
+    pub fn exchange(&self, mut packet: DnsPacket) -> crate::Result<Exchange> {
+        let mut tcp = self.tcp;
+        let mut attempt = 0;
+        loop {
+            // Every attempt goes out under a fresh ID and signature, so that
+            // answers forged for one attempt are of no use for the next
+            packet.header.id = random_u16()?;
+            let mut verifier = None;
+            if let Some(key) = &self.tsig {
+                let mut signer = TsigSigner::new(key.clone());
+                signer.sign(&mut packet, unix_time())?;
+                let request_mac = signer.mac().unwrap_or_default();
+                verifier = Some(TsigVerifier::for_response(key.clone(), request_mac));
+            }
+
+            let mut request_buffer = PacketBuffer::with_capacity(MAX_MESSAGE_LENGTH);
+            packet.write(&mut request_buffer)?;
+            let request = &request_buffer.buffer[..request_buffer.position];
+
+            let started = Instant::now();
+            let result = if tcp {
+                self.send_tcp(&packet, request, verifier)
+            } else {
+                self.send_udp(&packet, request, verifier)
+            };
+            if let Some(metrics) = &self.metrics {
+                match &result {
+                    Ok(_) => metrics.record_upstream(self.server, started.elapsed()),
+                    Err(_) => metrics.record_upstream_error(self.server),
+                }
+            }
+
+            match result {
+                Ok((response, _)) if response.header.truncated_message && !tcp => tcp = true,
+                Ok((response, message_size)) => {
+                    return Ok(Exchange {
+                        response,
+                        message_size,
+                        query_time: started.elapsed(),
+                        tcp,
+                    })
+                }
+                Err(DnsError::Timeout) if attempt < self.retries => attempt += 1,
+                // A UDP attempt that only got mismatched answers timed out
+                // all the same
+                Err(error) if !tcp && is_mismatch(&error) && attempt < self.retries => attempt += 1,
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// Sends `request`, the wire form of `packet`, in a datagram and waits
+    /// for a genuine response to it.
+    fn send_udp(
+        &self,
+        packet: &DnsPacket,
+        request: &[u8],
+        mut verifier: Option<TsigVerifier>,
+    ) -> crate::Result<(DnsPacket, usize)> {
+        let socket = self.bind()?;
+        socket
+            .send_to(request, self.server)
+            .map_err(|source| DnsError::SocketIO { source })?;
+        let local = socket.local_addr().ok();
+        let sent = self.log_query(Transport::Udp, local, request);
+
+        // Responses may be as large as the payload size advertised over EDNS
+        let capacity = match packet.edns() {
+            Some(DnsRecord::OPT {
+                udp_payload_size, ..
+            }) => (*udp_payload_size as usize).max(UDP_MESSAGE_LENGTH),
+            _ => UDP_MESSAGE_LENGTH,
+        };
+
+        // Why the last datagram that looked like an answer was rejected,
+        // reported in place of a plain timeout
+        let mut rejected = None;
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(rejected.unwrap_or(DnsError::Timeout));
+            }
+            socket
+                .set_read_timeout(Some(remaining))
+                .map_err(|source| DnsError::SocketIO { source })?;
+
+            let mut response_buffer = PacketBuffer::with_capacity(capacity);
+            let source = match socket.recv_from(&mut response_buffer.buffer) {
+                Ok((length, source)) => {
+                    response_buffer.length = length;
+                    source
+                }
+                Err(error)
+                    if error.kind() == ErrorKind::WouldBlock
+                        || error.kind() == ErrorKind::TimedOut =>
+                {
+                    return Err(rejected.unwrap_or(DnsError::Timeout));
+                }
+                Err(source) => return Err(DnsError::SocketIO { source }),
+            };
+
+            // Anything that fails to parse or validate is treated as a spoofing
+            // attempt and dropped, the real answer may still be on its way.
+            let response = match DnsPacket::from_buffer(&mut response_buffer) {
+                Ok(response) => response,
+                Err(_) => continue,
+            };
+            if let Err(error) = self.check_response(packet, &response, source) {
+                rejected = Some(error);
+                continue;
+            }
+            if let Some(verifier) = verifier.as_mut() {
+                let bytes = &response_buffer.buffer[..response_buffer.length];
+                match verifier.verify(bytes, unix_time()) {
+                    Ok(_) => {}
+                    // Only signed rejections are final, unsigned BADSIG and
+                    // BADKEY ones could come from anyone (RFC 8945 5.3.2)
+                    Err(error @ DnsError::TsigRejected(_)) => return Err(error),
+                    Err(_) => continue,
+                }
+            }
+
+            let bytes = &response_buffer.buffer[..response_buffer.length];
+            self.log_response(Transport::Udp, local, sent, bytes);
+            return Ok((response, response_buffer.length));
+        }
+    }
+
+    /// Sends `request`, the wire form of `packet`, over a new TCP connection
+    /// and reads the response. There is no one to spoof a connection, any
+    /// mismatch is an error.
+    fn send_tcp(
+        &self,
+        packet: &DnsPacket,
+        request: &[u8],
+        verifier: Option<TsigVerifier>,
+    ) -> crate::Result<(DnsPacket, usize)> {
+        let mut stream = TcpStream::connect_timeout(&self.server, self.timeout)
+            .map_err(|source| DnsError::SocketIO { source })?;
+        stream
+            .set_read_timeout(Some(self.timeout))
+            .and_then(|()| stream.set_write_timeout(Some(self.timeout)))
+            .map_err(|source| DnsError::SocketIO { source })?;
+
+        let mut framed = Vec::with_capacity(request.len() + 2);
+        framed.extend_from_slice(&(request.len() as u16).to_be_bytes());
+        framed.extend_from_slice(request);
+        stream.write_all(&framed).map_err(stream_error)?;
+        let local = stream.local_addr().ok();
+        let sent = self.log_query(Transport::Tcp, local, request);
+
+        let bytes = read_tcp_message(&mut stream)?.ok_or_else(|| DnsError::SocketIO {
+            source: ErrorKind::UnexpectedEof.into(),
+        })?;
+        let response = match verifier {
+            Some(mut verifier) => verifier.verify(&bytes, unix_time())?,
+            None => DnsPacket::from_buffer(&mut PacketBuffer::from_bytes(&bytes)?)?,
+        };
+        self.check_response(packet, &response, self.server)?;
+
+        self.log_response(Transport::Tcp, local, sent, &bytes);
+        Ok((response, bytes.len()))
+    }
+
+    /// Like `validate_response`, telling a question that only differs in case
+    /// from a 0x20 encoded query apart as `CaseMismatch`.
+    fn check_response(
+        &self,
+        packet: &DnsPacket,
+        response: &DnsPacket,
+        source: SocketAddr,
+    ) -> crate::Result<()> {
+        let exact_case = self.randomize_case;
+        match validate_response(packet, response, self.server, source, exact_case) {
+            Err(DnsError::QuestionMismatch)
+                if exact_case
+                    && validate_response(packet, response, self.server, source, false).is_ok() =>
+            {
+                Err(DnsError::CaseMismatch)
+            }
+            result => result,
+        }
+    }
+
+    /// Logs `request` sent from `local` to dnstap, returning when it was
+    /// sent.
+    fn log_query(
+        &self,
+        transport: Transport,
+        local: Option<SocketAddr>,
+        request: &[u8],
+    ) -> SystemTime {
+        let now = SystemTime::now();
+        if let (Some(dnstap), Some(local)) = (&self.dnstap, local) {
+            let kind = MessageType::ResolverQuery;
+            dnstap.log(&DnstapMessage::query(
+                kind,
+                transport,
+                local,
+                self.server,
+                now,
+                request,
+            ));
+        }
+
+        now
+    }
+
+    fn log_response(
+        &self,
+        transport: Transport,
+        local: Option<SocketAddr>,
+        sent: SystemTime,
+        response: &[u8],
+    ) {
+        if let (Some(dnstap), Some(local)) = (&self.dnstap, local) {
+            dnstap.log(&DnstapMessage::response(
+                MessageType::ResolverResponse,
+                transport,
+                local,
+                self.server,
+                Some(sent),
+                SystemTime::now(),
+                response,
+            ));
+        }
+    }
+
+    /// Binds a socket of the same address family as the server on a random
+    /// unprivileged port.
+    fn bind(&self) -> crate::Result<UdpSocket> {
+        let address = match self.server {
+            SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        };
+
+        for _ in 0..BIND_ATTEMPTS {
+            let port = random_port()?;
+            match UdpSocket::bind(SocketAddr::new(address.ip(), port)) {
+                Ok(socket) => return Ok(socket),
+                Err(error) if error.kind() == ErrorKind::AddrInUse => continue,
+                Err(source) => return Err(DnsError::SocketBind { source }),
+            }
+        }
+
+        UdpSocket::bind(address).map_err(|source| DnsError::SocketBind { source })
+    }
+}
+
+/// Whether `error` is one `validate_response` rejects answers with.
+fn is_mismatch(error: &DnsError) -> bool {
+    matches!(
+        error,
+        DnsError::UnexpectedSource { .. }
+            | DnsError::IdMismatch { .. }
+            | DnsError::QuestionMismatch
+            | DnsError::CaseMismatch
+    )
+}
+
+/// Checks that `response`, received from `source`, is a genuine answer to
+/// `query` sent to `server`.
+///
+/// Names are compared case-insensitively unless `exact_case` is set, which is
+/// required to verify 0x20 encoded queries.
+pub fn validate_response(
+    query: &DnsPacket,
+    response: &DnsPacket,
+    server: SocketAddr,
+    source: SocketAddr,
+    exact_case: bool,
+) -> crate::Result<()> {
+    if source != server {
+        return Err(DnsError::UnexpectedSource {
+            expected: server,
+            received: source,
+        });
+    }
+
+    if !response.header.response || response.header.id != query.header.id {
+        return Err(DnsError::IdMismatch {
+            expected: query.header.id,
+            received: response.header.id,
+        });
+    }
+
+    let questions_match = response.questions.len() == query.questions.len()
+        && response
+            .questions
+            .iter()
+            .zip(&query.questions)
+            .all(|(received, sent)| {
+                let name_matches = if exact_case {
+                    received.name.eq_exact(&sent.name)
+                } else {
+                    received.name == sent.name
+                };
+                received.qtype == sent.qtype && received.qclass == sent.qclass && name_matches
+            });
+    if !questions_match {
+        return Err(DnsError::QuestionMismatch);
+    }
+
+    Ok(())
+}
+
+pub fn random_u16() -> crate::Result<u16> {
+    let mut bytes = [0; 2];
+    getrandom::getrandom(&mut bytes).map_err(|source| DnsError::Random { source })?;
+
+    Ok(u16::from_be_bytes(bytes))
+}
+
+/// A port from `MIN_SOURCE_PORT` to 65535, all equally likely. Draws past
+/// the last whole multiple of the range are rejected rather than folded
+/// onto the lowest ports.
+fn random_port() -> crate::Result<u16> {
+    let range = u16::MAX as u32 - MIN_SOURCE_PORT as u32 + 1;
+    let limit = (1 << 16) / range * range;
+    loop {
+        let draw = random_u16()? as u32;
+        if draw < limit {
+            return Ok((MIN_SOURCE_PORT as u32 + draw % range) as u16);
+        }
+    }
+}
+
+/// Flips the case of every ASCII letter in `name` with probability one half.
+pub fn randomize_case(name: &Name) -> crate::Result<Name> {
+    let mut labels = Vec::with_capacity(name.num_labels());
+    for label in name.labels() {
+        let mut bits = vec![0; label.len()];
+        getrandom::getrandom(&mut bits).map_err(|source| DnsError::Random { source })?;
+
+        labels.push(
+            label
+                .iter()
+                .zip(bits)
+                .map(|(byte, bit)| {
+                    if bit & 1 == 1 {
+                        byte.to_ascii_uppercase()
+                    } else {
+                        byte.to_ascii_lowercase()
+                    }
+                })
+                .collect::<Vec<u8>>(),
+        );
+    }
+
+    Name::from_labels(labels)
+}
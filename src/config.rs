@@ -0,0 +1,711 @@
+//! TOML configuration of a server: listeners, zones, keys, ACLs, logging
+//! and limits.
+//!
+//! ```toml
+//! [server]
+//! listen = ["0.0.0.0:53", "[::]:53"]
+//! transports = ["udp", "tcp"]
+//! also_notify = ["192.0.2.2:53"]
+//!
+//! [[key]]
+//! name = "transfer"
+//! algorithm = "hmac-sha256"
+//! secret = "c2VjcmV0IGtleSBtYXRlcmlhbA=="
+//!
+//! [[zone]]
+//! origin = "example.com"
+//! journal = "/var/lib/tarnish/example.com.jnl"
+//! soa = { mname = "ns1.example.com", rname = "hostmaster.example.com" }
+//! ns = ["ns1.example.com", "ns2.example.net"]
+//!
+//! [[zone]]
+//! origin = "example.net"
+//! primary = "192.0.2.1:53"
+//! key = "transfer"
+//!
+//! [acl]
+//! transfer = ["192.0.2.0/24"]
+//! update = []
+//!
+//! [log.queries]
+//! path = "/var/log/tarnish/queries.log"
+//! max_bytes = 10485760
+//! keep = 5
+//!
+//! [log.dnstap]
+//! socket = "/run/dnstap.sock"
+//!
+//! [metrics]
+//! listen = "127.0.0.1:9153"
+//!
+//! [limits]
+//! tcp_idle_timeout = 10
+//! max_tcp_connections = 256
+//! ```
+//!
+//! A primary zone is created from its `soa` and `ns` settings while its
+//! journal holds no history yet, and from then on read back from the
+//! journal. Starting with neither is an error, not an empty zone.
+//!
+//! ACL settings left out keep the defaults of `Acl::new`, which only allow
+//! transfers and updates from loopback addresses. An empty list allows no
+//! client at all.
+//!
+//! Unknown settings are errors, as are values that do not make sense,
+//! reported with the setting they were found in.
+
+use std::collections::BTreeSet;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use base64::Engine;
+use serde::Deserialize;
+
+use crate::acl::{Acl, Network};
+use crate::dnstap::Dnstap;
+use crate::name::Name;
+use crate::protocol::{DnsClass, DnsRecord};
+use crate::querylog::{LogFormat, QueryLog, Rotation};
+use crate::secondary::SecondaryZone;
+use crate::server::{Catalog, DnsServer, DEFAULT_TCP_IDLE_TIMEOUT};
+use crate::tsig::{TsigAlgorithm, TsigKey};
+use crate::zone::Zone;
+use crate::DnsError;
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawConfig {
+    server: RawServer,
+    #[serde(rename = "key")]
+    keys: Vec<RawKey>,
+    #[serde(rename = "zone")]
+    zones: Vec<RawZone>,
+    acl: RawAcl,
+    log: RawLog,
+    metrics: Option<RawMetrics>,
+    limits: RawLimits,
+    // Accepted only to be rejected with a clearer message than an unknown
+    // field gets
+    upstreams: Option<toml::Value>,
+    cache: Option<toml::Value>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawServer {
+    listen: Vec<SocketAddr>,
+    transports: Option<Vec<String>>,
+    also_notify: Vec<SocketAddr>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawKey {
+    name: String,
+    algorithm: String,
+    secret: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawZone {
+    origin: String,
+    class: Option<String>,
+    journal: Option<PathBuf>,
+    primary: Option<SocketAddr>,
+    key: Option<String>,
+    soa: Option<RawSoa>,
+    ns: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawSoa {
+    mname: String,
+    rname: String,
+    serial: Option<u32>,
+    refresh: Option<u32>,
+    retry: Option<u32>,
+    expire: Option<u32>,
+    minimum: Option<u32>,
+    ttl: Option<u32>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawAcl {
+    query: Option<Vec<String>>,
+    transfer: Option<Vec<String>>,
+    update: Option<Vec<String>>,
+    notify: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawLog {
+    queries: Option<RawQueryLog>,
+    dnstap: Option<RawDnstap>,
+    capture: Option<RawCapture>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawQueryLog {
+    path: PathBuf,
+    format: Option<String>,
+    sample: Option<u64>,
+    #[serde(default)]
+    anonymize: bool,
+    max_bytes: Option<u64>,
+    keep: Option<usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawDnstap {
+    path: Option<PathBuf>,
+    socket: Option<PathBuf>,
+    identity: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawCapture {
+    path: PathBuf,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawMetrics {
+    listen: SocketAddr,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawLimits {
+    tcp_idle_timeout: Option<u64>,
+    max_tcp_connections: Option<usize>,
+}
+
+/// Where the zones of a server come from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ZoneSource {
+    /// A primary zone, kept in a journal and changed by dynamic updates.
+    /// `initial` holds the SOA and NS records it is created with when the
+    /// journal is still empty, if any.
+    Journal {
+        path: PathBuf,
+        initial: Vec<DnsRecord>,
+    },
+    /// A secondary zone, transferred from its primary.
+    Secondary {
+        primary: SocketAddr,
+        key: Option<TsigKey>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ZoneConfig {
+    pub origin: Name,
+    pub class: DnsClass,
+    pub source: ZoneSource,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QueryLogConfig {
+    /// The file written to, `-` for standard output.
+    pub path: PathBuf,
+    pub format: LogFormat,
+    pub sample: u64,
+    pub anonymize: bool,
+    pub rotation: Option<Rotation>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DnstapOutput {
+    File(PathBuf),
+    UnixSocket(PathBuf),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DnstapConfig {
+    pub output: DnstapOutput,
+    pub identity: Option<String>,
+}
+
+/// A validated server configuration.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerConfig {
+    pub listen: Vec<SocketAddr>,
+    pub udp: bool,
+    pub tcp: bool,
+    pub also_notify: Vec<SocketAddr>,
+    pub keys: Vec<TsigKey>,
+    pub zones: Vec<ZoneConfig>,
+    pub acl: Acl,
+    pub query_log: Option<QueryLogConfig>,
+    pub dnstap: Option<DnstapConfig>,
+    /// File the traffic is captured to, in pcap format.
+    pub capture: Option<PathBuf>,
+    /// Address the `/metrics` endpoint is served on.
+    pub metrics: Option<SocketAddr>,
+    pub tcp_idle_timeout: Duration,
+    pub max_tcp_connections: Option<usize>,
+}
+
+fn invalid(setting: &str, message: impl std::fmt::Display) -> DnsError {
+    DnsError::InvalidConfig(format!("{}: {}", setting, message))
+}
+
+impl ServerConfig {
+    /// Reads and validates the configuration file at `path`.
+    pub fn load(path: impl AsRef<Path>) -> crate::Result<ServerConfig> {
+        let text = std::fs::read_to_string(path).map_err(|source| DnsError::ConfigIO { source })?;
+        ServerConfig::parse(&text)
+    }
+
+    /// Parses and validates a configuration.
+    pub fn parse(text: &str) -> crate::Result<ServerConfig> {
+        let raw: RawConfig =
+            toml::from_str(text).map_err(|error| DnsError::InvalidConfig(error.to_string()))?;
+
+        if raw.upstreams.is_some() {
+            return Err(invalid(
+                "upstreams",
+                "forwarding is not supported, the server only answers from its own zones",
+            ));
+        }
+        if raw.cache.is_some() {
+            return Err(invalid(
+                "cache",
+                "caching is not supported, the server only answers from its own zones",
+            ));
+        }
+
+        if raw.server.listen.is_empty() {
+            return Err(invalid("server.listen", "at least one address is needed"));
+        }
+        let (mut udp, mut tcp) = (
+            raw.server.transports.is_none(),
+            raw.server.transports.is_none(),
+        );
+        for transport in raw.server.transports.iter().flatten() {
+            match transport.to_ascii_lowercase().as_str() {
+                "udp" => udp = true,
+                "tcp" => tcp = true,
+                _ => {
+                    return Err(invalid(
+                        "server.transports",
+                        format!("unknown transport `{}`, expected `udp` or `tcp`", transport),
+                    ))
+                }
+            }
+        }
+        if !udp && !tcp {
+            return Err(invalid(
+                "server.transports",
+                "at least one transport is needed",
+            ));
+        }
+
+        let keys = raw
+            .keys
+            .iter()
+            .map(parse_key)
+            .collect::<crate::Result<Vec<_>>>()?;
+        let mut key_names = BTreeSet::new();
+        for key in &keys {
+            if !key_names.insert(&key.name) {
+                return Err(invalid(&format!("key `{}`", key.name), "defined twice"));
+            }
+        }
+
+        let mut zones: Vec<ZoneConfig> = Vec::new();
+        for zone in &raw.zones {
+            let zone = parse_zone(zone, &keys)?;
+            if zones
+                .iter()
+                .any(|other| other.origin == zone.origin && other.class == zone.class)
+            {
+                return Err(invalid(&format!("zone `{}`", zone.origin), "defined twice"));
+            }
+            zones.push(zone);
+        }
+
+        let defaults = Acl::new();
+        let acl = Acl {
+            query: parse_networks("acl.query", raw.acl.query, defaults.query)?,
+            transfer: parse_networks("acl.transfer", raw.acl.transfer, defaults.transfer)?,
+            update: parse_networks("acl.update", raw.acl.update, defaults.update)?,
+            notify: parse_networks("acl.notify", raw.acl.notify, defaults.notify)?,
+        };
+
+        let query_log = raw.log.queries.map(parse_query_log).transpose()?;
+        let dnstap = raw.log.dnstap.map(parse_dnstap).transpose()?;
+
+        let tcp_idle_timeout = match raw.limits.tcp_idle_timeout {
+            Some(0) => {
+                return Err(invalid(
+                    "limits.tcp_idle_timeout",
+                    "must be at least 1 second",
+                ))
+            }
+            Some(seconds) => Duration::from_secs(seconds),
+            None => DEFAULT_TCP_IDLE_TIMEOUT,
+        };
+        if raw.limits.max_tcp_connections == Some(0) {
+            return Err(invalid("limits.max_tcp_connections", "must be at least 1"));
+        }
+
+        Ok(ServerConfig {
+            listen: raw.server.listen,
+            udp,
+            tcp,
+            also_notify: raw.server.also_notify,
+            keys,
+            zones,
+            acl,
+            query_log,
+            dnstap,
+            capture: raw.log.capture.map(|capture| capture.path),
+            metrics: raw.metrics.map(|metrics| metrics.listen),
+            tcp_idle_timeout,
+            max_tcp_connections: raw.limits.max_tcp_connections,
+        })
+    }
+
+    /// Creates a server set up as configured, with its journals, logs and
+    /// capture opened. Secondary zones are left to `secondary_zones`, and
+    /// listening to the caller.
+    pub fn build_server(&self) -> crate::Result<DnsServer> {
+        let mut server = DnsServer::new(Catalog::new());
+        server.keys = self.keys.clone();
+        server.also_notify = self.also_notify.clone();
+        server.acl = self.acl.clone();
+        server.tcp_idle_timeout = self.tcp_idle_timeout;
+        server.max_tcp_connections = self.max_tcp_connections;
+
+        for zone in &self.zones {
+            if let ZoneSource::Journal { path, initial } = &zone.source {
+                if !initial.is_empty() {
+                    let mut seed = Zone::new(zone.origin.clone(), zone.class);
+                    for record in initial {
+                        seed.insert(record.clone());
+                    }
+                    server
+                        .catalog
+                        .write()
+                        .unwrap_or_else(|error| error.into_inner())
+                        .insert(seed);
+                }
+                server.open_journal(zone.origin.clone(), zone.class, path)?;
+                let catalog = server
+                    .catalog
+                    .read()
+                    .unwrap_or_else(|error| error.into_inner());
+                if catalog.get(&zone.origin, zone.class).is_none() {
+                    return Err(empty_journal(zone, path));
+                }
+            }
+        }
+
+        if let Some(config) = &self.query_log {
+            let mut query_log = if config.path == Path::new("-") {
+                QueryLog::to_writer(std::io::stdout())
+            } else {
+                QueryLog::to_file(&config.path, config.rotation)?
+            };
+            query_log.format = config.format;
+            query_log.sample = config.sample;
+            query_log.anonymize = config.anonymize;
+            server.query_log = Some(query_log);
+        }
+
+        if let Some(config) = &self.dnstap {
+            let mut dnstap = match &config.output {
+                DnstapOutput::File(path) => Dnstap::to_file(path)?,
+                #[cfg(unix)]
+                DnstapOutput::UnixSocket(path) => Dnstap::to_unix_socket(path),
+                #[cfg(not(unix))]
+                DnstapOutput::UnixSocket(_) => {
+                    return Err(invalid(
+                        "log.dnstap.socket",
+                        "Unix sockets are not supported",
+                    ))
+                }
+            };
+            dnstap.identity = config.identity.clone().map(String::into_bytes);
+            server.dnstap = Some(dnstap);
+        }
+
+        if let Some(path) = &self.capture {
+            let file =
+                std::fs::File::create(path).map_err(|source| DnsError::CaptureIO { source })?;
+            server.capture_to(file)?;
+        }
+
+        Ok(server)
+    }
+
+    /// Checks that every primary zone has something to start from: either
+    /// its `soa` and `ns` settings or a journal that already holds the zone.
+    pub fn check_zones(&self) -> crate::Result<()> {
+        for zone in &self.zones {
+            if let ZoneSource::Journal { path, initial } = &zone.source {
+                // Records only reach a journal as part of a base copy
+                if initial.is_empty() && !path.exists() {
+                    return Err(empty_journal(zone, path));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The secondary zones, to be added to a `SecondaryManager`.
+    pub fn secondary_zones(&self) -> Vec<SecondaryZone> {
+        self.zones
+            .iter()
+            .filter_map(|zone| match &zone.source {
+                ZoneSource::Secondary { primary, key } => Some(SecondaryZone::new(
+                    zone.origin.clone(),
+                    zone.class,
+                    *primary,
+                    key.clone(),
+                )),
+                ZoneSource::Journal { .. } => None,
+            })
+            .collect()
+    }
+}
+
+fn parse_key(raw: &RawKey) -> crate::Result<TsigKey> {
+    let setting = format!("key `{}`", raw.name);
+    let name: Name = raw
+        .name
+        .parse()
+        .map_err(|error| invalid(&setting, format!("invalid name: {}", error)))?;
+    let algorithm = raw
+        .algorithm
+        .parse::<Name>()
+        .ok()
+        .and_then(|name| TsigAlgorithm::from_name(&name))
+        .ok_or_else(|| {
+            invalid(
+                &setting,
+                format!(
+                    "unknown algorithm `{}`, expected hmac-sha256, hmac-sha384 or hmac-sha512",
+                    raw.algorithm
+                ),
+            )
+        })?;
+    let secret = base64::engine::general_purpose::STANDARD
+        .decode(&raw.secret)
+        .map_err(|error| invalid(&setting, format!("secret is not valid base64: {}", error)))?;
+    if secret.is_empty() {
+        return Err(invalid(&setting, "secret is empty"));
+    }
+
+    Ok(TsigKey::new(name, algorithm, secret))
+}
+
+fn parse_zone(raw: &RawZone, keys: &[TsigKey]) -> crate::Result<ZoneConfig> {
+    let setting = format!("zone `{}`", raw.origin);
+    let origin: Name = raw
+        .origin
+        .parse()
+        .map_err(|error| invalid(&setting, format!("invalid origin: {}", error)))?;
+    let class = match &raw.class {
+        Some(class) => class
+            .parse()
+            .map_err(|_| invalid(&setting, format!("unknown class `{}`", class)))?,
+        None => DnsClass::IN,
+    };
+
+    let source = match (&raw.journal, raw.primary) {
+        (Some(_), Some(_)) => {
+            return Err(invalid(
+                &setting,
+                "`journal` and `primary` are exclusive, a zone is either primary or secondary",
+            ))
+        }
+        (Some(journal), None) => {
+            if raw.key.is_some() {
+                return Err(invalid(&setting, "`key` only applies to secondary zones"));
+            }
+            ZoneSource::Journal {
+                path: journal.clone(),
+                initial: parse_initial(raw, &setting, &origin, class)?,
+            }
+        }
+        (None, Some(primary)) => {
+            if raw.soa.is_some() || raw.ns.is_some() {
+                return Err(invalid(
+                    &setting,
+                    "`soa` and `ns` only apply to primary zones, secondaries transfer theirs",
+                ));
+            }
+            let key = match &raw.key {
+                Some(key_name) => {
+                    let key = key_name
+                        .parse::<Name>()
+                        .ok()
+                        .and_then(|name| keys.iter().find(|key| key.name == name));
+                    let key = key.ok_or_else(|| {
+                        invalid(&setting, format!("key `{}` is not defined", key_name))
+                    })?;
+                    Some(key.clone())
+                }
+                None => None,
+            };
+            ZoneSource::Secondary { primary, key }
+        }
+        (None, None) => {
+            return Err(invalid(
+                &setting,
+                "needs a `journal` for a primary zone or a `primary` for a secondary one",
+            ))
+        }
+    };
+
+    Ok(ZoneConfig {
+        origin,
+        class,
+        source,
+    })
+}
+
+/// The SOA and NS records a primary zone is created with, empty when the
+/// configuration gives neither.
+fn parse_initial(
+    raw: &RawZone,
+    setting: &str,
+    origin: &Name,
+    class: DnsClass,
+) -> crate::Result<Vec<DnsRecord>> {
+    let parse_name = |field: &str, text: &str| {
+        text.parse::<Name>()
+            .map_err(|error| invalid(setting, format!("invalid `{}` name: {}", field, error)))
+    };
+
+    let (soa, servers) = match (&raw.soa, &raw.ns) {
+        (None, None) => return Ok(Vec::new()),
+        (Some(soa), Some(servers)) if !servers.is_empty() => (soa, servers),
+        (Some(_), _) => return Err(invalid(setting, "`soa` needs at least one `ns`")),
+        (None, Some(_)) => return Err(invalid(setting, "`ns` needs an `soa`")),
+    };
+    let ttl = soa.ttl.unwrap_or(3600);
+    let mut records = vec![DnsRecord::SOA {
+        domain: origin.clone(),
+        class,
+        m_name: parse_name("mname", &soa.mname)?,
+        r_name: parse_name("rname", &soa.rname)?,
+        serial: soa.serial.unwrap_or(1),
+        refresh: soa.refresh.unwrap_or(3600),
+        retry: soa.retry.unwrap_or(900),
+        expire: soa.expire.unwrap_or(1209600),
+        minimum: soa.minimum.unwrap_or(300),
+        ttl,
+    }];
+    for server in servers {
+        records.push(DnsRecord::NS {
+            domain: origin.clone(),
+            class,
+            host: parse_name("ns", server)?,
+            ttl,
+        });
+    }
+
+    Ok(records)
+}
+
+fn empty_journal(zone: &ZoneConfig, path: &Path) -> DnsError {
+    invalid(
+        &format!("zone `{}`", zone.origin),
+        format!(
+            "journal `{}` holds no zone yet, give its `soa` and `ns` to create it",
+            path.display()
+        ),
+    )
+}
+
+/// Parses the networks of an ACL setting, `default` standing in for a
+/// missing one. An empty list stays empty, allowing no client.
+fn parse_networks(
+    setting: &str,
+    networks: Option<Vec<String>>,
+    default: Vec<Network>,
+) -> crate::Result<Vec<Network>> {
+    let Some(networks) = networks else {
+        return Ok(default);
+    };
+    networks
+        .iter()
+        .map(|network| network.parse().map_err(|error| invalid(setting, error)))
+        .collect()
+}
+
+fn parse_query_log(raw: RawQueryLog) -> crate::Result<QueryLogConfig> {
+    let format = match raw.format.as_deref() {
+        None | Some("json") => LogFormat::Json,
+        Some("text") => LogFormat::Text,
+        Some(format) => {
+            return Err(invalid(
+                "log.queries.format",
+                format!("unknown format `{}`, expected `json` or `text`", format),
+            ))
+        }
+    };
+    let sample = match raw.sample {
+        Some(0) => return Err(invalid("log.queries.sample", "must be at least 1")),
+        Some(sample) => sample,
+        None => 1,
+    };
+    let rotation = match (raw.max_bytes, raw.keep) {
+        (Some(0), _) => return Err(invalid("log.queries.max_bytes", "must be at least 1")),
+        (Some(max_bytes), keep) => Some(Rotation {
+            max_bytes,
+            keep: keep.unwrap_or(1),
+        }),
+        (None, Some(_)) => {
+            return Err(invalid(
+                "log.queries.keep",
+                "only applies to rotated logs, set `max_bytes` too",
+            ))
+        }
+        (None, None) => None,
+    };
+    if rotation.is_some() && raw.path == Path::new("-") {
+        return Err(invalid(
+            "log.queries.max_bytes",
+            "standard output cannot be rotated",
+        ));
+    }
+
+    Ok(QueryLogConfig {
+        path: raw.path,
+        format,
+        sample,
+        anonymize: raw.anonymize,
+        rotation,
+    })
+}
+
+fn parse_dnstap(raw: RawDnstap) -> crate::Result<DnstapConfig> {
+    let output = match (raw.path, raw.socket) {
+        (Some(path), None) => DnstapOutput::File(path),
+        (None, Some(socket)) => DnstapOutput::UnixSocket(socket),
+        _ => {
+            return Err(invalid(
+                "log.dnstap",
+                "needs exactly one of `path` for a file or `socket` for a collector",
+            ))
+        }
+    };
+
+    Ok(DnstapConfig {
+        output,
+        identity: raw.identity,
+    })
+}
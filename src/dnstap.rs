@@ -0,0 +1,677 @@
+//! dnstap logging: protobuf encoded `Dnstap` messages carried over Frame
+//! Streams.
+//!
+//! A `Dnstap` handle encodes messages on the calling thread and queues them
+//! for a background writer, so that logging never holds up answering. When
+//! the queue is full messages are dropped and counted instead.
+//!
+//! Files get a unidirectional stream. Unix sockets get the bidirectional
+//! handshake expected by collectors such as `fstrm_capture`, and are
+//! reconnected when the collector goes away.
+
+use std::fs::File;
+use std::io::{BufWriter, ErrorKind, Read, Write};
+use std::net::{IpAddr, SocketAddr};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError, TrySendError};
+use std::sync::Arc;
+use std::thread;
+#[cfg(unix)]
+use std::time::Instant;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::pcap::{same_family, Transport};
+use crate::DnsError;
+
+/// Content type of the frames, announced in the control frames.
+pub const CONTENT_TYPE: &[u8] = b"protobuf:dnstap.Dnstap";
+
+const CONTROL_ACCEPT: u32 = 0x01;
+const CONTROL_START: u32 = 0x02;
+const CONTROL_STOP: u32 = 0x03;
+const CONTROL_READY: u32 = 0x04;
+const CONTROL_FIELD_CONTENT_TYPE: u32 = 0x01;
+
+/// Largest control or data frame read.
+const MAX_FRAME_LENGTH: usize = 1 << 20;
+
+/// Messages waiting for the writer before new ones are dropped.
+const QUEUE_LENGTH: usize = 4096;
+
+/// Time a collector has to answer the handshake.
+#[cfg(unix)]
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Time between attempts to reach a collector that went away.
+#[cfg(unix)]
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// `Dnstap.Type.MESSAGE`, the only type of `Dnstap` message.
+const DNSTAP_TYPE_MESSAGE: u64 = 1;
+const SOCKET_FAMILY_INET: u64 = 1;
+const SOCKET_FAMILY_INET6: u64 = 2;
+const SOCKET_PROTOCOL_UDP: u64 = 1;
+const SOCKET_PROTOCOL_TCP: u64 = 2;
+
+/// The events logged, with their `Message.Type` numbers.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MessageType {
+    /// A query sent by this host to a name server.
+    ResolverQuery,
+    ResolverResponse,
+    /// A query received by the server from a client.
+    ClientQuery,
+    ClientResponse,
+}
+
+impl MessageType {
+    pub fn to_number(&self) -> u64 {
+        match self {
+            MessageType::ResolverQuery => 3,
+            MessageType::ResolverResponse => 4,
+            MessageType::ClientQuery => 5,
+            MessageType::ClientResponse => 6,
+        }
+    }
+
+    pub fn from_number(num: u64) -> Option<MessageType> {
+        match num {
+            3 => Some(MessageType::ResolverQuery),
+            4 => Some(MessageType::ResolverResponse),
+            5 => Some(MessageType::ClientQuery),
+            6 => Some(MessageType::ClientResponse),
+            _ => None,
+        }
+    }
+}
+
+/// One logged event, the `Message` of a `Dnstap` message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DnstapMessage {
+    pub message_type: MessageType,
+    pub transport: Transport,
+    /// The end that sent the query.
+    pub query_address: SocketAddr,
+    pub response_address: SocketAddr,
+    pub query_time: Option<SystemTime>,
+    /// The query in wire format.
+    pub query_message: Option<Vec<u8>>,
+    pub response_time: Option<SystemTime>,
+    pub response_message: Option<Vec<u8>>,
+}
+
+impl DnstapMessage {
+    /// A query event, `message` going from `client` to `server`.
+    pub fn query(
+        message_type: MessageType,
+        transport: Transport,
+        client: SocketAddr,
+        server: SocketAddr,
+        time: SystemTime,
+        message: &[u8],
+    ) -> DnstapMessage {
+        DnstapMessage {
+            message_type,
+            transport,
+            query_address: client,
+            response_address: server,
+            query_time: Some(time),
+            query_message: Some(message.to_vec()),
+            response_time: None,
+            response_message: None,
+        }
+    }
+
+    /// A response event, `message` going from `server` to `client` in
+    /// answer to a query made at `query_time`.
+    pub fn response(
+        message_type: MessageType,
+        transport: Transport,
+        client: SocketAddr,
+        server: SocketAddr,
+        query_time: Option<SystemTime>,
+        time: SystemTime,
+        message: &[u8],
+    ) -> DnstapMessage {
+        DnstapMessage {
+            message_type,
+            transport,
+            query_address: client,
+            response_address: server,
+            query_time,
+            query_message: None,
+            response_time: Some(time),
+            response_message: Some(message.to_vec()),
+        }
+    }
+
+    /// Encodes the message inside a `Dnstap` message.
+    pub fn encode(&self, identity: Option<&[u8]>, version: Option<&[u8]>) -> Vec<u8> {
+        let (query_ip, response_ip) =
+            same_family(self.query_address.ip(), self.response_address.ip());
+        let family = match query_ip {
+            IpAddr::V4(_) => SOCKET_FAMILY_INET,
+            IpAddr::V6(_) => SOCKET_FAMILY_INET6,
+        };
+        let protocol = match self.transport {
+            Transport::Udp => SOCKET_PROTOCOL_UDP,
+            Transport::Tcp => SOCKET_PROTOCOL_TCP,
+        };
+
+        let mut message = Protobuf::default();
+        message.varint(1, self.message_type.to_number());
+        message.varint(2, family);
+        message.varint(3, protocol);
+        message.bytes(4, &ip_octets(query_ip));
+        message.bytes(5, &ip_octets(response_ip));
+        message.varint(6, self.query_address.port() as u64);
+        message.varint(7, self.response_address.port() as u64);
+        if let Some(time) = self.query_time {
+            let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+            message.varint(8, since_epoch.as_secs());
+            message.fixed32(9, since_epoch.subsec_nanos());
+        }
+        if let Some(query) = &self.query_message {
+            message.bytes(10, query);
+        }
+        if let Some(time) = self.response_time {
+            let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+            message.varint(12, since_epoch.as_secs());
+            message.fixed32(13, since_epoch.subsec_nanos());
+        }
+        if let Some(response) = &self.response_message {
+            message.bytes(14, response);
+        }
+
+        let mut dnstap = Protobuf::default();
+        if let Some(identity) = identity {
+            dnstap.bytes(1, identity);
+        }
+        if let Some(version) = version {
+            dnstap.bytes(2, version);
+        }
+        dnstap.bytes(14, &message.0);
+        dnstap.varint(15, DNSTAP_TYPE_MESSAGE);
+
+        dnstap.0
+    }
+
+    /// Decodes the `Message` of a `Dnstap` message, ignoring the fields not
+    /// represented here.
+    pub fn decode(bytes: &[u8]) -> crate::Result<DnstapMessage> {
+        let mut message = None;
+        for field in fields(bytes) {
+            if let (14, Value::Bytes(bytes)) = field? {
+                message = Some(bytes);
+            }
+        }
+        let message = message.ok_or_else(|| invalid("no message"))?;
+
+        let mut message_type = None;
+        let mut transport = Transport::Udp;
+        let mut addresses: [Option<IpAddr>; 2] = [None, None];
+        let mut ports = [0u16; 2];
+        let mut times: [(Option<u64>, u32); 2] = [(None, 0), (None, 0)];
+        let mut query_message = None;
+        let mut response_message = None;
+
+        for field in fields(message) {
+            match field? {
+                (1, Value::Varint(number)) => message_type = MessageType::from_number(number),
+                (3, Value::Varint(SOCKET_PROTOCOL_TCP)) => transport = Transport::Tcp,
+                (field @ (4 | 5), Value::Bytes(octets)) => {
+                    let address = match octets.len() {
+                        4 => <[u8; 4]>::try_from(octets).map(IpAddr::from).ok(),
+                        16 => <[u8; 16]>::try_from(octets).map(IpAddr::from).ok(),
+                        _ => None,
+                    };
+                    addresses[field as usize - 4] =
+                        Some(address.ok_or_else(|| invalid("bad address"))?);
+                }
+                (field @ (6 | 7), Value::Varint(port)) => {
+                    ports[field as usize - 6] =
+                        u16::try_from(port).map_err(|_| invalid("bad port"))?;
+                }
+                (8, Value::Varint(seconds)) => times[0].0 = Some(seconds),
+                (9, Value::Fixed32(nanos)) => times[0].1 = nanos,
+                (12, Value::Varint(seconds)) => times[1].0 = Some(seconds),
+                (13, Value::Fixed32(nanos)) => times[1].1 = nanos,
+                (10, Value::Bytes(bytes)) => query_message = Some(bytes.to_vec()),
+                (14, Value::Bytes(bytes)) => response_message = Some(bytes.to_vec()),
+                _ => {}
+            }
+        }
+
+        let address = |index: usize| -> crate::Result<SocketAddr> {
+            let ip = addresses[index].ok_or_else(|| invalid("missing address"))?;
+            Ok(SocketAddr::new(ip, ports[index]))
+        };
+        let time = |(seconds, nanos): (Option<u64>, u32)| {
+            seconds.and_then(|seconds| {
+                UNIX_EPOCH.checked_add(Duration::new(seconds, nanos.min(999_999_999)))
+            })
+        };
+
+        Ok(DnstapMessage {
+            message_type: message_type.ok_or_else(|| invalid("unknown message type"))?,
+            transport,
+            query_address: address(0)?,
+            response_address: address(1)?,
+            query_time: time(times[0]),
+            query_message,
+            response_time: time(times[1]),
+            response_message,
+        })
+    }
+}
+
+fn ip_octets(address: IpAddr) -> Vec<u8> {
+    match address {
+        IpAddr::V4(address) => address.octets().to_vec(),
+        IpAddr::V6(address) => address.octets().to_vec(),
+    }
+}
+
+/// A protobuf message being encoded, fields in the order they are added.
+#[derive(Default)]
+struct Protobuf(Vec<u8>);
+
+impl Protobuf {
+    fn varint(&mut self, field: u32, value: u64) {
+        self.raw_varint((field as u64) << 3);
+        self.raw_varint(value);
+    }
+
+    fn bytes(&mut self, field: u32, bytes: &[u8]) {
+        self.raw_varint(((field as u64) << 3) | 2);
+        self.raw_varint(bytes.len() as u64);
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn fixed32(&mut self, field: u32, value: u32) {
+        self.raw_varint(((field as u64) << 3) | 5);
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn raw_varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+}
+
+enum Value<'a> {
+    Varint(u64),
+    Fixed64,
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+/// The fields of an encoded protobuf message, as field numbers and values.
+fn fields(mut bytes: &[u8]) -> impl Iterator<Item = crate::Result<(u32, Value<'_>)>> {
+    std::iter::from_fn(move || {
+        if bytes.is_empty() {
+            return None;
+        }
+
+        let mut field = || -> crate::Result<(u32, Value<'_>)> {
+            let key = read_varint(&mut bytes)?;
+            let number = u32::try_from(key >> 3).map_err(|_| invalid("bad field number"))?;
+            let value = match key & 0x07 {
+                0 => Value::Varint(read_varint(&mut bytes)?),
+                1 => {
+                    take(&mut bytes, 8)?;
+                    Value::Fixed64
+                }
+                2 => {
+                    let length = read_varint(&mut bytes)?;
+                    let length = usize::try_from(length).map_err(|_| invalid("bad length"))?;
+                    Value::Bytes(take(&mut bytes, length)?)
+                }
+                5 => {
+                    let value = take(&mut bytes, 4)?;
+                    Value::Fixed32(u32::from_le_bytes([value[0], value[1], value[2], value[3]]))
+                }
+                _ => return Err(invalid("unsupported wire type")),
+            };
+
+            Ok((number, value))
+        };
+
+        let field = field();
+        // Nothing after a malformed field can be trusted
+        if field.is_err() {
+            bytes = &[];
+        }
+        Some(field)
+    })
+}
+
+fn read_varint(bytes: &mut &[u8]) -> crate::Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *take(bytes, 1)?.first().unwrap_or(&0);
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err(invalid("varint too long"))
+}
+
+fn take<'a>(bytes: &mut &'a [u8], length: usize) -> crate::Result<&'a [u8]> {
+    if bytes.len() < length {
+        return Err(invalid("truncated message"));
+    }
+    let (taken, rest) = bytes.split_at(length);
+    *bytes = rest;
+
+    Ok(taken)
+}
+
+fn invalid(reason: &str) -> DnsError {
+    DnsError::InvalidDnstap(reason.to_string())
+}
+
+fn dnstap_error(source: std::io::Error) -> DnsError {
+    DnsError::DnstapIO { source }
+}
+
+/// A control frame of type `control`, announcing the dnstap content type.
+fn control_frame(control: u32) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(20 + CONTENT_TYPE.len());
+    // A data frame length of zero escapes a control frame
+    frame.extend_from_slice(&0u32.to_be_bytes());
+    frame.extend_from_slice(&(12 + CONTENT_TYPE.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&control.to_be_bytes());
+    frame.extend_from_slice(&CONTROL_FIELD_CONTENT_TYPE.to_be_bytes());
+    frame.extend_from_slice(&(CONTENT_TYPE.len() as u32).to_be_bytes());
+    frame.extend_from_slice(CONTENT_TYPE);
+    frame
+}
+
+fn data_frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(4 + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+fn read_u32(reader: &mut impl Read) -> std::io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+enum StreamFrame {
+    /// A control frame, its type and content types.
+    Control(u32, Vec<Vec<u8>>),
+    Data(Vec<u8>),
+}
+
+fn read_frame(reader: &mut impl Read) -> std::io::Result<StreamFrame> {
+    let too_long = || std::io::Error::new(ErrorKind::InvalidData, "frame too long");
+
+    let length = read_u32(reader)? as usize;
+    if length > MAX_FRAME_LENGTH {
+        return Err(too_long());
+    }
+    if length > 0 {
+        let mut payload = vec![0; length];
+        reader.read_exact(&mut payload)?;
+        return Ok(StreamFrame::Data(payload));
+    }
+
+    let length = read_u32(reader)? as usize;
+    if !(4..=MAX_FRAME_LENGTH).contains(&length) {
+        return Err(too_long());
+    }
+    let mut control = vec![0; length];
+    reader.read_exact(&mut control)?;
+    let control_type = u32::from_be_bytes([control[0], control[1], control[2], control[3]]);
+
+    let mut content_types = Vec::new();
+    let mut rest = &control[4..];
+    while rest.len() >= 8 {
+        let field = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]);
+        let length = u32::from_be_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize;
+        let Some(value) = rest.get(8..8 + length) else {
+            break;
+        };
+        if field == CONTROL_FIELD_CONTENT_TYPE {
+            content_types.push(value.to_vec());
+        }
+        rest = &rest[8 + length..];
+    }
+
+    Ok(StreamFrame::Control(control_type, content_types))
+}
+
+/// Reads the messages of a unidirectional dnstap stream, such as a file
+/// written by `Dnstap::to_file`. A stream cut off without its STOP frame
+/// ends at its last whole frame.
+pub fn read_stream<R: Read>(mut reader: R) -> crate::Result<Vec<DnstapMessage>> {
+    match read_frame(&mut reader).map_err(dnstap_error)? {
+        StreamFrame::Control(CONTROL_START, content_types)
+            if content_types.is_empty() || content_types.iter().any(|t| t == CONTENT_TYPE) => {}
+        _ => return Err(invalid("stream does not start with a dnstap START frame")),
+    }
+
+    let mut messages = Vec::new();
+    loop {
+        match read_frame(&mut reader) {
+            Ok(StreamFrame::Data(payload)) => messages.push(DnstapMessage::decode(&payload)?),
+            Ok(StreamFrame::Control(CONTROL_STOP, _)) => return Ok(messages),
+            Ok(StreamFrame::Control(..)) => return Err(invalid("unexpected control frame")),
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(messages),
+            Err(source) => return Err(dnstap_error(source)),
+        }
+    }
+}
+
+/// Logs dnstap messages through a background writer. Clones share the
+/// writer, which finishes the stream once every clone is dropped.
+#[derive(Clone)]
+pub struct Dnstap {
+    sender: SyncSender<Vec<u8>>,
+    dropped: Arc<AtomicU64>,
+    /// Sent as the `identity` of every message, usually the host name.
+    pub identity: Option<Vec<u8>>,
+    /// Sent as the `version` of every message.
+    pub version: Option<Vec<u8>>,
+}
+
+impl Dnstap {
+    /// Writes a unidirectional stream to the file at `path`, replacing it.
+    pub fn to_file(path: impl AsRef<Path>) -> crate::Result<Dnstap> {
+        let file = File::create(path).map_err(dnstap_error)?;
+        Dnstap::to_writer(file)
+    }
+
+    /// Writes a unidirectional stream to `writer`.
+    pub fn to_writer<W: Write + Send + 'static>(writer: W) -> crate::Result<Dnstap> {
+        let boxed: Box<dyn Write + Send> = Box::new(writer);
+        let mut writer = BufWriter::new(boxed);
+        writer
+            .write_all(&control_frame(CONTROL_START))
+            .and_then(|()| writer.flush())
+            .map_err(dnstap_error)?;
+
+        Ok(Dnstap::spawn(Sink::Stream(Some(writer))))
+    }
+
+    /// Sends messages to the collector listening on the Unix socket at
+    /// `path`. Messages logged while it cannot be reached are dropped.
+    #[cfg(unix)]
+    pub fn to_unix_socket(path: impl AsRef<Path>) -> Dnstap {
+        Dnstap::spawn(Sink::Socket {
+            path: path.as_ref().to_path_buf(),
+            connection: None,
+            retry_at: Instant::now(),
+        })
+    }
+
+    fn spawn(sink: Sink) -> Dnstap {
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_LENGTH);
+        thread::spawn(move || write_frames(receiver, sink));
+
+        Dnstap {
+            sender,
+            dropped: Arc::new(AtomicU64::new(0)),
+            identity: None,
+            version: Some(format!("tarnish-dns {}", env!("CARGO_PKG_VERSION")).into_bytes()),
+        }
+    }
+
+    pub fn log(&self, message: &DnstapMessage) {
+        let payload = message.encode(self.identity.as_deref(), self.version.as_deref());
+        match self.sender.try_send(payload) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Number of messages dropped because the writer fell behind.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+enum Sink {
+    /// `None` once writing failed, the rest of the stream is dropped.
+    Stream(Option<BufWriter<Box<dyn Write + Send>>>),
+    #[cfg(unix)]
+    Socket {
+        path: PathBuf,
+        connection: Option<BufWriter<UnixStream>>,
+        retry_at: Instant,
+    },
+}
+
+impl Sink {
+    fn write(&mut self, payload: &[u8]) {
+        let frame = data_frame(payload);
+        match self {
+            Sink::Stream(writer) => {
+                if let Some(stream) = writer {
+                    if stream.write_all(&frame).is_err() {
+                        *writer = None;
+                    }
+                }
+            }
+            #[cfg(unix)]
+            Sink::Socket {
+                path,
+                connection,
+                retry_at,
+            } => {
+                if connection.is_none() && Instant::now() >= *retry_at {
+                    *connection = connect(path).ok().map(BufWriter::new);
+                    *retry_at = Instant::now() + RECONNECT_INTERVAL;
+                }
+                if let Some(stream) = connection {
+                    if stream.write_all(&frame).is_err() {
+                        *connection = None;
+                    }
+                }
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        match self {
+            Sink::Stream(writer) => {
+                if writer
+                    .as_mut()
+                    .is_some_and(|stream| stream.flush().is_err())
+                {
+                    *writer = None;
+                }
+            }
+            #[cfg(unix)]
+            Sink::Socket { connection, .. } => {
+                if connection
+                    .as_mut()
+                    .is_some_and(|stream| stream.flush().is_err())
+                {
+                    *connection = None;
+                }
+            }
+        }
+    }
+
+    /// Ends the stream with a STOP frame, waiting for the FINISH of a
+    /// collector.
+    fn finish(&mut self) {
+        let stop = control_frame(CONTROL_STOP);
+        match self {
+            Sink::Stream(writer) => {
+                if let Some(stream) = writer {
+                    let _ = stream.write_all(&stop).and_then(|()| stream.flush());
+                }
+            }
+            #[cfg(unix)]
+            Sink::Socket { connection, .. } => {
+                if let Some(stream) = connection {
+                    let _ = stream.write_all(&stop).and_then(|()| stream.flush());
+                    // The collector answers with FINISH before closing
+                    let _ = read_frame(stream.get_mut());
+                }
+            }
+        }
+    }
+}
+
+/// Connects to a collector and goes through the handshake of bidirectional
+/// Frame Streams.
+#[cfg(unix)]
+fn connect(path: &Path) -> std::io::Result<UnixStream> {
+    let mut stream = UnixStream::connect(path)?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    stream.write_all(&control_frame(CONTROL_READY))?;
+
+    match read_frame(&mut stream)? {
+        StreamFrame::Control(CONTROL_ACCEPT, content_types)
+            if content_types.iter().any(|t| t == CONTENT_TYPE) => {}
+        _ => {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                "collector does not accept dnstap",
+            ))
+        }
+    }
+    stream.write_all(&control_frame(CONTROL_START))?;
+
+    Ok(stream)
+}
+
+/// Writes queued messages until every handle is dropped, flushing whenever
+/// the queue runs empty.
+fn write_frames(receiver: Receiver<Vec<u8>>, mut sink: Sink) {
+    loop {
+        let payload = match receiver.try_recv() {
+            Ok(payload) => payload,
+            Err(TryRecvError::Empty) => {
+                sink.flush();
+                match receiver.recv() {
+                    Ok(payload) => payload,
+                    Err(_) => break,
+                }
+            }
+            Err(TryRecvError::Disconnected) => break,
+        };
+        sink.write(&payload);
+    }
+
+    sink.finish();
+}
@@ -0,0 +1,449 @@
+//! Annotated hex dumps of messages in wire format, for debugging.
+//!
+//! Each field is shown with its offset, its bytes and what it means, down to
+//! single labels and compression pointers. The dump is made by walking the
+//! bytes independently of the parser, so that it goes as far as the message
+//! allows even when `DnsPacket::from_buffer` rejects it, and the parse error
+//! is shown next to the bytes it is about.
+
+use std::fmt;
+
+use crate::buffer::PacketBuffer;
+use crate::name::Name;
+use crate::protocol::{
+    DnsClass, DnsPacket, DnsRecord, Location, Opcode, QueryType, ResultCode, Section,
+};
+use crate::DnsError;
+
+/// Bytes shown on one line, longer fields continue on the next ones.
+const BYTES_PER_LINE: usize = 8;
+
+/// One field of the message, or a heading when `length` is zero.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DumpLine {
+    pub offset: usize,
+    pub length: usize,
+    pub text: String,
+}
+
+/// The fields of a message along with the error from parsing it, if any.
+pub struct HexDump {
+    pub bytes: Vec<u8>,
+    pub lines: Vec<DumpLine>,
+    /// Entries of the message and the offsets where they start.
+    pub entries: Vec<(Location, usize)>,
+    pub error: Option<DnsError>,
+}
+
+impl HexDump {
+    pub fn new(bytes: &[u8]) -> HexDump {
+        let mut walker = Walker {
+            bytes,
+            position: 0,
+            location: Location::default(),
+            lines: Vec::new(),
+            entries: Vec::new(),
+        };
+        walker.message();
+
+        let error = PacketBuffer::from_bytes(bytes)
+            .and_then(|mut buffer| DnsPacket::from_buffer(&mut buffer))
+            .err();
+
+        HexDump {
+            bytes: bytes.to_vec(),
+            lines: walker.lines,
+            entries: walker.entries,
+            error,
+        }
+    }
+
+    /// Index of the line the parse error is reported after: the field
+    /// holding the offset of the error when it has one, or the heading of
+    /// the entry it happened in.
+    pub fn error_line(&self) -> Option<usize> {
+        let error = self.error.as_ref()?;
+        let (offset, location) = error_position(error);
+
+        if let Some(offset) = offset {
+            if offset >= self.bytes.len() {
+                return self.lines.len().checked_sub(1);
+            }
+            let containing = self
+                .lines
+                .iter()
+                .position(|line| (line.offset..line.offset + line.length).contains(&offset));
+            if containing.is_some() {
+                return containing;
+            }
+        }
+
+        let start = location.and_then(|location| {
+            self.entries
+                .iter()
+                .find(|(entry, _)| *entry == location)
+                .map(|(_, start)| *start)
+        });
+        let after = match start {
+            Some(start) => self
+                .lines
+                .iter()
+                .position(|line| line.offset == start && line.length == 0),
+            None => None,
+        };
+
+        after.or(self.lines.len().checked_sub(1))
+    }
+}
+
+impl fmt::Display for HexDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let error_line = self.error_line();
+
+        for (index, line) in self.lines.iter().enumerate() {
+            if line.length == 0 {
+                writeln!(f, ";; {}", line.text)?;
+            } else {
+                let bytes = &self.bytes[line.offset..line.offset + line.length];
+                for (chunk_index, chunk) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+                    let offset = line.offset + chunk_index * BYTES_PER_LINE;
+                    let hex: Vec<String> =
+                        chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+                    match chunk_index {
+                        0 => writeln!(
+                            f,
+                            "{:04x}  {:width$}  {}",
+                            offset,
+                            hex.join(" "),
+                            line.text,
+                            width = BYTES_PER_LINE * 3 - 1
+                        )?,
+                        _ => writeln!(f, "{:04x}  {}", offset, hex.join(" "))?,
+                    }
+                }
+            }
+
+            if error_line == Some(index) {
+                if let Some(error) = &self.error {
+                    match error_position(error).0 {
+                        Some(offset) => writeln!(f, "^^^^  error at {:04x}: {}", offset, error)?,
+                        None => writeln!(f, "^^^^  error: {}", error)?,
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The offset and the entry a parse error is about, when it tells.
+fn error_position(error: &DnsError) -> (Option<usize>, Option<Location>) {
+    match *error {
+        DnsError::TruncatedHeader { length } => (Some(length), None),
+        DnsError::BadSectionCount { offset, .. } => (Some(offset), None),
+        DnsError::Truncated { offset, location }
+        | DnsError::ForwardPointer {
+            offset, location, ..
+        }
+        | DnsError::OversizedName { offset, location }
+        | DnsError::RdataOverrun { offset, location }
+        | DnsError::RdataLengthMismatch {
+            offset, location, ..
+        } => (Some(offset), Some(location)),
+        _ => (None, None),
+    }
+}
+
+/// Walks the message field by field, stopping at the first one that does
+/// not fit.
+struct Walker<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    /// Entry being walked, for the errors of the names pointed to.
+    location: Location,
+    lines: Vec<DumpLine>,
+    entries: Vec<(Location, usize)>,
+}
+
+impl Walker<'_> {
+    fn message(&mut self) -> Option<()> {
+        self.heading(Location::default());
+        let counts = self.header()?;
+
+        for index in 0..counts[0] {
+            self.heading(Location::new(Section::Question, index));
+            self.name()?;
+            self.u16("TYPE", type_text)?;
+            self.u16("CLASS", class_text)?;
+        }
+
+        let sections = [Section::Answer, Section::Authority, Section::Additional];
+        for (section, count) in sections.into_iter().zip(&counts[1..]) {
+            for index in 0..*count {
+                self.record(Location::new(section, index))?;
+            }
+        }
+
+        if self.position < self.bytes.len() {
+            let length = self.bytes.len() - self.position;
+            self.line(length, format!("{} bytes after the last entry", length));
+        }
+
+        Some(())
+    }
+
+    /// The header fields, returning the four section counts.
+    fn header(&mut self) -> Option<[usize; 4]> {
+        self.u16("ID", |id| format!("{}", id))?;
+        self.u16("flags", flags_text)?;
+
+        let mut counts = [0; 4];
+        for (count, name) in counts
+            .iter_mut()
+            .zip(["QDCOUNT", "ANCOUNT", "NSCOUNT", "ARCOUNT"])
+        {
+            *count = self.u16(name, |count| format!("{}", count))? as usize;
+        }
+
+        Some(counts)
+    }
+
+    fn record(&mut self, location: Location) -> Option<()> {
+        let start = self.position;
+        self.heading(location);
+        self.name()?;
+
+        let rtype = self.u16("TYPE", type_text)?;
+        let opt = rtype == QueryType::OPT.to_number();
+        if opt {
+            self.u16("CLASS", |size| format!("UDP payload size {}", size))?;
+            self.u32("TTL", |ttl| {
+                format!(
+                    "extended RCODE {}, version {}, flags {:#06x}{}",
+                    ttl >> 24,
+                    (ttl >> 16) & 0xFF,
+                    ttl & 0xFFFF,
+                    if ttl & 0x8000 != 0 { " (DO)" } else { "" }
+                )
+            })?;
+        } else {
+            self.u16("CLASS", class_text)?;
+            self.u32("TTL", |ttl| format!("{}", ttl))?;
+        }
+        let length = self.u16("RDLENGTH", |length| format!("{}", length))? as usize;
+
+        if opt {
+            return self.options(length);
+        }
+
+        if length == 0 {
+            return Some(());
+        }
+        let text = match self.decode(start, location) {
+            Ok(record) => format!("RDATA {}", record.data_to_string()),
+            Err(error) => format!("RDATA, not decodable: {}", error),
+        };
+        self.field(length, "RDATA")?;
+        self.lines.last_mut()?.text = text;
+
+        Some(())
+    }
+
+    /// The EDNS options filling `length` bytes of OPT RDATA.
+    fn options(&mut self, length: usize) -> Option<()> {
+        let end = self.position + length;
+        if end > self.bytes.len() {
+            return self.field(length, "RDATA");
+        }
+
+        while self.position < end {
+            self.u16("option code", |code| format!("{}", code))?;
+            let data = self.u16("option length", |length| format!("{}", length))? as usize;
+            if self.position + data > end {
+                return self.field(end - self.position, "option data past RDATA");
+            }
+            if data > 0 {
+                self.field(data, "option data")?;
+            }
+        }
+
+        Some(())
+    }
+
+    /// Parses the record starting at `start` the way the parser does, for
+    /// its RDATA in presentation form.
+    fn decode(&self, start: usize, location: Location) -> crate::Result<DnsRecord> {
+        let mut buffer = PacketBuffer::from_bytes(self.bytes)?;
+        buffer.location = location;
+        buffer.seek(start)?;
+        DnsRecord::read(&mut buffer)
+    }
+
+    /// The labels of a name, following no pointers but naming their targets.
+    fn name(&mut self) -> Option<()> {
+        loop {
+            let length = *self.bytes.get(self.position).or_else(|| {
+                self.truncated(1, "label length");
+                None
+            })?;
+
+            match length & 0xC0 {
+                0xC0 => {
+                    let pointer = self.bytes.get(self.position..self.position + 2);
+                    let Some(pointer) = pointer else {
+                        return self.truncated(2, "compression pointer");
+                    };
+                    let target = (((pointer[0] & 0x3F) as usize) << 8) | pointer[1] as usize;
+                    // The parser stops at a bad pointer, so does the walk
+                    return match self.pointed_name() {
+                        Ok(name) => {
+                            let text = format!("pointer to {:#06x}: {}", target, fqdn(&name));
+                            self.field(2, &text)
+                        }
+                        Err(error) => {
+                            let text = format!("pointer to {:#06x}, invalid: {}", target, error);
+                            self.field(2, &text)?;
+                            None
+                        }
+                    };
+                }
+                0x00 if length == 0 => return self.field(1, "root label"),
+                0x00 => {
+                    let length = length as usize;
+                    let label = self
+                        .bytes
+                        .get(self.position + 1..self.position + 1 + length);
+                    let Some(label) = label else {
+                        return self.truncated(1 + length, "label");
+                    };
+                    let text = match Name::root().prepend_label(label) {
+                        Ok(label) => format!("label \"{}\"", label),
+                        Err(error) => format!("label, invalid: {}", error),
+                    };
+                    self.field(1 + length, &text)?;
+                }
+                reserved => {
+                    let text = format!("label type {:#04x} is reserved", reserved);
+                    self.field(1, &text)?;
+                    return None;
+                }
+            }
+        }
+    }
+
+    /// The name the compression pointer at the current position refers to,
+    /// read as the parser reads it.
+    fn pointed_name(&self) -> crate::Result<Name> {
+        let mut buffer = PacketBuffer::from_bytes(self.bytes)?;
+        buffer.location = self.location;
+        buffer.seek(self.position)?;
+        let mut name = Name::root();
+        buffer.read_qname(&mut name)?;
+
+        Ok(name)
+    }
+
+    fn u16<F: Fn(u16) -> String>(&mut self, field: &str, describe: F) -> Option<u16> {
+        let bytes = self.bytes.get(self.position..self.position + 2);
+        let Some(bytes) = bytes else {
+            self.truncated(2, field);
+            return None;
+        };
+        let value = u16::from_be_bytes([bytes[0], bytes[1]]);
+        self.line(2, format!("{} {}", field, describe(value)));
+
+        Some(value)
+    }
+
+    fn u32<F: Fn(u32) -> String>(&mut self, field: &str, describe: F) -> Option<u32> {
+        let bytes = self.bytes.get(self.position..self.position + 4);
+        let Some(bytes) = bytes else {
+            self.truncated(4, field);
+            return None;
+        };
+        let value = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        self.line(4, format!("{} {}", field, describe(value)));
+
+        Some(value)
+    }
+
+    /// A field of `length` bytes described by `text`.
+    fn field(&mut self, length: usize, text: &str) -> Option<()> {
+        if self.position + length > self.bytes.len() {
+            return self.truncated(length, text);
+        }
+        self.line(length, text.to_string());
+
+        Some(())
+    }
+
+    /// Shows what is left of a field that runs past the end of the message
+    /// and stops the walk.
+    fn truncated(&mut self, length: usize, field: &str) -> Option<()> {
+        let left = self.bytes.len() - self.position;
+        let text = format!("{}, truncated: {} of {} bytes", field, left, length);
+        self.lines.push(DumpLine {
+            offset: self.position,
+            length: left,
+            text,
+        });
+        self.position = self.bytes.len();
+
+        None
+    }
+
+    fn line(&mut self, length: usize, text: String) {
+        self.lines.push(DumpLine {
+            offset: self.position,
+            length,
+            text,
+        });
+        self.position += length;
+    }
+
+    fn heading(&mut self, location: Location) {
+        self.location = location;
+        self.entries.push((location, self.position));
+        self.lines.push(DumpLine {
+            offset: self.position,
+            length: 0,
+            text: location.to_string(),
+        });
+    }
+}
+
+fn flags_text(flags: u16) -> String {
+    let bit = |shift: u16| (flags >> shift) & 1;
+    let opcode = ((flags >> 11) & 0x0F) as u8;
+    let opcode = Opcode::from_number(opcode);
+
+    format!(
+        "QR={} OPCODE={} AA={} TC={} RD={} RA={} Z={} AD={} CD={} RCODE={}",
+        bit(15),
+        opcode,
+        bit(10),
+        bit(9),
+        bit(8),
+        bit(7),
+        bit(6),
+        bit(5),
+        bit(4),
+        ResultCode::from_number(flags & 0x0F)
+    )
+}
+
+fn type_text(qtype: u16) -> String {
+    format!("{} ({})", QueryType::from_number(qtype), qtype)
+}
+
+fn class_text(class: u16) -> String {
+    format!("{} ({})", DnsClass::from_number(class), class)
+}
+
+fn fqdn(name: &Name) -> String {
+    match name.is_root() {
+        true => ".".to_string(),
+        false => format!("{}.", name),
+    }
+}
@@ -0,0 +1,371 @@
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::buffer::{PacketBuffer, MAX_MESSAGE_LENGTH};
+use crate::name::Name;
+use crate::protocol::{DnsClass, DnsRecord};
+use crate::zone::{serial_greater, Diff, Zone};
+use crate::DnsError;
+
+const BASE_MAGIC: &[u8; 8] = b"TDNSBAS1";
+const LOG_MAGIC: &[u8; 8] = b"TDNSJNL1";
+
+/// Log size past which recording a change compacts the journal instead of
+/// appending to it.
+const MAX_LOG_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Largest payload of a log entry. Larger changes are recorded by
+/// compacting, so that a length past this bound is known to be damaged
+/// rather than the start of an entry torn by a crash.
+const MAX_ENTRY_SIZE: usize = 1024 * 1024;
+
+/// The on-disk history of a zone: a base copy of the whole zone, and an
+/// append-only log of the changes made since.
+///
+/// The base lives at the path given to `open`, the log next to it with a
+/// `.jnl` extension added. Each log entry carries its length and a checksum
+/// and is synced before `append` returns, so an entry torn by a crash is
+/// detected and dropped on `load`. Compaction writes the new base and the
+/// emptied log to temporary files renamed into place, leaving either the old
+/// or the new history behind after a crash, never a mix.
+#[derive(Debug)]
+pub struct Journal {
+    base: PathBuf,
+    log: PathBuf,
+    file: File,
+}
+
+impl Journal {
+    /// Opens the journal whose base copy is at `base`, creating an empty log
+    /// if there is none yet.
+    pub fn open(base: impl AsRef<Path>) -> crate::Result<Journal> {
+        let base = base.as_ref().to_path_buf();
+        let mut log = OsString::from(base.as_os_str());
+        log.push(".jnl");
+        let log = PathBuf::from(log);
+
+        if !log.exists() {
+            replace_file(&log, LOG_MAGIC)?;
+        }
+        let file = OpenOptions::new()
+            .append(true)
+            .open(&log)
+            .map_err(journal_error)?;
+
+        Ok(Journal { base, log, file })
+    }
+
+    /// Rebuilds the zone at `origin` by replaying the log over the base copy,
+    /// an empty zone when the journal holds no history yet.
+    ///
+    /// An incomplete entry at the end of the log, left by a crash while it
+    /// was appended, is dropped from the file. A bad entry followed by more
+    /// entries, or with a length larger than any entry written, was not torn
+    /// by a crash, and fails the load as corrupt.
+    pub fn load(&mut self, origin: Name, class: DnsClass) -> crate::Result<Zone> {
+        let mut zone = Zone::new(origin, class);
+        if let Some(records) = self.read_base()? {
+            for record in records {
+                zone.insert(record);
+            }
+        }
+
+        let log = fs::read(&self.log).map_err(journal_error)?;
+        if !log.starts_with(LOG_MAGIC) {
+            return Err(self.corrupt_log(0));
+        }
+
+        let mut offset = LOG_MAGIC.len();
+        while offset < log.len() {
+            let payload = match log_entry(&log[offset..]) {
+                LogEntry::Complete(payload) => payload,
+                LogEntry::Torn => break,
+                LogEntry::Corrupt => return Err(self.corrupt_log(offset)),
+            };
+            let diff = decode_diff(payload).map_err(|_| self.corrupt_log(offset))?;
+            let current = zone.serial().unwrap_or_default();
+
+            // Entries up to the base serial survive a crash during
+            // compaction and are already part of the base
+            if diff.old_serial() == current {
+                zone.apply_diff(&diff)?;
+            } else if serial_greater(diff.new_serial(), current) {
+                return Err(self.corrupt_log(offset));
+            }
+
+            offset += 8 + payload.len();
+        }
+
+        if offset < log.len() {
+            self.file
+                .set_len(offset as u64)
+                .and_then(|()| self.file.sync_data())
+                .map_err(journal_error)?;
+        }
+
+        Ok(zone)
+    }
+
+    /// Appends `diff` to the log, returning once it is on disk. Changes too
+    /// large for one entry are refused, they need a `compact`.
+    pub fn append(&mut self, diff: &Diff) -> crate::Result<()> {
+        let payload = encode_diff(diff)?;
+        if payload.len() > MAX_ENTRY_SIZE {
+            return Err(journal_error(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "change too large for a journal entry",
+            )));
+        }
+        self.append_payload(&payload)
+    }
+
+    fn append_payload(&mut self, payload: &[u8]) -> crate::Result<()> {
+        let mut entry = Vec::with_capacity(payload.len() + 8);
+        entry.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        entry.extend_from_slice(&crc32fast::hash(payload).to_be_bytes());
+        entry.extend_from_slice(payload);
+
+        self.file
+            .write_all(&entry)
+            .and_then(|()| self.file.sync_data())
+            .map_err(journal_error)
+    }
+
+    /// Replaces the history with `zone` as the new base and an empty log.
+    pub fn compact(&mut self, zone: &Zone) -> crate::Result<()> {
+        let mut base = BASE_MAGIC.to_vec();
+        let mut scratch = PacketBuffer::with_capacity(MAX_MESSAGE_LENGTH);
+        base.extend_from_slice(&(zone.len() as u32).to_be_bytes());
+        for record in zone.records() {
+            encode_record(record, &mut scratch, &mut base)?;
+        }
+        let checksum = crc32fast::hash(&base);
+        base.extend_from_slice(&checksum.to_be_bytes());
+
+        // A crash between the two renames leaves log entries that the new
+        // base already includes, which `load` skips
+        replace_file(&self.base, &base)?;
+        replace_file(&self.log, LOG_MAGIC)?;
+        self.file = OpenOptions::new()
+            .append(true)
+            .open(&self.log)
+            .map_err(journal_error)?;
+
+        Ok(())
+    }
+
+    /// Brings the journal up to date with `zone`, which was at
+    /// `previous_serial` when last recorded. The changes since are appended
+    /// when the zone journal has them, otherwise, once the log grows too
+    /// large or for a change too large for one entry, the journal is
+    /// compacted into a new base.
+    pub fn record(&mut self, previous_serial: Option<u32>, zone: &Zone) -> crate::Result<()> {
+        let diffs = previous_serial.and_then(|serial| zone.diffs_since(serial));
+        match diffs {
+            Some(diffs) if self.log_size()? < MAX_LOG_SIZE => {
+                for diff in diffs {
+                    let payload = encode_diff(diff)?;
+                    if payload.len() > MAX_ENTRY_SIZE {
+                        return self.compact(zone);
+                    }
+                    self.append_payload(&payload)?;
+                }
+                Ok(())
+            }
+            _ => self.compact(zone),
+        }
+    }
+
+    pub fn log_size(&self) -> crate::Result<u64> {
+        let metadata = self.file.metadata().map_err(journal_error)?;
+
+        Ok(metadata.len())
+    }
+
+    /// The records of the base copy, `None` when no base was written yet.
+    fn read_base(&self) -> crate::Result<Option<Vec<DnsRecord>>> {
+        let base = match fs::read(&self.base) {
+            Ok(base) => base,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(source) => return Err(DnsError::JournalIO { source }),
+        };
+
+        let corrupt = |offset: usize| DnsError::CorruptJournal {
+            path: self.base.clone(),
+            offset: offset as u64,
+        };
+        let header = BASE_MAGIC.len() + 4;
+        if base.len() < header + 4 || !base.starts_with(BASE_MAGIC) {
+            return Err(corrupt(0));
+        }
+        let (contents, checksum) = base.split_at(base.len() - 4);
+        if crc32fast::hash(contents).to_be_bytes() != checksum {
+            return Err(corrupt(contents.len()));
+        }
+
+        let count = u32::from_be_bytes(contents[BASE_MAGIC.len()..header].try_into().unwrap());
+        let mut position = header;
+        let mut records = Vec::new();
+        for _ in 0..count {
+            records.push(decode_record(contents, &mut position).map_err(|_| corrupt(position))?);
+        }
+        if position != contents.len() {
+            return Err(corrupt(position));
+        }
+
+        Ok(Some(records))
+    }
+
+    fn corrupt_log(&self, offset: usize) -> DnsError {
+        DnsError::CorruptJournal {
+            path: self.log.clone(),
+            offset: offset as u64,
+        }
+    }
+}
+
+fn journal_error(source: std::io::Error) -> DnsError {
+    DnsError::JournalIO { source }
+}
+
+/// What `log_entry` found at the start of the rest of a log.
+enum LogEntry<'a> {
+    /// A whole entry, its payload matching its checksum.
+    Complete(&'a [u8]),
+    /// An entry running to the end of the log that is cut short or fails its
+    /// checksum, as left by a crash while appending it.
+    Torn,
+    /// An entry failing its checksum with more of the log after it, or with
+    /// a length no entry is written with.
+    Corrupt,
+}
+
+/// Reads the entry at the start of `log`: a payload length, a CRC-32 of the
+/// payload, and the payload.
+fn log_entry(log: &[u8]) -> LogEntry<'_> {
+    let (Some(length), Some(checksum)) = (log.get(..4), log.get(4..8)) else {
+        return LogEntry::Torn;
+    };
+    let length = u32::from_be_bytes(length.try_into().unwrap()) as usize;
+    let checksum = u32::from_be_bytes(checksum.try_into().unwrap());
+    // A length past the bound was damaged, not cut short by a crash, and
+    // says nothing about where the entry ends
+    let end = match 8usize.checked_add(length) {
+        Some(end) if length <= MAX_ENTRY_SIZE => end,
+        _ => return LogEntry::Corrupt,
+    };
+    let Some(payload) = log.get(8..end) else {
+        return LogEntry::Torn;
+    };
+
+    match crc32fast::hash(payload) == checksum {
+        true => LogEntry::Complete(payload),
+        false if log.len() == end => LogEntry::Torn,
+        false => LogEntry::Corrupt,
+    }
+}
+
+/// Atomically replaces the file at `path` with `contents`, synced to disk
+/// along with its directory entry.
+fn replace_file(path: &Path, contents: &[u8]) -> crate::Result<()> {
+    let mut temporary = OsString::from(path.as_os_str());
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+
+    let mut file = File::create(&temporary).map_err(journal_error)?;
+    file.write_all(contents)
+        .and_then(|()| file.sync_all())
+        .and_then(|()| fs::rename(&temporary, path))
+        .map_err(journal_error)?;
+
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(directory)
+        .and_then(|directory| directory.sync_all())
+        .map_err(journal_error)
+}
+
+/// Lays out `diff` as the number of removed and added records, followed by
+/// the old SOA, the removed records, the new SOA and the added records.
+fn encode_diff(diff: &Diff) -> crate::Result<Vec<u8>> {
+    let mut payload = Vec::new();
+    payload.extend_from_slice(&(diff.removed.len() as u32).to_be_bytes());
+    payload.extend_from_slice(&(diff.added.len() as u32).to_be_bytes());
+
+    let mut scratch = PacketBuffer::with_capacity(MAX_MESSAGE_LENGTH);
+    encode_record(&diff.old_soa, &mut scratch, &mut payload)?;
+    for record in &diff.removed {
+        encode_record(record, &mut scratch, &mut payload)?;
+    }
+    encode_record(&diff.new_soa, &mut scratch, &mut payload)?;
+    for record in &diff.added {
+        encode_record(record, &mut scratch, &mut payload)?;
+    }
+
+    Ok(payload)
+}
+
+fn decode_diff(payload: &[u8]) -> crate::Result<Diff> {
+    let count = |range: std::ops::Range<usize>| -> crate::Result<usize> {
+        let bytes = payload.get(range).ok_or(DnsError::BufferEnd)?;
+        Ok(u32::from_be_bytes(bytes.try_into().unwrap()) as usize)
+    };
+    let removed_count = count(0..4)?;
+    let added_count = count(4..8)?;
+
+    let mut position = 8;
+    let mut records = |count: usize| -> crate::Result<Vec<DnsRecord>> {
+        (0..count)
+            .map(|_| decode_record(payload, &mut position))
+            .collect()
+    };
+    let old_soa = records(1)?.remove(0);
+    let removed = records(removed_count)?;
+    let new_soa = records(1)?.remove(0);
+    let added = records(added_count)?;
+
+    if position != payload.len() {
+        return Err(DnsError::BufferEnd);
+    }
+
+    Ok(Diff {
+        old_soa,
+        removed,
+        new_soa,
+        added,
+    })
+}
+
+/// Appends `record` to `out` in uncompressed wire format behind its two byte
+/// length, using `scratch` to lay it out.
+fn encode_record(
+    record: &DnsRecord,
+    scratch: &mut PacketBuffer,
+    out: &mut Vec<u8>,
+) -> crate::Result<()> {
+    scratch.position = 0;
+    let length = record.write(scratch)?;
+
+    out.extend_from_slice(&(length as u16).to_be_bytes());
+    out.extend_from_slice(&scratch.buffer[..length]);
+
+    Ok(())
+}
+
+fn decode_record(data: &[u8], position: &mut usize) -> crate::Result<DnsRecord> {
+    let prefix = data
+        .get(*position..*position + 2)
+        .ok_or(DnsError::BufferEnd)?;
+    let length = u16::from_be_bytes([prefix[0], prefix[1]]) as usize;
+    let start = *position + 2;
+    let bytes = data.get(start..start + length).ok_or(DnsError::BufferEnd)?;
+
+    let record = DnsRecord::read(&mut PacketBuffer::from_bytes(bytes)?)?;
+    *position = start + length;
+
+    Ok(record)
+}
@@ -0,0 +1,508 @@
+//! JSON representation of DNS messages (RFC 8427).
+//!
+//! `DnsPacket` serializes to and from this format through `JsonMessage`,
+//! which can also carry the raw message as `messageOctetsHEX`. Every record
+//! is written with both its RDATA in hex and, for the types that have one,
+//! its presentation form in the `rdata*` member named after the type.
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::buffer::{PacketBuffer, MAX_MESSAGE_LENGTH};
+use crate::name::Name;
+use crate::protocol::{
+    DnsClass, DnsHeader, DnsPacket, DnsQuestion, DnsRecord, Opcode, QueryType, ResultCode,
+};
+use crate::DnsError;
+
+/// A DNS message with the members defined by RFC 8427. All members are
+/// optional when reading, a message given as `messageOctetsHEX` needs no
+/// others.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JsonMessage {
+    #[serde(rename = "ID")]
+    pub id: u16,
+    #[serde(rename = "QR", deserialize_with = "flag")]
+    pub qr: bool,
+    #[serde(rename = "Opcode")]
+    pub opcode: u8,
+    #[serde(rename = "AA", deserialize_with = "flag")]
+    pub aa: bool,
+    #[serde(rename = "TC", deserialize_with = "flag")]
+    pub tc: bool,
+    #[serde(rename = "RD", deserialize_with = "flag")]
+    pub rd: bool,
+    #[serde(rename = "RA", deserialize_with = "flag")]
+    pub ra: bool,
+    #[serde(rename = "AD", deserialize_with = "flag")]
+    pub ad: bool,
+    #[serde(rename = "CD", deserialize_with = "flag")]
+    pub cd: bool,
+    /// The 4 bits of the header, extended codes are in the OPT record.
+    #[serde(rename = "RCODE")]
+    pub rcode: u8,
+    #[serde(rename = "QDCOUNT")]
+    pub qdcount: u16,
+    #[serde(rename = "ANCOUNT")]
+    pub ancount: u16,
+    #[serde(rename = "NSCOUNT")]
+    pub nscount: u16,
+    #[serde(rename = "ARCOUNT")]
+    pub arcount: u16,
+    #[serde(rename = "questionRRs")]
+    pub questions: Vec<JsonQuestion>,
+    #[serde(rename = "answerRRs")]
+    pub answers: Vec<JsonRecord>,
+    #[serde(rename = "authorityRRs")]
+    pub authorities: Vec<JsonRecord>,
+    #[serde(rename = "additionalRRs")]
+    pub additionals: Vec<JsonRecord>,
+    #[serde(rename = "messageOctetsHEX", skip_serializing_if = "Option::is_none")]
+    pub message_octets: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JsonQuestion {
+    #[serde(rename = "NAME")]
+    pub name: String,
+    #[serde(rename = "TYPE")]
+    pub qtype: u16,
+    #[serde(rename = "TYPEname", skip_serializing_if = "Option::is_none")]
+    pub type_name: Option<String>,
+    #[serde(rename = "CLASS")]
+    pub class: u16,
+    #[serde(rename = "CLASSname", skip_serializing_if = "Option::is_none")]
+    pub class_name: Option<String>,
+}
+
+/// A resource record. Reading one takes the RDATA from `RDATAHEX` when
+/// present, and from the `rdata*` member of its type otherwise.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JsonRecord {
+    #[serde(rename = "NAME")]
+    pub name: String,
+    #[serde(rename = "TYPE")]
+    pub rtype: u16,
+    #[serde(rename = "TYPEname", skip_serializing_if = "Option::is_none")]
+    pub type_name: Option<String>,
+    #[serde(rename = "CLASS")]
+    pub class: u16,
+    #[serde(rename = "CLASSname", skip_serializing_if = "Option::is_none")]
+    pub class_name: Option<String>,
+    #[serde(rename = "TTL")]
+    pub ttl: u32,
+    #[serde(rename = "RDLENGTH", skip_serializing_if = "Option::is_none")]
+    pub rdlength: Option<u16>,
+    #[serde(rename = "RDATAHEX", skip_serializing_if = "Option::is_none")]
+    pub rdata_hex: Option<String>,
+    #[serde(rename = "rdataA", skip_serializing_if = "Option::is_none")]
+    pub rdata_a: Option<String>,
+    #[serde(rename = "rdataAAAA", skip_serializing_if = "Option::is_none")]
+    pub rdata_aaaa: Option<String>,
+    #[serde(rename = "rdataNS", skip_serializing_if = "Option::is_none")]
+    pub rdata_ns: Option<String>,
+    #[serde(rename = "rdataCNAME", skip_serializing_if = "Option::is_none")]
+    pub rdata_cname: Option<String>,
+    #[serde(rename = "rdataSOA", skip_serializing_if = "Option::is_none")]
+    pub rdata_soa: Option<String>,
+    #[serde(rename = "rdataMX", skip_serializing_if = "Option::is_none")]
+    pub rdata_mx: Option<String>,
+    #[serde(rename = "rdataTXT", skip_serializing_if = "Option::is_none")]
+    pub rdata_txt: Option<String>,
+}
+
+/// Reads a Boolean member, given either as `true` and `false` or as `1` and
+/// `0` as in the examples of RFC 8427.
+fn flag<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Flag {
+        Bool(bool),
+        Number(u8),
+    }
+
+    match Flag::deserialize(deserializer)? {
+        Flag::Bool(value) => Ok(value),
+        Flag::Number(value) => Ok(value != 0),
+    }
+}
+
+impl JsonMessage {
+    /// Describes `packet`, along with its wire form as written by this
+    /// crate when `include_octets` is set.
+    pub fn from_packet(packet: &DnsPacket, include_octets: bool) -> crate::Result<JsonMessage> {
+        let header = &packet.header;
+        let mut scratch = PacketBuffer::with_capacity(MAX_MESSAGE_LENGTH);
+        let records = |records: &[DnsRecord], scratch: &mut PacketBuffer| {
+            records
+                .iter()
+                .map(|record| JsonRecord::from_record(record, scratch))
+                .collect::<crate::Result<Vec<JsonRecord>>>()
+        };
+
+        let message_octets = match include_octets {
+            true => {
+                let mut buffer = PacketBuffer::with_capacity(MAX_MESSAGE_LENGTH);
+                packet.clone().write(&mut buffer)?;
+                Some(hex(&buffer.buffer[..buffer.position]))
+            }
+            false => None,
+        };
+
+        Ok(JsonMessage {
+            id: header.id,
+            qr: header.response,
+            opcode: header.opcode.to_number(),
+            aa: header.authoritative_answer,
+            tc: header.truncated_message,
+            rd: header.recursion_desired,
+            ra: header.recursion_available,
+            ad: header.authed_data,
+            cd: header.checking_disabled,
+            rcode: (header.rescode.to_number() & 0x0F) as u8,
+            qdcount: packet.questions.len() as u16,
+            ancount: packet.answers.len() as u16,
+            nscount: packet.authorities.len() as u16,
+            arcount: packet.resources.len() as u16,
+            questions: packet.questions.iter().map(JsonQuestion::from).collect(),
+            answers: records(&packet.answers, &mut scratch)?,
+            authorities: records(&packet.authorities, &mut scratch)?,
+            additionals: records(&packet.resources, &mut scratch)?,
+            message_octets,
+        })
+    }
+
+    /// Describes the received message `bytes`, keeping them as
+    /// `messageOctetsHEX`.
+    pub fn from_octets(bytes: &[u8]) -> crate::Result<JsonMessage> {
+        let packet = DnsPacket::from_buffer(&mut PacketBuffer::from_bytes(bytes)?)?;
+        let mut message = JsonMessage::from_packet(&packet, false)?;
+        message.message_octets = Some(hex(bytes));
+
+        Ok(message)
+    }
+
+    /// The message described, parsed from `messageOctetsHEX` when present.
+    pub fn to_packet(&self) -> crate::Result<DnsPacket> {
+        if let Some(octets) = &self.message_octets {
+            let bytes = unhex(octets)?;
+            return DnsPacket::from_buffer(&mut PacketBuffer::from_bytes(&bytes)?);
+        }
+
+        let mut header = DnsHeader::new();
+        header.id = self.id;
+        header.response = self.qr;
+        if self.opcode > 0x0F {
+            return Err(invalid(format!("opcode {} is out of range", self.opcode)));
+        }
+        header.opcode = Opcode::from_number(self.opcode);
+        header.authoritative_answer = self.aa;
+        header.truncated_message = self.tc;
+        header.recursion_desired = self.rd;
+        header.recursion_available = self.ra;
+        header.authed_data = self.ad;
+        header.checking_disabled = self.cd;
+        header.rescode = ResultCode::from_number((self.rcode & 0x0F) as u16);
+
+        let records = |records: &[JsonRecord]| {
+            records
+                .iter()
+                .map(JsonRecord::to_record)
+                .collect::<crate::Result<Vec<DnsRecord>>>()
+        };
+
+        let mut packet = DnsPacket::new();
+        packet.header = header;
+        packet.questions = self
+            .questions
+            .iter()
+            .map(JsonQuestion::to_question)
+            .collect::<crate::Result<_>>()?;
+        packet.answers = records(&self.answers)?;
+        packet.authorities = records(&self.authorities)?;
+        packet.resources = records(&self.additionals)?;
+
+        let header = &mut packet.header;
+        header.questions = packet.questions.len() as u16;
+        header.answers = packet.answers.len() as u16;
+        header.authoritative_entries = packet.authorities.len() as u16;
+        header.resource_entries = packet.resources.len() as u16;
+
+        Ok(packet)
+    }
+}
+
+impl From<&DnsQuestion> for JsonQuestion {
+    fn from(question: &DnsQuestion) -> JsonQuestion {
+        JsonQuestion {
+            name: fqdn(&question.name),
+            qtype: question.qtype.to_number(),
+            type_name: Some(question.qtype.to_string()),
+            class: question.qclass.to_number(),
+            class_name: Some(question.qclass.to_string()),
+        }
+    }
+}
+
+impl JsonQuestion {
+    pub fn to_question(&self) -> crate::Result<DnsQuestion> {
+        let mut question = DnsQuestion::new(self.name.parse()?, QueryType::from_number(self.qtype));
+        question.qclass = DnsClass::from_number(self.class);
+
+        Ok(question)
+    }
+}
+
+impl JsonRecord {
+    /// Describes `record`, laid out in `scratch` to take the class, TTL and
+    /// RDATA exactly as they go on the wire.
+    fn from_record(record: &DnsRecord, scratch: &mut PacketBuffer) -> crate::Result<JsonRecord> {
+        scratch.position = 0;
+        let length = record.write(scratch)?;
+
+        // The fixed fields follow the uncompressed owner name
+        let fixed = record.domain().wire_length();
+        let field = |offset: usize, size: usize| {
+            scratch.buffer[fixed + offset..fixed + offset + size]
+                .iter()
+                .fold(0u32, |value, byte| (value << 8) | *byte as u32)
+        };
+        let rtype = field(0, 2) as u16;
+        let class = field(2, 2) as u16;
+        let ttl = field(4, 4);
+        let rdata = &scratch.buffer[fixed + 10..length];
+
+        let mut json = JsonRecord {
+            name: fqdn(record.domain()),
+            rtype,
+            type_name: Some(record.qtype().to_string()),
+            class,
+            class_name: match record {
+                DnsRecord::OPT { .. } => None,
+                _ => Some(record.class().to_string()),
+            },
+            ttl,
+            rdlength: Some(rdata.len() as u16),
+            rdata_hex: Some(hex(rdata)),
+            ..JsonRecord::default()
+        };
+
+        let data = Some(record.data_to_string());
+        match record {
+            DnsRecord::A { .. } => json.rdata_a = data,
+            DnsRecord::AAAA { .. } => json.rdata_aaaa = data,
+            DnsRecord::NS { .. } => json.rdata_ns = data,
+            DnsRecord::CNAME { .. } => json.rdata_cname = data,
+            DnsRecord::SOA { .. } => json.rdata_soa = data,
+            DnsRecord::MX { .. } => json.rdata_mx = data,
+            DnsRecord::TXT { .. } => json.rdata_txt = data,
+            DnsRecord::UNKNOWN { .. } | DnsRecord::OPT { .. } | DnsRecord::TSIG { .. } => {}
+        }
+
+        Ok(json)
+    }
+
+    pub fn to_record(&self) -> crate::Result<DnsRecord> {
+        let domain: Name = self.name.parse()?;
+        let class = DnsClass::from_number(self.class);
+        let ttl = self.ttl;
+
+        if let Some(rdata) = &self.rdata_hex {
+            return self.read_wire(&domain, &unhex(rdata)?);
+        }
+
+        let host = |text: &str| text.parse::<Name>();
+        let record = match QueryType::from_number(self.rtype) {
+            QueryType::A => DnsRecord::A {
+                domain,
+                class,
+                address: parse_address::<Ipv4Addr>(self.rdata_a.as_deref())?,
+                ttl,
+            },
+            QueryType::AAAA => DnsRecord::AAAA {
+                domain,
+                class,
+                address: parse_address::<Ipv6Addr>(self.rdata_aaaa.as_deref())?,
+                ttl,
+            },
+            QueryType::NS => DnsRecord::NS {
+                domain,
+                class,
+                host: host(required(self.rdata_ns.as_deref())?)?,
+                ttl,
+            },
+            QueryType::CNAME => DnsRecord::CNAME {
+                domain,
+                class,
+                host: host(required(self.rdata_cname.as_deref())?)?,
+                ttl,
+            },
+            QueryType::MX => {
+                let fields = fields::<2>(self.rdata_mx.as_deref())?;
+                DnsRecord::MX {
+                    domain,
+                    class,
+                    priority: number(fields[0])?,
+                    host: host(fields[1])?,
+                    ttl,
+                }
+            }
+            QueryType::SOA => {
+                let fields = fields::<7>(self.rdata_soa.as_deref())?;
+                DnsRecord::SOA {
+                    domain,
+                    class,
+                    m_name: host(fields[0])?,
+                    r_name: host(fields[1])?,
+                    serial: number(fields[2])?,
+                    refresh: number(fields[3])?,
+                    retry: number(fields[4])?,
+                    expire: number(fields[5])?,
+                    minimum: number(fields[6])?,
+                    ttl,
+                }
+            }
+            QueryType::TXT => DnsRecord::TXT {
+                domain,
+                class,
+                data: character_strings(required(self.rdata_txt.as_deref())?)?,
+                ttl,
+            },
+            qtype => {
+                return Err(invalid(format!("{} record without RDATAHEX", qtype)));
+            }
+        };
+
+        Ok(record)
+    }
+
+    /// Reads the record from its fields in wire format, so that every type
+    /// the parser knows comes out typed.
+    fn read_wire(&self, domain: &Name, rdata: &[u8]) -> crate::Result<DnsRecord> {
+        let mut buffer = PacketBuffer::with_capacity(MAX_MESSAGE_LENGTH);
+        buffer.write_qname(domain)?;
+        buffer.write_u16(self.rtype)?;
+        buffer.write_u16(self.class)?;
+        buffer.write_u32(self.ttl)?;
+        buffer.write_u16(rdata.len() as u16)?;
+        for byte in rdata {
+            buffer.write_u8(*byte)?;
+        }
+
+        buffer.length = buffer.position;
+        buffer.position = 0;
+        DnsRecord::read(&mut buffer)
+    }
+}
+
+impl Serialize for DnsPacket {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        JsonMessage::from_packet(self, false)
+            .map_err(serde::ser::Error::custom)?
+            .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for DnsPacket {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        JsonMessage::deserialize(deserializer)?
+            .to_packet()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+fn invalid(message: String) -> DnsError {
+    DnsError::InvalidJson(message)
+}
+
+/// The name with its trailing dot, as RFC 8427 shows it.
+fn fqdn(name: &Name) -> String {
+    match name.is_root() {
+        true => ".".to_string(),
+        false => format!("{}.", name),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+fn unhex(text: &str) -> crate::Result<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return Err(invalid(format!("`{}` is not hex", text)));
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|index| {
+            u8::from_str_radix(&text[index..index + 2], 16)
+                .map_err(|_| invalid(format!("`{}` is not hex", text)))
+        })
+        .collect()
+}
+
+fn required(data: Option<&str>) -> crate::Result<&str> {
+    data.ok_or_else(|| invalid("record without RDATA".to_string()))
+}
+
+fn parse_address<T: std::str::FromStr>(data: Option<&str>) -> crate::Result<T> {
+    let data = required(data)?;
+    data.parse()
+        .map_err(|_| invalid(format!("`{}` is not an address", data)))
+}
+
+fn number<T: std::str::FromStr>(text: &str) -> crate::Result<T> {
+    text.parse()
+        .map_err(|_| invalid(format!("`{}` is not a number", text)))
+}
+
+fn fields<const N: usize>(data: Option<&str>) -> crate::Result<[&str; N]> {
+    let data = required(data)?;
+    let fields: Vec<&str> = data.split_whitespace().collect();
+    fields
+        .try_into()
+        .map_err(|_| invalid(format!("`{}` needs {} fields", data, N)))
+}
+
+/// Reads the quoted character strings of TXT presentation data, with `\X`
+/// and `\DDD` escapes.
+fn character_strings(data: &str) -> crate::Result<Vec<Vec<u8>>> {
+    let malformed = || invalid(format!("`{}` is not a list of quoted strings", data));
+
+    let mut strings = Vec::new();
+    let mut bytes = data.bytes().peekable();
+    loop {
+        while bytes.next_if(u8::is_ascii_whitespace).is_some() {}
+        match bytes.next() {
+            None => return Ok(strings),
+            Some(b'"') => {}
+            Some(_) => return Err(malformed()),
+        }
+
+        let mut string = Vec::new();
+        loop {
+            match bytes.next().ok_or_else(malformed)? {
+                b'"' => break,
+                b'\\' => {
+                    let escaped = bytes.next().ok_or_else(malformed)?;
+                    if !escaped.is_ascii_digit() {
+                        string.push(escaped);
+                        continue;
+                    }
+                    let mut value = (escaped - b'0') as u32;
+                    for _ in 0..2 {
+                        let digit = bytes.next().filter(u8::is_ascii_digit);
+                        value = value * 10 + (digit.ok_or_else(malformed)? - b'0') as u32;
+                    }
+                    string.push(u8::try_from(value).map_err(|_| malformed())?);
+                }
+                byte => string.push(byte),
+            }
+        }
+        strings.push(string);
+    }
+}
@@ -1,8 +1,37 @@
+pub mod acl;
+pub mod batch;
 pub mod buffer;
+pub mod client;
+#[cfg(feature = "config")]
+pub mod config;
+pub mod dnstap;
+pub mod dump;
+pub mod journal;
+#[cfg(feature = "serde")]
+pub mod json;
+pub mod metrics;
+pub mod name;
+pub mod notify;
+pub mod pcap;
+pub mod presentation;
 pub mod protocol;
+pub mod querylog;
+pub mod secondary;
+pub mod server;
+pub mod trace;
+pub mod transfer;
+pub mod tsig;
+pub mod update;
+pub mod zone;
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
 
 use thiserror::Error;
 
+use crate::name::Name;
+use crate::protocol::{Location, ResultCode, Section};
+
 pub type Result<T> = std::result::Result<T, DnsError>;
 
 #[derive(Error, Debug)]
@@ -13,8 +42,114 @@ pub enum DnsError {
     MaxJumps(u32),
     #[error("Single label exceeds the max characters of length (63)")]
     LabelExceedsMaxLengthSize,
+    #[error("Name exceeds the max length (255)")]
+    NameTooLong,
+    #[error("Character string exceeds the max length (255)")]
+    StringTooLong,
+    #[error("Name contains an empty label")]
+    EmptyLabel,
+    #[error("Invalid escape sequence at character `{0}`")]
+    InvalidEscape(usize),
+    #[error("Invalid internationalized name: `{0}`")]
+    InvalidIdn(String),
+    #[error("Truncated header: message is `{length}` bytes, a header needs 12")]
+    TruncatedHeader { length: usize },
+    #[error("Unexpected end of message at offset `{offset}` in {location}")]
+    Truncated { offset: usize, location: Location },
+    #[error("Header claims `{count}` {section} entries, more than fit after offset `{offset}`")]
+    BadSectionCount {
+        section: Section,
+        count: u16,
+        offset: usize,
+    },
+    #[error("Compression pointer at offset `{offset}` in {location} points forward to `{target}`")]
+    ForwardPointer {
+        offset: usize,
+        target: usize,
+        location: Location,
+    },
+    #[error("Name exceeds the max length (255) at label offset `{offset}` in {location}")]
+    OversizedName { offset: usize, location: Location },
+    #[error("RDATA in {location} ends at offset `{offset}` before its fields do")]
+    RdataOverrun { offset: usize, location: Location },
+    #[error(
+        "RDATA at offset `{offset}` in {location} is `{expected}` bytes but `{actual}` were parsed"
+    )]
+    RdataLengthMismatch {
+        offset: usize,
+        location: Location,
+        expected: u16,
+        actual: usize,
+    },
     #[error("Error Reading Socket: `{source}`")]
     SocketIO { source: std::io::Error },
     #[error("Error Binding Socket: `{source}`")]
     SocketBind { source: std::io::Error },
+    #[error("Timed out waiting for a response")]
+    Timeout,
+    #[error("Response came from `{received}` instead of `{expected}`")]
+    UnexpectedSource {
+        expected: SocketAddr,
+        received: SocketAddr,
+    },
+    #[error("Response ID `{received}` does not match query ID `{expected}`")]
+    IdMismatch { expected: u16, received: u16 },
+    #[error("Response question does not match the query")]
+    QuestionMismatch,
+    #[error("Response question only differs from the 0x20 encoded query in case")]
+    CaseMismatch,
+    #[error("Error Generating Random Number: `{source}`")]
+    Random { source: getrandom::Error },
+    #[error("Message is not signed with TSIG")]
+    TsigMissing,
+    #[error("TSIG record is not the last record of the message")]
+    TsigMisplaced,
+    #[error("Unknown TSIG key `{0}`")]
+    TsigUnknownKey(Name),
+    #[error("TSIG signature does not match")]
+    TsigBadSignature,
+    #[error("TSIG time signed `{time_signed}` is outside the fudge window around `{now}`")]
+    TsigBadTime { time_signed: u64, now: u64 },
+    #[error("Peer rejected the TSIG signature with error `{}`", .0.to_number())]
+    TsigRejected(ResultCode),
+    #[error("Too many unsigned messages in a TSIG signed stream")]
+    TsigUnsignedRun,
+    #[error("Changes start at serial `{received}` but the zone is at `{expected}`")]
+    SerialMismatch { expected: u32, received: u32 },
+    #[error("Zone transfer refused with `{0:?}`")]
+    TransferRefused(ResultCode),
+    #[error("Malformed zone transfer response")]
+    MalformedTransfer,
+    #[error("NOTIFY refused with `{0:?}`")]
+    NotifyRefused(ResultCode),
+    #[error("Referral to `{0}` does not lead closer to the answer")]
+    BadReferral(Name),
+    #[error("No address found for any name server of `{0}`")]
+    NoNameServerAddress(Name),
+    #[error("Gave up after `{0}` referrals")]
+    TooManyReferrals(usize),
+    #[error("Unknown type or class `{0}`")]
+    UnknownMnemonic(String),
+    #[error("Error Accessing Journal: `{source}`")]
+    JournalIO { source: std::io::Error },
+    #[error("Corrupt journal file `{}` at offset `{offset}`", path.display())]
+    CorruptJournal { path: PathBuf, offset: u64 },
+    #[error("Invalid JSON message: {0}")]
+    InvalidJson(String),
+    #[error("Error Accessing Capture: `{source}`")]
+    CaptureIO { source: std::io::Error },
+    #[error("Invalid capture file: {0}")]
+    InvalidCapture(String),
+    #[error("Error Writing dnstap: `{source}`")]
+    DnstapIO { source: std::io::Error },
+    #[error("Invalid dnstap data: {0}")]
+    InvalidDnstap(String),
+    #[error("Error Writing Query Log: `{source}`")]
+    QueryLogIO { source: std::io::Error },
+    #[error("Invalid network `{0}`")]
+    InvalidNetwork(String),
+    #[error("Error Reading Configuration: `{source}`")]
+    ConfigIO { source: std::io::Error },
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
 }
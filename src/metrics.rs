@@ -0,0 +1,343 @@
+//! Counters and histograms in the Prometheus text exposition format,
+//! served over HTTP on `/metrics`.
+//!
+//! A `Metrics` handle is cheap to clone, clones share the same values. The
+//! server and clients only record into it when one is set on them.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::pcap::Transport;
+use crate::protocol::DnsPacket;
+use crate::DnsError;
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+const BUCKETS: [f64; 12] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+
+/// Time an HTTP client has to send its request.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest request header read from an HTTP client.
+const MAX_REQUEST_LENGTH: usize = 8192;
+
+/// Content type of the text exposition format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+#[derive(Clone, Debug, Default)]
+struct Histogram {
+    /// Observations per bucket, not cumulative, the last one past every
+    /// bound.
+    buckets: [u64; BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let index = BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(BUCKETS.len());
+        self.buckets[index] += 1;
+        self.sum += seconds;
+        self.count += 1;
+    }
+
+    /// Writes the `_bucket`, `_sum` and `_count` series, with `labels` a
+    /// list of `name="value"` pairs.
+    fn render(&self, output: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(self.buckets) {
+            cumulative += count;
+            let _ = writeln!(
+                output,
+                "{}_bucket{{{},le=\"{}\"}} {}",
+                name, labels, bound, cumulative
+            );
+        }
+        let _ = writeln!(
+            output,
+            "{}_bucket{{{},le=\"+Inf\"}} {}",
+            name, labels, self.count
+        );
+        let _ = writeln!(output, "{}_sum{{{}}} {}", name, labels, self.sum);
+        let _ = writeln!(output, "{}_count{{{}}} {}", name, labels, self.count);
+    }
+}
+
+#[derive(Debug, Default)]
+struct Values {
+    /// Answered queries by type, rcode and transport.
+    queries: BTreeMap<(String, String, &'static str), u64>,
+    query_duration: BTreeMap<&'static str, Histogram>,
+    in_flight: u64,
+    malformed: u64,
+    dropped: u64,
+    upstream_duration: BTreeMap<SocketAddr, Histogram>,
+    upstream_errors: BTreeMap<SocketAddr, u64>,
+}
+
+/// Shared counters and histograms of a server and its clients.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    values: Arc<Mutex<Values>>,
+}
+
+/// Counts a query as in flight until dropped.
+pub struct InFlight {
+    metrics: Metrics,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.metrics.update(|values| values.in_flight -= 1);
+    }
+}
+
+fn transport_label(transport: Transport) -> &'static str {
+    match transport {
+        Transport::Udp => "udp",
+        Transport::Tcp => "tcp",
+    }
+}
+
+/// Writes the HELP and TYPE lines introducing a metric.
+fn describe(output: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(output, "# HELP {} {}", name, help);
+    let _ = writeln!(output, "# TYPE {} {}", name, kind);
+}
+
+/// Escapes a label value, as the text format wants backslashes, quotes and
+/// newlines.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    fn update(&self, change: impl FnOnce(&mut Values)) {
+        let mut values = self
+            .values
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        change(&mut values);
+    }
+
+    /// Marks a query as in flight, until the returned guard is dropped.
+    pub fn start_query(&self) -> InFlight {
+        self.update(|values| values.in_flight += 1);
+        InFlight {
+            metrics: self.clone(),
+        }
+    }
+
+    /// Counts `response`, sent over `transport` after `duration` spent
+    /// answering, under the type of its question and its rcode.
+    pub fn record_query(&self, transport: Transport, response: &DnsPacket, duration: Duration) {
+        let qtype = match response.questions.first() {
+            Some(question) => question.qtype.to_string(),
+            None => "NONE".to_string(),
+        };
+        let rcode = response.rcode().to_string();
+        let transport = transport_label(transport);
+
+        self.update(|values| {
+            *values.queries.entry((qtype, rcode, transport)).or_default() += 1;
+            values
+                .query_duration
+                .entry(transport)
+                .or_default()
+                .observe(duration);
+        });
+    }
+
+    /// Counts a message that could not be parsed.
+    pub fn record_malformed(&self) {
+        self.update(|values| values.malformed += 1);
+    }
+
+    /// Counts a message that was received but never answered.
+    pub fn record_dropped(&self) {
+        self.update(|values| values.dropped += 1);
+    }
+
+    /// Records the time `server` took to answer a query.
+    pub fn record_upstream(&self, server: SocketAddr, duration: Duration) {
+        self.update(|values| {
+            values
+                .upstream_duration
+                .entry(server)
+                .or_default()
+                .observe(duration)
+        });
+    }
+
+    /// Counts a query to `server` that failed or timed out.
+    pub fn record_upstream_error(&self, server: SocketAddr) {
+        self.update(|values| *values.upstream_errors.entry(server).or_default() += 1);
+    }
+
+    /// Renders every metric in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let values = self
+            .values
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        let mut output = String::new();
+
+        describe(
+            &mut output,
+            "tarnish_dns_queries_total",
+            "counter",
+            "Queries answered.",
+        );
+        for ((qtype, rcode, transport), count) in &values.queries {
+            let _ = writeln!(
+                output,
+                "tarnish_dns_queries_total{{qtype=\"{}\",rcode=\"{}\",transport=\"{}\"}} {}",
+                escape(qtype),
+                escape(rcode),
+                transport,
+                count
+            );
+        }
+
+        describe(
+            &mut output,
+            "tarnish_dns_query_duration_seconds",
+            "histogram",
+            "Time from receiving a query to answering it.",
+        );
+        for (transport, histogram) in &values.query_duration {
+            let labels = format!("transport=\"{}\"", transport);
+            histogram.render(&mut output, "tarnish_dns_query_duration_seconds", &labels);
+        }
+
+        describe(
+            &mut output,
+            "tarnish_dns_queries_in_flight",
+            "gauge",
+            "Queries being answered.",
+        );
+        let _ = writeln!(output, "tarnish_dns_queries_in_flight {}", values.in_flight);
+
+        describe(
+            &mut output,
+            "tarnish_dns_malformed_packets_total",
+            "counter",
+            "Messages that could not be parsed.",
+        );
+        let _ = writeln!(
+            output,
+            "tarnish_dns_malformed_packets_total {}",
+            values.malformed
+        );
+
+        describe(
+            &mut output,
+            "tarnish_dns_dropped_packets_total",
+            "counter",
+            "Messages received and never answered.",
+        );
+        let _ = writeln!(
+            output,
+            "tarnish_dns_dropped_packets_total {}",
+            values.dropped
+        );
+
+        describe(
+            &mut output,
+            "tarnish_dns_upstream_duration_seconds",
+            "histogram",
+            "Time upstream servers took to answer.",
+        );
+        for (server, histogram) in &values.upstream_duration {
+            let labels = format!("server=\"{}\"", server);
+            histogram.render(
+                &mut output,
+                "tarnish_dns_upstream_duration_seconds",
+                &labels,
+            );
+        }
+
+        describe(
+            &mut output,
+            "tarnish_dns_upstream_errors_total",
+            "counter",
+            "Queries to upstream servers that failed.",
+        );
+        for (server, count) in &values.upstream_errors {
+            let _ = writeln!(
+                output,
+                "tarnish_dns_upstream_errors_total{{server=\"{}\"}} {}",
+                server, count
+            );
+        }
+
+        output
+    }
+
+    /// Serves `/metrics` to every connection accepted on `listener`, each on
+    /// its own thread, until accepting fails.
+    pub fn serve_http(&self, listener: &TcpListener) -> crate::Result<()> {
+        loop {
+            let (stream, _) = listener
+                .accept()
+                .map_err(|source| DnsError::SocketIO { source })?;
+
+            let metrics = self.clone();
+            thread::spawn(move || metrics.handle_http(stream));
+        }
+    }
+
+    /// Answers one HTTP request, closing the connection after it.
+    fn handle_http(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        stream.set_read_timeout(Some(HTTP_TIMEOUT))?;
+        stream.set_write_timeout(Some(HTTP_TIMEOUT))?;
+
+        let mut reader = BufReader::new((&stream).take(MAX_REQUEST_LENGTH as u64));
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        // The headers are of no interest, but are read so that closing the
+        // connection does not reset it under the response
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 2 {
+            header.clear();
+        }
+
+        let mut fields = request_line.split_whitespace();
+        let (status, body) = match (fields.next(), fields.next()) {
+            (Some("GET" | "HEAD"), Some(path)) if path.split('?').next() == Some("/metrics") => {
+                ("200 OK", self.render())
+            }
+            (Some("GET" | "HEAD"), Some(_)) => ("404 Not Found", "Not Found\n".to_string()),
+            _ => ("400 Bad Request", "Bad Request\n".to_string()),
+        };
+
+        let mut response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            status,
+            CONTENT_TYPE,
+            body.len()
+        );
+        if !request_line.starts_with("HEAD ") {
+            response.push_str(&body);
+        }
+        stream.write_all(response.as_bytes())?;
+        stream.flush()
+    }
+}
@@ -0,0 +1,327 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+use crate::DnsError;
+
+/// Maximum length of a single label, in bytes.
+pub const MAX_LABEL_LENGTH: usize = 63;
+
+/// Maximum length of a whole name in wire format, including the length bytes
+/// and the terminating root label.
+pub const MAX_NAME_LENGTH: usize = 255;
+
+/// The root name, for places that need a reference to it.
+pub static ROOT: Name = Name::root();
+
+/// A domain name, stored as a sequence of raw labels without the root label.
+///
+/// Labels keep the case they were created or received with, but equality,
+/// hashing and ordering are ASCII case-insensitive as required by RFC 4343.
+/// Ordering follows the canonical ordering of RFC 4034 section 6.1.
+#[derive(Clone, Default)]
+pub struct Name {
+    labels: Vec<Vec<u8>>,
+}
+
+impl Name {
+    /// The root name `.`.
+    pub const fn root() -> Name {
+        Name { labels: Vec::new() }
+    }
+
+    /// Builds a name from raw labels, ordered from the leftmost one.
+    pub fn from_labels<I, L>(labels: I) -> crate::Result<Name>
+    where
+        I: IntoIterator<Item = L>,
+        L: Into<Vec<u8>>,
+    {
+        let mut name = Name::root();
+        for label in labels {
+            name.push_label(label.into())?;
+        }
+
+        Ok(name)
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    pub fn num_labels(&self) -> usize {
+        self.labels.len()
+    }
+
+    /// Iterates over the labels from the leftmost one, the root label is not
+    /// included.
+    pub fn labels(&self) -> impl DoubleEndedIterator<Item = &[u8]> + ExactSizeIterator {
+        self.labels.iter().map(|label| label.as_slice())
+    }
+
+    /// Length of the uncompressed wire format of this name.
+    pub fn wire_length(&self) -> usize {
+        self.labels
+            .iter()
+            .map(|label| label.len() + 1)
+            .sum::<usize>()
+            + 1
+    }
+
+    /// The name with its leftmost label removed, `None` for the root.
+    pub fn parent(&self) -> Option<Name> {
+        if self.is_root() {
+            return None;
+        }
+
+        Some(Name {
+            labels: self.labels[1..].to_vec(),
+        })
+    }
+
+    /// Parses a name that may contain Unicode labels, converting them to their
+    /// A-label (`xn--`) form with UTS #46 processing, which also lowercases
+    /// the name. Names that are already ASCII are parsed as presentation
+    /// format instead, keeping their case and escapes.
+    pub fn from_unicode(text: &str) -> crate::Result<Name> {
+        if text.is_ascii() {
+            return text.parse();
+        }
+
+        let ascii = idna::domain_to_ascii(text)
+            .map_err(|error| DnsError::InvalidIdn(format!("{}: {}", text, error)))?;
+
+        ascii.parse()
+    }
+
+    /// Presentation format with every A-label decoded to its Unicode form.
+    /// Labels that are not valid A-labels are kept as they are.
+    pub fn to_unicode(&self) -> String {
+        let ascii = self.to_string();
+        let has_a_label = self
+            .labels()
+            .any(|label| label.len() > 4 && label[..4].eq_ignore_ascii_case(b"xn--"));
+        if !has_a_label {
+            return ascii;
+        }
+
+        match idna::domain_to_unicode(&ascii) {
+            (unicode, Ok(())) => unicode,
+            (_, Err(_)) => ascii,
+        }
+    }
+
+    /// Whether this name is equal to or below `other`.
+    pub fn is_subdomain_of(&self, other: &Name) -> bool {
+        self.labels.len() >= other.labels.len()
+            && self
+                .labels()
+                .rev()
+                .zip(other.labels().rev())
+                .all(|(ours, theirs)| ours.eq_ignore_ascii_case(theirs))
+    }
+
+    /// Prepends `label` to this name.
+    pub fn prepend_label(&self, label: &[u8]) -> crate::Result<Name> {
+        let mut labels = vec![label.to_vec()];
+        labels.extend(self.labels.iter().cloned());
+
+        Name::from_labels(labels)
+    }
+
+    /// Appends all labels of `suffix` to this name.
+    pub fn concat(&self, suffix: &Name) -> crate::Result<Name> {
+        Name::from_labels(self.labels.iter().chain(&suffix.labels).cloned())
+    }
+
+    /// A copy of this name with every label lowercased.
+    pub fn to_lowercase(&self) -> Name {
+        Name {
+            labels: self
+                .labels
+                .iter()
+                .map(|label| label.to_ascii_lowercase())
+                .collect(),
+        }
+    }
+
+    /// Case-sensitive comparison, unlike `==`.
+    pub fn eq_exact(&self, other: &Name) -> bool {
+        self.labels == other.labels
+    }
+
+    pub(crate) fn push_label(&mut self, label: Vec<u8>) -> crate::Result<()> {
+        if label.is_empty() {
+            return Err(DnsError::EmptyLabel);
+        }
+        if label.len() > MAX_LABEL_LENGTH {
+            return Err(DnsError::LabelExceedsMaxLengthSize);
+        }
+        if self.wire_length() + label.len() + 1 > MAX_NAME_LENGTH {
+            return Err(DnsError::NameTooLong);
+        }
+
+        self.labels.push(label);
+
+        Ok(())
+    }
+}
+
+impl FromStr for Name {
+    type Err = DnsError;
+
+    /// Parses a name in presentation format. A trailing dot is optional,
+    /// `\.` escapes a dot inside a label, `\DDD` a byte in decimal and `\X`
+    /// any other character.
+    fn from_str(text: &str) -> crate::Result<Name> {
+        let mut name = Name::root();
+        if text == "." || text.is_empty() {
+            return Ok(name);
+        }
+
+        let bytes = text.as_bytes();
+        let mut label = Vec::new();
+        let mut position = 0;
+        while position < bytes.len() {
+            match bytes[position] {
+                b'.' => {
+                    name.push_label(std::mem::take(&mut label))?;
+                    position += 1;
+                    // A single trailing dot terminates the name
+                    if position == bytes.len() {
+                        return Ok(name);
+                    }
+                }
+                b'\\' => {
+                    let digits = bytes.get(position + 1..position + 4);
+                    match digits {
+                        Some(digits) if digits.iter().all(u8::is_ascii_digit) => {
+                            let value = digits
+                                .iter()
+                                .fold(0u32, |value, digit| value * 10 + (digit - b'0') as u32);
+                            if value > 0xFF {
+                                return Err(DnsError::InvalidEscape(position));
+                            }
+                            label.push(value as u8);
+                            position += 4;
+                        }
+                        _ => {
+                            let escaped = bytes
+                                .get(position + 1)
+                                .ok_or(DnsError::InvalidEscape(position))?;
+                            if escaped.is_ascii_digit() {
+                                return Err(DnsError::InvalidEscape(position));
+                            }
+                            label.push(*escaped);
+                            position += 2;
+                        }
+                    }
+                }
+                byte => {
+                    label.push(byte);
+                    position += 1;
+                }
+            }
+        }
+        name.push_label(label)?;
+
+        Ok(name)
+    }
+}
+
+impl fmt::Display for Name {
+    /// Writes the name in presentation format without a trailing dot, the root
+    /// is written as `.`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_root() {
+            return f.write_str(".");
+        }
+
+        for (index, label) in self.labels.iter().enumerate() {
+            if index > 0 {
+                f.write_str(".")?;
+            }
+            for &byte in label {
+                match byte {
+                    b'.' | b'\\' => write!(f, "\\{}", byte as char)?,
+                    0x21..=0x7E => write!(f, "{}", byte as char)?,
+                    _ => write!(f, "\\{:03}", byte)?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.to_string())
+    }
+}
+
+impl PartialEq for Name {
+    fn eq(&self, other: &Name) -> bool {
+        self.labels.len() == other.labels.len()
+            && self
+                .labels()
+                .zip(other.labels())
+                .all(|(ours, theirs)| ours.eq_ignore_ascii_case(theirs))
+    }
+}
+
+impl Eq for Name {}
+
+impl Hash for Name {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_usize(self.labels.len());
+        for label in &self.labels {
+            state.write_usize(label.len());
+            for byte in label {
+                state.write_u8(byte.to_ascii_lowercase());
+            }
+        }
+    }
+}
+
+impl Ord for Name {
+    /// Canonical ordering: labels are compared from the rightmost one as
+    /// lowercased byte strings, a name sorts before any of its subdomains.
+    fn cmp(&self, other: &Name) -> Ordering {
+        for (ours, theirs) in self.labels().rev().zip(other.labels().rev()) {
+            let ordering = ours
+                .iter()
+                .map(u8::to_ascii_lowercase)
+                .cmp(theirs.iter().map(u8::to_ascii_lowercase));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+
+        self.labels.len().cmp(&other.labels.len())
+    }
+}
+
+impl PartialOrd for Name {
+    fn partial_cmp(&self, other: &Name) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Name {
+    /// Generates only valid names, labels that would exceed the total length
+    /// limit end the name early.
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let mut name = Name::root();
+        for _ in 0..u.int_in_range(0..=8)? {
+            let length = u.int_in_range(1..=MAX_LABEL_LENGTH)?;
+            let label = u.bytes(length)?.to_vec();
+            if name.push_label(label).is_err() {
+                break;
+            }
+        }
+
+        Ok(name)
+    }
+}
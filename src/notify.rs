@@ -0,0 +1,73 @@
+use std::net::SocketAddr;
+
+use crate::client::DnsClient;
+use crate::name::Name;
+use crate::protocol::{DnsClass, DnsPacket, DnsQuestion, DnsRecord, Opcode, QueryType, ResultCode};
+use crate::tsig::TsigKey;
+use crate::zone::Zone;
+use crate::DnsError;
+
+/// Times a NOTIFY is sent before giving up on an unresponsive secondary.
+const NOTIFY_ATTEMPTS: usize = 3;
+
+/// A NOTIFY received by the server, handed over to the secondary manager.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Notify {
+    pub origin: Name,
+    pub class: DnsClass,
+    pub source: SocketAddr,
+    /// Serial announced by the primary, only a hint as it is unauthenticated.
+    pub serial: Option<u32>,
+}
+
+impl Notify {
+    /// Reads the zone announced by a NOTIFY `request` received from `source`.
+    pub fn from_request(request: &DnsPacket, source: SocketAddr) -> Option<Notify> {
+        let [question] = request.questions.as_slice() else {
+            return None;
+        };
+        if request.header.opcode != Opcode::NOTIFY || question.qtype != QueryType::SOA {
+            return None;
+        }
+
+        let serial = request.answers.iter().find_map(|record| match record {
+            DnsRecord::SOA { domain, serial, .. } if *domain == question.name => Some(*serial),
+            _ => None,
+        });
+
+        Some(Notify {
+            origin: question.name.clone(),
+            class: question.qclass,
+            source,
+            serial,
+        })
+    }
+}
+
+/// Builds a NOTIFY (RFC 1996) announcing the current version of `zone`.
+pub fn notify_request(zone: &Zone) -> DnsPacket {
+    let mut packet = DnsPacket::new();
+    packet.header.opcode = Opcode::NOTIFY;
+    packet.header.authoritative_answer = true;
+
+    let mut question = DnsQuestion::new(zone.origin.clone(), QueryType::SOA);
+    question.qclass = zone.class;
+    packet.questions.push(question);
+    packet.answers.extend(zone.soa().cloned());
+
+    packet
+}
+
+/// Sends a NOTIFY for `zone` to the secondary at `target`, retrying until it
+/// is acknowledged.
+pub fn send_notify(zone: &Zone, target: SocketAddr, tsig: Option<&TsigKey>) -> crate::Result<()> {
+    let mut client = DnsClient::new(target);
+    client.tsig = tsig.cloned();
+    client.retries = NOTIFY_ATTEMPTS - 1;
+
+    let response = client.send(notify_request(zone))?;
+    match response.rcode() {
+        ResultCode::NOERROR => Ok(()),
+        rcode => Err(DnsError::NotifyRefused(rcode)),
+    }
+}
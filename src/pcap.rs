@@ -0,0 +1,875 @@
+//! Reading DNS traffic from packet captures, and writing it to them.
+//!
+//! `CaptureReader` reads pcap and pcapng files, decodes the link, IP and
+//! transport layers, and yields the DNS messages found on the DNS port with
+//! their timestamps and endpoints. Messages over TCP are reassembled from
+//! their segments in sequence order, fragmented IP datagrams are skipped.
+//!
+//! `PcapWriter` writes messages to a pcap file as raw IP packets, which is
+//! how `DnsServer::capture_to` records the traffic of a server.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::buffer::PacketBuffer;
+use crate::protocol::DnsPacket;
+use crate::DnsError;
+
+/// Magic number of pcap files with microsecond timestamps.
+const PCAP_MAGIC: u32 = 0xA1B2_C3D4;
+/// Magic number of pcap files with nanosecond timestamps.
+const PCAP_MAGIC_NANOS: u32 = 0xA1B2_3C4D;
+/// Block type of the section header opening pcapng files, the same in
+/// either byte order.
+const PCAPNG_SECTION_HEADER: [u8; 4] = [0x0A, 0x0D, 0x0D, 0x0A];
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 1;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+const PCAPNG_OPTION_TSRESOL: u16 = 9;
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LOOP: u32 = 108;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88A8;
+
+const PROTOCOL_TCP: u8 = 6;
+const PROTOCOL_UDP: u8 = 17;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+
+/// Largest block or record read from a capture, to bound memory on corrupt
+/// files.
+const MAX_CAPTURE_RECORD: usize = 1 << 24;
+
+/// Most bytes held back per TCP stream while waiting for a missing segment.
+/// Past it, the stream skips the gap.
+const MAX_TCP_BACKLOG: usize = 1 << 20;
+
+/// Payload carried by each TCP segment written, as with a 1500 byte MTU.
+const TCP_SEGMENT_SIZE: usize = 1460;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Transport {
+    Udp,
+    Tcp,
+}
+
+/// A DNS message found in a capture.
+#[derive(Debug)]
+pub struct CapturedMessage {
+    /// Time the datagram, or the segment completing the message, was
+    /// captured.
+    pub timestamp: SystemTime,
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    pub transport: Transport,
+    /// The message, without the TCP length prefix.
+    pub bytes: Vec<u8>,
+    pub packet: crate::Result<DnsPacket>,
+}
+
+/// A captured link-layer frame.
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub timestamp: SystemTime,
+    pub link_type: u32,
+    pub data: Vec<u8>,
+}
+
+enum Format {
+    Pcap {
+        big_endian: bool,
+        nanos: bool,
+        link_type: u32,
+    },
+    Pcapng {
+        big_endian: bool,
+        /// Link type and timestamp units per second of each interface of
+        /// the current section.
+        interfaces: Vec<(u32, u64)>,
+    },
+}
+
+/// Reads the frames of a pcap or pcapng capture, telling the format from
+/// its first bytes.
+pub struct PcapReader<R: Read> {
+    reader: R,
+    format: Format,
+}
+
+impl<R: Read> PcapReader<R> {
+    pub fn new(mut reader: R) -> crate::Result<PcapReader<R>> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic).map_err(capture_error)?;
+
+        let format = if magic == PCAPNG_SECTION_HEADER {
+            let mut length = [0; 4];
+            reader.read_exact(&mut length).map_err(capture_error)?;
+            Format::Pcapng {
+                big_endian: read_section_header(&mut reader, length)?,
+                interfaces: Vec::new(),
+            }
+        } else {
+            let big_endian = match u32::from_le_bytes(magic) {
+                PCAP_MAGIC | PCAP_MAGIC_NANOS => false,
+                _ => match u32::from_be_bytes(magic) {
+                    PCAP_MAGIC | PCAP_MAGIC_NANOS => true,
+                    _ => return Err(invalid("not a pcap or pcapng file")),
+                },
+            };
+
+            let mut header = [0; 20];
+            reader.read_exact(&mut header).map_err(capture_error)?;
+            Format::Pcap {
+                big_endian,
+                nanos: read_u32(&magic, big_endian) == PCAP_MAGIC_NANOS,
+                // The upper bits of the field may describe the FCS
+                link_type: read_u32(&header[16..20], big_endian) & 0xFFFF,
+            }
+        };
+
+        Ok(PcapReader { reader, format })
+    }
+
+    /// The next frame, or `None` at the end of the capture. A record cut
+    /// short by the end of the file, as left by an interrupted capture,
+    /// also ends it.
+    pub fn next_frame(&mut self) -> crate::Result<Option<Frame>> {
+        let Format::Pcap {
+            big_endian,
+            nanos,
+            link_type,
+        } = self.format
+        else {
+            return self.next_pcapng_frame();
+        };
+
+        let Some(header) = read_or_end::<16>(&mut self.reader)? else {
+            return Ok(None);
+        };
+        let seconds = read_u32(&header[0..4], big_endian) as u64;
+        let fraction = read_u32(&header[4..8], big_endian) as u64;
+        let length = read_u32(&header[8..12], big_endian) as usize;
+        if length > MAX_CAPTURE_RECORD {
+            return Err(invalid("record larger than any frame"));
+        }
+
+        let mut data = vec![0; length];
+        if !read_all_or_end(&mut self.reader, &mut data)? {
+            return Ok(None);
+        }
+        let fraction = match nanos {
+            true => Duration::from_nanos(fraction),
+            false => Duration::from_micros(fraction),
+        };
+
+        Ok(Some(Frame {
+            timestamp: UNIX_EPOCH + Duration::from_secs(seconds) + fraction,
+            link_type,
+            data,
+        }))
+    }
+
+    fn next_pcapng_frame(&mut self) -> crate::Result<Option<Frame>> {
+        loop {
+            let Some(header) = read_or_end::<8>(&mut self.reader)? else {
+                return Ok(None);
+            };
+            let Format::Pcapng {
+                ref mut big_endian,
+                ref mut interfaces,
+            } = self.format
+            else {
+                unreachable!("pcapng blocks are only read from pcapng files")
+            };
+
+            // A new section may change the byte order and starts over with
+            // its own interfaces
+            if header[0..4] == PCAPNG_SECTION_HEADER {
+                let length = [header[4], header[5], header[6], header[7]];
+                *big_endian = read_section_header(&mut self.reader, length)?;
+                interfaces.clear();
+                continue;
+            }
+
+            let big_endian = *big_endian;
+            let block_type = read_u32(&header[0..4], big_endian);
+            let length = read_u32(&header[4..8], big_endian) as usize;
+            if !(12..=MAX_CAPTURE_RECORD).contains(&length) || !length.is_multiple_of(4) {
+                return Err(invalid("bad pcapng block length"));
+            }
+
+            let mut body = vec![0; length - 8];
+            if !read_all_or_end(&mut self.reader, &mut body)? {
+                return Ok(None);
+            }
+            // The block ends with a copy of its length
+            let body = &body[..body.len() - 4];
+
+            match block_type {
+                PCAPNG_INTERFACE_DESCRIPTION if body.len() >= 8 => {
+                    let link_type = read_u16(&body[0..2], big_endian) as u32;
+                    let resolution = interface_resolution(&body[8..], big_endian);
+                    interfaces.push((link_type, resolution));
+                }
+                PCAPNG_ENHANCED_PACKET if body.len() >= 20 => {
+                    let interface = read_u32(&body[0..4], big_endian) as usize;
+                    let Some((link_type, resolution)) = interfaces.get(interface).copied() else {
+                        return Err(invalid("packet on an undescribed interface"));
+                    };
+                    let high = read_u32(&body[4..8], big_endian) as u64;
+                    let low = read_u32(&body[8..12], big_endian) as u64;
+                    let captured = read_u32(&body[12..16], big_endian) as usize;
+                    let data = body
+                        .get(20..20 + captured)
+                        .ok_or_else(|| invalid("packet larger than its block"))?;
+
+                    return Ok(Some(Frame {
+                        timestamp: timestamp((high << 32) | low, resolution),
+                        link_type,
+                        data: data.to_vec(),
+                    }));
+                }
+                PCAPNG_SIMPLE_PACKET if body.len() >= 4 => {
+                    let Some((link_type, _)) = interfaces.first().copied() else {
+                        return Err(invalid("packet on an undescribed interface"));
+                    };
+
+                    // Simple packets carry no timestamp
+                    return Ok(Some(Frame {
+                        timestamp: UNIX_EPOCH,
+                        link_type,
+                        data: body[4..].to_vec(),
+                    }));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Reads the rest of a section header block whose length field was
+/// `length`, returning whether the section is big endian.
+fn read_section_header<R: Read>(reader: &mut R, length: [u8; 4]) -> crate::Result<bool> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic).map_err(capture_error)?;
+    let big_endian = match u32::from_le_bytes(magic) {
+        PCAPNG_BYTE_ORDER_MAGIC => false,
+        _ if u32::from_be_bytes(magic) == PCAPNG_BYTE_ORDER_MAGIC => true,
+        _ => return Err(invalid("bad pcapng byte order magic")),
+    };
+
+    let length = read_u32(&length, big_endian) as usize;
+    if !(28..=MAX_CAPTURE_RECORD).contains(&length) || !length.is_multiple_of(4) {
+        return Err(invalid("bad pcapng section header length"));
+    }
+
+    // Nothing else in the header matters for reading packets
+    let mut rest = vec![0; length - 12];
+    reader.read_exact(&mut rest).map_err(capture_error)?;
+
+    Ok(big_endian)
+}
+
+/// Timestamp units per second of an interface, from its `if_tsresol`
+/// option: a power of ten, or of two when the high bit is set.
+fn interface_resolution(mut options: &[u8], big_endian: bool) -> u64 {
+    const DEFAULT: u64 = 1_000_000;
+
+    while options.len() >= 4 {
+        let code = read_u16(&options[0..2], big_endian);
+        let length = read_u16(&options[2..4], big_endian) as usize;
+        let padded = 4 + length.next_multiple_of(4);
+        if code == 0 || options.len() < 4 + length {
+            break;
+        }
+
+        if code == PCAPNG_OPTION_TSRESOL && length >= 1 {
+            let exponent = options[4];
+            let resolution = match exponent & 0x80 {
+                0 => 10u64.checked_pow(exponent as u32),
+                _ => 2u64.checked_pow((exponent & 0x7F) as u32),
+            };
+            return resolution.filter(|units| *units > 0).unwrap_or(DEFAULT);
+        }
+        options = options.get(padded..).unwrap_or_default();
+    }
+
+    DEFAULT
+}
+
+fn timestamp(units: u64, per_second: u64) -> SystemTime {
+    let seconds = units / per_second;
+    let nanos = (units % per_second) as u128 * 1_000_000_000 / per_second as u128;
+
+    // Nonsense timestamps from corrupt files must not overflow
+    UNIX_EPOCH
+        .checked_add(Duration::from_secs(seconds) + Duration::from_nanos(nanos as u64))
+        .unwrap_or(UNIX_EPOCH)
+}
+
+/// Reads exactly `N` bytes, or `None` when the input ends first.
+fn read_or_end<const N: usize>(reader: &mut impl Read) -> crate::Result<Option<[u8; N]>> {
+    let mut bytes = [0; N];
+    Ok(read_all_or_end(reader, &mut bytes)?.then_some(bytes))
+}
+
+fn read_all_or_end(reader: &mut impl Read, bytes: &mut [u8]) -> crate::Result<bool> {
+    match reader.read_exact(bytes) {
+        Ok(()) => Ok(true),
+        Err(error) if error.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(source) => Err(capture_error(source)),
+    }
+}
+
+fn read_u16(bytes: &[u8], big_endian: bool) -> u16 {
+    let bytes = [bytes[0], bytes[1]];
+    match big_endian {
+        true => u16::from_be_bytes(bytes),
+        false => u16::from_le_bytes(bytes),
+    }
+}
+
+fn read_u32(bytes: &[u8], big_endian: bool) -> u32 {
+    let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+    match big_endian {
+        true => u32::from_be_bytes(bytes),
+        false => u32::from_le_bytes(bytes),
+    }
+}
+
+fn capture_error(source: std::io::Error) -> DnsError {
+    match source.kind() {
+        ErrorKind::UnexpectedEof => invalid("capture ends inside its header"),
+        _ => DnsError::CaptureIO { source },
+    }
+}
+
+fn invalid(reason: &str) -> DnsError {
+    DnsError::InvalidCapture(reason.to_string())
+}
+
+/// A transport segment or datagram decoded from a frame.
+struct Segment<'a> {
+    source: SocketAddr,
+    destination: SocketAddr,
+    /// TCP sequence number and flags, `None` for UDP.
+    tcp: Option<(u32, u8)>,
+    payload: &'a [u8],
+}
+
+/// The IP packet carried by `frame`, for the link types captures of DNS
+/// traffic come in.
+fn network_layer(frame: &Frame) -> Option<&[u8]> {
+    let data = frame.data.as_slice();
+    match frame.link_type {
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => Some(data),
+        // The address family is in host byte order, the IP version tells
+        // just as well
+        LINKTYPE_NULL | LINKTYPE_LOOP => data.get(4..),
+        LINKTYPE_ETHERNET => {
+            let mut offset = 12;
+            let mut ethertype = u16::from_be_bytes([*data.get(12)?, *data.get(13)?]);
+            while ethertype == ETHERTYPE_VLAN || ethertype == ETHERTYPE_QINQ {
+                offset += 4;
+                ethertype = u16::from_be_bytes([*data.get(offset)?, *data.get(offset + 1)?]);
+            }
+            match ethertype {
+                ETHERTYPE_IPV4 | ETHERTYPE_IPV6 => data.get(offset + 2..),
+                _ => None,
+            }
+        }
+        LINKTYPE_LINUX_SLL => data.get(16..),
+        LINKTYPE_LINUX_SLL2 => data.get(20..),
+        _ => None,
+    }
+}
+
+/// The UDP or TCP segment in the IP packet `ip`, unless it is a fragment.
+fn transport_layer(ip: &[u8]) -> Option<Segment<'_>> {
+    let (source, destination, protocol, payload): (IpAddr, IpAddr, u8, &[u8]) =
+        match ip.first()? >> 4 {
+            4 => {
+                let header_length = (ip[0] & 0x0F) as usize * 4;
+                let total_length = u16::from_be_bytes([*ip.get(2)?, *ip.get(3)?]) as usize;
+                let fragment = u16::from_be_bytes([*ip.get(6)?, *ip.get(7)?]);
+                if fragment & 0x3FFF != 0 {
+                    return None;
+                }
+                let source: [u8; 4] = ip.get(12..16)?.try_into().ok()?;
+                let destination: [u8; 4] = ip.get(16..20)?.try_into().ok()?;
+                // Frames may be padded past the end of the packet
+                let end = total_length.min(ip.len());
+                (
+                    Ipv4Addr::from(source).into(),
+                    Ipv4Addr::from(destination).into(),
+                    ip[9],
+                    ip.get(header_length..end)?,
+                )
+            }
+            6 => {
+                let payload_length = u16::from_be_bytes([*ip.get(4)?, *ip.get(5)?]) as usize;
+                let source: [u8; 16] = ip.get(8..24)?.try_into().ok()?;
+                let destination: [u8; 16] = ip.get(24..40)?.try_into().ok()?;
+                let end = (40 + payload_length).min(ip.len());
+
+                let mut next_header = ip[6];
+                let mut offset = 40;
+                // Hop-by-hop, routing and destination options come before
+                // the transport header, fragments are not reassembled
+                while matches!(next_header, 0 | 43 | 60) {
+                    next_header = *ip.get(offset)?;
+                    offset += (*ip.get(offset + 1)? as usize + 1) * 8;
+                }
+                (
+                    Ipv6Addr::from(source).into(),
+                    Ipv6Addr::from(destination).into(),
+                    next_header,
+                    ip.get(offset..end)?,
+                )
+            }
+            _ => return None,
+        };
+
+    let port = |offset: usize| {
+        Some(u16::from_be_bytes([
+            *payload.get(offset)?,
+            *payload.get(offset + 1)?,
+        ]))
+    };
+    let source = SocketAddr::new(source, port(0)?);
+    let destination = SocketAddr::new(destination, port(2)?);
+
+    match protocol {
+        PROTOCOL_UDP => {
+            let length = (port(4)? as usize).min(payload.len());
+            Some(Segment {
+                source,
+                destination,
+                tcp: None,
+                payload: payload.get(8..length)?,
+            })
+        }
+        PROTOCOL_TCP => {
+            let sequence = u32::from_be_bytes(payload.get(4..8)?.try_into().ok()?);
+            let header_length = (*payload.get(12)? >> 4) as usize * 4;
+            let flags = *payload.get(13)?;
+            Some(Segment {
+                source,
+                destination,
+                tcp: Some((sequence, flags)),
+                payload: payload.get(header_length..)?,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// One direction of a TCP connection, put back in sequence order.
+#[derive(Default)]
+struct TcpFlow {
+    /// Sequence number of the next byte expected, unknown until the first
+    /// segment when the capture starts mid-connection.
+    next: Option<u32>,
+    /// Segments received ahead of a missing one.
+    backlog: Vec<(u32, Vec<u8>)>,
+    /// Bytes in order, not yet forming a whole message.
+    data: Vec<u8>,
+}
+
+impl TcpFlow {
+    /// Adds a segment, returning the messages it completes.
+    fn segment(&mut self, mut sequence: u32, flags: u8, payload: &[u8]) -> Vec<Vec<u8>> {
+        if flags & TCP_SYN != 0 {
+            *self = TcpFlow::default();
+            sequence = sequence.wrapping_add(1);
+        }
+        let next = *self.next.get_or_insert(sequence);
+        if !payload.is_empty() {
+            self.backlog.push((sequence, payload.to_vec()));
+        }
+
+        self.next = Some(self.drain_backlog(next));
+        let held: usize = self.backlog.iter().map(|(_, bytes)| bytes.len()).sum();
+        if held > MAX_TCP_BACKLOG {
+            // Give up on the missing bytes, the messages around them are lost
+            let next = self.next.unwrap_or(sequence);
+            let resume = self
+                .backlog
+                .iter()
+                .map(|(start, _)| *start)
+                .min_by_key(|start| start.wrapping_sub(next))
+                .unwrap_or(next);
+            self.data.clear();
+            self.next = Some(self.drain_backlog(resume));
+        }
+
+        let mut messages = Vec::new();
+        while self.data.len() >= 2 {
+            let length = u16::from_be_bytes([self.data[0], self.data[1]]) as usize;
+            if self.data.len() < 2 + length {
+                break;
+            }
+            messages.push(self.data[2..2 + length].to_vec());
+            self.data.drain(..2 + length);
+        }
+
+        messages
+    }
+
+    /// Appends the segments that continue the stream at `next`, returning
+    /// the sequence number following them.
+    fn drain_backlog(&mut self, mut next: u32) -> u32 {
+        // Sequence numbers wrap, a segment starts at or before `next` when
+        // the distance back to it is small
+        while let Some(index) = self
+            .backlog
+            .iter()
+            .position(|(start, _)| (next.wrapping_sub(*start) as i32) >= 0)
+        {
+            let (start, bytes) = self.backlog.swap_remove(index);
+            let skip = next.wrapping_sub(start) as usize;
+            if skip < bytes.len() {
+                self.data.extend_from_slice(&bytes[skip..]);
+                next = start.wrapping_add(bytes.len() as u32);
+            }
+        }
+
+        next
+    }
+}
+
+/// Reads the DNS messages of a capture, in the order they were completed.
+pub struct CaptureReader<R: Read> {
+    frames: PcapReader<R>,
+    /// Messages are those with this port at either end.
+    pub port: u16,
+    flows: HashMap<(SocketAddr, SocketAddr), TcpFlow>,
+    pending: VecDeque<CapturedMessage>,
+    failed: bool,
+}
+
+impl<R: Read> CaptureReader<R> {
+    pub fn new(reader: R) -> crate::Result<CaptureReader<R>> {
+        Ok(CaptureReader {
+            frames: PcapReader::new(reader)?,
+            port: 53,
+            flows: HashMap::new(),
+            pending: VecDeque::new(),
+            failed: false,
+        })
+    }
+
+    /// The next message, or `None` at the end of the capture. Frames that
+    /// are not DNS traffic are skipped.
+    pub fn next_message(&mut self) -> crate::Result<Option<CapturedMessage>> {
+        loop {
+            if let Some(message) = self.pending.pop_front() {
+                return Ok(Some(message));
+            }
+            let Some(frame) = self.frames.next_frame()? else {
+                return Ok(None);
+            };
+            self.add_frame(&frame);
+        }
+    }
+
+    fn add_frame(&mut self, frame: &Frame) {
+        let Some(segment) = network_layer(frame).and_then(transport_layer) else {
+            return;
+        };
+        if segment.source.port() != self.port && segment.destination.port() != self.port {
+            return;
+        }
+
+        let message = |bytes: Vec<u8>, transport: Transport| {
+            let packet = PacketBuffer::from_bytes(&bytes)
+                .and_then(|mut buffer| DnsPacket::from_buffer(&mut buffer));
+            CapturedMessage {
+                timestamp: frame.timestamp,
+                source: segment.source,
+                destination: segment.destination,
+                transport,
+                bytes,
+                packet,
+            }
+        };
+
+        let Some((sequence, flags)) = segment.tcp else {
+            self.pending
+                .push_back(message(segment.payload.to_vec(), Transport::Udp));
+            return;
+        };
+
+        let key = (segment.source, segment.destination);
+        let flow = self.flows.entry(key).or_default();
+        for bytes in flow.segment(sequence, flags, segment.payload) {
+            self.pending.push_back(message(bytes, Transport::Tcp));
+        }
+        if flags & (TCP_FIN | TCP_RST) != 0 {
+            self.flows.remove(&key);
+        }
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = crate::Result<CapturedMessage>;
+
+    /// Yields the messages, then the error that ended the capture early if
+    /// there was one.
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let next = self.next_message().transpose();
+        self.failed = matches!(next, Some(Err(_)));
+        next
+    }
+}
+
+/// Writes DNS messages to a pcap file as raw IP packets, with the UDP and
+/// TCP headers they were carried in.
+///
+/// TCP messages are written as the segments of one connection per pair of
+/// endpoints, each direction starting with a SYN when first written to.
+pub struct PcapWriter<W: Write> {
+    writer: W,
+    sequences: HashMap<(SocketAddr, SocketAddr), u32>,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Writes the file header to `writer`.
+    pub fn new(mut writer: W) -> crate::Result<PcapWriter<W>> {
+        let mut header = Vec::with_capacity(24);
+        header.extend_from_slice(&PCAP_MAGIC.to_le_bytes());
+        header.extend_from_slice(&2u16.to_le_bytes());
+        header.extend_from_slice(&4u16.to_le_bytes());
+        header.extend_from_slice(&0i32.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&(u16::MAX as u32).to_le_bytes());
+        header.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+        writer.write_all(&header).map_err(capture_error)?;
+
+        Ok(PcapWriter {
+            writer,
+            sequences: HashMap::new(),
+        })
+    }
+
+    pub fn write_udp(
+        &mut self,
+        timestamp: SystemTime,
+        source: SocketAddr,
+        destination: SocketAddr,
+        message: &[u8],
+    ) -> crate::Result<()> {
+        let mut datagram = Vec::with_capacity(8 + message.len());
+        datagram.extend_from_slice(&source.port().to_be_bytes());
+        datagram.extend_from_slice(&destination.port().to_be_bytes());
+        datagram.extend_from_slice(&(8 + message.len() as u16).to_be_bytes());
+        datagram.extend_from_slice(&[0, 0]);
+        datagram.extend_from_slice(message);
+
+        self.write_packet(timestamp, source, destination, PROTOCOL_UDP, datagram)
+    }
+
+    /// Writes `message` with its length prefix, split over as many segments
+    /// as it takes.
+    pub fn write_tcp(
+        &mut self,
+        timestamp: SystemTime,
+        source: SocketAddr,
+        destination: SocketAddr,
+        message: &[u8],
+    ) -> crate::Result<()> {
+        let mut framed = Vec::with_capacity(2 + message.len());
+        framed.extend_from_slice(&(message.len() as u16).to_be_bytes());
+        framed.extend_from_slice(message);
+
+        // Each direction opens with a SYN, so that readers know where its
+        // stream starts
+        let key = (source, destination);
+        if !self.sequences.contains_key(&key) {
+            let flags = match self.sequences.contains_key(&(destination, source)) {
+                true => TCP_SYN | TCP_ACK,
+                false => TCP_SYN,
+            };
+            self.write_segment(timestamp, key, 0, flags, &[])?;
+            self.sequences.insert(key, 1);
+        }
+
+        for chunk in framed.chunks(TCP_SEGMENT_SIZE) {
+            let sequence = self.sequences[&key];
+            self.write_segment(timestamp, key, sequence, TCP_PSH | TCP_ACK, chunk)?;
+            self.sequences
+                .insert(key, sequence.wrapping_add(chunk.len() as u32));
+        }
+
+        Ok(())
+    }
+
+    fn write_segment(
+        &mut self,
+        timestamp: SystemTime,
+        (source, destination): (SocketAddr, SocketAddr),
+        sequence: u32,
+        flags: u8,
+        data: &[u8],
+    ) -> crate::Result<()> {
+        let acknowledged = self.sequences.get(&(destination, source)).copied();
+
+        let mut segment = Vec::with_capacity(20 + data.len());
+        segment.extend_from_slice(&source.port().to_be_bytes());
+        segment.extend_from_slice(&destination.port().to_be_bytes());
+        segment.extend_from_slice(&sequence.to_be_bytes());
+        segment.extend_from_slice(&acknowledged.unwrap_or(0).to_be_bytes());
+        segment.extend_from_slice(&[5 << 4, flags]);
+        segment.extend_from_slice(&u16::MAX.to_be_bytes());
+        segment.extend_from_slice(&[0, 0, 0, 0]);
+        segment.extend_from_slice(data);
+
+        self.write_packet(timestamp, source, destination, PROTOCOL_TCP, segment)
+    }
+
+    pub fn flush(&mut self) -> crate::Result<()> {
+        self.writer.flush().map_err(capture_error)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Wraps the `transport` segment in an IP header and writes it as one
+    /// record, filling in the transport checksum.
+    fn write_packet(
+        &mut self,
+        timestamp: SystemTime,
+        source: SocketAddr,
+        destination: SocketAddr,
+        protocol: u8,
+        mut transport: Vec<u8>,
+    ) -> crate::Result<()> {
+        let checksum_offset = match protocol {
+            PROTOCOL_UDP => 6,
+            _ => 16,
+        };
+
+        let packet = match same_family(source.ip(), destination.ip()) {
+            (IpAddr::V4(source), IpAddr::V4(destination)) => {
+                let total_length = u16::try_from(20 + transport.len())
+                    .map_err(|_| invalid("message too large for an IPv4 packet"))?;
+
+                let mut pseudo = Vec::with_capacity(12 + transport.len());
+                pseudo.extend_from_slice(&source.octets());
+                pseudo.extend_from_slice(&destination.octets());
+                pseudo.extend_from_slice(&[0, protocol]);
+                pseudo.extend_from_slice(&(transport.len() as u16).to_be_bytes());
+                pseudo.extend_from_slice(&transport);
+                let checksum = transport_checksum(&pseudo);
+                transport[checksum_offset..checksum_offset + 2]
+                    .copy_from_slice(&checksum.to_be_bytes());
+
+                let mut packet = Vec::with_capacity(total_length as usize);
+                packet.extend_from_slice(&[0x45, 0]);
+                packet.extend_from_slice(&total_length.to_be_bytes());
+                // No identification, don't fragment, a TTL of 64
+                packet.extend_from_slice(&[0, 0, 0x40, 0, 64, protocol, 0, 0]);
+                packet.extend_from_slice(&source.octets());
+                packet.extend_from_slice(&destination.octets());
+                let checksum = internet_checksum(&packet);
+                packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+                packet.extend_from_slice(&transport);
+                packet
+            }
+            (source, destination) => {
+                let (IpAddr::V6(source), IpAddr::V6(destination)) = (source, destination) else {
+                    unreachable!("addresses of mixed families are mapped to IPv6")
+                };
+                let payload_length = u16::try_from(transport.len())
+                    .map_err(|_| invalid("message too large for an IPv6 packet"))?;
+
+                let mut pseudo = Vec::with_capacity(40 + transport.len());
+                pseudo.extend_from_slice(&source.octets());
+                pseudo.extend_from_slice(&destination.octets());
+                pseudo.extend_from_slice(&(transport.len() as u32).to_be_bytes());
+                pseudo.extend_from_slice(&[0, 0, 0, protocol]);
+                pseudo.extend_from_slice(&transport);
+                let checksum = transport_checksum(&pseudo);
+                transport[checksum_offset..checksum_offset + 2]
+                    .copy_from_slice(&checksum.to_be_bytes());
+
+                let mut packet = Vec::with_capacity(40 + transport.len());
+                packet.extend_from_slice(&[0x60, 0, 0, 0]);
+                packet.extend_from_slice(&payload_length.to_be_bytes());
+                packet.extend_from_slice(&[protocol, 64]);
+                packet.extend_from_slice(&source.octets());
+                packet.extend_from_slice(&destination.octets());
+                packet.extend_from_slice(&transport);
+                packet
+            }
+        };
+
+        let since_epoch = timestamp.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut record = Vec::with_capacity(16 + packet.len());
+        record.extend_from_slice(&(since_epoch.as_secs() as u32).to_le_bytes());
+        record.extend_from_slice(&since_epoch.subsec_micros().to_le_bytes());
+        record.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        record.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        record.extend_from_slice(&packet);
+
+        self.writer.write_all(&record).map_err(capture_error)
+    }
+}
+
+/// Both addresses in one family, IPv4 ones mapped into IPv6 when the other
+/// is IPv6.
+pub(crate) fn same_family(source: IpAddr, destination: IpAddr) -> (IpAddr, IpAddr) {
+    let v6 = |address: IpAddr| match address {
+        IpAddr::V4(address) => IpAddr::V6(address.to_ipv6_mapped()),
+        address => address,
+    };
+
+    match (source, destination) {
+        (IpAddr::V4(_), IpAddr::V4(_)) => (source, destination),
+        _ => (v6(source), v6(destination)),
+    }
+}
+
+/// The one's complement sum of RFC 1071.
+fn internet_checksum(bytes: &[u8]) -> u16 {
+    let mut sum: u32 = bytes
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]) as u32)
+        .sum();
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+
+    !(sum as u16)
+}
+
+/// The checksum of a UDP or TCP segment behind its pseudo header, where a
+/// computed zero is sent as all ones.
+fn transport_checksum(pseudo: &[u8]) -> u16 {
+    match internet_checksum(pseudo) {
+        0 => 0xFFFF,
+        checksum => checksum,
+    }
+}
@@ -0,0 +1,428 @@
+//! Presentation format (RFC 1035 section 5.1) of types, classes, records and
+//! whole messages, as printed by dig and found in zone files.
+
+use std::fmt;
+use std::str::FromStr;
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+
+use crate::protocol::{
+    DnsClass, DnsPacket, DnsQuestion, DnsRecord, Opcode, QueryType, ResultCode, EDNS_DNSSEC_OK,
+};
+use crate::DnsError;
+
+/// Every type with a mnemonic, in the order they are tried when parsing.
+const QUERY_TYPES: [QueryType; 12] = [
+    QueryType::A,
+    QueryType::NS,
+    QueryType::CNAME,
+    QueryType::SOA,
+    QueryType::MX,
+    QueryType::TXT,
+    QueryType::AAAA,
+    QueryType::OPT,
+    QueryType::TSIG,
+    QueryType::IXFR,
+    QueryType::AXFR,
+    QueryType::ANY,
+];
+
+const CLASSES: [DnsClass; 5] = [
+    DnsClass::IN,
+    DnsClass::CH,
+    DnsClass::HS,
+    DnsClass::NONE,
+    DnsClass::ANY,
+];
+
+impl fmt::Display for QueryType {
+    /// Writes the mnemonic, or `TYPE` and the number for types without one
+    /// (RFC 3597).
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mnemonic = match self {
+            QueryType::UNKNOWN(number) => return write!(f, "TYPE{}", number),
+            QueryType::A => "A",
+            QueryType::NS => "NS",
+            QueryType::CNAME => "CNAME",
+            QueryType::SOA => "SOA",
+            QueryType::MX => "MX",
+            QueryType::TXT => "TXT",
+            QueryType::AAAA => "AAAA",
+            QueryType::OPT => "OPT",
+            QueryType::TSIG => "TSIG",
+            QueryType::IXFR => "IXFR",
+            QueryType::AXFR => "AXFR",
+            QueryType::ANY => "ANY",
+        };
+
+        f.pad(mnemonic)
+    }
+}
+
+impl FromStr for QueryType {
+    type Err = DnsError;
+
+    /// Parses a mnemonic or the `TYPE` number form, ignoring case.
+    fn from_str(text: &str) -> crate::Result<QueryType> {
+        if let Some(qtype) = QUERY_TYPES
+            .into_iter()
+            .find(|qtype| qtype.to_string().eq_ignore_ascii_case(text))
+        {
+            return Ok(qtype);
+        }
+
+        generic_number(text, "TYPE")
+            .map(QueryType::from_number)
+            .ok_or_else(|| DnsError::UnknownMnemonic(text.to_string()))
+    }
+}
+
+impl fmt::Display for DnsClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mnemonic = match self {
+            DnsClass::UNKNOWN(number) => return write!(f, "CLASS{}", number),
+            DnsClass::IN => "IN",
+            DnsClass::CH => "CH",
+            DnsClass::HS => "HS",
+            DnsClass::NONE => "NONE",
+            DnsClass::ANY => "ANY",
+        };
+
+        f.pad(mnemonic)
+    }
+}
+
+impl FromStr for DnsClass {
+    type Err = DnsError;
+
+    /// Parses a mnemonic or the `CLASS` number form, ignoring case.
+    fn from_str(text: &str) -> crate::Result<DnsClass> {
+        if let Some(class) = CLASSES
+            .into_iter()
+            .find(|class| class.to_string().eq_ignore_ascii_case(text))
+        {
+            return Ok(class);
+        }
+
+        generic_number(text, "CLASS")
+            .map(DnsClass::from_number)
+            .ok_or_else(|| DnsError::UnknownMnemonic(text.to_string()))
+    }
+}
+
+/// The number in the generic `TYPE1234` or `CLASS1234` form.
+fn generic_number(text: &str, prefix: &str) -> Option<u16> {
+    let head = text.get(..prefix.len())?;
+    if !head.eq_ignore_ascii_case(prefix) {
+        return None;
+    }
+
+    text[prefix.len()..].parse().ok()
+}
+
+impl fmt::Display for ResultCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mnemonic = match self {
+            ResultCode::UNKNOWN(number) => return write!(f, "RESERVED{}", number),
+            ResultCode::NOERROR => "NOERROR",
+            ResultCode::FORMERR => "FORMERR",
+            ResultCode::SERVFAIL => "SERVFAIL",
+            ResultCode::NXDOMAIN => "NXDOMAIN",
+            ResultCode::NOTIMP => "NOTIMP",
+            ResultCode::REFUSED => "REFUSED",
+            ResultCode::YXDOMAIN => "YXDOMAIN",
+            ResultCode::YXRRSET => "YXRRSET",
+            ResultCode::NXRRSET => "NXRRSET",
+            ResultCode::NOTAUTH => "NOTAUTH",
+            ResultCode::NOTZONE => "NOTZONE",
+            ResultCode::DSOTYPENI => "DSOTYPENI",
+            ResultCode::BADVERS => "BADVERS",
+            ResultCode::BADKEY => "BADKEY",
+            ResultCode::BADTIME => "BADTIME",
+            ResultCode::BADMODE => "BADMODE",
+            ResultCode::BADNAME => "BADNAME",
+            ResultCode::BADALG => "BADALG",
+            ResultCode::BADTRUNC => "BADTRUNC",
+            ResultCode::BADCOOKIE => "BADCOOKIE",
+        };
+
+        f.pad(mnemonic)
+    }
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mnemonic = match self {
+            Opcode::UNKNOWN(number) => return write!(f, "RESERVED{}", number),
+            Opcode::QUERY => "QUERY",
+            Opcode::IQUERY => "IQUERY",
+            Opcode::STATUS => "STATUS",
+            Opcode::NOTIFY => "NOTIFY",
+            Opcode::UPDATE => "UPDATE",
+            Opcode::DSO => "DSO",
+        };
+
+        f.pad(mnemonic)
+    }
+}
+
+impl fmt::Display for DnsQuestion {
+    /// Writes the question as dig does, commented out.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            ";{}.\t\t{}\t{}",
+            fqdn(&self.name),
+            self.qclass,
+            self.qtype
+        )
+    }
+}
+
+impl fmt::Display for DnsRecord {
+    /// Writes the record as a zone file line: owner, TTL, class, type and
+    /// RDATA. Names are written fully qualified.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.\t{}\t", fqdn(self.domain()), self.ttl())?;
+        match self {
+            DnsRecord::OPT {
+                udp_payload_size, ..
+            } => write!(f, "CLASS{}", udp_payload_size)?,
+            _ => write!(f, "{}", self.class())?,
+        }
+        write!(f, "\t{}\t", self.qtype())?;
+        self.fmt_data(f)
+    }
+}
+
+impl DnsRecord {
+    /// Writes the RDATA alone, as printed by `dig +short`.
+    pub fn data_to_string(&self) -> String {
+        struct Data<'a>(&'a DnsRecord);
+
+        impl fmt::Display for Data<'_> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.fmt_data(f)
+            }
+        }
+
+        Data(self).to_string()
+    }
+
+    fn fmt_data(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnsRecord::UNKNOWN { data, .. } => write_generic(f, data),
+            DnsRecord::A { address, .. } => write!(f, "{}", address),
+            DnsRecord::AAAA { address, .. } => write!(f, "{}", address),
+            DnsRecord::NS { host, .. } | DnsRecord::CNAME { host, .. } => {
+                write!(f, "{}.", fqdn(host))
+            }
+            DnsRecord::MX { priority, host, .. } => write!(f, "{} {}.", priority, fqdn(host)),
+            DnsRecord::SOA {
+                m_name,
+                r_name,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+                ..
+            } => write!(
+                f,
+                "{}. {}. {} {} {} {} {}",
+                fqdn(m_name),
+                fqdn(r_name),
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum
+            ),
+            DnsRecord::TXT { data, .. } => {
+                for (index, string) in data.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(" ")?;
+                    }
+                    write_character_string(f, string)?;
+                }
+                Ok(())
+            }
+            DnsRecord::OPT {
+                extended_rcode,
+                version,
+                flags,
+                options,
+                ..
+            } => {
+                write!(f, "; EDNS: version: {}, flags:", version)?;
+                if flags & EDNS_DNSSEC_OK != 0 {
+                    f.write_str(" do")?;
+                }
+                write!(f, "; extended rcode: {}", extended_rcode)?;
+                for option in options {
+                    write!(f, "; option {}: ", option.code)?;
+                    write_hex(f, &option.data)?;
+                }
+                Ok(())
+            }
+            DnsRecord::TSIG {
+                algorithm,
+                time_signed,
+                fudge,
+                mac,
+                original_id,
+                error,
+                other,
+                ..
+            } => {
+                write!(
+                    f,
+                    "{}. {} {} {} {} {} {} {}",
+                    fqdn(algorithm),
+                    time_signed,
+                    fudge,
+                    mac.len(),
+                    BASE64_STANDARD.encode(mac),
+                    original_id,
+                    error,
+                    other.len()
+                )?;
+                if !other.is_empty() {
+                    write!(f, " {}", BASE64_STANDARD.encode(other))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The name to write before the trailing dot, empty for the root so that it
+/// comes out as a single `.`.
+fn fqdn(name: &crate::name::Name) -> String {
+    if name.is_root() {
+        String::new()
+    } else {
+        name.to_string()
+    }
+}
+
+/// Writes RDATA of an unknown type in the generic `\# length hex` form.
+fn write_generic(f: &mut fmt::Formatter<'_>, data: &[u8]) -> fmt::Result {
+    write!(f, "\\# {}", data.len())?;
+    if !data.is_empty() {
+        f.write_str(" ")?;
+        write_hex(f, data)?;
+    }
+
+    Ok(())
+}
+
+fn write_hex(f: &mut fmt::Formatter<'_>, data: &[u8]) -> fmt::Result {
+    for byte in data {
+        write!(f, "{:02X}", byte)?;
+    }
+
+    Ok(())
+}
+
+/// Writes a quoted character string, escaping quotes, backslashes and
+/// unprintable bytes.
+fn write_character_string(f: &mut fmt::Formatter<'_>, string: &[u8]) -> fmt::Result {
+    f.write_str("\"")?;
+    for &byte in string {
+        match byte {
+            b'"' | b'\\' => write!(f, "\\{}", byte as char)?,
+            0x20..=0x7E => write!(f, "{}", byte as char)?,
+            _ => write!(f, "\\{:03}", byte)?,
+        }
+    }
+
+    f.write_str("\"")
+}
+
+impl fmt::Display for DnsPacket {
+    /// Writes the message as dig does: the header, the EDNS pseudosection
+    /// and every non-empty section.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let header = &self.header;
+        writeln!(
+            f,
+            ";; ->>HEADER<<- opcode: {}, status: {}, id: {}",
+            header.opcode,
+            self.rcode(),
+            header.id
+        )?;
+
+        let flags = [
+            (header.response, "qr"),
+            (header.authoritative_answer, "aa"),
+            (header.truncated_message, "tc"),
+            (header.recursion_desired, "rd"),
+            (header.recursion_available, "ra"),
+            (header.authed_data, "ad"),
+            (header.checking_disabled, "cd"),
+        ];
+        f.write_str(";; flags:")?;
+        for (_, flag) in flags.iter().filter(|(set, _)| *set) {
+            write!(f, " {}", flag)?;
+        }
+        writeln!(
+            f,
+            "; QUERY: {}, ANSWER: {}, AUTHORITY: {}, ADDITIONAL: {}",
+            self.questions.len(),
+            self.answers.len(),
+            self.authorities.len(),
+            self.resources.len()
+        )?;
+
+        if let Some(DnsRecord::OPT {
+            udp_payload_size,
+            version,
+            flags,
+            options,
+            ..
+        }) = self.edns()
+        {
+            writeln!(f, "\n;; OPT PSEUDOSECTION:")?;
+            write!(f, "; EDNS: version: {}, flags:", version)?;
+            if flags & EDNS_DNSSEC_OK != 0 {
+                f.write_str(" do")?;
+            }
+            writeln!(f, "; udp: {}", udp_payload_size)?;
+            for option in options {
+                write!(f, "; OPTION {}: ", option.code)?;
+                write_hex(f, &option.data)?;
+                writeln!(f)?;
+            }
+        }
+
+        if !self.questions.is_empty() {
+            writeln!(f, "\n;; QUESTION SECTION:")?;
+            for question in &self.questions {
+                writeln!(f, "{}", question)?;
+            }
+        }
+
+        let additional: Vec<&DnsRecord> = self
+            .resources
+            .iter()
+            .filter(|record| !matches!(record, DnsRecord::OPT { .. }))
+            .collect();
+        let sections = [
+            ("ANSWER", self.answers.iter().collect::<Vec<_>>()),
+            ("AUTHORITY", self.authorities.iter().collect()),
+            ("ADDITIONAL", additional),
+        ];
+        for (section, records) in sections {
+            if records.is_empty() {
+                continue;
+            }
+            writeln!(f, "\n;; {} SECTION:", section)?;
+            for record in records {
+                writeln!(f, "{}", record)?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,41 +1,205 @@
-use std::net::{Ipv4Addr, Ipv6Addr};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
 use crate::buffer::PacketBuffer;
+use crate::name::{Name, ROOT};
+use crate::DnsError;
+
+/// Size of the fixed message header.
+pub const HEADER_LENGTH: usize = 12;
+
+/// Smallest possible question: a root name, type and class.
+const MIN_QUESTION_LENGTH: usize = 5;
+
+/// Smallest possible record: a root name, type, class, TTL and RDLENGTH.
+const MIN_RECORD_LENGTH: usize = 11;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Section {
+    #[default]
+    Header,
+    Question,
+    Answer,
+    Authority,
+    Additional,
+}
+
+impl fmt::Display for Section {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Section::Header => "header",
+            Section::Question => "question",
+            Section::Answer => "answer",
+            Section::Authority => "authority",
+            Section::Additional => "additional",
+        };
+
+        f.write_str(name)
+    }
+}
+
+/// Position of an entry inside a message, used to report parse errors.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Location {
+    pub section: Section,
+    pub index: usize,
+}
+
+impl Location {
+    pub fn new(section: Section, index: usize) -> Location {
+        Location { section, index }
+    }
+}
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.section {
+            Section::Header => write!(f, "header"),
+            section => write!(f, "{} #{}", section, self.index),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum Opcode {
+    UNKNOWN(u8),
+    QUERY,
+    IQUERY,
+    STATUS,
+    NOTIFY,
+    UPDATE,
+    DSO,
+}
+
+impl Opcode {
+    pub fn to_number(&self) -> u8 {
+        match *self {
+            Opcode::UNKNOWN(x) => x,
+            Opcode::QUERY => 0,
+            Opcode::IQUERY => 1,
+            Opcode::STATUS => 2,
+            Opcode::NOTIFY => 4,
+            Opcode::UPDATE => 5,
+            Opcode::DSO => 6,
+        }
+    }
+
+    /// Unassigned values are kept as `UNKNOWN`, so that such requests can
+    /// still be answered with NOTIMP.
+    pub fn from_number(num: u8) -> Opcode {
+        match num {
+            0 => Opcode::QUERY,
+            1 => Opcode::IQUERY,
+            2 => Opcode::STATUS,
+            4 => Opcode::NOTIFY,
+            5 => Opcode::UPDATE,
+            6 => Opcode::DSO,
+            _ => Opcode::UNKNOWN(num),
+        }
+    }
+}
+
+/// Response codes, covering both the 4 bit header values and the 12 bit
+/// extended codes that need the upper bits stored in an EDNS OPT record.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum ResultCode {
-    NOERROR = 0,
-    FORMERR = 1,
-    SERVFAIL = 2,
-    NXDOMAIN = 3,
-    NOTIMP = 4,
-    REFUSED = 5,
+    UNKNOWN(u16),
+    NOERROR,
+    FORMERR,
+    SERVFAIL,
+    NXDOMAIN,
+    NOTIMP,
+    REFUSED,
+    YXDOMAIN,
+    YXRRSET,
+    NXRRSET,
+    NOTAUTH,
+    NOTZONE,
+    DSOTYPENI,
+    BADVERS,
+    BADKEY,
+    BADTIME,
+    BADMODE,
+    BADNAME,
+    BADALG,
+    BADTRUNC,
+    BADCOOKIE,
 }
 
 impl ResultCode {
-    pub fn from_number(num: u8) -> ResultCode {
+    /// TSIG signature failure, sharing its value with `BADVERS`.
+    pub const BADSIG: ResultCode = ResultCode::BADVERS;
+
+    pub fn to_number(&self) -> u16 {
+        match *self {
+            ResultCode::UNKNOWN(x) => x,
+            ResultCode::NOERROR => 0,
+            ResultCode::FORMERR => 1,
+            ResultCode::SERVFAIL => 2,
+            ResultCode::NXDOMAIN => 3,
+            ResultCode::NOTIMP => 4,
+            ResultCode::REFUSED => 5,
+            ResultCode::YXDOMAIN => 6,
+            ResultCode::YXRRSET => 7,
+            ResultCode::NXRRSET => 8,
+            ResultCode::NOTAUTH => 9,
+            ResultCode::NOTZONE => 10,
+            ResultCode::DSOTYPENI => 11,
+            // Also BADSIG when carried in a TSIG record
+            ResultCode::BADVERS => 16,
+            ResultCode::BADKEY => 17,
+            ResultCode::BADTIME => 18,
+            ResultCode::BADMODE => 19,
+            ResultCode::BADNAME => 20,
+            ResultCode::BADALG => 21,
+            ResultCode::BADTRUNC => 22,
+            ResultCode::BADCOOKIE => 23,
+        }
+    }
+
+    pub fn from_number(num: u16) -> ResultCode {
         match num {
+            0 => ResultCode::NOERROR,
             1 => ResultCode::FORMERR,
             2 => ResultCode::SERVFAIL,
             3 => ResultCode::NXDOMAIN,
             4 => ResultCode::NOTIMP,
             5 => ResultCode::REFUSED,
-            0 | _ => ResultCode::NOERROR,
+            6 => ResultCode::YXDOMAIN,
+            7 => ResultCode::YXRRSET,
+            8 => ResultCode::NXRRSET,
+            9 => ResultCode::NOTAUTH,
+            10 => ResultCode::NOTZONE,
+            11 => ResultCode::DSOTYPENI,
+            16 => ResultCode::BADVERS,
+            17 => ResultCode::BADKEY,
+            18 => ResultCode::BADTIME,
+            19 => ResultCode::BADMODE,
+            20 => ResultCode::BADNAME,
+            21 => ResultCode::BADALG,
+            22 => ResultCode::BADTRUNC,
+            23 => ResultCode::BADCOOKIE,
+            _ => ResultCode::UNKNOWN(num),
         }
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct DnsHeader {
     pub id: u16,
 
     pub recursion_desired: bool,
     pub truncated_message: bool,
     pub authoritative_answer: bool,
-    pub opcode: u8, // 4 bits
+    pub opcode: Opcode,
     pub response: bool,
 
-    pub rescode: ResultCode, // 4 bits
+    /// Lower 4 bits of the response code, see `DnsPacket::rcode` for the
+    /// full value.
+    pub rescode: ResultCode,
     pub checking_disabled: bool,
     pub authed_data: bool,
     pub z: bool,
@@ -47,6 +211,12 @@ pub struct DnsHeader {
     pub resource_entries: u16,
 }
 
+impl Default for DnsHeader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl DnsHeader {
     pub fn new() -> DnsHeader {
         DnsHeader {
@@ -55,7 +225,7 @@ impl DnsHeader {
             recursion_desired: false,
             truncated_message: false,
             authoritative_answer: false,
-            opcode: 0,
+            opcode: Opcode::QUERY,
             response: false,
 
             rescode: ResultCode::NOERROR,
@@ -72,6 +242,12 @@ impl DnsHeader {
     }
 
     pub fn read(&mut self, buffer: &mut PacketBuffer) -> crate::Result<()> {
+        if buffer.length < HEADER_LENGTH {
+            return Err(DnsError::TruncatedHeader {
+                length: buffer.length,
+            });
+        }
+
         self.id = buffer.read_u16()?;
 
         let flags = buffer.read_u16()?;
@@ -80,10 +256,10 @@ impl DnsHeader {
         self.recursion_desired = (a & (1 << 0)) > 0;
         self.truncated_message = (a & (1 << 1)) > 0;
         self.authoritative_answer = (a & (1 << 2)) > 0;
-        self.opcode = (a >> 3) & 0x0F;
+        self.opcode = Opcode::from_number((a >> 3) & 0x0F);
         self.response = (a & (1 << 7)) > 0;
 
-        self.rescode = ResultCode::from_number(b & 0x0F);
+        self.rescode = ResultCode::from_number((b & 0x0F) as u16);
         self.checking_disabled = (b & (1 << 4)) > 0;
         self.authed_data = (b & (1 << 5)) > 0;
         self.z = (b & (1 << 6)) > 0;
@@ -97,6 +273,42 @@ impl DnsHeader {
         Ok(())
     }
 
+    /// Rejects section counts that could not possibly fit in the rest of the
+    /// message, before any time is spent parsing the entries.
+    fn check_counts(&self, buffer: &PacketBuffer) -> crate::Result<()> {
+        let offset = buffer.position();
+        let remaining = buffer.length.saturating_sub(offset);
+
+        let sections = [
+            (Section::Question, self.questions, MIN_QUESTION_LENGTH),
+            (Section::Answer, self.answers, MIN_RECORD_LENGTH),
+            (
+                Section::Authority,
+                self.authoritative_entries,
+                MIN_RECORD_LENGTH,
+            ),
+            (
+                Section::Additional,
+                self.resource_entries,
+                MIN_RECORD_LENGTH,
+            ),
+        ];
+
+        let mut needed = 0;
+        for (section, count, min_length) in sections {
+            needed += count as usize * min_length;
+            if needed > remaining {
+                return Err(DnsError::BadSectionCount {
+                    section,
+                    count,
+                    offset,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn write(&self, buffer: &mut PacketBuffer) -> crate::Result<()> {
         buffer.write_u16(self.id)?;
 
@@ -104,12 +316,12 @@ impl DnsHeader {
             (self.recursion_desired as u8)
                 | ((self.truncated_message as u8) << 1)
                 | ((self.authoritative_answer as u8) << 2)
-                | (self.opcode << 3)
-                | ((self.response as u8) << 7) as u8,
+                | ((self.opcode.to_number() & 0x0F) << 3)
+                | ((self.response as u8) << 7),
         )?;
 
         buffer.write_u8(
-            (self.rescode as u8)
+            ((self.rescode.to_number() & 0x0F) as u8)
                 | ((self.checking_disabled as u8) << 4)
                 | ((self.authed_data as u8) << 5)
                 | ((self.z as u8) << 6)
@@ -126,13 +338,25 @@ impl DnsHeader {
 }
 
 #[derive(PartialEq, Eq, Debug, Clone, Hash, Copy)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum QueryType {
     UNKNOWN(u16),
     A,
     NS,
     CNAME,
+    SOA,
     MX,
+    TXT,
     AAAA,
+    OPT,
+    /// Transaction signature, only valid in the additional section.
+    TSIG,
+    /// Incremental zone transfer, only valid in questions.
+    IXFR,
+    /// Full zone transfer, only valid in questions.
+    AXFR,
+    /// Only valid in questions and UPDATE messages.
+    ANY,
 }
 
 impl QueryType {
@@ -142,8 +366,15 @@ impl QueryType {
             QueryType::A => 1,
             QueryType::NS => 2,
             QueryType::CNAME => 5,
+            QueryType::SOA => 6,
             QueryType::MX => 15,
+            QueryType::TXT => 16,
             QueryType::AAAA => 28,
+            QueryType::OPT => 41,
+            QueryType::TSIG => 250,
+            QueryType::IXFR => 251,
+            QueryType::AXFR => 252,
+            QueryType::ANY => 255,
         }
     }
 
@@ -152,28 +383,78 @@ impl QueryType {
             1 => QueryType::A,
             2 => QueryType::NS,
             5 => QueryType::CNAME,
+            6 => QueryType::SOA,
             15 => QueryType::MX,
+            16 => QueryType::TXT,
             28 => QueryType::AAAA,
+            41 => QueryType::OPT,
+            250 => QueryType::TSIG,
+            251 => QueryType::IXFR,
+            252 => QueryType::AXFR,
+            255 => QueryType::ANY,
             _ => QueryType::UNKNOWN(num),
         }
     }
 }
 
+#[derive(PartialEq, Eq, Debug, Clone, Hash, Copy, PartialOrd, Ord)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum DnsClass {
+    UNKNOWN(u16),
+    IN,
+    CH,
+    HS,
+    /// Only meaningful in UPDATE messages.
+    NONE,
+    /// Matches any class in questions and UPDATE messages.
+    ANY,
+}
+
+impl DnsClass {
+    pub fn to_number(&self) -> u16 {
+        match *self {
+            DnsClass::UNKNOWN(x) => x,
+            DnsClass::IN => 1,
+            DnsClass::CH => 3,
+            DnsClass::HS => 4,
+            DnsClass::NONE => 254,
+            DnsClass::ANY => 255,
+        }
+    }
+
+    pub fn from_number(num: u16) -> DnsClass {
+        match num {
+            1 => DnsClass::IN,
+            3 => DnsClass::CH,
+            4 => DnsClass::HS,
+            254 => DnsClass::NONE,
+            255 => DnsClass::ANY,
+            _ => DnsClass::UNKNOWN(num),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct DnsQuestion {
-    pub name: String,
+    pub name: Name,
     pub qtype: QueryType,
+    pub qclass: DnsClass,
 }
 
 impl DnsQuestion {
-    pub fn new(name: String, qtype: QueryType) -> DnsQuestion {
-        DnsQuestion { name, qtype }
+    pub fn new(name: Name, qtype: QueryType) -> DnsQuestion {
+        DnsQuestion {
+            name,
+            qtype,
+            qclass: DnsClass::IN,
+        }
     }
 
     pub fn read(&mut self, buffer: &mut PacketBuffer) -> crate::Result<()> {
         buffer.read_qname(&mut self.name)?;
         self.qtype = QueryType::from_number(buffer.read_u16()?);
-        let _class = buffer.read_u16()?;
+        self.qclass = DnsClass::from_number(buffer.read_u16()?);
 
         Ok(())
     }
@@ -183,72 +464,319 @@ impl DnsQuestion {
 
         let type_numeric = self.qtype.to_number();
         buffer.write_u16(type_numeric)?;
-        buffer.write_u16(1)?;
+        buffer.write_u16(self.qclass.to_number())?;
 
         Ok(())
     }
 }
 
+/// EDNS header flag asking for DNSSEC records to be included.
+pub const EDNS_DNSSEC_OK: u16 = 0x8000;
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct EdnsOption {
+    pub code: u16,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[allow(dead_code)]
 pub enum DnsRecord {
     UNKNOWN {
-        domain: String,
+        domain: Name,
+        class: DnsClass,
         qtype: u16,
-        data_length: u16,
+        data: Vec<u8>,
         ttl: u32,
     },
     A {
-        domain: String,
+        domain: Name,
+        class: DnsClass,
         address: Ipv4Addr,
         ttl: u32,
     },
     NS {
-        domain: String,
-        host: String,
+        domain: Name,
+        class: DnsClass,
+        host: Name,
         ttl: u32,
     },
     CNAME {
-        domain: String,
-        host: String,
+        domain: Name,
+        class: DnsClass,
+        host: Name,
+        ttl: u32,
+    },
+    SOA {
+        domain: Name,
+        class: DnsClass,
+        m_name: Name,
+        r_name: Name,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
         ttl: u32,
     },
     MX {
-        domain: String,
+        domain: Name,
+        class: DnsClass,
         priority: u16,
-        host: String,
+        host: Name,
+        ttl: u32,
+    },
+    TXT {
+        domain: Name,
+        class: DnsClass,
+        data: Vec<Vec<u8>>,
         ttl: u32,
     },
     AAAA {
-        domain: String,
+        domain: Name,
+        class: DnsClass,
         address: Ipv6Addr,
         ttl: u32,
     },
+    /// EDNS pseudo-record, always owned by the root.
+    OPT {
+        udp_payload_size: u16,
+        extended_rcode: u8,
+        version: u8,
+        flags: u16,
+        options: Vec<EdnsOption>,
+    },
+    /// Transaction signature, owned by the key name. `time_signed` holds
+    /// seconds since the epoch in 48 bits.
+    TSIG {
+        domain: Name,
+        class: DnsClass,
+        algorithm: Name,
+        time_signed: u64,
+        fudge: u16,
+        mac: Vec<u8>,
+        original_id: u16,
+        error: ResultCode,
+        other: Vec<u8>,
+        ttl: u32,
+    },
 }
 
 impl DnsRecord {
+    pub fn domain(&self) -> &Name {
+        match self {
+            DnsRecord::UNKNOWN { domain, .. }
+            | DnsRecord::A { domain, .. }
+            | DnsRecord::NS { domain, .. }
+            | DnsRecord::CNAME { domain, .. }
+            | DnsRecord::SOA { domain, .. }
+            | DnsRecord::MX { domain, .. }
+            | DnsRecord::TXT { domain, .. }
+            | DnsRecord::AAAA { domain, .. }
+            | DnsRecord::TSIG { domain, .. } => domain,
+            DnsRecord::OPT { .. } => &ROOT,
+        }
+    }
+
+    /// The record class, OPT records repurpose the field and report `UNKNOWN`
+    /// with their payload size.
+    pub fn class(&self) -> DnsClass {
+        match *self {
+            DnsRecord::UNKNOWN { class, .. }
+            | DnsRecord::A { class, .. }
+            | DnsRecord::NS { class, .. }
+            | DnsRecord::CNAME { class, .. }
+            | DnsRecord::SOA { class, .. }
+            | DnsRecord::MX { class, .. }
+            | DnsRecord::TXT { class, .. }
+            | DnsRecord::AAAA { class, .. }
+            | DnsRecord::TSIG { class, .. } => class,
+            DnsRecord::OPT {
+                udp_payload_size, ..
+            } => DnsClass::UNKNOWN(udp_payload_size),
+        }
+    }
+
+    pub fn ttl(&self) -> u32 {
+        match *self {
+            DnsRecord::UNKNOWN { ttl, .. }
+            | DnsRecord::A { ttl, .. }
+            | DnsRecord::NS { ttl, .. }
+            | DnsRecord::CNAME { ttl, .. }
+            | DnsRecord::SOA { ttl, .. }
+            | DnsRecord::MX { ttl, .. }
+            | DnsRecord::TXT { ttl, .. }
+            | DnsRecord::AAAA { ttl, .. }
+            | DnsRecord::TSIG { ttl, .. } => ttl,
+            DnsRecord::OPT { .. } => 0,
+        }
+    }
+
+    pub fn qtype(&self) -> QueryType {
+        match *self {
+            DnsRecord::UNKNOWN { qtype, .. } => QueryType::from_number(qtype),
+            DnsRecord::A { .. } => QueryType::A,
+            DnsRecord::NS { .. } => QueryType::NS,
+            DnsRecord::CNAME { .. } => QueryType::CNAME,
+            DnsRecord::SOA { .. } => QueryType::SOA,
+            DnsRecord::MX { .. } => QueryType::MX,
+            DnsRecord::TXT { .. } => QueryType::TXT,
+            DnsRecord::AAAA { .. } => QueryType::AAAA,
+            DnsRecord::OPT { .. } => QueryType::OPT,
+            DnsRecord::TSIG { .. } => QueryType::TSIG,
+        }
+    }
+
+    pub fn set_ttl(&mut self, new_ttl: u32) {
+        match self {
+            DnsRecord::UNKNOWN { ttl, .. }
+            | DnsRecord::A { ttl, .. }
+            | DnsRecord::NS { ttl, .. }
+            | DnsRecord::CNAME { ttl, .. }
+            | DnsRecord::SOA { ttl, .. }
+            | DnsRecord::MX { ttl, .. }
+            | DnsRecord::TXT { ttl, .. }
+            | DnsRecord::AAAA { ttl, .. }
+            | DnsRecord::TSIG { ttl, .. } => *ttl = new_ttl,
+            DnsRecord::OPT { .. } => {}
+        }
+    }
+
+    pub fn set_class(&mut self, new_class: DnsClass) {
+        match self {
+            DnsRecord::UNKNOWN { class, .. }
+            | DnsRecord::A { class, .. }
+            | DnsRecord::NS { class, .. }
+            | DnsRecord::CNAME { class, .. }
+            | DnsRecord::SOA { class, .. }
+            | DnsRecord::MX { class, .. }
+            | DnsRecord::TXT { class, .. }
+            | DnsRecord::AAAA { class, .. }
+            | DnsRecord::TSIG { class, .. } => *class = new_class,
+            DnsRecord::OPT { .. } => {}
+        }
+    }
+
+    /// Whether both records have the same owner, type, class and data, only
+    /// differing in TTL.
+    pub fn same_data(&self, other: &DnsRecord) -> bool {
+        let mut other = other.clone();
+        other.set_ttl(self.ttl());
+
+        *self == other
+    }
+
     pub fn read(buffer: &mut PacketBuffer) -> crate::Result<DnsRecord> {
-        let mut domain = String::new();
+        DnsRecord::read_checked(buffer)?.map_err(|(error, _)| error)
+    }
+
+    /// Reads a record, parsing its RDATA within exactly RDLENGTH bytes.
+    ///
+    /// Errors inside the RDATA are returned in the inner result together with
+    /// the offset where the record ends, so that a lenient caller can skip it
+    /// and carry on with the next one. Errors in the fixed part of the record
+    /// leave the buffer desynchronized and are returned in the outer result.
+    fn read_checked(
+        buffer: &mut PacketBuffer,
+    ) -> crate::Result<std::result::Result<DnsRecord, (DnsError, usize)>> {
+        let mut domain = Name::root();
         buffer.read_qname(&mut domain)?;
 
         let qtype_num = buffer.read_u16()?;
         let qtype = QueryType::from_number(qtype_num);
-        let _class = buffer.read_u16()?;
+        let class = buffer.read_u16()?;
         let ttl = buffer.read_u32()?;
         let data_length = buffer.read_u16()?;
 
+        let data_start = buffer.position();
+        let data_end = data_start + data_length as usize;
+        if data_end > buffer.length {
+            return Err(DnsError::Truncated {
+                offset: buffer.length,
+                location: buffer.location,
+            });
+        }
+
+        // UPDATE messages use empty RDATA with class ANY or NONE to refer to
+        // whole RRsets and names, these have no typed form to parse into.
+        let empty_update = data_length == 0
+            && matches!(DnsClass::from_number(class), DnsClass::ANY | DnsClass::NONE);
+        if empty_update && qtype != QueryType::OPT {
+            return Ok(Ok(DnsRecord::UNKNOWN {
+                domain,
+                class: DnsClass::from_number(class),
+                qtype: qtype_num,
+                data: Vec::new(),
+                ttl,
+            }));
+        }
+
+        buffer.window_end = Some(data_end);
+        let record = DnsRecord::read_data(buffer, domain, qtype, class, ttl, data_length);
+        buffer.window_end = None;
+
+        let record = record.and_then(|record| {
+            let consumed = buffer.position() - data_start;
+            if consumed != data_length as usize {
+                return Err(DnsError::RdataLengthMismatch {
+                    offset: data_start,
+                    location: buffer.location,
+                    expected: data_length,
+                    actual: consumed,
+                });
+            }
+
+            Ok(record)
+        });
+
+        Ok(record.map_err(|error| (error, data_end)))
+    }
+
+    fn read_data(
+        buffer: &mut PacketBuffer,
+        domain: Name,
+        qtype: QueryType,
+        raw_class: u16,
+        ttl: u32,
+        data_length: u16,
+    ) -> crate::Result<DnsRecord> {
+        let class = DnsClass::from_number(raw_class);
+        let data_end = buffer.position() + data_length as usize;
+
         match qtype {
+            QueryType::OPT => {
+                let mut options = Vec::new();
+                while buffer.position() < data_end {
+                    let code = buffer.read_u16()?;
+                    let length = buffer.read_u16()? as usize;
+                    let data = buffer.get_range(buffer.position(), length)?.to_vec();
+                    buffer.step(length)?;
+                    options.push(EdnsOption { code, data });
+                }
+
+                // The class and TTL fields are repurposed by EDNS
+                Ok(DnsRecord::OPT {
+                    udp_payload_size: raw_class,
+                    extended_rcode: (ttl >> 24) as u8,
+                    version: ((ttl >> 16) & 0xFF) as u8,
+                    flags: (ttl & 0xFFFF) as u16,
+                    options,
+                })
+            }
             QueryType::A => {
                 let raw_address = buffer.read_u32()?;
                 let address = Ipv4Addr::new(
                     ((raw_address >> 24) & 0xFF) as u8,
                     ((raw_address >> 16) & 0xFF) as u8,
                     ((raw_address >> 8) & 0xFF) as u8,
-                    ((raw_address >> 0) & 0xFF) as u8,
+                    (raw_address & 0xFF) as u8,
                 );
 
                 Ok(DnsRecord::A {
                     domain,
+                    class,
                     address,
                     ttl,
                 })
@@ -260,60 +788,134 @@ impl DnsRecord {
                 let raw_address4 = buffer.read_u32()?;
                 let address = Ipv6Addr::new(
                     ((raw_address1 >> 16) & 0xFFFF) as u16,
-                    ((raw_address1 >> 0) & 0xFFFF) as u16,
+                    (raw_address1 & 0xFFFF) as u16,
                     ((raw_address2 >> 16) & 0xFFFF) as u16,
-                    ((raw_address2 >> 0) & 0xFFFF) as u16,
+                    (raw_address2 & 0xFFFF) as u16,
                     ((raw_address3 >> 16) & 0xFFFF) as u16,
-                    ((raw_address3 >> 0) & 0xFFFF) as u16,
+                    (raw_address3 & 0xFFFF) as u16,
                     ((raw_address4 >> 16) & 0xFFFF) as u16,
-                    ((raw_address4 >> 0) & 0xFFFF) as u16,
+                    (raw_address4 & 0xFFFF) as u16,
                 );
 
                 Ok(DnsRecord::AAAA {
                     domain,
+                    class,
                     address,
                     ttl,
                 })
             }
             QueryType::NS => {
-                let mut ns = String::new();
+                let mut ns = Name::root();
                 buffer.read_qname(&mut ns)?;
 
                 Ok(DnsRecord::NS {
                     domain,
+                    class,
                     host: ns,
                     ttl,
                 })
             }
             QueryType::CNAME => {
-                let mut cname = String::new();
+                let mut cname = Name::root();
                 buffer.read_qname(&mut cname)?;
 
                 Ok(DnsRecord::CNAME {
                     domain,
+                    class,
                     host: cname,
                     ttl,
                 })
             }
+            QueryType::SOA => {
+                let mut m_name = Name::root();
+                buffer.read_qname(&mut m_name)?;
+                let mut r_name = Name::root();
+                buffer.read_qname(&mut r_name)?;
+
+                Ok(DnsRecord::SOA {
+                    domain,
+                    class,
+                    m_name,
+                    r_name,
+                    serial: buffer.read_u32()?,
+                    refresh: buffer.read_u32()?,
+                    retry: buffer.read_u32()?,
+                    expire: buffer.read_u32()?,
+                    minimum: buffer.read_u32()?,
+                    ttl,
+                })
+            }
             QueryType::MX => {
                 let priority = buffer.read_u16()?;
-                let mut mx = String::new();
+                let mut mx = Name::root();
                 buffer.read_qname(&mut mx)?;
 
                 Ok(DnsRecord::MX {
                     domain,
+                    class,
                     priority,
                     host: mx,
                     ttl,
                 })
             }
-            QueryType::UNKNOWN(_) => {
+            QueryType::TXT => {
+                // A sequence of length prefixed character strings
+                let mut data = Vec::new();
+                while buffer.position() < data_end {
+                    let length = buffer.read()? as usize;
+                    data.push(buffer.get_range(buffer.position(), length)?.to_vec());
+                    buffer.step(length)?;
+                }
+
+                Ok(DnsRecord::TXT {
+                    domain,
+                    class,
+                    data,
+                    ttl,
+                })
+            }
+            QueryType::TSIG => {
+                let mut algorithm = Name::root();
+                buffer.read_qname(&mut algorithm)?;
+                let time_high = buffer.read_u16()? as u64;
+                let time_low = buffer.read_u32()? as u64;
+                let fudge = buffer.read_u16()?;
+
+                let mac_length = buffer.read_u16()? as usize;
+                let mac = buffer.get_range(buffer.position(), mac_length)?.to_vec();
+                buffer.step(mac_length)?;
+
+                let original_id = buffer.read_u16()?;
+                let error = ResultCode::from_number(buffer.read_u16()?);
+
+                let other_length = buffer.read_u16()? as usize;
+                let other = buffer.get_range(buffer.position(), other_length)?.to_vec();
+                buffer.step(other_length)?;
+
+                Ok(DnsRecord::TSIG {
+                    domain,
+                    class,
+                    algorithm,
+                    time_signed: (time_high << 32) | time_low,
+                    fudge,
+                    mac,
+                    original_id,
+                    error,
+                    other,
+                    ttl,
+                })
+            }
+            QueryType::UNKNOWN(_) | QueryType::IXFR | QueryType::AXFR | QueryType::ANY => {
+                let data = buffer
+                    .get_range(buffer.position(), data_length as usize)?
+                    .to_vec();
                 buffer.step(data_length as usize)?;
 
                 Ok(DnsRecord::UNKNOWN {
                     domain,
-                    qtype: qtype_num,
-                    data_length,
+                    class,
+                    qtype: qtype.to_number(),
+                    data,
                     ttl,
                 })
             }
@@ -326,12 +928,13 @@ impl DnsRecord {
         match *self {
             DnsRecord::A {
                 ref domain,
+                class,
                 address: ref addr,
                 ttl,
             } => {
                 buffer.write_qname(domain)?;
                 buffer.write_u16(QueryType::A.to_number())?;
-                buffer.write_u16(1)?;
+                buffer.write_u16(class.to_number())?;
                 buffer.write_u32(ttl)?;
                 buffer.write_u16(4)?;
 
@@ -343,12 +946,13 @@ impl DnsRecord {
             }
             DnsRecord::NS {
                 ref domain,
+                class,
                 ref host,
                 ttl,
             } => {
                 buffer.write_qname(domain)?;
                 buffer.write_u16(QueryType::NS.to_number())?;
-                buffer.write_u16(1)?;
+                buffer.write_u16(class.to_number())?;
                 buffer.write_u32(ttl)?;
 
                 let position = buffer.position();
@@ -361,12 +965,13 @@ impl DnsRecord {
             }
             DnsRecord::CNAME {
                 ref domain,
+                class,
                 ref host,
                 ttl,
             } => {
                 buffer.write_qname(domain)?;
                 buffer.write_u16(QueryType::CNAME.to_number())?;
-                buffer.write_u16(1)?;
+                buffer.write_u16(class.to_number())?;
                 buffer.write_u32(ttl)?;
 
                 let position = buffer.position();
@@ -377,15 +982,47 @@ impl DnsRecord {
                 let size = buffer.position() - (position + 2);
                 buffer.set_u16(position, size as u16)?;
             }
+            DnsRecord::SOA {
+                ref domain,
+                class,
+                ref m_name,
+                ref r_name,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::SOA.to_number())?;
+                buffer.write_u16(class.to_number())?;
+                buffer.write_u32(ttl)?;
+
+                let position = buffer.position();
+                buffer.write_u16(0)?;
+
+                buffer.write_qname(m_name)?;
+                buffer.write_qname(r_name)?;
+                buffer.write_u32(serial)?;
+                buffer.write_u32(refresh)?;
+                buffer.write_u32(retry)?;
+                buffer.write_u32(expire)?;
+                buffer.write_u32(minimum)?;
+
+                let size = buffer.position() - (position + 2);
+                buffer.set_u16(position, size as u16)?;
+            }
             DnsRecord::MX {
                 ref domain,
+                class,
                 priority,
                 ref host,
                 ttl,
             } => {
                 buffer.write_qname(domain)?;
                 buffer.write_u16(QueryType::MX.to_number())?;
-                buffer.write_u16(1)?;
+                buffer.write_u16(class.to_number())?;
                 buffer.write_u32(ttl)?;
 
                 let position = buffer.position();
@@ -397,14 +1034,42 @@ impl DnsRecord {
                 let size = buffer.position() - (position + 2);
                 buffer.set_u16(position, size as u16)?;
             }
+            DnsRecord::TXT {
+                ref domain,
+                class,
+                ref data,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::TXT.to_number())?;
+                buffer.write_u16(class.to_number())?;
+                buffer.write_u32(ttl)?;
+
+                let position = buffer.position();
+                buffer.write_u16(0)?;
+
+                for string in data {
+                    if string.len() > 0xFF {
+                        return Err(DnsError::StringTooLong);
+                    }
+                    buffer.write_u8(string.len() as u8)?;
+                    for byte in string {
+                        buffer.write_u8(*byte)?;
+                    }
+                }
+
+                let size = buffer.position() - (position + 2);
+                buffer.set_u16(position, size as u16)?;
+            }
             DnsRecord::AAAA {
                 ref domain,
+                class,
                 ref address,
                 ttl,
             } => {
                 buffer.write_qname(domain)?;
                 buffer.write_u16(QueryType::AAAA.to_number())?;
-                buffer.write_u16(1)?;
+                buffer.write_u16(class.to_number())?;
                 buffer.write_u32(ttl)?;
                 buffer.write_u16(16)?;
 
@@ -412,8 +1077,88 @@ impl DnsRecord {
                     buffer.write_u16(*octet)?;
                 }
             }
-            DnsRecord::UNKNOWN { .. } => {
-                println!("Skipping record: {:?}", self);
+            DnsRecord::OPT {
+                udp_payload_size,
+                extended_rcode,
+                version,
+                flags,
+                ref options,
+            } => {
+                buffer.write_qname(&ROOT)?;
+                buffer.write_u16(QueryType::OPT.to_number())?;
+                buffer.write_u16(udp_payload_size)?;
+                buffer.write_u32(
+                    ((extended_rcode as u32) << 24) | ((version as u32) << 16) | flags as u32,
+                )?;
+
+                let position = buffer.position();
+                buffer.write_u16(0)?;
+
+                for option in options {
+                    buffer.write_u16(option.code)?;
+                    buffer.write_u16(option.data.len() as u16)?;
+                    for byte in &option.data {
+                        buffer.write_u8(*byte)?;
+                    }
+                }
+
+                let size = buffer.position() - (position + 2);
+                buffer.set_u16(position, size as u16)?;
+            }
+            DnsRecord::TSIG {
+                ref domain,
+                class,
+                ref algorithm,
+                time_signed,
+                fudge,
+                ref mac,
+                original_id,
+                error,
+                ref other,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::TSIG.to_number())?;
+                buffer.write_u16(class.to_number())?;
+                buffer.write_u32(ttl)?;
+
+                let position = buffer.position();
+                buffer.write_u16(0)?;
+
+                buffer.write_qname(algorithm)?;
+                buffer.write_u16((time_signed >> 32) as u16)?;
+                buffer.write_u32(time_signed as u32)?;
+                buffer.write_u16(fudge)?;
+                buffer.write_u16(mac.len() as u16)?;
+                for byte in mac {
+                    buffer.write_u8(*byte)?;
+                }
+                buffer.write_u16(original_id)?;
+                buffer.write_u16(error.to_number())?;
+                buffer.write_u16(other.len() as u16)?;
+                for byte in other {
+                    buffer.write_u8(*byte)?;
+                }
+
+                let size = buffer.position() - (position + 2);
+                buffer.set_u16(position, size as u16)?;
+            }
+            DnsRecord::UNKNOWN {
+                ref domain,
+                class,
+                qtype,
+                ref data,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(qtype)?;
+                buffer.write_u16(class.to_number())?;
+                buffer.write_u32(ttl)?;
+                buffer.write_u16(data.len() as u16)?;
+
+                for byte in data {
+                    buffer.write_u8(*byte)?;
+                }
             }
         }
 
@@ -421,7 +1166,8 @@ impl DnsRecord {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct DnsPacket {
     pub header: DnsHeader,
     pub questions: Vec<DnsQuestion>,
@@ -430,6 +1176,12 @@ pub struct DnsPacket {
     pub resources: Vec<DnsRecord>,
 }
 
+impl Default for DnsPacket {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl DnsPacket {
     pub fn new() -> DnsPacket {
         DnsPacket {
@@ -442,31 +1194,135 @@ impl DnsPacket {
     }
 
     pub fn from_buffer(buffer: &mut PacketBuffer) -> crate::Result<DnsPacket> {
+        DnsPacket::parse(buffer, None)
+    }
+
+    /// Like `from_buffer`, but records with malformed RDATA are skipped
+    /// instead of failing the whole packet. The errors of the skipped records
+    /// are returned alongside the packet.
+    pub fn from_buffer_lenient(
+        buffer: &mut PacketBuffer,
+    ) -> crate::Result<(DnsPacket, Vec<DnsError>)> {
+        let mut skipped = Vec::new();
+        let packet = DnsPacket::parse(buffer, Some(&mut skipped))?;
+
+        Ok((packet, skipped))
+    }
+
+    fn parse(
+        buffer: &mut PacketBuffer,
+        mut skipped: Option<&mut Vec<DnsError>>,
+    ) -> crate::Result<DnsPacket> {
         let mut result = DnsPacket::new();
+        buffer.location = Location::default();
         result.header.read(buffer)?;
+        result.header.check_counts(buffer)?;
 
-        for _ in 0..result.header.questions {
-            let mut question = DnsQuestion::new("".to_string(), QueryType::UNKNOWN(0));
+        for index in 0..result.header.questions as usize {
+            buffer.location = Location::new(Section::Question, index);
+            let mut question = DnsQuestion::new(Name::root(), QueryType::UNKNOWN(0));
             question.read(buffer)?;
             result.questions.push(question);
         }
 
-        for _ in 0..result.header.answers {
-            let record = DnsRecord::read(buffer)?;
-            result.answers.push(record);
-        }
-        for _ in 0..result.header.authoritative_entries {
-            let record = DnsRecord::read(buffer)?;
-            result.authorities.push(record);
-        }
-        for _ in 0..result.header.resource_entries {
-            let record = DnsRecord::read(buffer)?;
-            result.resources.push(record);
+        let sections = [
+            (Section::Answer, result.header.answers),
+            (Section::Authority, result.header.authoritative_entries),
+            (Section::Additional, result.header.resource_entries),
+        ];
+        for (section, count) in sections {
+            for index in 0..count as usize {
+                buffer.location = Location::new(section, index);
+                let record = match DnsRecord::read_checked(buffer)? {
+                    Ok(record) => record,
+                    Err((error, record_end)) => match skipped.as_deref_mut() {
+                        Some(skipped) => {
+                            skipped.push(error);
+                            buffer.seek(record_end)?;
+                            continue;
+                        }
+                        None => return Err(error),
+                    },
+                };
+
+                match section {
+                    Section::Answer => result.answers.push(record),
+                    Section::Authority => result.authorities.push(record),
+                    _ => result.resources.push(record),
+                }
+            }
         }
 
         Ok(result)
     }
 
+    /// The EDNS OPT pseudo-record, if the message carries one.
+    pub fn edns(&self) -> Option<&DnsRecord> {
+        self.resources
+            .iter()
+            .find(|record| matches!(record, DnsRecord::OPT { .. }))
+    }
+
+    /// The full response code, combining the header bits with the extended
+    /// bits of the OPT record.
+    pub fn rcode(&self) -> ResultCode {
+        let extended = match self.edns() {
+            Some(DnsRecord::OPT { extended_rcode, .. }) => *extended_rcode as u16,
+            _ => 0,
+        };
+
+        ResultCode::from_number((extended << 4) | (self.header.rescode.to_number() & 0x0F))
+    }
+
+    /// Sets the full response code, adding an OPT record when the code does
+    /// not fit in the header alone.
+    pub fn set_rcode(&mut self, rcode: ResultCode) {
+        let value = rcode.to_number();
+        self.header.rescode = ResultCode::from_number(value & 0x0F);
+
+        let extended = (value >> 4) as u8;
+        let opt = self
+            .resources
+            .iter_mut()
+            .find(|record| matches!(record, DnsRecord::OPT { .. }));
+        match opt {
+            Some(DnsRecord::OPT { extended_rcode, .. }) => *extended_rcode = extended,
+            _ if extended != 0 => self.resources.push(DnsRecord::OPT {
+                udp_payload_size: 512,
+                extended_rcode: extended,
+                version: 0,
+                flags: 0,
+                options: Vec::new(),
+            }),
+            _ => {}
+        }
+    }
+
+    /// The NS records of a referral towards `qname`: those in the authority
+    /// section owned by `qname` or one of its ancestors.
+    pub fn referral_ns<'a>(&'a self, qname: &'a Name) -> impl Iterator<Item = &'a DnsRecord> {
+        self.authorities.iter().filter(move |record| {
+            matches!(record, DnsRecord::NS { domain, .. } if qname.is_subdomain_of(domain))
+        })
+    }
+
+    /// The addresses of `host` in the additional section, the glue sent
+    /// along with a referral.
+    pub fn glue(&self, host: &Name) -> Vec<IpAddr> {
+        self.resources
+            .iter()
+            .filter_map(|record| match record {
+                DnsRecord::A {
+                    domain, address, ..
+                } if domain == host => Some(IpAddr::V4(*address)),
+                DnsRecord::AAAA {
+                    domain, address, ..
+                } if domain == host => Some(IpAddr::V6(*address)),
+                _ => None,
+            })
+            .collect()
+    }
+
     pub fn write(&mut self, buffer: &mut PacketBuffer) -> crate::Result<()> {
         self.header.questions = self.questions.len() as u16;
         self.header.answers = self.answers.len() as u16;
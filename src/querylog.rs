@@ -0,0 +1,340 @@
+//! Per-query logging of the answers a server gives, as JSON lines or as
+//! one line of text per query.
+//!
+//! Logs written to files can be rotated once they reach a size, and client
+//! addresses can be anonymized by dropping their host part.
+
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::batch::json_string;
+use crate::pcap::Transport;
+use crate::protocol::{DnsPacket, DnsQuestion, ResultCode};
+use crate::DnsError;
+
+/// How log lines are written.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// One JSON object per line.
+    Json,
+    /// Space separated fields: time, client, transport, name, type, rcode,
+    /// answer count and latency.
+    Text,
+}
+
+/// When a log file is rotated, and how many rotated files are kept.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Rotation {
+    /// Size past which the file is rotated.
+    pub max_bytes: u64,
+    /// Rotated files kept as `path.1` to `path.N`, the oldest discarded.
+    pub keep: usize,
+}
+
+/// One answered query.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QueryLogEntry {
+    pub time: SystemTime,
+    pub client: IpAddr,
+    pub transport: Transport,
+    /// `None` for requests without a question.
+    pub question: Option<DnsQuestion>,
+    pub rcode: ResultCode,
+    pub answers: usize,
+    /// Time from receiving the query to answering it.
+    pub latency: Duration,
+}
+
+impl QueryLogEntry {
+    /// The entry for `response`, sent to `client` at `time`.
+    pub fn new(
+        time: SystemTime,
+        client: IpAddr,
+        transport: Transport,
+        response: &DnsPacket,
+        latency: Duration,
+    ) -> QueryLogEntry {
+        QueryLogEntry {
+            time,
+            client,
+            transport,
+            question: response.questions.first().cloned(),
+            rcode: response.rcode(),
+            answers: response.answers.len(),
+            latency,
+        }
+    }
+
+    /// Writes the entry as a single line JSON object.
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{");
+        write!(json, "\"time\":\"{}\",", rfc3339(self.time)).ok();
+        write!(json, "\"client\":\"{}\",", self.client).ok();
+        write!(
+            json,
+            "\"transport\":\"{}\",",
+            transport_name(self.transport)
+        )
+        .ok();
+        match &self.question {
+            Some(question) => write!(
+                json,
+                "\"name\":{},\"type\":\"{}\",",
+                json_string(&question.name.to_string()),
+                question.qtype
+            )
+            .ok(),
+            None => write!(json, "\"name\":null,\"type\":null,").ok(),
+        };
+        write!(json, "\"rcode\":\"{}\",", self.rcode).ok();
+        write!(json, "\"answers\":{},", self.answers).ok();
+        write!(json, "\"latency_ms\":{:.3}", self.latency_ms()).ok();
+        json.push('}');
+
+        json
+    }
+
+    /// Writes the entry as a line of space separated fields, `-` standing
+    /// for a missing question.
+    pub fn to_text(&self) -> String {
+        let (name, qtype) = match &self.question {
+            Some(question) => (question.name.to_string(), question.qtype.to_string()),
+            None => ("-".to_string(), "-".to_string()),
+        };
+
+        format!(
+            "{} {} {} {} {} {} {} {:.3}ms",
+            rfc3339(self.time),
+            self.client,
+            transport_name(self.transport),
+            name,
+            qtype,
+            self.rcode,
+            self.answers,
+            self.latency_ms()
+        )
+    }
+
+    fn latency_ms(&self) -> f64 {
+        self.latency.as_secs_f64() * 1000.0
+    }
+}
+
+fn transport_name(transport: Transport) -> &'static str {
+    match transport {
+        Transport::Udp => "udp",
+        Transport::Tcp => "tcp",
+    }
+}
+
+/// Formats `time` as an RFC 3339 UTC timestamp with milliseconds.
+fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (days, seconds_of_day) = (seconds / 86400, seconds % 86400);
+
+    // Civil date from days since the epoch, counting in 400 year eras that
+    // start on the 1st of March
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Drops the host part of `address`, keeping the /24 of IPv4 addresses and
+/// the /48 of IPv6 ones.
+pub fn anonymize(address: IpAddr) -> IpAddr {
+    match address {
+        IpAddr::V4(address) => {
+            let [a, b, c, _] = address.octets();
+            IpAddr::V4(Ipv4Addr::new(a, b, c, 0))
+        }
+        IpAddr::V6(address) => {
+            let segments = address.segments();
+            IpAddr::V6(Ipv6Addr::new(
+                segments[0],
+                segments[1],
+                segments[2],
+                0,
+                0,
+                0,
+                0,
+                0,
+            ))
+        }
+    }
+}
+
+struct LogFile {
+    path: PathBuf,
+    rotation: Rotation,
+    written: u64,
+}
+
+struct Sink {
+    writer: BufWriter<Box<dyn Write + Send>>,
+    /// Set for files, which may be rotated.
+    file: Option<LogFile>,
+}
+
+impl Sink {
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        let length = line.len() as u64 + 1;
+        if let Some(file) = &self.file {
+            if file.written > 0 && file.written + length > file.rotation.max_bytes {
+                self.rotate()?;
+            }
+        }
+
+        self.writer.write_all(line.as_bytes())?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()?;
+        if let Some(file) = &mut self.file {
+            file.written += length;
+        }
+
+        Ok(())
+    }
+
+    /// Shifts `path.N-1` to `path.N` down to `path` to `path.1`, and starts
+    /// a new file at `path`.
+    fn rotate(&mut self) -> std::io::Result<()> {
+        let Some(file) = &mut self.file else {
+            return Ok(());
+        };
+        self.writer.flush()?;
+
+        let rotated = |index: usize| {
+            let mut path = file.path.clone().into_os_string();
+            path.push(format!(".{}", index));
+            PathBuf::from(path)
+        };
+        if file.rotation.keep > 0 {
+            for index in (1..file.rotation.keep).rev() {
+                if rotated(index).exists() {
+                    fs::rename(rotated(index), rotated(index + 1))?;
+                }
+            }
+            fs::rename(&file.path, rotated(1))?;
+        }
+
+        let boxed: Box<dyn Write + Send> = Box::new(File::create(&file.path)?);
+        self.writer = BufWriter::new(boxed);
+        file.written = 0;
+
+        Ok(())
+    }
+}
+
+/// Writes a `QueryLogEntry` for every answered query. Clones share the
+/// writer.
+///
+/// Only one query in every `sample` is logged, and client addresses are
+/// passed through `anonymize` when `anonymize` is set. Failing to log never
+/// affects answering, lines that cannot be written are lost.
+#[derive(Clone)]
+pub struct QueryLog {
+    sink: Arc<Mutex<Sink>>,
+    queries: Arc<AtomicU64>,
+    pub format: LogFormat,
+    pub sample: u64,
+    pub anonymize: bool,
+}
+
+impl QueryLog {
+    /// Appends to the file at `path`, rotating it as set by `rotation`.
+    pub fn to_file(path: impl AsRef<Path>, rotation: Option<Rotation>) -> crate::Result<QueryLog> {
+        let path = path.as_ref();
+        let log_error = |source| DnsError::QueryLogIO { source };
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(log_error)?;
+        let written = file.metadata().map_err(log_error)?.len();
+
+        let boxed: Box<dyn Write + Send> = Box::new(file);
+        Ok(QueryLog::new(Sink {
+            writer: BufWriter::new(boxed),
+            file: rotation.map(|rotation| LogFile {
+                path: path.to_path_buf(),
+                rotation,
+                written,
+            }),
+        }))
+    }
+
+    /// Writes to `writer`, e.g. standard output.
+    pub fn to_writer<W: Write + Send + 'static>(writer: W) -> QueryLog {
+        let boxed: Box<dyn Write + Send> = Box::new(writer);
+        QueryLog::new(Sink {
+            writer: BufWriter::new(boxed),
+            file: None,
+        })
+    }
+
+    fn new(sink: Sink) -> QueryLog {
+        QueryLog {
+            sink: Arc::new(Mutex::new(sink)),
+            queries: Arc::new(AtomicU64::new(0)),
+            format: LogFormat::Json,
+            sample: 1,
+            anonymize: false,
+        }
+    }
+
+    /// Logs `entry`, unless sampling skips it.
+    pub fn log(&self, entry: &QueryLogEntry) {
+        let count = self.queries.fetch_add(1, Ordering::Relaxed);
+        if !count.is_multiple_of(self.sample.max(1)) {
+            return;
+        }
+
+        let line = if self.anonymize {
+            let entry = QueryLogEntry {
+                client: anonymize(entry.client),
+                ..entry.clone()
+            };
+            self.format_entry(&entry)
+        } else {
+            self.format_entry(entry)
+        };
+
+        let mut sink = self.sink.lock().unwrap_or_else(|error| error.into_inner());
+        let _ = sink.write_line(&line);
+    }
+
+    fn format_entry(&self, entry: &QueryLogEntry) -> String {
+        match self.format {
+            LogFormat::Json => entry.to_json(),
+            LogFormat::Text => entry.to_text(),
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::client::DnsClient;
+use crate::name::Name;
+use crate::notify::Notify;
+use crate::protocol::{DnsClass, DnsRecord, QueryType};
+use crate::server::DnsServer;
+use crate::transfer::TransferClient;
+use crate::tsig::TsigKey;
+use crate::zone::{serial_greater, Zone};
+use crate::DnsError;
+
+/// Delay between attempts to load a zone that was never transferred, when
+/// there is no SOA to take the retry timer from yet.
+const INITIAL_RETRY: Duration = Duration::from_secs(60);
+
+/// Longest the manager sleeps without checking its timers.
+const MAX_IDLE: Duration = Duration::from_secs(60);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ZoneState {
+    /// Checked against the primary within the refresh interval.
+    Fresh,
+    /// Refresh due or failing, the zone is still served until it expires.
+    Refreshing,
+    /// Never loaded, or not refreshed within the expire interval. The zone
+    /// is not served.
+    Expired,
+}
+
+/// A zone kept in sync with its primary, and the timers driving it.
+#[derive(Clone, Debug)]
+pub struct SecondaryZone {
+    pub origin: Name,
+    pub class: DnsClass,
+    pub primary: SocketAddr,
+    pub tsig: Option<TsigKey>,
+    pub state: ZoneState,
+    pub serial: Option<u32>,
+    /// When the primary was last reached and the zone found up to date.
+    pub last_refresh: Option<Instant>,
+    pub next_refresh: Instant,
+}
+
+impl SecondaryZone {
+    pub fn new(
+        origin: Name,
+        class: DnsClass,
+        primary: SocketAddr,
+        tsig: Option<TsigKey>,
+    ) -> SecondaryZone {
+        SecondaryZone {
+            origin,
+            class,
+            primary,
+            tsig,
+            state: ZoneState::Expired,
+            serial: None,
+            last_refresh: None,
+            next_refresh: Instant::now(),
+        }
+    }
+}
+
+/// Keeps secondary zones in the catalog of a server in sync with their
+/// primaries, following the SOA refresh, retry and expire timers (RFC 1034
+/// section 4.3.5) and refreshing early when a primary sends a NOTIFY.
+pub struct SecondaryManager {
+    server: DnsServer,
+    zones: Mutex<BTreeMap<(Name, DnsClass), SecondaryZone>>,
+    notifications: Mutex<Receiver<Notify>>,
+}
+
+impl SecondaryManager {
+    /// Creates a manager for secondary zones served by `server`, which is
+    /// set up to pass received NOTIFY messages on to it.
+    pub fn new(server: &mut DnsServer) -> SecondaryManager {
+        let (sender, receiver) = mpsc::channel();
+        server.notify = Some(sender);
+
+        SecondaryManager {
+            server: server.clone(),
+            zones: Mutex::new(BTreeMap::new()),
+            notifications: Mutex::new(receiver),
+        }
+    }
+
+    /// Adds a zone, to be transferred on the next `tick`.
+    pub fn add_zone(&self, zone: SecondaryZone) {
+        self.lock().insert((zone.origin.clone(), zone.class), zone);
+    }
+
+    pub fn state(&self, origin: &Name, class: DnsClass) -> Option<ZoneState> {
+        self.lock()
+            .get(&(origin.clone(), class))
+            .map(|zone| zone.state)
+    }
+
+    /// A snapshot of every zone and its timers, for monitoring.
+    pub fn zones(&self) -> Vec<SecondaryZone> {
+        self.lock().values().cloned().collect()
+    }
+
+    /// Runs the timers, and refreshes zones as NOTIFY messages arrive.
+    pub fn run(&self) {
+        loop {
+            self.tick(Instant::now());
+
+            let wait = self
+                .next_refresh()
+                .map(|next| next.saturating_duration_since(Instant::now()))
+                .unwrap_or(MAX_IDLE)
+                .min(MAX_IDLE);
+            let received = self
+                .notifications
+                .lock()
+                .unwrap_or_else(|error| error.into_inner())
+                .recv_timeout(wait);
+            match received {
+                Ok(notify) => self.handle_notify(&notify, Instant::now()),
+                Err(RecvTimeoutError::Timeout) => {}
+                // The server is gone, only the timers are left
+                Err(RecvTimeoutError::Disconnected) => thread::sleep(wait),
+            }
+        }
+    }
+
+    /// Makes the zone announced by `notify` due for a refresh, if it came
+    /// from the primary of the zone.
+    pub fn handle_notify(&self, notify: &Notify, now: Instant) {
+        let mut zones = self.lock();
+        let Some(zone) = zones.get_mut(&(notify.origin.clone(), notify.class)) else {
+            return;
+        };
+        if zone.primary.ip() != notify.source.ip() {
+            return;
+        }
+
+        zone.next_refresh = now;
+    }
+
+    /// Refreshes every zone whose timer is due at `now`.
+    pub fn tick(&self, now: Instant) {
+        let due: Vec<SecondaryZone> = {
+            let mut zones = self.lock();
+            zones
+                .values_mut()
+                .filter(|zone| zone.next_refresh <= now)
+                .map(|zone| {
+                    if zone.state == ZoneState::Fresh {
+                        zone.state = ZoneState::Refreshing;
+                    }
+                    zone.clone()
+                })
+                .collect()
+        };
+
+        // The network is only used without holding the lock, so that the
+        // state can still be monitored during slow transfers
+        for zone in due {
+            let result = self.refresh(&zone);
+            self.finish_refresh(&zone.origin, zone.class, result, now);
+        }
+    }
+
+    fn next_refresh(&self) -> Option<Instant> {
+        self.lock().values().map(|zone| zone.next_refresh).min()
+    }
+
+    /// Checks the serial of the primary and transfers the zone when it is
+    /// newer, returning the SOA of the zone now held.
+    fn refresh(&self, secondary: &SecondaryZone) -> crate::Result<DnsRecord> {
+        let current = self
+            .server
+            .catalog
+            .read()
+            .unwrap_or_else(|error| error.into_inner())
+            .get(&secondary.origin, secondary.class)
+            .cloned();
+
+        let mut client = DnsClient::new(secondary.primary);
+        client.tsig = secondary.tsig.clone();
+        let response = client.query_class(&secondary.origin, QueryType::SOA, secondary.class)?;
+        let primary_serial = response.answers.iter().find_map(|record| match record {
+            DnsRecord::SOA { serial, .. } => Some(*serial),
+            _ => None,
+        });
+
+        // Serials compare in serial number arithmetic, a primary that went
+        // backwards is not followed
+        let held = current
+            .as_ref()
+            .and_then(|zone| Some((zone.serial()?, zone.soa()?)));
+        if let (Some(primary), Some((serial, soa))) = (primary_serial, held) {
+            if !serial_greater(primary, serial) {
+                return Ok(soa.clone());
+            }
+        }
+
+        let mut transfer = TransferClient::new(secondary.primary);
+        transfer.tsig = secondary.tsig.clone();
+        let mut zone =
+            current.unwrap_or_else(|| Zone::new(secondary.origin.clone(), secondary.class));
+        let changed = transfer.refresh(&mut zone)?;
+        let soa = zone.soa().cloned().ok_or(DnsError::MalformedTransfer)?;
+
+        if changed {
+            let mut catalog = self
+                .server
+                .catalog
+                .write()
+                .unwrap_or_else(|error| error.into_inner());
+            let previous_serial = catalog
+                .get(&secondary.origin, secondary.class)
+                .and_then(Zone::serial);
+            self.server.record_change(previous_serial, &zone)?;
+            self.server.notify_changed(&zone);
+            catalog.insert(zone);
+        }
+
+        Ok(soa)
+    }
+
+    /// Updates the state and timers of the zone at `origin` after a refresh
+    /// attempt started at `now`.
+    fn finish_refresh(
+        &self,
+        origin: &Name,
+        class: DnsClass,
+        result: crate::Result<DnsRecord>,
+        now: Instant,
+    ) {
+        let mut zones = self.lock();
+        let Some(zone) = zones.get_mut(&(origin.clone(), class)) else {
+            return;
+        };
+
+        match result {
+            Ok(DnsRecord::SOA {
+                serial, refresh, ..
+            }) => {
+                zone.state = ZoneState::Fresh;
+                zone.serial = Some(serial);
+                zone.last_refresh = Some(now);
+                zone.next_refresh = now + Duration::from_secs(refresh as u64);
+            }
+            _ => {
+                let timers = self
+                    .server
+                    .catalog
+                    .read()
+                    .unwrap_or_else(|error| error.into_inner())
+                    .get(origin, class)
+                    .and_then(|held| held.soa().cloned());
+                let (retry, expire) = match timers {
+                    Some(DnsRecord::SOA { retry, expire, .. }) => (
+                        Duration::from_secs(retry as u64),
+                        Duration::from_secs(expire as u64),
+                    ),
+                    _ => (INITIAL_RETRY, Duration::ZERO),
+                };
+
+                zone.next_refresh = now + retry;
+                let expired = zone
+                    .last_refresh
+                    .is_none_or(|last_refresh| now >= last_refresh + expire);
+                if expired {
+                    zone.state = ZoneState::Expired;
+                    self.server
+                        .catalog
+                        .write()
+                        .unwrap_or_else(|error| error.into_inner())
+                        .remove(origin, class);
+                } else {
+                    zone.state = ZoneState::Refreshing;
+                }
+            }
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<(Name, DnsClass), SecondaryZone>> {
+        self.zones.lock().unwrap_or_else(|error| error.into_inner())
+    }
+}
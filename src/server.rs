@@ -0,0 +1,824 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::acl::Acl;
+use crate::buffer::{PacketBuffer, MAX_MESSAGE_LENGTH, UDP_MESSAGE_LENGTH};
+use crate::dnstap::{Dnstap, DnstapMessage, MessageType};
+use crate::journal::Journal;
+use crate::metrics::Metrics;
+use crate::name::Name;
+use crate::notify::{send_notify, Notify};
+use crate::pcap::{PcapWriter, Transport};
+use crate::protocol::{DnsClass, DnsPacket, DnsRecord, Opcode, QueryType, ResultCode};
+use crate::querylog::{QueryLog, QueryLogEntry};
+use crate::transfer::{read_tcp_message, transfer_messages, transfer_records, write_tcp_bytes};
+use crate::tsig::{tsig_record, unix_time, TsigKey, TsigSigner, TsigVerifier};
+use crate::update::{apply_update, update_response};
+use crate::zone::{Lookup, Zone};
+use crate::DnsError;
+
+/// How long a TCP connection may sit idle between requests before the server
+/// closes it, unless set otherwise.
+pub const DEFAULT_TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where a server records its traffic.
+type Capture = PcapWriter<Box<dyn Write + Send>>;
+
+/// The zones a server is authoritative for, keyed by origin and class.
+#[derive(Clone, Debug, Default)]
+pub struct Catalog {
+    zones: BTreeMap<(Name, DnsClass), Zone>,
+}
+
+impl Catalog {
+    pub fn new() -> Catalog {
+        Catalog {
+            zones: BTreeMap::new(),
+        }
+    }
+
+    /// Adds `zone`, replacing any zone with the same origin and class.
+    pub fn insert(&mut self, zone: Zone) {
+        self.zones.insert((zone.origin.clone(), zone.class), zone);
+    }
+
+    pub fn get(&self, origin: &Name, class: DnsClass) -> Option<&Zone> {
+        self.zones.get(&(origin.clone(), class))
+    }
+
+    pub fn get_mut(&mut self, origin: &Name, class: DnsClass) -> Option<&mut Zone> {
+        self.zones.get_mut(&(origin.clone(), class))
+    }
+
+    pub fn remove(&mut self, origin: &Name, class: DnsClass) -> Option<Zone> {
+        self.zones.remove(&(origin.clone(), class))
+    }
+
+    /// The most specific zone of `class` containing `name`.
+    pub fn find(&self, name: &Name, class: DnsClass) -> Option<&Zone> {
+        let mut candidate = Some(name.clone());
+        while let Some(origin) = candidate {
+            if let Some(zone) = self.get(&origin, class) {
+                return Some(zone);
+            }
+            candidate = origin.parent();
+        }
+
+        None
+    }
+
+    pub fn zones(&self) -> impl Iterator<Item = &Zone> {
+        self.zones.values()
+    }
+}
+
+/// An authoritative server answering queries from, and applying dynamic
+/// updates to, the zones of its catalog.
+///
+/// Requests signed with one of `keys` get signed responses. Once any key is
+/// configured, dynamic updates and zone transfers are refused unless signed.
+///
+/// Received NOTIFY messages are passed on to `notify`, usually the channel of
+/// a `SecondaryManager`, and every change to a zone is announced to the
+/// secondaries in `also_notify`.
+///
+/// Zones opened with `open_journal` have every change written to their
+/// journal before it is served.
+///
+/// A server set to `capture_to` a writer records every message it receives
+/// and sends there, in pcap format. With `dnstap` set, the same messages
+/// are logged there as CLIENT_QUERY and CLIENT_RESPONSE events.
+///
+/// With `metrics` set, every request is counted there by type, rcode and
+/// transport, along with those that are malformed or go unanswered. With
+/// `query_log` set, every answer is logged there.
+///
+/// Requests from clients outside the networks of `acl` for their kind are
+/// refused, even when signed; by default only loopback clients may transfer
+/// or update zones. Connections past `max_tcp_connections` are closed as soon as
+/// they are accepted.
+#[derive(Clone)]
+pub struct DnsServer {
+    pub catalog: Arc<RwLock<Catalog>>,
+    pub keys: Vec<TsigKey>,
+    pub notify: Option<Sender<Notify>>,
+    pub also_notify: Vec<SocketAddr>,
+    pub dnstap: Option<Dnstap>,
+    pub metrics: Option<Metrics>,
+    pub query_log: Option<QueryLog>,
+    pub acl: Acl,
+    pub tcp_idle_timeout: Duration,
+    pub max_tcp_connections: Option<usize>,
+    journals: Arc<Mutex<BTreeMap<(Name, DnsClass), Journal>>>,
+    capture: Option<Arc<Mutex<Capture>>>,
+    connections: Arc<AtomicUsize>,
+}
+
+impl DnsServer {
+    pub fn new(catalog: Catalog) -> DnsServer {
+        DnsServer {
+            catalog: Arc::new(RwLock::new(catalog)),
+            keys: Vec::new(),
+            notify: None,
+            also_notify: Vec::new(),
+            dnstap: None,
+            metrics: None,
+            query_log: None,
+            acl: Acl::new(),
+            tcp_idle_timeout: DEFAULT_TCP_IDLE_TIMEOUT,
+            max_tcp_connections: None,
+            journals: Arc::new(Mutex::new(BTreeMap::new())),
+            capture: None,
+            connections: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Loads the zone at `origin` from the journal at `path`, and keeps the
+    /// journal up to date with every later change to the zone.
+    ///
+    /// A zone already in the catalog seeds a journal that holds no history
+    /// yet, otherwise the zone replayed from the journal replaces it.
+    pub fn open_journal(
+        &self,
+        origin: Name,
+        class: DnsClass,
+        path: impl AsRef<Path>,
+    ) -> crate::Result<()> {
+        let mut journal = Journal::open(path)?;
+        let loaded = journal.load(origin.clone(), class)?;
+
+        let mut catalog = self
+            .catalog
+            .write()
+            .unwrap_or_else(|error| error.into_inner());
+        match catalog.get(&origin, class) {
+            Some(zone) if loaded.soa().is_none() => journal.compact(zone)?,
+            _ if loaded.soa().is_some() => catalog.insert(loaded),
+            _ => {}
+        }
+
+        self.journals
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .insert((origin, class), journal);
+
+        Ok(())
+    }
+
+    /// Writes the changes to `zone` since `previous_serial` to its journal,
+    /// if it has one. Callers hold the catalog lock, so that changes reach
+    /// the journal in order.
+    pub fn record_change(&self, previous_serial: Option<u32>, zone: &Zone) -> crate::Result<()> {
+        let mut journals = self
+            .journals
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+
+        match journals.get_mut(&(zone.origin.clone(), zone.class)) {
+            Some(journal) => journal.record(previous_serial, zone),
+            None => Ok(()),
+        }
+    }
+
+    /// Records the traffic of the server to `writer` as a pcap capture,
+    /// starting with the file header.
+    pub fn capture_to<W: Write + Send + 'static>(&mut self, writer: W) -> crate::Result<()> {
+        let writer: Box<dyn Write + Send> = Box::new(writer);
+        self.capture = Some(Arc::new(Mutex::new(PcapWriter::new(writer)?)));
+
+        Ok(())
+    }
+
+    /// Records a request from `client` to the capture and dnstap log,
+    /// returning the time it was received.
+    fn received(
+        &self,
+        transport: Transport,
+        client: SocketAddr,
+        local: SocketAddr,
+        message: &[u8],
+    ) -> SystemTime {
+        let now = SystemTime::now();
+        self.capture(now, transport, client, local, message);
+        if let Some(dnstap) = &self.dnstap {
+            let kind = MessageType::ClientQuery;
+            dnstap.log(&DnstapMessage::query(
+                kind, transport, client, local, now, message,
+            ));
+        }
+
+        now
+    }
+
+    /// Records a response to `client` for a request received at
+    /// `query_time`.
+    fn sent(
+        &self,
+        transport: Transport,
+        client: SocketAddr,
+        local: SocketAddr,
+        query_time: SystemTime,
+        message: &[u8],
+    ) {
+        let now = SystemTime::now();
+        self.capture(now, transport, local, client, message);
+        if let Some(dnstap) = &self.dnstap {
+            dnstap.log(&DnstapMessage::response(
+                MessageType::ClientResponse,
+                transport,
+                client,
+                local,
+                Some(query_time),
+                now,
+                message,
+            ));
+        }
+    }
+
+    /// Counts and logs `response`, sent to `client` in answer to a request
+    /// received at `received`.
+    fn answered(
+        &self,
+        transport: Transport,
+        client: SocketAddr,
+        response: &DnsPacket,
+        received: SystemTime,
+    ) {
+        let now = SystemTime::now();
+        let latency = now.duration_since(received).unwrap_or_default();
+        if let Some(metrics) = &self.metrics {
+            metrics.record_query(transport, response, latency);
+        }
+        if let Some(query_log) = &self.query_log {
+            let entry = QueryLogEntry::new(now, client.ip(), transport, response, latency);
+            query_log.log(&entry);
+        }
+    }
+
+    fn record(&self, record: impl FnOnce(&Metrics)) {
+        if let Some(metrics) = &self.metrics {
+            record(metrics);
+        }
+    }
+
+    fn capture(
+        &self,
+        time: SystemTime,
+        transport: Transport,
+        source: SocketAddr,
+        destination: SocketAddr,
+        message: &[u8],
+    ) {
+        let Some(capture) = &self.capture else {
+            return;
+        };
+
+        let mut capture = capture.lock().unwrap_or_else(|error| error.into_inner());
+        let written = match transport {
+            Transport::Udp => capture.write_udp(time, source, destination, message),
+            Transport::Tcp => capture.write_tcp(time, source, destination, message),
+        };
+        // Failing to capture does not affect serving
+        let _ = written.and_then(|()| capture.flush());
+    }
+
+    /// Sends a NOTIFY for `zone` to every secondary in `also_notify`, in the
+    /// background.
+    pub fn notify_changed(&self, zone: &Zone) {
+        for target in self.also_notify.iter().copied() {
+            let zone = zone.clone();
+            // Unreachable secondaries still catch up on their refresh timer
+            thread::spawn(move || send_notify(&zone, target, None));
+        }
+    }
+
+    /// Answers every datagram received on `socket`, until reading from it
+    /// fails.
+    pub fn serve_udp(&self, socket: &UdpSocket) -> crate::Result<()> {
+        let local = socket
+            .local_addr()
+            .map_err(|source| DnsError::SocketIO { source })?;
+
+        loop {
+            let mut request_buffer = PacketBuffer::with_capacity(MAX_MESSAGE_LENGTH);
+            let (length, source) = socket
+                .recv_from(&mut request_buffer.buffer)
+                .map_err(|source| DnsError::SocketIO { source })?;
+            request_buffer.length = length;
+            let _in_flight = self.metrics.as_ref().map(Metrics::start_query);
+            let received = self.received(
+                Transport::Udp,
+                source,
+                local,
+                &request_buffer.buffer[..length],
+            );
+
+            let Some(mut response) = self.handle_buffer(&mut request_buffer, source) else {
+                self.record(Metrics::record_dropped);
+                continue;
+            };
+
+            // The response was already cut down to what the client takes
+            let mut response_buffer = PacketBuffer::with_capacity(MAX_MESSAGE_LENGTH);
+            if response.write(&mut response_buffer).is_err() {
+                response = truncated(&response);
+                response_buffer = PacketBuffer::new();
+                response.write(&mut response_buffer)?;
+            }
+
+            // A failed send only affects this client
+            let message = &response_buffer.buffer[..response_buffer.position];
+            match socket.send_to(message, source) {
+                Ok(_) => self.answered(Transport::Udp, source, &response, received),
+                Err(_) => self.record(Metrics::record_dropped),
+            }
+            self.sent(Transport::Udp, source, local, received, message);
+        }
+    }
+
+    /// Accepts connections on `listener`, serving each on its own thread,
+    /// until accepting fails.
+    pub fn serve_tcp(&self, listener: &TcpListener) -> crate::Result<()> {
+        loop {
+            let (stream, _) = listener
+                .accept()
+                .map_err(|source| DnsError::SocketIO { source })?;
+
+            let connections = self.connections.load(Ordering::Relaxed);
+            if self
+                .max_tcp_connections
+                .is_some_and(|max| connections >= max)
+            {
+                // Dropping the stream closes it
+                continue;
+            }
+
+            self.connections.fetch_add(1, Ordering::Relaxed);
+            let server = self.clone();
+            thread::spawn(move || {
+                let result = server.handle_connection(stream);
+                server.connections.fetch_sub(1, Ordering::Relaxed);
+                result
+            });
+        }
+    }
+
+    /// Answers the requests of one TCP connection, until the client closes
+    /// it, it idles for too long or a malformed message arrives.
+    fn handle_connection(&self, mut stream: TcpStream) -> crate::Result<()> {
+        stream
+            .set_read_timeout(Some(self.tcp_idle_timeout))
+            .map_err(|source| DnsError::SocketIO { source })?;
+        let source = stream
+            .peer_addr()
+            .map_err(|source| DnsError::SocketIO { source })?;
+        let local = stream
+            .local_addr()
+            .map_err(|source| DnsError::SocketIO { source })?;
+
+        while let Some(bytes) = read_tcp_message(&mut stream)? {
+            let _in_flight = self.metrics.as_ref().map(Metrics::start_query);
+            let received = self.received(Transport::Tcp, source, local, &bytes);
+
+            let mut buffer = PacketBuffer::from_bytes(&bytes)?;
+            let responses = self.handle_message(&mut buffer, source, true);
+            match responses.first() {
+                Some(response) => self.answered(Transport::Tcp, source, response, received),
+                None => self.record(Metrics::record_dropped),
+            }
+            for mut response in responses {
+                let mut response_buffer = PacketBuffer::with_capacity(MAX_MESSAGE_LENGTH);
+                response.write(&mut response_buffer)?;
+
+                let message = &response_buffer.buffer[..response_buffer.position];
+                write_tcp_bytes(&mut stream, message)?;
+                self.sent(Transport::Tcp, source, local, received, message);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Parses and handles a UDP message received from `source`. Malformed
+    /// requests get a FORMERR when at least their ID could be read, responses
+    /// are ignored, malformed or not. Answers larger than the payload size
+    /// the client advertises over EDNS, or 512 bytes without it, come back
+    /// truncated.
+    pub fn handle_buffer(
+        &self,
+        buffer: &mut PacketBuffer,
+        source: SocketAddr,
+    ) -> Option<DnsPacket> {
+        self.handle_message(buffer, source, false)
+            .into_iter()
+            .next()
+    }
+
+    /// Like `handle_buffer`, for messages received over either transport.
+    /// Zone transfers are only served over TCP, where they take a stream of
+    /// responses.
+    fn handle_message(
+        &self,
+        buffer: &mut PacketBuffer,
+        source: SocketAddr,
+        tcp: bool,
+    ) -> Vec<DnsPacket> {
+        let bytes = buffer.buffer[..buffer.length].to_vec();
+        match DnsPacket::from_buffer(buffer) {
+            Ok(request) if request.header.response => Vec::new(),
+            Ok(request) => self.handle_signed(&request, &bytes, source, tcp),
+            // Only queries get a FORMERR, answering a response that failed
+            // to parse could start a loop with its sender
+            Err(_) if bytes.len() >= 2 && bytes.get(2).is_none_or(|flags| flags & 0x80 == 0) => {
+                self.record(Metrics::record_malformed);
+                let mut response = DnsPacket::new();
+                response.header.id = u16::from_be_bytes([buffer.buffer[0], buffer.buffer[1]]);
+                response.header.response = true;
+                response.set_rcode(ResultCode::FORMERR);
+                vec![response]
+            }
+            Err(_) => {
+                self.record(Metrics::record_malformed);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Handles `request`, received as `bytes`, checking its TSIG signature
+    /// and signing the responses when it carries one.
+    fn handle_signed(
+        &self,
+        request: &DnsPacket,
+        bytes: &[u8],
+        source: SocketAddr,
+        tcp: bool,
+    ) -> Vec<DnsPacket> {
+        let mut signer = match self.authenticate(request, bytes) {
+            Ok(signer) => signer,
+            Err(response) => return vec![response],
+        };
+
+        let transfer = request.header.opcode == Opcode::QUERY
+            && request
+                .questions
+                .iter()
+                .any(|question| matches!(question.qtype, QueryType::AXFR | QueryType::IXFR));
+        let restricted = transfer || request.header.opcode == Opcode::UPDATE;
+        let allowed = match request.header.opcode {
+            _ if transfer => &self.acl.transfer,
+            Opcode::UPDATE => &self.acl.update,
+            Opcode::NOTIFY => &self.acl.notify,
+            _ => &self.acl.query,
+        };
+
+        let mut responses = match signer {
+            _ if !Acl::allows(allowed, source.ip()) => {
+                let mut response = response_to(request);
+                response.set_rcode(ResultCode::REFUSED);
+                vec![response]
+            }
+            None if restricted && !self.keys.is_empty() => {
+                let mut response = response_to(request);
+                response.set_rcode(ResultCode::REFUSED);
+                vec![response]
+            }
+            _ if transfer && tcp => self.handle_transfer(request),
+            _ => vec![self.handle_request(request, source)],
+        };
+
+        let time_signed = unix_time();
+        // A UDP response the client has no room for, TSIG included, is cut
+        // down here, so that a signed request still gets a signed TC=1 reply
+        if !tcp {
+            let capacity = udp_payload_size(request);
+            for response in &mut responses {
+                let mut sized = response.clone();
+                let signed = match signer.clone() {
+                    Some(mut signer) => signer.sign(&mut sized, time_signed).is_ok(),
+                    None => true,
+                };
+                let fits = signed
+                    && sized
+                        .write(&mut PacketBuffer::with_capacity(capacity))
+                        .is_ok();
+                if !fits {
+                    *response = truncated(response);
+                }
+            }
+        }
+
+        if let Some(signer) = signer.as_mut() {
+            let signed = responses
+                .iter_mut()
+                .try_for_each(|response| signer.sign(response, time_signed));
+            if signed.is_err() {
+                let mut response = response_to(request);
+                response.set_rcode(ResultCode::SERVFAIL);
+                responses = vec![response];
+            }
+        }
+
+        responses
+    }
+
+    /// Verifies the TSIG of `request`, returning the signer for the response
+    /// of a signed request or the error response to send instead.
+    fn authenticate(
+        &self,
+        request: &DnsPacket,
+        bytes: &[u8],
+    ) -> Result<Option<TsigSigner>, DnsPacket> {
+        let record = match tsig_record(request) {
+            Ok(Some(record)) => record,
+            Ok(None) => return Ok(None),
+            Err(_) => {
+                let mut response = response_to(request);
+                response.set_rcode(ResultCode::FORMERR);
+                return Err(response);
+            }
+        };
+        let DnsRecord::TSIG {
+            domain,
+            algorithm,
+            time_signed,
+            mac,
+            ..
+        } = record
+        else {
+            return Ok(None);
+        };
+
+        let key = self
+            .keys
+            .iter()
+            .find(|key| key.name == *domain && key.algorithm.name() == *algorithm);
+        let Some(key) = key else {
+            return Err(tsig_failure(request, record, ResultCode::BADKEY));
+        };
+
+        let now = unix_time();
+        let mut verifier = TsigVerifier::new(key.clone());
+        match verifier.verify(bytes, now) {
+            Ok(_) => Ok(Some(TsigSigner::for_response(key.clone(), mac))),
+            Err(DnsError::TsigBadTime { .. }) => {
+                // Signed, carrying the server time so that the client can
+                // tell its clock is off
+                let mut response = response_to(request);
+                response.set_rcode(ResultCode::NOTAUTH);
+                let mut signer = TsigSigner::for_response(key.clone(), mac);
+                let other = now.to_be_bytes()[2..].to_vec();
+                match signer.sign_with_error(
+                    &mut response,
+                    *time_signed,
+                    ResultCode::BADTIME,
+                    other,
+                ) {
+                    Ok(()) => Err(response),
+                    Err(_) => Err(tsig_failure(request, record, ResultCode::BADTIME)),
+                }
+            }
+            Err(_) => Err(tsig_failure(request, record, ResultCode::BADSIG)),
+        }
+    }
+
+    /// Handles a parsed `request` received from `source`.
+    pub fn handle_request(&self, request: &DnsPacket, source: SocketAddr) -> DnsPacket {
+        match request.header.opcode {
+            Opcode::QUERY => self.handle_query(request),
+            Opcode::NOTIFY => self.handle_notify(request, source),
+            Opcode::UPDATE => self.handle_update(request),
+            _ => {
+                let mut response = response_to(request);
+                response.set_rcode(ResultCode::NOTIMP);
+                response
+            }
+        }
+    }
+
+    fn handle_query(&self, request: &DnsPacket) -> DnsPacket {
+        let mut response = response_to(request);
+
+        let [question] = request.questions.as_slice() else {
+            response.set_rcode(ResultCode::FORMERR);
+            return response;
+        };
+
+        let catalog = self
+            .catalog
+            .read()
+            .unwrap_or_else(|error| error.into_inner());
+        let Some(zone) = catalog.find(&question.name, question.qclass) else {
+            response.set_rcode(ResultCode::REFUSED);
+            return response;
+        };
+
+        match question.qtype {
+            // Transfers need TCP, over UDP an IXFR client only learns the
+            // current SOA and retries over TCP when it is behind
+            QueryType::IXFR if question.name == zone.origin => {
+                response.header.authoritative_answer = true;
+                response.answers.extend(zone.soa().cloned());
+                return response;
+            }
+            QueryType::IXFR | QueryType::AXFR => {
+                response.set_rcode(ResultCode::REFUSED);
+                return response;
+            }
+            _ => {}
+        }
+
+        match zone.lookup(&question.name, question.qtype) {
+            Lookup::Answer(records) => {
+                response.header.authoritative_answer = true;
+                response.answers = records;
+            }
+            Lookup::Referral { ns, glue } => {
+                response.authorities = ns;
+                response.resources = glue;
+            }
+            Lookup::NoData => {
+                response.header.authoritative_answer = true;
+                response.authorities.extend(negative_soa(zone));
+            }
+            Lookup::NxDomain => {
+                response.header.authoritative_answer = true;
+                response.set_rcode(ResultCode::NXDOMAIN);
+                response.authorities.extend(negative_soa(zone));
+            }
+        }
+
+        response
+    }
+
+    /// Answers an AXFR or IXFR request with the stream of messages carrying
+    /// the zone, or its changes since the serial of the client.
+    fn handle_transfer(&self, request: &DnsPacket) -> Vec<DnsPacket> {
+        let mut response = response_to(request);
+
+        let [question] = request.questions.as_slice() else {
+            response.set_rcode(ResultCode::FORMERR);
+            return vec![response];
+        };
+
+        let catalog = self
+            .catalog
+            .read()
+            .unwrap_or_else(|error| error.into_inner());
+        let Some(zone) = catalog.get(&question.name, question.qclass) else {
+            response.set_rcode(ResultCode::NOTAUTH);
+            return vec![response];
+        };
+        if zone.soa().is_none() {
+            response.set_rcode(ResultCode::SERVFAIL);
+            return vec![response];
+        }
+
+        // IXFR requests carry the SOA of the version the client holds
+        let client_serial = match question.qtype {
+            QueryType::IXFR => {
+                let serial = request.authorities.iter().find_map(|record| match record {
+                    DnsRecord::SOA { serial, .. } => Some(*serial),
+                    _ => None,
+                });
+                let Some(serial) = serial else {
+                    response.set_rcode(ResultCode::FORMERR);
+                    return vec![response];
+                };
+                Some(serial)
+            }
+            _ => None,
+        };
+
+        transfer_messages(request, transfer_records(zone, client_serial))
+    }
+
+    fn handle_update(&self, request: &DnsPacket) -> DnsPacket {
+        let Some(question) = request.questions.first() else {
+            return update_response(request, ResultCode::FORMERR);
+        };
+
+        let mut catalog = self
+            .catalog
+            .write()
+            .unwrap_or_else(|error| error.into_inner());
+        let Some(zone) = catalog.get(&question.name, question.qclass) else {
+            return update_response(request, ResultCode::NOTAUTH);
+        };
+
+        // The update is only served once it is durable
+        let mut updated = zone.clone();
+        let rcode = apply_update(&mut updated, request);
+        if updated != *zone {
+            if self.record_change(zone.serial(), &updated).is_err() {
+                return update_response(request, ResultCode::SERVFAIL);
+            }
+            self.notify_changed(&updated);
+            catalog.insert(updated);
+        }
+
+        update_response(request, rcode)
+    }
+
+    /// Acknowledges a NOTIFY, passing it on to the secondary manager. It is
+    /// up to the manager to check that `source` is a primary of the zone.
+    fn handle_notify(&self, request: &DnsPacket, source: SocketAddr) -> DnsPacket {
+        let mut response = response_to(request);
+
+        let Some(sender) = &self.notify else {
+            response.set_rcode(ResultCode::NOTIMP);
+            return response;
+        };
+        let Some(notify) = Notify::from_request(request, source) else {
+            response.set_rcode(ResultCode::FORMERR);
+            return response;
+        };
+
+        if sender.send(notify).is_err() {
+            response.set_rcode(ResultCode::SERVFAIL);
+        }
+        response
+    }
+}
+
+/// An empty response echoing the ID, opcode, RD flag and questions.
+fn response_to(request: &DnsPacket) -> DnsPacket {
+    let mut response = DnsPacket::new();
+    response.header.id = request.header.id;
+    response.header.opcode = request.header.opcode;
+    response.header.recursion_desired = request.header.recursion_desired;
+    response.header.response = true;
+    response.questions = request.questions.clone();
+
+    response
+}
+
+/// An unsigned NOTAUTH response to `request`, whose TSIG `record` failed
+/// with `error`.
+fn tsig_failure(request: &DnsPacket, record: &DnsRecord, error: ResultCode) -> DnsPacket {
+    let mut response = response_to(request);
+    response.set_rcode(ResultCode::NOTAUTH);
+
+    if let DnsRecord::TSIG {
+        domain,
+        algorithm,
+        time_signed,
+        fudge,
+        ..
+    } = record
+    {
+        response.resources.push(DnsRecord::TSIG {
+            domain: domain.clone(),
+            class: DnsClass::ANY,
+            algorithm: algorithm.clone(),
+            time_signed: *time_signed,
+            fudge: *fudge,
+            mac: Vec::new(),
+            original_id: request.header.id,
+            error,
+            other: Vec::new(),
+            ttl: 0,
+        });
+    }
+
+    response
+}
+
+/// The SOA record for negative answers, with its TTL capped to the minimum
+/// field as RFC 2308 requires.
+fn negative_soa(zone: &Zone) -> Option<DnsRecord> {
+    let mut soa = zone.soa()?.clone();
+    if let DnsRecord::SOA { minimum, ttl, .. } = &mut soa {
+        *ttl = (*ttl).min(*minimum);
+    }
+
+    Some(soa)
+}
+
+/// The largest UDP response `request` takes, as advertised over EDNS but no
+/// less than 512 bytes.
+fn udp_payload_size(request: &DnsPacket) -> usize {
+    match request.edns() {
+        Some(DnsRecord::OPT {
+            udp_payload_size, ..
+        }) => (*udp_payload_size as usize).max(UDP_MESSAGE_LENGTH),
+        _ => UDP_MESSAGE_LENGTH,
+    }
+}
+
+/// `response` stripped of its records, flagged as truncated so that the
+/// client retries over another transport.
+fn truncated(response: &DnsPacket) -> DnsPacket {
+    let mut truncated = response_to(response);
+    truncated.header.response = true;
+    truncated.header.truncated_message = true;
+    truncated.header.rescode = response.header.rescode;
+
+    truncated
+}
@@ -0,0 +1,277 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use crate::client::{random_u16, DnsClient, Exchange};
+use crate::dnstap::Dnstap;
+use crate::metrics::Metrics;
+use crate::name::Name;
+use crate::protocol::{DnsPacket, DnsQuestion, DnsRecord, QueryType, ResultCode};
+use crate::DnsError;
+
+/// Most referrals followed in one trace, counting those followed to find the
+/// addresses of name servers that came without glue.
+const MAX_REFERRALS: usize = 32;
+
+/// EDNS payload size advertised, so that referrals fit with all their glue.
+const TRACE_BUFSIZE: u16 = 1232;
+
+/// The root name servers and their IPv4 addresses, from the IANA root hints.
+pub const ROOT_HINTS: [(&str, Ipv4Addr); 13] = [
+    ("a.root-servers.net", Ipv4Addr::new(198, 41, 0, 4)),
+    ("b.root-servers.net", Ipv4Addr::new(170, 247, 170, 2)),
+    ("c.root-servers.net", Ipv4Addr::new(192, 33, 4, 12)),
+    ("d.root-servers.net", Ipv4Addr::new(199, 7, 91, 13)),
+    ("e.root-servers.net", Ipv4Addr::new(192, 203, 230, 10)),
+    ("f.root-servers.net", Ipv4Addr::new(192, 5, 5, 241)),
+    ("g.root-servers.net", Ipv4Addr::new(192, 112, 36, 4)),
+    ("h.root-servers.net", Ipv4Addr::new(198, 97, 190, 53)),
+    ("i.root-servers.net", Ipv4Addr::new(192, 36, 148, 17)),
+    ("j.root-servers.net", Ipv4Addr::new(192, 58, 128, 30)),
+    ("k.root-servers.net", Ipv4Addr::new(193, 0, 14, 129)),
+    ("l.root-servers.net", Ipv4Addr::new(199, 7, 83, 42)),
+    ("m.root-servers.net", Ipv4Addr::new(202, 12, 27, 33)),
+];
+
+/// One query made while tracing.
+pub struct TraceStep {
+    /// Zone the server is authoritative for, according to the referral that
+    /// led to it.
+    pub zone: Name,
+    pub server_name: Name,
+    pub server: SocketAddr,
+    /// The response, or why there was none. Failed servers are skipped for
+    /// the next server of the zone.
+    pub result: crate::Result<Exchange>,
+}
+
+/// Resolves a question iteratively, starting from the root servers and
+/// following every referral down to the servers holding the answer, as
+/// `dig +trace` does.
+///
+/// Servers are queried without recursion. Name servers whose addresses are
+/// missing from the glue are resolved with a trace of their own.
+///
+/// Every query and response is logged to `dnstap` when set, and the time
+/// every server took to answer is recorded in `metrics` when set.
+pub struct Tracer {
+    pub roots: Vec<(Name, SocketAddr)>,
+    /// Port the name servers found in referrals are queried on.
+    pub port: u16,
+    pub timeout: Duration,
+    pub retries: usize,
+    pub tcp: bool,
+    pub dnstap: Option<Dnstap>,
+    pub metrics: Option<Metrics>,
+}
+
+impl Default for Tracer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Tracer {
+    pub fn new() -> Tracer {
+        let roots = ROOT_HINTS
+            .iter()
+            .filter_map(|(name, address)| {
+                let name = name.parse().ok()?;
+                Some((name, SocketAddr::from((*address, 53))))
+            })
+            .collect();
+
+        Tracer {
+            roots,
+            port: 53,
+            timeout: Duration::from_secs(5),
+            retries: 0,
+            tcp: false,
+            dnstap: None,
+            metrics: None,
+        }
+    }
+
+    /// Traces `question`, calling `on_step` after every query as it
+    /// happens, and returns the final response: an answer, a referral-free
+    /// negative response or an error from the last zone reached.
+    pub fn trace<F>(&self, question: &DnsQuestion, mut on_step: F) -> crate::Result<DnsPacket>
+    where
+        F: FnMut(&TraceStep),
+    {
+        let mut referrals = 0;
+        self.follow(question, &mut on_step, &mut referrals)
+    }
+
+    fn follow(
+        &self,
+        question: &DnsQuestion,
+        on_step: &mut dyn FnMut(&TraceStep),
+        referrals: &mut usize,
+    ) -> crate::Result<DnsPacket> {
+        let mut zone = Name::root();
+        let mut servers: Vec<(Name, Option<SocketAddr>)> = self
+            .roots
+            .iter()
+            .map(|(name, address)| (name.clone(), Some(*address)))
+            .collect();
+
+        loop {
+            let response = self.query_zone(&zone, &servers, question, on_step, referrals)?;
+            if response.rcode() != ResultCode::NOERROR || !response.answers.is_empty() {
+                return Ok(response);
+            }
+
+            let ns: Vec<&DnsRecord> = response.referral_ns(&question.name).collect();
+            let Some(cut) = ns.first().map(|record| record.domain().clone()) else {
+                // No data, the name exists without records of the type
+                return Ok(response);
+            };
+            if cut == zone || !cut.is_subdomain_of(&zone) {
+                return Err(DnsError::BadReferral(cut));
+            }
+
+            *referrals += 1;
+            if *referrals > MAX_REFERRALS {
+                return Err(DnsError::TooManyReferrals(MAX_REFERRALS));
+            }
+
+            servers = self.referral_servers(&response, &cut, &ns)?;
+            zone = cut;
+        }
+    }
+
+    /// The servers of the `cut` zone a referral points to: addresses from
+    /// the glue first, IPv4 before IPv6, then the servers without glue.
+    fn referral_servers(
+        &self,
+        response: &DnsPacket,
+        cut: &Name,
+        ns: &[&DnsRecord],
+    ) -> crate::Result<Vec<(Name, Option<SocketAddr>)>> {
+        let hosts = ns.iter().filter_map(|record| match record {
+            DnsRecord::NS { domain, host, .. } if domain == cut => Some(host),
+            _ => None,
+        });
+
+        let mut glued = Vec::new();
+        let mut unglued = Vec::new();
+        for host in hosts {
+            let glue = response.glue(host);
+            if glue.is_empty() {
+                unglued.push((host.clone(), None));
+            }
+            for address in glue {
+                glued.push((host.clone(), Some(SocketAddr::new(address, self.port))));
+            }
+        }
+
+        // Spread the load over the servers of the zone, as resolvers do
+        let ipv4 = glued.iter().filter(|(_, address)| is_ipv4(address)).count();
+        if ipv4 > 0 {
+            let offset = random_u16()? as usize % ipv4;
+            glued.sort_by_key(|(_, address)| !is_ipv4(address));
+            glued[..ipv4].rotate_left(offset);
+        }
+        glued.extend(unglued);
+
+        Ok(glued)
+    }
+
+    /// Queries the servers of `zone` in turn until one answers, returning
+    /// the first usable response.
+    fn query_zone(
+        &self,
+        zone: &Name,
+        servers: &[(Name, Option<SocketAddr>)],
+        question: &DnsQuestion,
+        on_step: &mut dyn FnMut(&TraceStep),
+        referrals: &mut usize,
+    ) -> crate::Result<DnsPacket> {
+        let mut last = Err(DnsError::NoNameServerAddress(zone.clone()));
+        for (server_name, address) in servers {
+            let addresses = match address {
+                Some(address) => vec![*address],
+                None => self.resolve(server_name, referrals),
+            };
+
+            for server in addresses {
+                let result = self.query(server, question);
+                let step = TraceStep {
+                    zone: zone.clone(),
+                    server_name: server_name.clone(),
+                    server,
+                    result,
+                };
+                on_step(&step);
+
+                // Lame servers are skipped like unreachable ones
+                match step.result {
+                    Ok(exchange) if is_lame(&exchange.response) => {
+                        last = Ok(exchange.response);
+                    }
+                    Ok(exchange) => return Ok(exchange.response),
+                    Err(error) => {
+                        if last.is_err() {
+                            last = Err(error);
+                        }
+                    }
+                }
+            }
+        }
+
+        last
+    }
+
+    /// The addresses of the name server `host`, traced from the root without
+    /// reporting the steps. Failures leave the server out.
+    fn resolve(&self, host: &Name, referrals: &mut usize) -> Vec<SocketAddr> {
+        let question = DnsQuestion::new(host.clone(), QueryType::A);
+        let Ok(response) = self.follow(&question, &mut |_| {}, referrals) else {
+            return Vec::new();
+        };
+
+        response
+            .answers
+            .iter()
+            .filter_map(|record| match record {
+                DnsRecord::A { address, .. } => {
+                    Some(SocketAddr::new(IpAddr::V4(*address), self.port))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn query(&self, server: SocketAddr, question: &DnsQuestion) -> crate::Result<Exchange> {
+        let mut packet = DnsPacket::new();
+        packet.questions.push(question.clone());
+        packet.resources.push(DnsRecord::OPT {
+            udp_payload_size: TRACE_BUFSIZE,
+            extended_rcode: 0,
+            version: 0,
+            flags: 0,
+            options: Vec::new(),
+        });
+
+        let mut client = DnsClient::new(server);
+        client.timeout = self.timeout;
+        client.retries = self.retries;
+        client.tcp = self.tcp;
+        client.dnstap = self.dnstap.clone();
+        client.metrics = self.metrics.clone();
+        client.exchange(packet)
+    }
+}
+
+fn is_ipv4(address: &Option<SocketAddr>) -> bool {
+    matches!(address, Some(SocketAddr::V4(_)))
+}
+
+/// Whether `response` shows that the server does not serve the zone it was
+/// referred to for.
+fn is_lame(response: &DnsPacket) -> bool {
+    matches!(
+        response.rcode(),
+        ResultCode::SERVFAIL | ResultCode::REFUSED | ResultCode::NOTAUTH
+    )
+}
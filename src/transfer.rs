@@ -0,0 +1,363 @@
+use std::io::{ErrorKind, Read, Write};
+use std::iter::Peekable;
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use crate::buffer::{PacketBuffer, MAX_MESSAGE_LENGTH};
+use crate::client::random_u16;
+use crate::name::Name;
+use crate::protocol::{
+    DnsClass, DnsPacket, DnsQuestion, DnsRecord, QueryType, ResultCode, HEADER_LENGTH,
+};
+use crate::tsig::{unix_time, TsigKey, TsigSigner, TsigVerifier};
+use crate::zone::{serial_greater, Diff, Zone};
+use crate::DnsError;
+
+/// Size each message of an outgoing transfer is filled up to, well below the
+/// TCP limit so that a TSIG record always fits.
+const TRANSFER_MESSAGE_LENGTH: usize = 16384;
+
+/// Reads one length prefixed message from a TCP stream, `None` when the peer
+/// closed the connection between messages.
+pub fn read_tcp_message<R: Read>(stream: &mut R) -> crate::Result<Option<Vec<u8>>> {
+    let mut prefix = [0; 2];
+    match stream.read_exact(&mut prefix) {
+        Ok(()) => {}
+        Err(error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(source) => return Err(stream_error(source)),
+    }
+
+    let mut message = vec![0; u16::from_be_bytes(prefix) as usize];
+    stream.read_exact(&mut message).map_err(stream_error)?;
+
+    Ok(Some(message))
+}
+
+/// Writes `packet` to a TCP stream behind its two byte length prefix.
+pub fn write_tcp_message<W: Write>(stream: &mut W, packet: &mut DnsPacket) -> crate::Result<()> {
+    let mut buffer = PacketBuffer::with_capacity(MAX_MESSAGE_LENGTH);
+    packet.write(&mut buffer)?;
+
+    write_tcp_bytes(stream, &buffer.buffer[..buffer.position])
+}
+
+/// Writes a message already in wire format to a TCP stream behind its
+/// length prefix.
+pub(crate) fn write_tcp_bytes<W: Write>(stream: &mut W, message: &[u8]) -> crate::Result<()> {
+    let mut framed = Vec::with_capacity(message.len() + 2);
+    framed.extend_from_slice(&(message.len() as u16).to_be_bytes());
+    framed.extend_from_slice(message);
+    stream.write_all(&framed).map_err(stream_error)
+}
+
+pub(crate) fn stream_error(source: std::io::Error) -> DnsError {
+    match source.kind() {
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => DnsError::Timeout,
+        _ => DnsError::SocketIO { source },
+    }
+}
+
+/// The records answering a transfer of `zone`, in the order they are sent.
+///
+/// AXFR sends the SOA, every other record and the SOA again. IXFR sends the
+/// current SOA alone when `client_serial` is up to date, the journaled
+/// changes since `client_serial` between two copies of the current SOA when
+/// they are available, and the whole zone as for AXFR otherwise.
+pub fn transfer_records(zone: &Zone, client_serial: Option<u32>) -> Vec<DnsRecord> {
+    let (Some(soa), Some(serial)) = (zone.soa(), zone.serial()) else {
+        return Vec::new();
+    };
+
+    let mut records = vec![soa.clone()];
+    if let Some(client_serial) = client_serial {
+        if !serial_greater(serial, client_serial) {
+            return records;
+        }
+
+        if let Some(diffs) = zone.diffs_since(client_serial) {
+            for diff in diffs {
+                records.push(diff.old_soa.clone());
+                records.extend(diff.removed.iter().cloned());
+                records.push(diff.new_soa.clone());
+                records.extend(diff.added.iter().cloned());
+            }
+            records.push(soa.clone());
+            return records;
+        }
+    }
+
+    records.extend(
+        zone.records()
+            .filter(|record| record.qtype() != QueryType::SOA)
+            .cloned(),
+    );
+    records.push(soa.clone());
+
+    records
+}
+
+/// Splits `records` over as many responses to `request` as needed, only the
+/// first one repeats the question.
+pub fn transfer_messages(request: &DnsPacket, records: Vec<DnsRecord>) -> Vec<DnsPacket> {
+    let response = |first: bool| {
+        let mut response = DnsPacket::new();
+        response.header.id = request.header.id;
+        response.header.opcode = request.header.opcode;
+        response.header.response = true;
+        response.header.authoritative_answer = true;
+        if first {
+            response.questions = request.questions.clone();
+        }
+        response
+    };
+
+    let mut messages = Vec::new();
+    let mut current = response(true);
+    let mut scratch = PacketBuffer::with_capacity(MAX_MESSAGE_LENGTH);
+    current.write(&mut scratch).ok();
+    let mut length = scratch.position;
+
+    for record in records {
+        scratch.position = 0;
+        let record_length = record.write(&mut scratch).unwrap_or(MAX_MESSAGE_LENGTH);
+        if length + record_length > TRANSFER_MESSAGE_LENGTH && !current.answers.is_empty() {
+            messages.push(std::mem::replace(&mut current, response(false)));
+            length = HEADER_LENGTH;
+        }
+
+        length += record_length;
+        current.answers.push(record);
+    }
+    messages.push(current);
+
+    messages
+}
+
+/// Pulls zones from a primary server over TCP, as a secondary does.
+///
+/// When `tsig` is set the transfer requests are signed and every response
+/// must be signed with the same key.
+pub struct TransferClient {
+    pub server: SocketAddr,
+    pub timeout: Duration,
+    pub tsig: Option<TsigKey>,
+}
+
+impl TransferClient {
+    pub fn new(server: SocketAddr) -> TransferClient {
+        TransferClient {
+            server,
+            timeout: Duration::from_secs(30),
+            tsig: None,
+        }
+    }
+
+    /// Fetches a full copy of the zone at `origin`.
+    pub fn axfr(&self, origin: &Name, class: DnsClass) -> crate::Result<Zone> {
+        let mut zone = Zone::new(origin.clone(), class);
+        self.pull(&mut zone, QueryType::AXFR)?;
+
+        Ok(zone)
+    }
+
+    /// Brings `zone` up to date with the primary, returning whether it
+    /// changed. Zones with an SOA are refreshed incrementally, the primary
+    /// may still answer with the full zone.
+    pub fn refresh(&self, zone: &mut Zone) -> crate::Result<bool> {
+        let qtype = match zone.soa() {
+            Some(_) => QueryType::IXFR,
+            None => QueryType::AXFR,
+        };
+
+        self.pull(zone, qtype)
+    }
+
+    fn pull(&self, zone: &mut Zone, qtype: QueryType) -> crate::Result<bool> {
+        let mut request = DnsPacket::new();
+        request.header.id = random_u16()?;
+        let mut question = DnsQuestion::new(zone.origin.clone(), qtype);
+        question.qclass = zone.class;
+        request.questions.push(question);
+
+        let client_serial = match qtype {
+            QueryType::IXFR => {
+                request.authorities.extend(zone.soa().cloned());
+                zone.serial()
+            }
+            _ => None,
+        };
+
+        let records = self.exchange(request, client_serial)?;
+        apply_transfer(zone, records)
+    }
+
+    /// Sends a transfer `request` and collects the answers of the response
+    /// stream until the transfer is complete.
+    fn exchange(
+        &self,
+        mut request: DnsPacket,
+        client_serial: Option<u32>,
+    ) -> crate::Result<Vec<DnsRecord>> {
+        let mut verifier = None;
+        if let Some(key) = &self.tsig {
+            let mut signer = TsigSigner::new(key.clone());
+            signer.sign(&mut request, unix_time())?;
+            let request_mac = signer.mac().unwrap_or_default();
+            verifier = Some(TsigVerifier::for_response(key.clone(), request_mac));
+        }
+
+        let mut stream = TcpStream::connect_timeout(&self.server, self.timeout)
+            .map_err(|source| DnsError::SocketIO { source })?;
+        stream
+            .set_read_timeout(Some(self.timeout))
+            .and_then(|()| stream.set_write_timeout(Some(self.timeout)))
+            .map_err(|source| DnsError::SocketIO { source })?;
+        write_tcp_message(&mut stream, &mut request)?;
+
+        let mut records: Vec<DnsRecord> = Vec::new();
+        loop {
+            let Some(bytes) = read_tcp_message(&mut stream)? else {
+                return Err(DnsError::MalformedTransfer);
+            };
+
+            let response = match verifier.as_mut() {
+                Some(verifier) => verifier.verify(&bytes, unix_time())?,
+                None => DnsPacket::from_buffer(&mut PacketBuffer::from_bytes(&bytes)?)?,
+            };
+            if !response.header.response || response.header.id != request.header.id {
+                return Err(DnsError::IdMismatch {
+                    expected: request.header.id,
+                    received: response.header.id,
+                });
+            }
+            if response.rcode() != ResultCode::NOERROR {
+                return Err(DnsError::TransferRefused(response.rcode()));
+            }
+            if records.is_empty() && response.questions != request.questions {
+                return Err(DnsError::QuestionMismatch);
+            }
+
+            records.extend(response.answers);
+            if is_complete(&records, client_serial) {
+                break;
+            }
+        }
+
+        if let Some(verifier) = &verifier {
+            verifier.finish()?;
+        }
+
+        Ok(records)
+    }
+}
+
+fn soa_serial(record: &DnsRecord) -> Option<u32> {
+    match record {
+        DnsRecord::SOA { serial, .. } => Some(*serial),
+        _ => None,
+    }
+}
+
+/// Whether `records`, the answers received so far, form a complete transfer.
+fn is_complete(records: &[DnsRecord], client_serial: Option<u32>) -> bool {
+    let Some(serial) = records.first().and_then(soa_serial) else {
+        return false;
+    };
+
+    // A lone SOA tells an IXFR client that it is up to date
+    if records.len() == 1 {
+        return client_serial.is_some_and(|client_serial| !serial_greater(serial, client_serial));
+    }
+
+    let Some(last) = records.last().and_then(soa_serial) else {
+        return false;
+    };
+    match soa_serial(&records[1]) {
+        Some(second) if second != serial => {}
+        // The whole zone ends at the first repeat of the SOA
+        _ => return last == serial,
+    }
+
+    // Incremental changes alternate between removals, opened by the old SOA,
+    // and additions, opened by the new one. The current SOA opening another
+    // section of removals ends the transfer.
+    let mut adding = true;
+    for (index, record) in records.iter().enumerate().skip(1) {
+        let Some(record_serial) = soa_serial(record) else {
+            continue;
+        };
+        if adding && record_serial == serial {
+            return index == records.len() - 1;
+        }
+        adding = !adding;
+    }
+
+    false
+}
+
+/// Applies the complete transfer `records` to `zone`, returning whether it
+/// changed.
+fn apply_transfer(zone: &mut Zone, records: Vec<DnsRecord>) -> crate::Result<bool> {
+    let Some(serial) = records.first().and_then(soa_serial) else {
+        return Err(DnsError::MalformedTransfer);
+    };
+    let outside = records
+        .iter()
+        .any(|record| !zone.contains(record.domain()) || record.class() != zone.class);
+    if outside || *records[0].domain() != zone.origin {
+        return Err(DnsError::MalformedTransfer);
+    }
+
+    if records.len() == 1 {
+        return Ok(false);
+    }
+
+    let incremental = matches!(soa_serial(&records[1]), Some(second) if second != serial);
+    if !incremental {
+        let mut newer = Zone::new(zone.origin.clone(), zone.class);
+        for record in &records[..records.len() - 1] {
+            newer.insert(record.clone());
+        }
+
+        let changed = zone.serial() != Some(serial) || zone.records().ne(newer.records());
+        zone.replace_with(newer);
+        return Ok(changed);
+    }
+
+    // Apply to a copy so that a broken chain of changes leaves the zone as
+    // it was
+    let mut updated = zone.clone();
+    let mut records = records[1..records.len() - 1].iter().cloned().peekable();
+    while let Some(old_soa) = records.next() {
+        let removed = take_records(&mut records);
+        let new_soa = records.next().ok_or(DnsError::MalformedTransfer)?;
+        let added = take_records(&mut records);
+
+        updated.apply_diff(&Diff {
+            old_soa,
+            removed,
+            new_soa,
+            added,
+        })?;
+    }
+
+    if updated.serial() != Some(serial) {
+        return Err(DnsError::MalformedTransfer);
+    }
+    *zone = updated;
+
+    Ok(true)
+}
+
+/// Takes the records up to the next SOA, which opens the following section.
+fn take_records<I>(records: &mut Peekable<I>) -> Vec<DnsRecord>
+where
+    I: Iterator<Item = DnsRecord>,
+{
+    let mut taken = Vec::new();
+    while let Some(record) = records.next_if(|record| record.qtype() != QueryType::SOA) {
+        taken.push(record);
+    }
+
+    taken
+}
@@ -0,0 +1,418 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::digest::KeyInit;
+use hmac::{Hmac, Mac};
+use sha2::{Sha256, Sha384, Sha512};
+
+use crate::buffer::{PacketBuffer, MAX_MESSAGE_LENGTH};
+use crate::name::Name;
+use crate::protocol::{
+    DnsClass, DnsHeader, DnsPacket, DnsQuestion, DnsRecord, QueryType, ResultCode,
+};
+use crate::DnsError;
+
+/// Clock skew tolerated between signer and verifier, in seconds, as
+/// recommended by RFC 8945.
+pub const DEFAULT_FUDGE: u16 = 300;
+
+/// Most consecutive unsigned messages accepted in a signed stream, RFC 8945
+/// section 5.3.1.
+const MAX_UNSIGNED_MESSAGES: usize = 99;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TsigAlgorithm {
+    HmacSha256,
+    HmacSha384,
+    HmacSha512,
+}
+
+impl TsigAlgorithm {
+    pub fn name(&self) -> Name {
+        let name = match self {
+            TsigAlgorithm::HmacSha256 => "hmac-sha256",
+            TsigAlgorithm::HmacSha384 => "hmac-sha384",
+            TsigAlgorithm::HmacSha512 => "hmac-sha512",
+        };
+
+        name.parse().expect("algorithm names are valid")
+    }
+
+    pub fn from_name(name: &Name) -> Option<TsigAlgorithm> {
+        [
+            TsigAlgorithm::HmacSha256,
+            TsigAlgorithm::HmacSha384,
+            TsigAlgorithm::HmacSha512,
+        ]
+        .into_iter()
+        .find(|algorithm| algorithm.name() == *name)
+    }
+
+    /// Length of the untruncated MAC, in bytes.
+    pub fn mac_length(&self) -> usize {
+        match self {
+            TsigAlgorithm::HmacSha256 => 32,
+            TsigAlgorithm::HmacSha384 => 48,
+            TsigAlgorithm::HmacSha512 => 64,
+        }
+    }
+}
+
+/// A shared secret, known to both ends under the same name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TsigKey {
+    pub name: Name,
+    pub algorithm: TsigAlgorithm,
+    pub secret: Vec<u8>,
+}
+
+impl TsigKey {
+    pub fn new(name: Name, algorithm: TsigAlgorithm, secret: Vec<u8>) -> TsigKey {
+        TsigKey {
+            name,
+            algorithm,
+            secret,
+        }
+    }
+
+    fn compute(&self, data: &[u8]) -> Vec<u8> {
+        match self.algorithm {
+            TsigAlgorithm::HmacSha256 => hmac::<Hmac<Sha256>>(&self.secret, data),
+            TsigAlgorithm::HmacSha384 => hmac::<Hmac<Sha384>>(&self.secret, data),
+            TsigAlgorithm::HmacSha512 => hmac::<Hmac<Sha512>>(&self.secret, data),
+        }
+    }
+
+    /// Whether `mac` is the MAC of `data`, compared in constant time.
+    /// Truncated MACs are not accepted.
+    fn verify(&self, data: &[u8], mac: &[u8]) -> bool {
+        let expected = self.compute(data);
+        expected.len() == mac.len()
+            && expected
+                .iter()
+                .zip(mac)
+                .fold(0, |difference, (a, b)| difference | (a ^ b))
+                == 0
+    }
+}
+
+fn hmac<M: Mac + KeyInit>(secret: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = <M as KeyInit>::new_from_slice(secret).expect("HMAC takes keys of any length");
+    mac.update(data);
+
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Signs a request, or the messages answering one.
+///
+/// The first message is signed over all TSIG variables. Following messages
+/// of a stream, such as a zone transfer, are signed over the timers only and
+/// chain on the MAC of the previous message.
+#[derive(Clone)]
+pub struct TsigSigner {
+    pub key: TsigKey,
+    pub fudge: u16,
+    previous_mac: Option<Vec<u8>>,
+    signed: usize,
+}
+
+impl TsigSigner {
+    pub fn new(key: TsigKey) -> TsigSigner {
+        TsigSigner {
+            key,
+            fudge: DEFAULT_FUDGE,
+            previous_mac: None,
+            signed: 0,
+        }
+    }
+
+    /// A signer for the response to a request that carried `request_mac`.
+    pub fn for_response(key: TsigKey, request_mac: &[u8]) -> TsigSigner {
+        let mut signer = TsigSigner::new(key);
+        signer.previous_mac = Some(request_mac.to_vec());
+        signer
+    }
+
+    /// The MAC of the last signed message.
+    pub fn mac(&self) -> Option<&[u8]> {
+        match self.signed {
+            0 => None,
+            _ => self.previous_mac.as_deref(),
+        }
+    }
+
+    /// Appends a TSIG record signing `packet` at `time_signed`, replacing any
+    /// TSIG record it already carries.
+    pub fn sign(&mut self, packet: &mut DnsPacket, time_signed: u64) -> crate::Result<()> {
+        self.sign_with_error(packet, time_signed, ResultCode::NOERROR, Vec::new())
+    }
+
+    /// Like `sign`, with a TSIG error and other data, e.g. the server time
+    /// when rejecting a request with `BADTIME`.
+    pub fn sign_with_error(
+        &mut self,
+        packet: &mut DnsPacket,
+        time_signed: u64,
+        error: ResultCode,
+        other: Vec<u8>,
+    ) -> crate::Result<()> {
+        packet
+            .resources
+            .retain(|record| record.qtype() != QueryType::TSIG);
+
+        let mut buffer = PacketBuffer::with_capacity(MAX_MESSAGE_LENGTH);
+        packet.write(&mut buffer)?;
+
+        let mut record = DnsRecord::TSIG {
+            domain: self.key.name.clone(),
+            class: DnsClass::ANY,
+            algorithm: self.key.algorithm.name(),
+            time_signed,
+            fudge: self.fudge,
+            mac: Vec::new(),
+            original_id: packet.header.id,
+            error,
+            other,
+            ttl: 0,
+        };
+        let data = digest(
+            self.previous_mac.as_deref(),
+            &buffer.buffer[..buffer.position],
+            &variables(&record, self.signed > 0),
+        );
+        let signature = self.key.compute(&data);
+        if let DnsRecord::TSIG { mac, .. } = &mut record {
+            mac.clone_from(&signature);
+        }
+
+        packet.resources.push(record);
+        self.previous_mac = Some(signature);
+        self.signed += 1;
+
+        Ok(())
+    }
+}
+
+/// Verifies a signed request, or the messages answering one.
+///
+/// In a stream up to 99 consecutive messages may be unsigned, they are
+/// covered by the MAC of the next signed message. The first and the last
+/// message must be signed, see `finish`.
+pub struct TsigVerifier {
+    pub key: TsigKey,
+    previous_mac: Option<Vec<u8>>,
+    verified: usize,
+    unsigned: Vec<u8>,
+    unsigned_count: usize,
+}
+
+impl TsigVerifier {
+    pub fn new(key: TsigKey) -> TsigVerifier {
+        TsigVerifier {
+            key,
+            previous_mac: None,
+            verified: 0,
+            unsigned: Vec::new(),
+            unsigned_count: 0,
+        }
+    }
+
+    /// A verifier for the response to a request signed with `request_mac`.
+    pub fn for_response(key: TsigKey, request_mac: &[u8]) -> TsigVerifier {
+        let mut verifier = TsigVerifier::new(key);
+        verifier.previous_mac = Some(request_mac.to_vec());
+        verifier
+    }
+
+    /// The MAC of the last verified message.
+    pub fn mac(&self) -> Option<&[u8]> {
+        match self.verified {
+            0 => None,
+            _ => self.previous_mac.as_deref(),
+        }
+    }
+
+    /// Parses the message in `bytes`, received at `now`, and checks its
+    /// signature. The returned packet still carries its TSIG record.
+    pub fn verify(&mut self, bytes: &[u8], now: u64) -> crate::Result<DnsPacket> {
+        let mut buffer = PacketBuffer::from_bytes(bytes)?;
+        let packet = DnsPacket::from_buffer(&mut buffer)?;
+
+        let Some(record) = tsig_record(&packet)? else {
+            if self.verified == 0 {
+                return Err(DnsError::TsigMissing);
+            }
+            if self.unsigned_count == MAX_UNSIGNED_MESSAGES {
+                return Err(DnsError::TsigUnsignedRun);
+            }
+            self.unsigned.extend_from_slice(bytes);
+            self.unsigned_count += 1;
+            return Ok(packet);
+        };
+        let DnsRecord::TSIG {
+            domain,
+            algorithm,
+            time_signed,
+            fudge,
+            mac,
+            original_id,
+            error,
+            ..
+        } = record
+        else {
+            unreachable!("tsig_record only returns TSIG records");
+        };
+
+        if *domain != self.key.name || *algorithm != self.key.algorithm.name() {
+            return Err(DnsError::TsigUnknownKey(domain.clone()));
+        }
+        // The MAC covers the message as it was before the TSIG was added
+        let records = packet.answers.len() + packet.authorities.len() + packet.resources.len();
+        let mut message = bytes[..record_offset(bytes, records - 1)?].to_vec();
+        message[0..2].copy_from_slice(&original_id.to_be_bytes());
+        message[10..12].copy_from_slice(&(packet.resources.len() as u16 - 1).to_be_bytes());
+
+        let mut covered = std::mem::take(&mut self.unsigned);
+        covered.extend_from_slice(&message);
+        let data = digest(
+            self.previous_mac.as_deref(),
+            &covered,
+            &variables(record, self.verified > 0),
+        );
+        if !self.key.verify(&data, mac) {
+            return Err(DnsError::TsigBadSignature);
+        }
+        // A rejection only counts once its MAC checks out, the unsigned
+        // BADSIG and BADKEY ones stop at the check above
+        if *error != ResultCode::NOERROR {
+            return Err(DnsError::TsigRejected(*error));
+        }
+
+        if now.abs_diff(*time_signed) > *fudge as u64 {
+            return Err(DnsError::TsigBadTime {
+                time_signed: *time_signed,
+                now,
+            });
+        }
+
+        self.previous_mac = Some(mac.clone());
+        self.verified += 1;
+        self.unsigned_count = 0;
+
+        Ok(packet)
+    }
+
+    /// Checks that the stream ended with a signed message.
+    pub fn finish(&self) -> crate::Result<()> {
+        match self.verified == 0 || self.unsigned_count > 0 {
+            true => Err(DnsError::TsigMissing),
+            false => Ok(()),
+        }
+    }
+}
+
+/// The TSIG record of `packet`, which may only be the last record of the
+/// additional section.
+pub fn tsig_record(packet: &DnsPacket) -> crate::Result<Option<&DnsRecord>> {
+    let records: Vec<&DnsRecord> = packet
+        .answers
+        .iter()
+        .chain(&packet.authorities)
+        .chain(&packet.resources)
+        .collect();
+
+    match records
+        .iter()
+        .position(|record| record.qtype() == QueryType::TSIG)
+    {
+        None => Ok(None),
+        Some(index) if index + 1 == records.len() && !packet.resources.is_empty() => {
+            Ok(Some(records[index]))
+        }
+        Some(_) => Err(DnsError::TsigMisplaced),
+    }
+}
+
+/// Current time in seconds since the epoch, as carried in TSIG records.
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+/// Offset in the message `bytes` where the record following the first
+/// `records` resource records starts.
+fn record_offset(bytes: &[u8], records: usize) -> crate::Result<usize> {
+    let mut buffer = PacketBuffer::from_bytes(bytes)?;
+    let mut header = DnsHeader::new();
+    header.read(&mut buffer)?;
+
+    for _ in 0..header.questions {
+        let mut question = DnsQuestion::new(Name::root(), QueryType::UNKNOWN(0));
+        question.read(&mut buffer)?;
+    }
+    for _ in 0..records {
+        DnsRecord::read(&mut buffer)?;
+    }
+
+    Ok(buffer.position())
+}
+
+/// The data a MAC is computed over: the previous MAC when chaining, the
+/// message without its TSIG record and the TSIG variables.
+fn digest(previous_mac: Option<&[u8]>, message: &[u8], variables: &[u8]) -> Vec<u8> {
+    let mut data = Vec::new();
+    if let Some(mac) = previous_mac {
+        data.extend_from_slice(&(mac.len() as u16).to_be_bytes());
+        data.extend_from_slice(mac);
+    }
+    data.extend_from_slice(message);
+    data.extend_from_slice(variables);
+
+    data
+}
+
+/// The fields of a TSIG `record` covered by its MAC, only the timers for the
+/// subsequent messages of a stream.
+fn variables(record: &DnsRecord, timers_only: bool) -> Vec<u8> {
+    let DnsRecord::TSIG {
+        domain,
+        class,
+        algorithm,
+        time_signed,
+        fudge,
+        error,
+        other,
+        ttl,
+        ..
+    } = record
+    else {
+        return Vec::new();
+    };
+
+    let mut data = Vec::new();
+    if !timers_only {
+        write_canonical(domain, &mut data);
+        data.extend_from_slice(&class.to_number().to_be_bytes());
+        data.extend_from_slice(&ttl.to_be_bytes());
+        write_canonical(algorithm, &mut data);
+    }
+    data.extend_from_slice(&time_signed.to_be_bytes()[2..]);
+    data.extend_from_slice(&fudge.to_be_bytes());
+    if !timers_only {
+        data.extend_from_slice(&error.to_number().to_be_bytes());
+        data.extend_from_slice(&(other.len() as u16).to_be_bytes());
+        data.extend_from_slice(other);
+    }
+
+    data
+}
+
+/// Appends `name` in canonical wire form: lowercase and uncompressed.
+fn write_canonical(name: &Name, data: &mut Vec<u8>) {
+    for label in name.to_lowercase().labels() {
+        data.push(label.len() as u8);
+        data.extend_from_slice(label);
+    }
+    data.push(0);
+}
@@ -0,0 +1,349 @@
+use crate::name::Name;
+use crate::protocol::{DnsClass, DnsPacket, DnsQuestion, DnsRecord, Opcode, QueryType, ResultCode};
+use crate::zone::{serial_greater, Zone};
+
+/// Builds an RFC 2136 UPDATE message.
+///
+/// In UPDATE messages the question section holds the zone, the answer
+/// section the prerequisites and the authority section the updates.
+pub struct UpdateBuilder {
+    packet: DnsPacket,
+    class: DnsClass,
+}
+
+impl UpdateBuilder {
+    pub fn new(zone: Name, class: DnsClass) -> UpdateBuilder {
+        let mut packet = DnsPacket::new();
+        packet.header.opcode = Opcode::UPDATE;
+
+        let mut question = DnsQuestion::new(zone, QueryType::SOA);
+        question.qclass = class;
+        packet.questions.push(question);
+
+        UpdateBuilder { packet, class }
+    }
+
+    /// Requires at least one record of `qtype` at `name`.
+    pub fn require_rrset_exists(mut self, name: Name, qtype: QueryType) -> UpdateBuilder {
+        let record = empty_record(name, DnsClass::ANY, qtype);
+        self.packet.answers.push(record);
+        self
+    }
+
+    /// Requires the RRset of `records` to exist with exactly these records,
+    /// all of which must share owner and type.
+    pub fn require_rrset_matches(mut self, records: Vec<DnsRecord>) -> UpdateBuilder {
+        for mut record in records {
+            record.set_class(self.class);
+            record.set_ttl(0);
+            self.packet.answers.push(record);
+        }
+        self
+    }
+
+    /// Requires no record of `qtype` at `name`.
+    pub fn require_rrset_absent(mut self, name: Name, qtype: QueryType) -> UpdateBuilder {
+        let record = empty_record(name, DnsClass::NONE, qtype);
+        self.packet.answers.push(record);
+        self
+    }
+
+    /// Requires `name` to own at least one record.
+    pub fn require_name_in_use(mut self, name: Name) -> UpdateBuilder {
+        let record = empty_record(name, DnsClass::ANY, QueryType::ANY);
+        self.packet.answers.push(record);
+        self
+    }
+
+    /// Requires `name` to own no record.
+    pub fn require_name_absent(mut self, name: Name) -> UpdateBuilder {
+        let record = empty_record(name, DnsClass::NONE, QueryType::ANY);
+        self.packet.answers.push(record);
+        self
+    }
+
+    pub fn add_record(mut self, mut record: DnsRecord) -> UpdateBuilder {
+        record.set_class(self.class);
+        self.packet.authorities.push(record);
+        self
+    }
+
+    /// Deletes every record of `qtype` at `name`.
+    pub fn delete_rrset(mut self, name: Name, qtype: QueryType) -> UpdateBuilder {
+        let record = empty_record(name, DnsClass::ANY, qtype);
+        self.packet.authorities.push(record);
+        self
+    }
+
+    /// Deletes every record at `name`.
+    pub fn delete_name(mut self, name: Name) -> UpdateBuilder {
+        let record = empty_record(name, DnsClass::ANY, QueryType::ANY);
+        self.packet.authorities.push(record);
+        self
+    }
+
+    /// Deletes the record with the same owner, type and data as `record`.
+    pub fn delete_record(mut self, mut record: DnsRecord) -> UpdateBuilder {
+        record.set_class(DnsClass::NONE);
+        record.set_ttl(0);
+        self.packet.authorities.push(record);
+        self
+    }
+
+    pub fn build(self, id: u16) -> DnsPacket {
+        let mut packet = self.packet;
+        packet.header.id = id;
+        packet
+    }
+}
+
+fn empty_record(domain: Name, class: DnsClass, qtype: QueryType) -> DnsRecord {
+    DnsRecord::UNKNOWN {
+        domain,
+        class,
+        qtype: qtype.to_number(),
+        data: Vec::new(),
+        ttl: 0,
+    }
+}
+
+/// Whether `record` carries no RDATA, as in RRset and name references.
+fn is_empty(record: &DnsRecord) -> bool {
+    matches!(record, DnsRecord::UNKNOWN { data, .. } if data.is_empty())
+}
+
+/// Types that can not be added to or deleted from a zone.
+fn is_meta(qtype: QueryType) -> bool {
+    matches!(qtype, QueryType::ANY | QueryType::OPT) || matches!(qtype.to_number(), 128..=255)
+}
+
+/// Processes the UPDATE `request` against `zone` following RFC 2136 section
+/// 3. Either every update is applied or, on any failure, none is. When the
+/// zone changed and the update did not set a newer SOA itself, the serial is
+/// incremented, and the change is recorded in the zone journal. Zones
+/// without an SOA record take no updates and get `SERVFAIL`.
+pub fn apply_update(zone: &mut Zone, request: &DnsPacket) -> ResultCode {
+    // Zone section
+    let [question] = request.questions.as_slice() else {
+        return ResultCode::FORMERR;
+    };
+    if question.qtype != QueryType::SOA {
+        return ResultCode::FORMERR;
+    }
+    if question.name != zone.origin || question.qclass != zone.class {
+        return ResultCode::NOTAUTH;
+    }
+    // Without an SOA there is no serial to tell secondaries of the change
+    let Some(serial) = zone.serial() else {
+        return ResultCode::SERVFAIL;
+    };
+
+    if let Err(rcode) = check_prerequisites(zone, &request.answers) {
+        return rcode;
+    }
+    if let Err(rcode) = prescan(zone, &request.authorities) {
+        return rcode;
+    }
+
+    // Work on a copy so that the update is atomic
+    let mut updated = zone.clone();
+    let mut changed = false;
+    for record in &request.authorities {
+        changed |= apply_record(&mut updated, record);
+    }
+
+    if changed {
+        let new_serial = updated.serial().unwrap_or(serial);
+        if !serial_greater(new_serial, serial) {
+            updated.set_serial(serial.wrapping_add(1));
+        }
+        if let Some(diff) = updated.diff_from(zone) {
+            updated.record_diff(diff);
+        }
+        *zone = updated;
+    }
+
+    ResultCode::NOERROR
+}
+
+fn check_prerequisites(zone: &Zone, prerequisites: &[DnsRecord]) -> Result<(), ResultCode> {
+    // Value dependent prerequisites, grouped by RRset
+    let mut expected: Vec<(Name, QueryType, Vec<&DnsRecord>)> = Vec::new();
+
+    for record in prerequisites {
+        if record.ttl() != 0 {
+            return Err(ResultCode::FORMERR);
+        }
+        let name = record.domain();
+        if !zone.contains(name) {
+            return Err(ResultCode::NOTZONE);
+        }
+
+        let qtype = record.qtype();
+        match record.class() {
+            DnsClass::ANY => {
+                if !is_empty(record) {
+                    return Err(ResultCode::FORMERR);
+                }
+                if qtype == QueryType::ANY {
+                    if !zone.name_in_use(name) {
+                        return Err(ResultCode::NXDOMAIN);
+                    }
+                } else if zone.rrset(name, qtype).is_empty() {
+                    return Err(ResultCode::NXRRSET);
+                }
+            }
+            DnsClass::NONE => {
+                if !is_empty(record) {
+                    return Err(ResultCode::FORMERR);
+                }
+                if qtype == QueryType::ANY {
+                    if zone.name_in_use(name) {
+                        return Err(ResultCode::YXDOMAIN);
+                    }
+                } else if !zone.rrset(name, qtype).is_empty() {
+                    return Err(ResultCode::YXRRSET);
+                }
+            }
+            class if class == zone.class => {
+                match expected
+                    .iter_mut()
+                    .find(|(owner, rrtype, _)| owner == name && *rrtype == qtype)
+                {
+                    Some((_, _, records)) => records.push(record),
+                    None => expected.push((name.clone(), qtype, vec![record])),
+                }
+            }
+            _ => return Err(ResultCode::FORMERR),
+        }
+    }
+
+    for (name, qtype, records) in expected {
+        let existing = zone.rrset(&name, qtype);
+        let matches = existing.len() == records.len()
+            && existing
+                .iter()
+                .all(|existing| records.iter().any(|record| existing.same_data(record)));
+        if !matches {
+            return Err(ResultCode::NXRRSET);
+        }
+    }
+
+    Ok(())
+}
+
+fn prescan(zone: &Zone, updates: &[DnsRecord]) -> Result<(), ResultCode> {
+    for record in updates {
+        if !zone.contains(record.domain()) {
+            return Err(ResultCode::NOTZONE);
+        }
+
+        let qtype = record.qtype();
+        let valid = match record.class() {
+            class if class == zone.class => !is_meta(qtype) && !is_empty(record),
+            DnsClass::ANY => {
+                record.ttl() == 0
+                    && is_empty(record)
+                    && (qtype == QueryType::ANY || !is_meta(qtype))
+            }
+            DnsClass::NONE => record.ttl() == 0 && !is_meta(qtype) && !is_empty(record),
+            _ => false,
+        };
+        if !valid {
+            return Err(ResultCode::FORMERR);
+        }
+    }
+
+    Ok(())
+}
+
+/// Applies a single prescanned update, returns whether the zone changed.
+fn apply_record(zone: &mut Zone, record: &DnsRecord) -> bool {
+    let name = record.domain().clone();
+    let qtype = record.qtype();
+    let at_apex = name == zone.origin;
+
+    match record.class() {
+        class if class == zone.class => {
+            if qtype == QueryType::SOA {
+                // Only the apex SOA can be replaced, and only by a newer one
+                let newer = match (record, zone.serial()) {
+                    (DnsRecord::SOA { serial, .. }, Some(current)) => {
+                        serial_greater(*serial, current)
+                    }
+                    _ => false,
+                };
+                if !at_apex || !newer {
+                    return false;
+                }
+                zone.set_soa(record.clone());
+                return true;
+            }
+
+            // A CNAME can not coexist with any other data
+            let existing = zone.rrset(&name, QueryType::ANY);
+            let has_cname = existing.iter().any(|r| r.qtype() == QueryType::CNAME);
+            let has_other = existing.iter().any(|r| r.qtype() != QueryType::CNAME);
+            if qtype == QueryType::CNAME && has_other {
+                return false;
+            }
+            if qtype != QueryType::CNAME && has_cname {
+                return false;
+            }
+            if qtype == QueryType::CNAME {
+                zone.remove_rrset(&name, QueryType::CNAME);
+            }
+
+            zone.insert(record.clone())
+        }
+        DnsClass::ANY if qtype == QueryType::ANY => {
+            if !at_apex {
+                return zone.remove_name(&name);
+            }
+
+            // The apex keeps its SOA and NS records
+            let removable: Vec<QueryType> = zone
+                .rrset(&name, QueryType::ANY)
+                .iter()
+                .map(|record| record.qtype())
+                .filter(|qtype| *qtype != QueryType::SOA && *qtype != QueryType::NS)
+                .collect();
+            let mut changed = false;
+            for qtype in removable {
+                changed |= zone.remove_rrset(&name, qtype);
+            }
+            changed
+        }
+        DnsClass::ANY => {
+            if at_apex && (qtype == QueryType::SOA || qtype == QueryType::NS) {
+                return false;
+            }
+            zone.remove_rrset(&name, qtype)
+        }
+        DnsClass::NONE => {
+            if qtype == QueryType::SOA {
+                return false;
+            }
+            if at_apex && qtype == QueryType::NS && zone.rrset(&name, QueryType::NS).len() <= 1 {
+                return false;
+            }
+
+            let mut target = record.clone();
+            target.set_class(zone.class);
+            zone.remove(&target)
+        }
+        _ => false,
+    }
+}
+
+/// Builds the response to an UPDATE `request` carrying `rcode`.
+pub fn update_response(request: &DnsPacket, rcode: ResultCode) -> DnsPacket {
+    let mut response = DnsPacket::new();
+    response.header.id = request.header.id;
+    response.header.opcode = Opcode::UPDATE;
+    response.header.response = true;
+    response.questions = request.questions.clone();
+    response.set_rcode(rcode);
+
+    response
+}
@@ -0,0 +1,382 @@
+use std::collections::BTreeMap;
+
+use crate::name::Name;
+use crate::protocol::{DnsClass, DnsRecord, QueryType};
+use crate::DnsError;
+
+/// Most changes kept in a zone journal, older ones are dropped and clients
+/// that far behind get a full transfer instead.
+const MAX_JOURNAL_LENGTH: usize = 1000;
+
+/// Result of looking a name up in a zone.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Lookup {
+    /// Records answering the question, a CNAME is followed once when its
+    /// target is in the zone.
+    Answer(Vec<DnsRecord>),
+    /// The name is below a zone cut, the NS records of the cut and any glue
+    /// for them that the zone holds.
+    Referral {
+        ns: Vec<DnsRecord>,
+        glue: Vec<DnsRecord>,
+    },
+    /// The name exists but has no records of the requested type.
+    NoData,
+    NxDomain,
+}
+
+/// The changes taking a zone from one serial to the next, laid out as in
+/// IXFR responses: the old SOA and the removed records, then the new SOA and
+/// the added ones.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diff {
+    pub old_soa: DnsRecord,
+    pub removed: Vec<DnsRecord>,
+    pub new_soa: DnsRecord,
+    pub added: Vec<DnsRecord>,
+}
+
+impl Diff {
+    pub fn old_serial(&self) -> u32 {
+        soa_serial(&self.old_soa).unwrap_or_default()
+    }
+
+    pub fn new_serial(&self) -> u32 {
+        soa_serial(&self.new_soa).unwrap_or_default()
+    }
+}
+
+/// An authoritative zone held in memory, records grouped by owner name in
+/// canonical order, along with a journal of its recent changes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Zone {
+    pub origin: Name,
+    pub class: DnsClass,
+    nodes: BTreeMap<Name, Vec<DnsRecord>>,
+    journal: Vec<Diff>,
+}
+
+impl Zone {
+    pub fn new(origin: Name, class: DnsClass) -> Zone {
+        Zone {
+            origin,
+            class,
+            nodes: BTreeMap::new(),
+            journal: Vec::new(),
+        }
+    }
+
+    pub fn soa(&self) -> Option<&DnsRecord> {
+        self.nodes
+            .get(&self.origin)?
+            .iter()
+            .find(|record| record.qtype() == QueryType::SOA)
+    }
+
+    pub fn serial(&self) -> Option<u32> {
+        soa_serial(self.soa()?)
+    }
+
+    /// Sets the SOA serial, returns false when the zone has no SOA record.
+    pub fn set_serial(&mut self, new_serial: u32) -> bool {
+        let soa = self.nodes.get_mut(&self.origin).and_then(|records| {
+            records
+                .iter_mut()
+                .find(|record| record.qtype() == QueryType::SOA)
+        });
+        match soa {
+            Some(DnsRecord::SOA { serial, .. }) => {
+                *serial = new_serial;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Replaces the SOA record in place, or adds `soa` when the zone has
+    /// none.
+    pub fn set_soa(&mut self, soa: DnsRecord) {
+        let existing = self.nodes.get_mut(&self.origin).and_then(|records| {
+            records
+                .iter_mut()
+                .find(|record| record.qtype() == QueryType::SOA)
+        });
+        match existing {
+            Some(existing) => *existing = soa,
+            None => {
+                self.insert(soa);
+            }
+        }
+    }
+
+    /// Whether `name` is at or below the origin of this zone.
+    pub fn contains(&self, name: &Name) -> bool {
+        name.is_subdomain_of(&self.origin)
+    }
+
+    /// Whether any record is owned by `name`.
+    pub fn name_in_use(&self, name: &Name) -> bool {
+        self.nodes.contains_key(name)
+    }
+
+    /// Records of `qtype` owned by `name`, every record at the name for
+    /// `QueryType::ANY`.
+    pub fn rrset(&self, name: &Name, qtype: QueryType) -> Vec<&DnsRecord> {
+        self.nodes
+            .get(name)
+            .map(|records| {
+                records
+                    .iter()
+                    .filter(|record| qtype == QueryType::ANY || record.qtype() == qtype)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Iterates over all records, in canonical order of their owner names.
+    pub fn records(&self) -> impl Iterator<Item = &DnsRecord> {
+        self.nodes.values().flatten()
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Adds `record`, replacing one with the same data so that its TTL is
+    /// updated. Returns whether the zone changed.
+    pub fn insert(&mut self, record: DnsRecord) -> bool {
+        let records = self.nodes.entry(record.domain().clone()).or_default();
+        match records
+            .iter_mut()
+            .find(|existing| existing.same_data(&record))
+        {
+            Some(existing) if *existing == record => false,
+            Some(existing) => {
+                *existing = record;
+                true
+            }
+            None => {
+                records.push(record);
+                true
+            }
+        }
+    }
+
+    /// Removes the record with the same data as `record`, whatever its TTL.
+    pub fn remove(&mut self, record: &DnsRecord) -> bool {
+        self.remove_matching(record.domain(), |existing| existing.same_data(record))
+    }
+
+    pub fn remove_rrset(&mut self, name: &Name, qtype: QueryType) -> bool {
+        self.remove_matching(name, |existing| existing.qtype() == qtype)
+    }
+
+    pub fn remove_name(&mut self, name: &Name) -> bool {
+        self.nodes.remove(name).is_some()
+    }
+
+    fn remove_matching<F>(&mut self, name: &Name, matches: F) -> bool
+    where
+        F: Fn(&DnsRecord) -> bool,
+    {
+        let Some(records) = self.nodes.get_mut(name) else {
+            return false;
+        };
+
+        let before = records.len();
+        records.retain(|existing| !matches(existing));
+        let removed = records.len() != before;
+        if records.is_empty() {
+            self.nodes.remove(name);
+        }
+
+        removed
+    }
+
+    /// The changes from `older`, a previous version of this zone, or `None`
+    /// when either version lacks an SOA record.
+    pub fn diff_from(&self, older: &Zone) -> Option<Diff> {
+        let changed = |from: &Zone, to: &Zone| {
+            from.records()
+                .filter(|record| record.qtype() != QueryType::SOA)
+                .filter(|record| !to.rrset(record.domain(), record.qtype()).contains(record))
+                .cloned()
+                .collect()
+        };
+
+        Some(Diff {
+            old_soa: older.soa()?.clone(),
+            removed: changed(older, self),
+            new_soa: self.soa()?.clone(),
+            added: changed(self, older),
+        })
+    }
+
+    /// Applies `diff`, which must start at the current serial, and records
+    /// it in the journal.
+    pub fn apply_diff(&mut self, diff: &Diff) -> crate::Result<()> {
+        let current = self.serial().unwrap_or_default();
+        if diff.old_serial() != current {
+            return Err(DnsError::SerialMismatch {
+                expected: current,
+                received: diff.old_serial(),
+            });
+        }
+
+        for record in &diff.removed {
+            self.remove(record);
+        }
+        self.set_soa(diff.new_soa.clone());
+        for record in &diff.added {
+            self.insert(record.clone());
+        }
+
+        self.record_diff(diff.clone());
+
+        Ok(())
+    }
+
+    /// Replaces the records with those of `newer`, a full copy of another
+    /// version of the zone, journaling the difference.
+    pub fn replace_with(&mut self, newer: Zone) {
+        let diff = newer.diff_from(self);
+        self.nodes = newer.nodes;
+
+        if let Some(diff) = diff.filter(|diff| diff.old_serial() != diff.new_serial()) {
+            self.record_diff(diff);
+        }
+    }
+
+    /// Adds `diff` to the journal, dropping the oldest entries past its
+    /// limit.
+    pub fn record_diff(&mut self, diff: Diff) {
+        self.journal.push(diff);
+        if self.journal.len() > MAX_JOURNAL_LENGTH {
+            let excess = self.journal.len() - MAX_JOURNAL_LENGTH;
+            self.journal.drain(..excess);
+        }
+    }
+
+    pub fn journal(&self) -> &[Diff] {
+        &self.journal
+    }
+
+    /// The journaled changes leading from `serial` to the current serial,
+    /// `None` when the journal does not reach back that far.
+    pub fn diffs_since(&self, serial: u32) -> Option<&[Diff]> {
+        let start = self
+            .journal
+            .iter()
+            .rposition(|diff| diff.old_serial() == serial)?;
+        let diffs = &self.journal[start..];
+
+        let contiguous = diffs
+            .windows(2)
+            .all(|pair| pair[0].new_serial() == pair[1].old_serial());
+        let current = diffs.last().map(Diff::new_serial) == self.serial();
+
+        (contiguous && current).then_some(diffs)
+    }
+
+    pub fn lookup(&self, qname: &Name, qtype: QueryType) -> Lookup {
+        if let Some(referral) = self.referral(qname) {
+            return referral;
+        }
+
+        let Some(records) = self.nodes.get(qname) else {
+            // Empty non-terminals exist even though they own nothing
+            let has_descendants = self
+                .nodes
+                .range(qname.clone()..)
+                .next()
+                .is_some_and(|(name, _)| name.is_subdomain_of(qname));
+            return match has_descendants {
+                true => Lookup::NoData,
+                false => Lookup::NxDomain,
+            };
+        };
+
+        let answers: Vec<DnsRecord> = records
+            .iter()
+            .filter(|record| qtype == QueryType::ANY || record.qtype() == qtype)
+            .cloned()
+            .collect();
+        if !answers.is_empty() {
+            return Lookup::Answer(answers);
+        }
+
+        let cname = records
+            .iter()
+            .find(|record| record.qtype() == QueryType::CNAME);
+        if let Some(cname @ DnsRecord::CNAME { host, .. }) = cname {
+            let mut answers = vec![cname.clone()];
+            if host != qname {
+                if let Lookup::Answer(target) = self.lookup(host, qtype) {
+                    answers.extend(target.into_iter().filter(|record| {
+                        // Only one step is followed, never a loop back
+                        record.qtype() != QueryType::CNAME
+                    }));
+                }
+            }
+            return Lookup::Answer(answers);
+        }
+
+        Lookup::NoData
+    }
+
+    /// Finds the highest zone cut between the origin and `qname`.
+    fn referral(&self, qname: &Name) -> Option<Lookup> {
+        let mut cuts = Vec::new();
+        let mut name = qname.clone();
+        while name != self.origin {
+            cuts.push(name.clone());
+            name = name.parent()?;
+        }
+
+        for cut in cuts.iter().rev() {
+            let ns: Vec<DnsRecord> = self
+                .rrset(cut, QueryType::NS)
+                .into_iter()
+                .cloned()
+                .collect();
+            if ns.is_empty() {
+                continue;
+            }
+
+            let glue = ns
+                .iter()
+                .filter_map(|record| match record {
+                    DnsRecord::NS { host, .. } if host.is_subdomain_of(&self.origin) => Some(host),
+                    _ => None,
+                })
+                .flat_map(|host| {
+                    self.rrset(host, QueryType::A)
+                        .into_iter()
+                        .chain(self.rrset(host, QueryType::AAAA))
+                        .cloned()
+                })
+                .collect();
+
+            return Some(Lookup::Referral { ns, glue });
+        }
+
+        None
+    }
+}
+
+fn soa_serial(record: &DnsRecord) -> Option<u32> {
+    match record {
+        DnsRecord::SOA { serial, .. } => Some(*serial),
+        _ => None,
+    }
+}
+
+/// Whether `first` is greater than `second` in serial number arithmetic
+/// (RFC 1982), which tolerates the counter wrapping around.
+pub fn serial_greater(first: u32, second: u32) -> bool {
+    first != second && first.wrapping_sub(second) < 0x8000_0000
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use tarnish_dns::acl::{Acl, Network};
+use tarnish_dns::buffer::PacketBuffer;
+use tarnish_dns::client::DnsClient;
+use tarnish_dns::protocol::{DnsClass, DnsPacket, DnsRecord, QueryType, ResultCode};
+use tarnish_dns::server::{Catalog, DnsServer};
+use tarnish_dns::update::UpdateBuilder;
+use tarnish_dns::zone::Zone;
+
+use common::{name, origin, serve, soa, www_zone};
+
+fn ip(text: &str) -> IpAddr {
+    text.parse().unwrap()
+}
+
+#[test]
+fn networks_are_parsed_and_matched() {
+    let network: Network = "192.0.2.0/24".parse().unwrap();
+    assert!(network.contains(ip("192.0.2.200")));
+    assert!(!network.contains(ip("192.0.3.1")));
+    assert!(network.contains(ip("::ffff:192.0.2.1")));
+    assert!(!network.contains(ip("2001:db8::1")));
+
+    let network: Network = "2001:db8::/32".parse().unwrap();
+    assert!(network.contains(ip("2001:db8:ffff::1")));
+    assert!(!network.contains(ip("2001:db9::1")));
+
+    let host: Network = "198.51.100.7".parse().unwrap();
+    assert_eq!(host.to_string(), "198.51.100.7/32");
+    assert!(!host.contains(ip("198.51.100.8")));
+    assert!("0.0.0.0/0"
+        .parse::<Network>()
+        .unwrap()
+        .contains(ip("203.0.113.1")));
+
+    for invalid in [
+        "192.0.2.0/33",
+        "2001:db8::/129",
+        "example.com",
+        "192.0.2.0/x",
+    ] {
+        assert!(invalid.parse::<Network>().is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn clients_outside_the_acl_are_refused() {
+    let query = |query: Vec<Network>| {
+        let acl = Acl {
+            query,
+            ..Acl::new()
+        };
+        let address = serve(www_zone(), |server| server.acl = acl);
+        DnsClient::new(address)
+            .query(&name("www.example.com"), QueryType::A)
+            .unwrap()
+    };
+
+    let response = query(vec!["192.0.2.0/24".parse().unwrap()]);
+    assert_eq!(response.rcode(), ResultCode::REFUSED);
+    assert!(response.answers.is_empty());
+
+    let response = query(Acl::any());
+    assert_eq!(response.rcode(), ResultCode::NOERROR);
+    assert_eq!(response.answers.len(), 1);
+}
+
+/// Hands `request` to `server` as if received over UDP from `source`.
+fn handle(server: &DnsServer, mut request: DnsPacket, source: &str) -> DnsPacket {
+    let mut buffer = PacketBuffer::new();
+    request.write(&mut buffer).unwrap();
+    let mut buffer = PacketBuffer::from_bytes(&buffer.buffer[..buffer.position]).unwrap();
+    let source: SocketAddr = source.parse().unwrap();
+    server.handle_buffer(&mut buffer, source).unwrap()
+}
+
+#[test]
+fn updates_are_only_allowed_from_loopback_by_default() {
+    let mut zone = Zone::new(origin(), DnsClass::IN);
+    zone.insert(soa(1));
+    let mut catalog = Catalog::new();
+    catalog.insert(zone);
+    let mut server = DnsServer::new(catalog);
+
+    let update = UpdateBuilder::new(origin(), DnsClass::IN)
+        .add_record(DnsRecord::A {
+            domain: name("www.example.com"),
+            class: DnsClass::IN,
+            address: Ipv4Addr::new(192, 0, 2, 1),
+            ttl: 300,
+        })
+        .build(1);
+    for source in ["192.0.2.53:5353", "[2001:db8::53]:5353"] {
+        let response = handle(&server, update.clone(), source);
+        assert_eq!(response.rcode(), ResultCode::REFUSED, "{}", source);
+    }
+    let response = handle(&server, update.clone(), "127.0.0.1:5353");
+    assert_eq!(response.rcode(), ResultCode::NOERROR);
+
+    // An empty list allows nobody, loopback included
+    server.acl.update = Vec::new();
+    let response = handle(&server, update, "127.0.0.1:5353");
+    assert_eq!(response.rcode(), ResultCode::REFUSED);
+}
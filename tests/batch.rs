@@ -0,0 +1,135 @@
+mod common;
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use tarnish_dns::batch::{BatchQuery, BatchRunner};
+use tarnish_dns::protocol::{DnsClass, DnsRecord, QueryType, ResultCode};
+use tarnish_dns::zone::Zone;
+
+use common::{host, name, origin, serve, soa};
+
+/// Serves example.com over UDP, with `hostN` names for N below 100 and sixty
+/// addresses for `pool`, too many for 512 bytes.
+fn start_server() -> SocketAddr {
+    let mut zone = Zone::new(origin(), DnsClass::IN);
+    zone.insert(soa(1));
+    zone.insert(DnsRecord::TXT {
+        domain: name("text.example.com"),
+        class: DnsClass::IN,
+        data: vec![b"quoted \"text\", with a comma".to_vec()],
+        ttl: 300,
+    });
+    for index in 0..100 {
+        zone.insert(host(index));
+    }
+    for index in 0..60 {
+        zone.insert(DnsRecord::A {
+            domain: name("pool.example.com"),
+            class: DnsClass::IN,
+            address: Ipv4Addr::new(198, 51, 100, index),
+            ttl: 300,
+        });
+    }
+
+    serve(zone, |_| {})
+}
+
+#[test]
+fn batch_resolves_every_line() {
+    let mut runner = BatchRunner::new(start_server());
+    runner.concurrency = 8;
+
+    let mut input = String::from("# hosts\n\n");
+    for index in 0..100 {
+        input.push_str(&format!("host{index}.example.com A\n"));
+    }
+    input.push_str("missing.example.com,AAAA\nhost1.example.com BOGUS\n");
+
+    let mut results = Vec::new();
+    let mut errors = Vec::new();
+    runner.run(
+        input.as_bytes(),
+        |result| results.push(result),
+        |error| errors.push(error),
+    );
+
+    assert_eq!(results.len(), 101);
+    assert_eq!(errors, vec!["line 104: Unknown type or class `BOGUS`"]);
+
+    results.sort_by_key(|result| result.query.line);
+    assert_eq!(results[0].query.line, 3);
+    assert_eq!(results[0].rcode, Some(ResultCode::NOERROR));
+    assert_eq!(results[0].answers.len(), 1);
+    assert!(results[..100]
+        .iter()
+        .all(|result| result.rcode == Some(ResultCode::NOERROR)));
+    assert_eq!(results[100].query.qtype, QueryType::AAAA);
+    assert_eq!(results[100].rcode, Some(ResultCode::NXDOMAIN));
+}
+
+#[test]
+fn batch_rate_limit_paces_queries() {
+    let mut runner = BatchRunner::new(start_server());
+    runner.rate = Some(100);
+
+    let input = "host1.example.com\n".repeat(21);
+    let started = Instant::now();
+    let mut count = 0;
+    runner.run(input.as_bytes(), |_| count += 1, |_| {});
+
+    // The 21st query is due 200ms after the first
+    assert_eq!(count, 21);
+    assert!(started.elapsed() >= Duration::from_millis(200));
+}
+
+#[test]
+fn batch_output_escapes_fields() {
+    let runner = BatchRunner::new(start_server());
+    let query = BatchQuery::parse(7, "text.example.com TXT")
+        .unwrap()
+        .unwrap();
+    let result = runner.resolve(query);
+
+    assert_eq!(
+        result.to_csv().split_once(",0.").unwrap().0,
+        r#"7,text.example.com,TXT,NOERROR,"""quoted \""text\"", with a comma""""#
+    );
+    let json = result.to_json();
+    assert!(json.starts_with(r#"{"line":7,"name":"text.example.com","type":"TXT","rcode":"NOERROR","answers":[{"name":"text.example.com","type":"TXT","ttl":300,"data":"\"quoted \\\"text\\\", with a comma\""}],"latency_ms":"#));
+    assert!(json.ends_with(r#","error":null}"#));
+}
+
+#[test]
+fn batch_stops_at_read_errors() {
+    let runner = BatchRunner::new(start_server());
+    let input: &[u8] = b"host1.example.com\n\xff\nhost2.example.com\n";
+
+    let mut results = Vec::new();
+    let mut errors = Vec::new();
+    runner.run(
+        input,
+        |result| results.push(result),
+        |error| errors.push(error),
+    );
+
+    assert_eq!(results.len(), 1);
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].starts_with("line 2: "), "{}", errors[0]);
+}
+
+#[test]
+fn batch_queries_use_edns() {
+    let mut runner = BatchRunner::new(start_server());
+    runner.timeout = Duration::from_millis(500);
+    let query = || BatchQuery::parse(1, "pool.example.com").unwrap().unwrap();
+
+    // Without EDNS the answer is truncated, and no TCP server is listening
+    let result = runner.resolve(query());
+    assert!(result.error.is_some());
+
+    runner.edns = Some(4096);
+    let result = runner.resolve(query());
+    assert_eq!(result.error, None);
+    assert_eq!(result.answers.len(), 60);
+}
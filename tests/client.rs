@@ -0,0 +1,190 @@
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tarnish_dns::buffer::PacketBuffer;
+use tarnish_dns::client::DnsClient;
+use tarnish_dns::name::Name;
+use tarnish_dns::protocol::{DnsClass, DnsPacket, DnsRecord, QueryType};
+use tarnish_dns::DnsError;
+
+fn name(name: &str) -> Name {
+    name.parse().unwrap()
+}
+
+fn answer(request: &DnsPacket) -> DnsPacket {
+    let mut response = DnsPacket::new();
+    response.header.id = request.header.id;
+    response.header.response = true;
+    response.questions = request.questions.clone();
+    response.answers.push(DnsRecord::A {
+        domain: request.questions[0].name.clone(),
+        class: DnsClass::IN,
+        address: Ipv4Addr::new(192, 0, 2, 1),
+        ttl: 300,
+    });
+
+    response
+}
+
+fn to_bytes(packet: &mut DnsPacket) -> Vec<u8> {
+    let mut buffer = PacketBuffer::new();
+    packet.write(&mut buffer).unwrap();
+
+    buffer.buffer[..buffer.position].to_vec()
+}
+
+/// A server answering every query it receives with what `respond` returns
+/// for it, sent from its own socket or, when `respond` says so, from another
+/// one.
+fn start_server(respond: fn(&DnsPacket) -> (DnsPacket, bool)) -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let other = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let address = socket.local_addr().unwrap();
+    thread::spawn(move || loop {
+        let mut buffer = PacketBuffer::new();
+        let (length, client) = socket.recv_from(&mut buffer.buffer).unwrap();
+        buffer.length = length;
+        let request = DnsPacket::from_buffer(&mut buffer).unwrap();
+
+        let (mut response, from_other) = respond(&request);
+        let sender = if from_other { &other } else { &socket };
+        sender.send_to(&to_bytes(&mut response), client).unwrap();
+    });
+
+    address
+}
+
+fn client(address: SocketAddr) -> DnsClient {
+    let mut client = DnsClient::new(address);
+    client.timeout = Duration::from_millis(200);
+    client
+}
+
+#[test]
+fn answers_from_another_address_are_reported_after_the_timeout() {
+    let address = start_server(|request| (answer(request), true));
+
+    match client(address).query(&name("www.example.com"), QueryType::A) {
+        Err(DnsError::UnexpectedSource { expected, received }) => {
+            assert_eq!(expected, address);
+            assert_ne!(received, address);
+        }
+        other => panic!("expected an unexpected source, got {:?}", other),
+    }
+}
+
+#[test]
+fn answers_with_another_id_are_reported_after_the_timeout() {
+    let address = start_server(|request| {
+        let mut response = answer(request);
+        response.header.id = request.header.id.wrapping_add(1);
+        (response, false)
+    });
+
+    match client(address).query(&name("www.example.com"), QueryType::A) {
+        Err(DnsError::IdMismatch { expected, received }) => {
+            assert_eq!(received, expected.wrapping_add(1));
+        }
+        other => panic!("expected an ID mismatch, got {:?}", other),
+    }
+}
+
+#[test]
+fn answers_to_another_question_are_reported_after_the_timeout() {
+    let address = start_server(|request| {
+        let mut response = answer(request);
+        response.questions[0].name = name("mail.example.com");
+        (response, false)
+    });
+
+    assert!(matches!(
+        client(address).query(&name("www.example.com"), QueryType::A),
+        Err(DnsError::QuestionMismatch)
+    ));
+}
+
+#[test]
+fn answers_changing_the_case_of_0x20_queries_are_rejected() {
+    // The case of every letter flipped, which never matches the query
+    let address = start_server(|request| {
+        let mut response = answer(request);
+        let flipped = request.questions[0].name.labels().map(|label| {
+            label
+                .iter()
+                .map(|&byte| match byte.is_ascii_alphabetic() {
+                    true => byte ^ 0x20,
+                    false => byte,
+                })
+                .collect::<Vec<u8>>()
+        });
+        response.questions[0].name = Name::from_labels(flipped).unwrap();
+        (response, false)
+    });
+
+    let mut client = client(address);
+    client.randomize_case = true;
+    client.retries = 1;
+    let started = Instant::now();
+    assert!(matches!(
+        client.query(&name("www.example.com"), QueryType::A),
+        Err(DnsError::CaseMismatch)
+    ));
+    assert!(started.elapsed() >= client.timeout * 2);
+
+    client.randomize_case = false;
+    let response = client
+        .query(&name("www.example.com"), QueryType::A)
+        .unwrap();
+    assert_eq!(response.answers.len(), 1);
+}
+
+#[test]
+fn mismatched_answers_are_retried() {
+    let address = start_server(|request| {
+        let mut response = answer(request);
+        response.header.id = request.header.id.wrapping_add(1);
+        (response, false)
+    });
+
+    let mut client = client(address);
+    client.retries = 2;
+    let started = Instant::now();
+    assert!(matches!(
+        client.query(&name("www.example.com"), QueryType::A),
+        Err(DnsError::IdMismatch { .. })
+    ));
+    assert!(started.elapsed() >= client.timeout * 3);
+}
+
+#[test]
+fn silence_is_a_timeout() {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let address = socket.local_addr().unwrap();
+
+    assert!(matches!(
+        client(address).query(&name("www.example.com"), QueryType::A),
+        Err(DnsError::Timeout)
+    ));
+}
+
+#[test]
+fn every_attempt_has_a_fresh_id() {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let address = socket.local_addr().unwrap();
+    let mut client = client(address);
+    client.retries = 2;
+    let query = thread::spawn(move || client.query(&name("www.example.com"), QueryType::A));
+
+    let mut ids = Vec::new();
+    for _ in 0..3 {
+        let mut bytes = [0; 512];
+        let (length, _) = socket.recv_from(&mut bytes).unwrap();
+        let request =
+            DnsPacket::from_buffer(&mut PacketBuffer::from_bytes(&bytes[..length]).unwrap())
+                .unwrap();
+        ids.push(request.header.id);
+    }
+    assert!(matches!(query.join().unwrap(), Err(DnsError::Timeout)));
+    assert!(ids.iter().any(|&id| id != ids[0]), "{:?}", ids);
+}
@@ -0,0 +1,103 @@
+//! Fixtures shared by the integration tests, around the zone example.com.
+
+// Each test crate uses its own share of these
+#![allow(dead_code)]
+
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::thread;
+
+use tarnish_dns::name::Name;
+use tarnish_dns::protocol::{DnsClass, DnsRecord};
+use tarnish_dns::server::{Catalog, DnsServer};
+use tarnish_dns::zone::Zone;
+
+pub fn name(name: &str) -> Name {
+    name.parse().unwrap()
+}
+
+pub fn origin() -> Name {
+    name("example.com")
+}
+
+/// An A record for `host<index>.example.com`, with an address of its own.
+pub fn host(index: usize) -> DnsRecord {
+    DnsRecord::A {
+        domain: name(&format!("host{index}.example.com")),
+        class: DnsClass::IN,
+        address: Ipv4Addr::new(192, 0, (index >> 8) as u8, index as u8),
+        ttl: 3600,
+    }
+}
+
+pub fn soa(serial: u32) -> DnsRecord {
+    DnsRecord::SOA {
+        domain: origin(),
+        class: DnsClass::IN,
+        m_name: name("ns1.example.com"),
+        r_name: name("hostmaster.example.com"),
+        serial,
+        refresh: 3600,
+        retry: 600,
+        expire: 86400,
+        minimum: 300,
+        ttl: 3600,
+    }
+}
+
+pub fn ns(host: &str) -> DnsRecord {
+    DnsRecord::NS {
+        domain: origin(),
+        class: DnsClass::IN,
+        host: name(host),
+        ttl: 3600,
+    }
+}
+
+/// The zone as a primary starts out: an SOA at serial 1 and one NS record.
+pub fn primary_zone() -> Zone {
+    let mut zone = Zone::new(origin(), DnsClass::IN);
+    zone.insert(soa(1));
+    zone.insert(ns("ns1.example.com"));
+
+    zone
+}
+
+/// The zone the query tests ask: no SOA, only `www.example.com` at
+/// 192.0.2.1.
+pub fn www_zone() -> Zone {
+    let mut zone = Zone::new(origin(), DnsClass::IN);
+    zone.insert(DnsRecord::A {
+        domain: name("www.example.com"),
+        class: DnsClass::IN,
+        address: Ipv4Addr::new(192, 0, 2, 1),
+        ttl: 300,
+    });
+
+    zone
+}
+
+/// Serves `zone` over UDP on a free loopback port, from a server that
+/// `configure` sets up first, returning the address to query.
+pub fn serve(zone: Zone, configure: impl FnOnce(&mut DnsServer)) -> SocketAddr {
+    serve_at("127.0.0.1:0".parse().unwrap(), vec![zone], configure)
+}
+
+/// Like `serve`, for any number of zones at `address`.
+pub fn serve_at(
+    address: SocketAddr,
+    zones: Vec<Zone>,
+    configure: impl FnOnce(&mut DnsServer),
+) -> SocketAddr {
+    let mut catalog = Catalog::new();
+    for zone in zones {
+        catalog.insert(zone);
+    }
+    let mut server = DnsServer::new(catalog);
+    configure(&mut server);
+
+    let socket = UdpSocket::bind(address).unwrap();
+    let address = socket.local_addr().unwrap();
+    thread::spawn(move || server.serve_udp(&socket));
+
+    address
+}
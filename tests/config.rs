@@ -0,0 +1,371 @@
+#![cfg(feature = "config")]
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::Duration;
+
+use tarnish_dns::acl::Acl;
+use tarnish_dns::config::{DnstapOutput, ServerConfig, ZoneSource};
+use tarnish_dns::name::Name;
+use tarnish_dns::protocol::{DnsClass, DnsRecord, QueryType};
+use tarnish_dns::querylog::{LogFormat, Rotation};
+use tarnish_dns::server::DnsServer;
+use tarnish_dns::tsig::TsigAlgorithm;
+
+const EXAMPLE: &str = r#"
+[server]
+listen = ["127.0.0.1:5353", "[::1]:5353"]
+transports = ["udp"]
+also_notify = ["192.0.2.2:53"]
+
+[[key]]
+name = "transfer"
+algorithm = "hmac-sha256"
+secret = "c2VjcmV0IGtleSBtYXRlcmlhbA=="
+
+[[zone]]
+origin = "example.com"
+journal = "example.com.jnl"
+soa = { mname = "ns1.example.com", rname = "hostmaster.example.com", serial = 7 }
+ns = ["ns1.example.com"]
+
+[[zone]]
+origin = "example.net"
+class = "CH"
+primary = "192.0.2.1:53"
+key = "transfer"
+
+[acl]
+transfer = ["192.0.2.0/24", "2001:db8::1"]
+
+[log.queries]
+path = "queries.log"
+format = "text"
+sample = 10
+anonymize = true
+max_bytes = 1048576
+keep = 3
+
+[log.dnstap]
+socket = "/run/dnstap.sock"
+identity = "ns1"
+
+[metrics]
+listen = "127.0.0.1:9153"
+
+[limits]
+tcp_idle_timeout = 30
+max_tcp_connections = 100
+"#;
+
+fn name(name: &str) -> Name {
+    name.parse().unwrap()
+}
+
+fn directory(test: &str) -> PathBuf {
+    let directory =
+        std::env::temp_dir().join(format!("tarnish-config-{}-{test}", std::process::id()));
+    fs::remove_dir_all(&directory).ok();
+    fs::create_dir_all(&directory).unwrap();
+
+    directory
+}
+
+/// The error message for `text`, which must fail to validate.
+fn error(text: &str) -> String {
+    ServerConfig::parse(text).unwrap_err().to_string()
+}
+
+#[test]
+fn example_is_parsed() {
+    let config = ServerConfig::parse(EXAMPLE).unwrap();
+
+    assert_eq!(config.listen.len(), 2);
+    assert!(config.udp && !config.tcp);
+    assert_eq!(config.also_notify, vec!["192.0.2.2:53".parse().unwrap()]);
+
+    assert_eq!(config.keys.len(), 1);
+    assert_eq!(config.keys[0].name, name("transfer"));
+    assert_eq!(config.keys[0].algorithm, TsigAlgorithm::HmacSha256);
+    assert_eq!(config.keys[0].secret, b"secret key material");
+
+    assert_eq!(config.zones[0].origin, name("example.com"));
+    assert_eq!(config.zones[0].class, DnsClass::IN);
+    match &config.zones[0].source {
+        ZoneSource::Journal { path, initial } => {
+            assert_eq!(path, &PathBuf::from("example.com.jnl"));
+            assert_eq!(initial.len(), 2);
+            assert!(matches!(initial[0], DnsRecord::SOA { serial: 7, .. }));
+            assert!(
+                matches!(&initial[1], DnsRecord::NS { host, .. } if *host == name("ns1.example.com"))
+            );
+        }
+        other => panic!("expected a primary zone, got {:?}", other),
+    }
+    assert_eq!(config.zones[1].class, DnsClass::CH);
+    assert_eq!(
+        config.zones[1].source,
+        ZoneSource::Secondary {
+            primary: "192.0.2.1:53".parse().unwrap(),
+            key: Some(config.keys[0].clone()),
+        }
+    );
+    assert_eq!(config.secondary_zones().len(), 1);
+
+    assert_eq!(config.acl.query, Acl::any());
+    assert_eq!(config.acl.update, Acl::loopback());
+    assert_eq!(config.acl.transfer[1].to_string(), "2001:db8::1/128");
+
+    let query_log = config.query_log.unwrap();
+    assert_eq!(query_log.format, LogFormat::Text);
+    assert_eq!(query_log.sample, 10);
+    assert!(query_log.anonymize);
+    assert_eq!(
+        query_log.rotation,
+        Some(Rotation {
+            max_bytes: 1048576,
+            keep: 3
+        })
+    );
+    let dnstap = config.dnstap.unwrap();
+    assert_eq!(
+        dnstap.output,
+        DnstapOutput::UnixSocket("/run/dnstap.sock".into())
+    );
+    assert_eq!(dnstap.identity.as_deref(), Some("ns1"));
+
+    assert_eq!(config.metrics, Some("127.0.0.1:9153".parse().unwrap()));
+    assert_eq!(config.tcp_idle_timeout, Duration::from_secs(30));
+    assert_eq!(config.max_tcp_connections, Some(100));
+}
+
+#[test]
+fn defaults_apply_to_a_minimal_configuration() {
+    let config = ServerConfig::parse("[server]\nlisten = [\"127.0.0.1:53\"]\n").unwrap();
+    assert!(config.udp && config.tcp);
+    assert!(config.zones.is_empty() && config.query_log.is_none());
+    assert_eq!(config.tcp_idle_timeout, Duration::from_secs(10));
+}
+
+#[test]
+fn invalid_settings_are_reported() {
+    let listen = "[server]\nlisten = [\"127.0.0.1:53\"]\n";
+    let cases = [
+        ("", "server.listen: at least one address is needed"),
+        (
+            "[server]\nlisten = [\"127.0.0.1\"]\n",
+            "invalid socket address",
+        ),
+        (
+            "[server]\nlisten = [\"127.0.0.1:53\"]\nport = 53\n",
+            "unknown field `port`",
+        ),
+        (
+            "[server]\nlisten = [\"127.0.0.1:53\"]\ntransports = [\"quic\"]\n",
+            "server.transports: unknown transport `quic`",
+        ),
+        (
+            "[upstreams]\nservers = []\n",
+            "upstreams: forwarding is not supported",
+        ),
+        ("[cache]\nsize = 1000\n", "cache: caching is not supported"),
+        (
+            "[[zone]]\norigin = \"example.com\"\n",
+            "zone `example.com`: needs a `journal`",
+        ),
+        (
+            "[[zone]]\norigin = \"example.com\"\njournal = \"a\"\nprimary = \"192.0.2.1:53\"\n",
+            "zone `example.com`: `journal` and `primary` are exclusive",
+        ),
+        (
+            "[[zone]]\norigin = \"example.com\"\nprimary = \"192.0.2.1:53\"\nkey = \"missing\"\n",
+            "zone `example.com`: key `missing` is not defined",
+        ),
+        (
+            "[[zone]]\norigin = \"example.com\"\njournal = \"a\"\n\
+             soa = { mname = \"ns1.example.com\", rname = \"hostmaster.example.com\" }\n",
+            "zone `example.com`: `soa` needs at least one `ns`",
+        ),
+        (
+            "[[zone]]\norigin = \"example.com\"\njournal = \"a\"\nns = [\"ns1.example.com\"]\n",
+            "zone `example.com`: `ns` needs an `soa`",
+        ),
+        (
+            "[[zone]]\norigin = \"example.com\"\nprimary = \"192.0.2.1:53\"\n\
+             ns = [\"ns1.example.com\"]\n",
+            "zone `example.com`: `soa` and `ns` only apply to primary zones",
+        ),
+        (
+            "[[key]]\nname = \"k\"\nalgorithm = \"hmac-md5\"\nsecret = \"AAAA\"\n",
+            "key `k`: unknown algorithm `hmac-md5`",
+        ),
+        (
+            "[[key]]\nname = \"k\"\nalgorithm = \"hmac-sha256\"\nsecret = \"!!\"\n",
+            "key `k`: secret is not valid base64",
+        ),
+        (
+            "[acl]\nupdate = [\"192.0.2.0/40\"]\n",
+            "acl.update: Invalid network `192.0.2.0/40`",
+        ),
+        (
+            "[log.queries]\npath = \"q.log\"\nformat = \"xml\"\n",
+            "log.queries.format: unknown format `xml`",
+        ),
+        (
+            "[log.queries]\npath = \"q.log\"\nsample = 0\n",
+            "log.queries.sample: must be at least 1",
+        ),
+        (
+            "[log.queries]\npath = \"q.log\"\nkeep = 2\n",
+            "log.queries.keep: only applies to rotated logs",
+        ),
+        (
+            "[log.dnstap]\nidentity = \"ns1\"\n",
+            "log.dnstap: needs exactly one of",
+        ),
+        (
+            "[[zone]]\norigin = \"example.com\"\njournal = \"a\"\n\
+             [[zone]]\norigin = \"Example.com.\"\njournal = \"b\"\n",
+            "zone `Example.com`: defined twice",
+        ),
+        (
+            "[limits]\nmax_tcp_connections = 0\n",
+            "limits.max_tcp_connections: must be at least 1",
+        ),
+    ];
+
+    for (settings, expected) in cases {
+        // Every case but the first starts from a valid listener
+        let text = match settings.starts_with("[server]") || settings.is_empty() {
+            true => settings.to_string(),
+            false => format!("{}{}", listen, settings),
+        };
+        let message = error(&text);
+        assert!(
+            message.contains(expected),
+            "`{}` does not mention `{}`",
+            message,
+            expected
+        );
+    }
+
+    // Syntax errors point at their line
+    let message = error("[server]\nlisten = [\"127.0.0.1:53\"\n");
+    assert!(message.contains("line 2"), "{}", message);
+
+    // The same origin may be served once per class
+    let text = format!(
+        "{}[[zone]]\norigin = \"example.com\"\njournal = \"a\"\n\
+         [[zone]]\norigin = \"example.com\"\nclass = \"CH\"\njournal = \"b\"\n",
+        listen
+    );
+    assert_eq!(ServerConfig::parse(&text).unwrap().zones.len(), 2);
+}
+
+#[test]
+fn server_is_built_from_the_configuration() {
+    let directory = directory("build");
+    let text = format!(
+        "[server]\nlisten = [\"127.0.0.1:0\"]\n\
+         [[zone]]\norigin = \"example.com\"\njournal = {:?}\n\
+         soa = {{ mname = \"ns1.example.com\", rname = \"hostmaster.example.com\" }}\n\
+         ns = [\"ns1.example.com\"]\n\
+         [acl]\nquery = [\"127.0.0.0/8\"]\n\
+         [log.queries]\npath = {:?}\n\
+         [limits]\nmax_tcp_connections = 4\n",
+        directory.join("example.com.jnl"),
+        directory.join("queries.log")
+    );
+    let config = ServerConfig::parse(&text).unwrap();
+
+    let server = config.build_server().unwrap();
+    assert_eq!(server.acl, config.acl);
+    assert_eq!(server.max_tcp_connections, Some(4));
+    assert!(server.query_log.is_some());
+    assert!(directory.join("queries.log").exists());
+    let zone = |server: &DnsServer| {
+        let catalog = server.catalog.read().unwrap();
+        catalog.get(&name("example.com"), DnsClass::IN).cloned()
+    };
+    let created = zone(&server).unwrap();
+    assert_eq!(created.serial(), Some(1));
+    assert_eq!(created.rrset(&name("example.com"), QueryType::NS).len(), 1);
+
+    // Once the journal holds the zone, it no longer needs the SOA and NS
+    let text = format!(
+        "[server]\nlisten = [\"127.0.0.1:0\"]\n\
+         [[zone]]\norigin = \"example.com\"\njournal = {:?}\n",
+        directory.join("example.com.jnl")
+    );
+    let config = ServerConfig::parse(&text).unwrap();
+    config.check_zones().unwrap();
+    assert_eq!(zone(&config.build_server().unwrap()), Some(created));
+
+    // A new journal without them would leave the zone out
+    let text = format!(
+        "[server]\nlisten = [\"127.0.0.1:0\"]\n\
+         [[zone]]\norigin = \"example.com\"\njournal = {:?}\n",
+        directory.join("new.jnl")
+    );
+    let config = ServerConfig::parse(&text).unwrap();
+    for message in [
+        config.check_zones().unwrap_err().to_string(),
+        config.build_server().err().unwrap().to_string(),
+    ] {
+        assert!(
+            message.contains("zone `example.com`: journal")
+                && message.contains("holds no zone yet"),
+            "{}",
+            message
+        );
+    }
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn check_config_validates_without_serving() {
+    let directory = directory("check");
+    let valid = directory.join("valid.toml");
+    let invalid = directory.join("invalid.toml");
+    let empty = directory.join("empty.toml");
+    fs::write(&valid, EXAMPLE).unwrap();
+    fs::write(&invalid, "[server]\nlisten = []\n").unwrap();
+    fs::write(
+        &empty,
+        format!(
+            "[server]\nlisten = [\"127.0.0.1:53\"]\n\
+             [[zone]]\norigin = \"example.com\"\njournal = {:?}\n",
+            directory.join("example.com.jnl")
+        ),
+    )
+    .unwrap();
+
+    let run = |path: &PathBuf| {
+        Command::new(env!("CARGO_BIN_EXE_tarnish-server"))
+            .arg("--check-config")
+            .arg(path)
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&valid);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("configuration is valid"));
+
+    let output = run(&invalid);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("server.listen: at least one address is needed"),
+        "{}",
+        stderr
+    );
+
+    let output = run(&empty);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("holds no zone yet"), "{}", stderr);
+    assert!(!directory.join("example.com.jnl.jnl").exists());
+    fs::remove_dir_all(&directory).unwrap();
+}
@@ -0,0 +1,219 @@
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+
+use tarnish_dns::client::DnsClient;
+use tarnish_dns::dnstap::{read_stream, Dnstap, DnstapMessage, MessageType, CONTENT_TYPE};
+use tarnish_dns::pcap::Transport;
+use tarnish_dns::protocol::{DnsPacket, DnsQuestion, QueryType};
+
+use common::{name, www_zone};
+
+/// A writer whose contents stay readable after it is handed over.
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn serve() -> (SocketAddr, SharedBuffer) {
+    let log = SharedBuffer::default();
+    let writer = log.clone();
+    let address = common::serve(www_zone(), |server| {
+        server.dnstap = Some(Dnstap::to_writer(writer).unwrap());
+    });
+
+    (address, log)
+}
+
+fn www_query() -> DnsPacket {
+    let mut request = DnsPacket::new();
+    request
+        .questions
+        .push(DnsQuestion::new(name("www.example.com"), QueryType::A));
+    request
+}
+
+#[test]
+fn messages_are_encoded_and_decoded() {
+    let message = DnstapMessage::response(
+        MessageType::ResolverResponse,
+        Transport::Tcp,
+        "192.0.2.10:40000".parse().unwrap(),
+        "[2001:db8::53]:53".parse().unwrap(),
+        Some(UNIX_EPOCH + Duration::new(1_700_000_000, 5)),
+        UNIX_EPOCH + Duration::new(1_700_000_001, 123_456_789),
+        b"response",
+    );
+    let decoded = DnstapMessage::decode(&message.encode(Some(b"host"), None)).unwrap();
+
+    // IPv4 ends are logged as mapped addresses next to IPv6 ones
+    let mapped = SocketAddr::new(Ipv4Addr::new(192, 0, 2, 10).to_ipv6_mapped().into(), 40000);
+    assert_eq!(decoded.query_address, mapped);
+    assert_eq!(
+        DnstapMessage {
+            query_address: mapped,
+            ..message
+        },
+        decoded
+    );
+}
+
+#[test]
+fn server_messages_are_logged() {
+    let (address, log) = serve();
+    let response = DnsClient::new(address).send(www_query()).unwrap();
+    assert_eq!(response.answers.len(), 1);
+
+    // The response is logged right after it is sent
+    thread::sleep(Duration::from_millis(100));
+    let messages = read_stream(log.0.lock().unwrap().as_slice()).unwrap();
+    assert_eq!(messages.len(), 2);
+
+    let (query, answer) = (&messages[0], &messages[1]);
+    assert_eq!(query.message_type, MessageType::ClientQuery);
+    assert_eq!(query.response_address, address);
+    assert!(query.query_message.is_some());
+    assert_eq!(answer.message_type, MessageType::ClientResponse);
+    assert_eq!(answer.transport, Transport::Udp);
+    assert_eq!(answer.query_address, query.query_address);
+    assert_eq!(answer.query_time, query.query_time);
+    assert!(answer.response_time >= answer.query_time);
+
+    let bytes = answer.response_message.as_ref().unwrap();
+    assert_eq!(&bytes[..2], &response.header.id.to_be_bytes());
+}
+
+#[test]
+fn resolver_messages_are_logged() {
+    let (address, _) = serve();
+    let log = SharedBuffer::default();
+    let mut client = DnsClient::new(address);
+    client.dnstap = Some(Dnstap::to_writer(log.clone()).unwrap());
+    client.send(www_query()).unwrap();
+
+    // Dropping the last handle ends the stream
+    drop(client);
+    thread::sleep(Duration::from_millis(100));
+    let stream = log.0.lock().unwrap();
+    let stop = [
+        &[0, 0, 0, 0, 0, 0, 0, 34, 0, 0, 0, 3, 0, 0, 0, 1, 0, 0, 0, 22],
+        CONTENT_TYPE,
+    ]
+    .concat();
+    assert!(stream.ends_with(&stop));
+
+    let messages = read_stream(stream.as_slice()).unwrap();
+    let types: Vec<MessageType> = messages.iter().map(|m| m.message_type).collect();
+    assert_eq!(
+        types,
+        vec![MessageType::ResolverQuery, MessageType::ResolverResponse]
+    );
+    assert!(messages
+        .iter()
+        .all(|m| m.transport == Transport::Udp && m.response_address == address));
+}
+
+#[cfg(unix)]
+#[test]
+fn collectors_on_unix_sockets_get_the_handshake() {
+    use std::os::unix::net::UnixListener;
+
+    fn control(frame_type: u32, content_type: bool) -> Vec<u8> {
+        let mut frame = vec![0, 0, 0, 0];
+        let field_length = if content_type {
+            8 + CONTENT_TYPE.len()
+        } else {
+            0
+        };
+        frame.extend_from_slice(&(4 + field_length as u32).to_be_bytes());
+        frame.extend_from_slice(&frame_type.to_be_bytes());
+        if content_type {
+            frame.extend_from_slice(&1u32.to_be_bytes());
+            frame.extend_from_slice(&(CONTENT_TYPE.len() as u32).to_be_bytes());
+            frame.extend_from_slice(CONTENT_TYPE);
+        }
+        frame
+    }
+
+    fn read_u32(stream: &mut impl Read) -> u32 {
+        let mut bytes = [0; 4];
+        stream.read_exact(&mut bytes).unwrap();
+        u32::from_be_bytes(bytes)
+    }
+
+    /// Reads a frame, returning the type of control frames and the payload
+    /// of data frames.
+    fn read_frame(stream: &mut impl Read) -> (Option<u32>, Vec<u8>) {
+        let length = read_u32(stream);
+        let (control, length) = match length {
+            0 => (true, read_u32(stream)),
+            length => (false, length),
+        };
+        let mut payload = vec![0; length as usize];
+        stream.read_exact(&mut payload).unwrap();
+        match control {
+            true => (
+                Some(u32::from_be_bytes(payload[..4].try_into().unwrap())),
+                payload,
+            ),
+            false => (None, payload),
+        }
+    }
+
+    let directory = std::env::temp_dir().join(format!("tarnish-dnstap-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let path = directory.join("collector.sock");
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+
+    let collector = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let (ready, payload) = read_frame(&mut stream);
+        assert_eq!(ready, Some(4));
+        assert!(payload.ends_with(CONTENT_TYPE));
+        stream.write_all(&control(1, true)).unwrap();
+        assert_eq!(read_frame(&mut stream).0, Some(2));
+
+        let mut messages = Vec::new();
+        loop {
+            match read_frame(&mut stream) {
+                (None, payload) => messages.push(DnstapMessage::decode(&payload).unwrap()),
+                (Some(3), _) => break,
+                (other, _) => panic!("unexpected control frame {:?}", other),
+            }
+        }
+        stream.write_all(&control(5, false)).unwrap();
+        messages
+    });
+
+    let dnstap = Dnstap::to_unix_socket(&path);
+    let client: SocketAddr = "192.0.2.10:40000".parse().unwrap();
+    let server: SocketAddr = "192.0.2.53:53".parse().unwrap();
+    let query = DnstapMessage::query(
+        MessageType::ClientQuery,
+        Transport::Udp,
+        client,
+        server,
+        UNIX_EPOCH,
+        b"query",
+    );
+    dnstap.log(&query);
+    drop(dnstap);
+
+    let messages = collector.join().unwrap();
+    assert_eq!(messages, vec![query]);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
@@ -0,0 +1,71 @@
+use tarnish_dns::dump::HexDump;
+
+/// A response to `www.example.com A` whose answer owner is a compression
+/// pointer to the question name.
+fn response() -> Vec<u8> {
+    let mut bytes = vec![
+        0x12, 0x34, 0x81, 0x80, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+    ];
+    bytes.extend_from_slice(b"\x03www\x07example\x03com\x00\x00\x01\x00\x01");
+    bytes.extend_from_slice(&[0xC0, 0x0C, 0x00, 0x01, 0x00, 0x01]);
+    bytes.extend_from_slice(&[0x00, 0x00, 0x0E, 0x10, 0x00, 0x04, 192, 0, 2, 1]);
+    bytes
+}
+
+fn line_at(dump: &HexDump, offset: usize) -> &str {
+    dump.lines
+        .iter()
+        .find(|line| line.offset == offset && line.length > 0)
+        .map(|line| line.text.as_str())
+        .unwrap()
+}
+
+#[test]
+fn fields_are_annotated() {
+    let dump = HexDump::new(&response());
+    assert!(dump.error.is_none());
+
+    assert_eq!(line_at(&dump, 0), "ID 4660");
+    assert_eq!(
+        line_at(&dump, 2),
+        "flags QR=1 OPCODE=QUERY AA=0 TC=0 RD=1 RA=1 Z=0 AD=0 CD=0 RCODE=NOERROR"
+    );
+    assert_eq!(line_at(&dump, 16), "label \"example\"");
+    assert_eq!(line_at(&dump, 33), "pointer to 0x000c: www.example.com.");
+    assert_eq!(line_at(&dump, 35), "TYPE A (1)");
+    assert_eq!(line_at(&dump, 39), "TTL 3600");
+    assert_eq!(line_at(&dump, 45), "RDATA 192.0.2.1");
+
+    let text = dump.to_string();
+    assert!(text.contains(";; answer #0\n0021  c0 0c"));
+    assert!(!text.contains("error"));
+}
+
+#[test]
+fn truncation_is_marked_after_the_last_field() {
+    let mut bytes = response();
+    bytes.truncate(bytes.len() - 2);
+
+    let dump = HexDump::new(&bytes);
+    assert!(dump.error.is_some());
+    assert_eq!(line_at(&dump, 45), "RDATA, truncated: 2 of 4 bytes");
+    assert_eq!(dump.error_line(), Some(dump.lines.len() - 1));
+
+    let text = dump.to_string();
+    let marker = text.lines().last().unwrap();
+    assert!(marker.starts_with("^^^^  error at 002f: Unexpected end of message"));
+}
+
+#[test]
+fn bad_pointer_ends_the_dump_with_the_error() {
+    let mut bytes = response();
+    bytes[34] = 0x40;
+
+    let dump = HexDump::new(&bytes);
+    let line = dump.error_line().unwrap();
+    assert_eq!(dump.lines[line].offset, 33);
+    assert_eq!(line, dump.lines.len() - 1);
+    assert!(dump.lines[line]
+        .text
+        .starts_with("pointer to 0x0040, invalid"));
+}
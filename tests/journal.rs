@@ -0,0 +1,220 @@
+mod common;
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use tarnish_dns::journal::Journal;
+use tarnish_dns::protocol::{DnsClass, DnsRecord, ResultCode};
+use tarnish_dns::server::{Catalog, DnsServer};
+use tarnish_dns::update::{apply_update, UpdateBuilder};
+use tarnish_dns::zone::Zone;
+use tarnish_dns::DnsError;
+
+use common::{host, origin};
+
+/// The zone of a primary, with a first host.
+fn primary_zone() -> Zone {
+    let mut zone = common::primary_zone();
+    zone.insert(host(0));
+
+    zone
+}
+
+/// A fresh directory for the files of one test.
+fn directory(test: &str) -> PathBuf {
+    let directory =
+        std::env::temp_dir().join(format!("tarnish-journal-{}-{test}", std::process::id()));
+    fs::remove_dir_all(&directory).ok();
+    fs::create_dir_all(&directory).unwrap();
+
+    directory
+}
+
+/// Applies an update adding `added` to `zone`, returning the serial it had
+/// before.
+fn update(zone: &mut Zone, added: DnsRecord) -> Option<u32> {
+    let serial = zone.serial();
+    let request = UpdateBuilder::new(origin(), DnsClass::IN)
+        .add_record(added)
+        .build(1);
+    assert_eq!(apply_update(zone, &request), ResultCode::NOERROR);
+
+    serial
+}
+
+fn assert_same_records(loaded: &Zone, expected: &Zone) {
+    assert_eq!(loaded.serial(), expected.serial());
+    assert!(loaded.records().eq(expected.records()));
+}
+
+#[test]
+fn load_replays_changes_over_the_base() {
+    let base = directory("replay").join("example.com.zone");
+    let mut zone = primary_zone();
+
+    let mut journal = Journal::open(&base).unwrap();
+    journal.record(None, &zone).unwrap();
+    for index in 1..=3 {
+        let previous = update(&mut zone, host(index));
+        journal.record(previous, &zone).unwrap();
+    }
+    drop(journal);
+
+    let loaded = Journal::open(&base)
+        .unwrap()
+        .load(origin(), DnsClass::IN)
+        .unwrap();
+    assert_same_records(&loaded, &zone);
+    assert_eq!(loaded.serial(), Some(4));
+    assert_eq!(loaded.journal().len(), 3);
+}
+
+#[test]
+fn torn_entry_is_dropped() {
+    let base = directory("torn").join("example.com.zone");
+    let mut zone = primary_zone();
+
+    let mut journal = Journal::open(&base).unwrap();
+    journal.record(None, &zone).unwrap();
+    let previous = update(&mut zone, host(1));
+    journal.record(previous, &zone).unwrap();
+    let complete = journal.log_size().unwrap();
+    drop(journal);
+
+    // A crash in the middle of appending the next entry
+    let log = base.with_extension("zone.jnl");
+    let mut file = OpenOptions::new().append(true).open(&log).unwrap();
+    file.write_all(&[0, 0, 0, 40, 1, 2, 3]).unwrap();
+
+    let mut journal = Journal::open(&base).unwrap();
+    let loaded = journal.load(origin(), DnsClass::IN).unwrap();
+    assert_same_records(&loaded, &zone);
+    assert_eq!(journal.log_size().unwrap(), complete);
+
+    // Appending continues after the last complete entry
+    let previous = update(&mut zone, host(2));
+    journal.record(previous, &zone).unwrap();
+    let loaded = journal.load(origin(), DnsClass::IN).unwrap();
+    assert_same_records(&loaded, &zone);
+}
+
+#[test]
+fn corruption_before_the_last_entry_is_reported() {
+    let base = directory("corrupt").join("example.com.zone");
+    let mut zone = primary_zone();
+
+    let mut journal = Journal::open(&base).unwrap();
+    journal.record(None, &zone).unwrap();
+    let first = journal.log_size().unwrap();
+    for index in 1..=2 {
+        let previous = update(&mut zone, host(index));
+        journal.record(previous, &zone).unwrap();
+    }
+    let size = journal.log_size().unwrap();
+    drop(journal);
+
+    // A flipped bit in the payload of the first entry, with the second one
+    // still intact after it
+    let log = base.with_extension("zone.jnl");
+    let mut contents = fs::read(&log).unwrap();
+    contents[first as usize + 12] ^= 0x01;
+    fs::write(&log, contents).unwrap();
+
+    let mut journal = Journal::open(&base).unwrap();
+    match journal.load(origin(), DnsClass::IN) {
+        Err(DnsError::CorruptJournal { path, offset }) => {
+            assert_eq!(path, log);
+            assert_eq!(offset, first);
+        }
+        other => panic!("expected a corrupt journal, got {:?}", other.map(|_| ())),
+    }
+    // Nothing is dropped from a log that was not torn
+    assert_eq!(journal.log_size().unwrap(), size);
+
+    // A damaged length reaching past the end of the log is no torn entry
+    // either, though it would end with the file
+    let mut contents = fs::read(&log).unwrap();
+    contents[first as usize + 12] ^= 0x01;
+    contents[first as usize] = 0x40;
+    fs::write(&log, contents).unwrap();
+    match journal.load(origin(), DnsClass::IN) {
+        Err(DnsError::CorruptJournal { offset, .. }) => assert_eq!(offset, first),
+        other => panic!("expected a corrupt journal, got {:?}", other.map(|_| ())),
+    }
+    assert_eq!(journal.log_size().unwrap(), size);
+}
+
+#[test]
+fn compaction_starts_a_new_base() {
+    let base = directory("compact").join("example.com.zone");
+    let mut zone = primary_zone();
+
+    let mut journal = Journal::open(&base).unwrap();
+    journal.record(None, &zone).unwrap();
+    let empty = journal.log_size().unwrap();
+    for index in 1..=3 {
+        let previous = update(&mut zone, host(index));
+        journal.record(previous, &zone).unwrap();
+    }
+    assert!(journal.log_size().unwrap() > empty);
+
+    journal.compact(&zone).unwrap();
+    assert_eq!(journal.log_size().unwrap(), empty);
+
+    let loaded = journal.load(origin(), DnsClass::IN).unwrap();
+    assert_same_records(&loaded, &zone);
+    assert!(loaded.journal().is_empty());
+}
+
+#[test]
+fn damaged_base_is_reported() {
+    let base = directory("damaged").join("example.com.zone");
+    let mut journal = Journal::open(&base).unwrap();
+    journal.compact(&primary_zone()).unwrap();
+
+    let mut contents = fs::read(&base).unwrap();
+    contents[20] ^= 0xff;
+    fs::write(&base, contents).unwrap();
+
+    assert!(matches!(
+        journal.load(origin(), DnsClass::IN),
+        Err(DnsError::CorruptJournal { .. })
+    ));
+}
+
+#[test]
+fn server_restarts_with_journaled_updates() {
+    let base = directory("server").join("example.com.zone");
+    let source: SocketAddr = "127.0.0.1:53".parse().unwrap();
+
+    let mut catalog = Catalog::new();
+    catalog.insert(primary_zone());
+    let server = DnsServer::new(catalog);
+    server.open_journal(origin(), DnsClass::IN, &base).unwrap();
+
+    for index in 1..=2 {
+        let request = UpdateBuilder::new(origin(), DnsClass::IN)
+            .add_record(host(index))
+            .build(index as u16);
+        let response = server.handle_request(&request, source);
+        assert_eq!(response.rcode(), ResultCode::NOERROR);
+    }
+    let expected = server
+        .catalog
+        .read()
+        .unwrap()
+        .get(&origin(), DnsClass::IN)
+        .unwrap()
+        .clone();
+
+    let restarted = DnsServer::new(Catalog::new());
+    restarted
+        .open_journal(origin(), DnsClass::IN, &base)
+        .unwrap();
+    let catalog = restarted.catalog.read().unwrap();
+    let loaded = catalog.get(&origin(), DnsClass::IN).unwrap();
+    assert_same_records(loaded, &expected);
+    assert_eq!(loaded.diffs_since(1).map(<[_]>::len), Some(2));
+}
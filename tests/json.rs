@@ -0,0 +1,141 @@
+#![cfg(feature = "serde")]
+
+mod common;
+
+use std::net::Ipv4Addr;
+
+use tarnish_dns::buffer::PacketBuffer;
+use tarnish_dns::json::JsonMessage;
+use tarnish_dns::protocol::{DnsClass, DnsPacket, DnsQuestion, DnsRecord, QueryType, ResultCode};
+
+use common::name;
+
+fn response() -> DnsPacket {
+    let mut packet = DnsPacket::new();
+    packet.header.id = 0x1234;
+    packet.header.response = true;
+    packet.header.recursion_desired = true;
+    packet.header.recursion_available = true;
+    packet
+        .questions
+        .push(DnsQuestion::new(name("example.com"), QueryType::MX));
+    packet.answers.push(DnsRecord::MX {
+        domain: name("example.com"),
+        class: DnsClass::IN,
+        priority: 10,
+        host: name("mail.example.com"),
+        ttl: 300,
+    });
+    packet.answers.push(DnsRecord::TXT {
+        domain: name("example.com"),
+        class: DnsClass::IN,
+        data: vec![b"v=spf1 \"quoted\" -all".to_vec()],
+        ttl: 300,
+    });
+    packet.resources.push(DnsRecord::A {
+        domain: name("mail.example.com"),
+        class: DnsClass::IN,
+        address: Ipv4Addr::new(192, 0, 2, 25),
+        ttl: 300,
+    });
+    packet.resources.push(DnsRecord::OPT {
+        udp_payload_size: 1232,
+        extended_rcode: 0,
+        version: 0,
+        flags: 0x8000,
+        options: Vec::new(),
+    });
+
+    packet
+}
+
+fn wire(packet: &DnsPacket) -> Vec<u8> {
+    let mut buffer = PacketBuffer::new();
+    packet.clone().write(&mut buffer).unwrap();
+    buffer.buffer[..buffer.position].to_vec()
+}
+
+#[test]
+fn packet_roundtrips_through_json() {
+    let packet = response();
+
+    let json = serde_json::to_value(&packet).unwrap();
+    assert_eq!(json["ID"], 0x1234);
+    assert_eq!(json["QR"], true);
+    assert_eq!(json["ANCOUNT"], 2);
+    assert_eq!(json["questionRRs"][0]["NAME"], "example.com.");
+    assert_eq!(json["questionRRs"][0]["TYPEname"], "MX");
+    assert_eq!(json["answerRRs"][0]["rdataMX"], "10 mail.example.com.");
+    assert_eq!(json["additionalRRs"][0]["rdataA"], "192.0.2.25");
+    assert_eq!(json["additionalRRs"][1]["CLASS"], 1232);
+    assert_eq!(json["additionalRRs"][1]["TTL"], 0x8000);
+    assert!(json.get("messageOctetsHEX").is_none());
+
+    let parsed: DnsPacket = serde_json::from_value(json).unwrap();
+    assert_eq!(wire(&parsed), wire(&packet));
+}
+
+#[test]
+fn message_octets_are_kept_and_preferred() {
+    let bytes = wire(&response());
+
+    let mut message = JsonMessage::from_octets(&bytes).unwrap();
+    let hex = message.message_octets.clone().unwrap();
+    assert_eq!(hex.len(), bytes.len() * 2);
+    assert!(hex.starts_with("1234"));
+
+    // The parsed members are ignored when the raw message is present
+    message.id = 1;
+    message.answers.clear();
+    assert_eq!(wire(&message.to_packet().unwrap()), bytes);
+
+    let from_packet = JsonMessage::from_packet(&response(), true).unwrap();
+    assert_eq!(from_packet.message_octets, Some(hex));
+}
+
+#[test]
+fn presentation_rdata_is_read_without_hex() {
+    let json = r#"{
+        "ID": 32784, "QR": 1, "Opcode": 0, "AA": 1, "TC": 0, "RD": 0,
+        "RA": 0, "AD": 0, "CD": 0, "RCODE": 3,
+        "QDCOUNT": 1, "ANCOUNT": 0, "NSCOUNT": 1, "ARCOUNT": 0,
+        "questionRRs": [{"NAME": "missing.example.com.", "TYPE": 1, "CLASS": 1}],
+        "authorityRRs": [{
+            "NAME": "example.com.", "TYPE": 6, "CLASS": 1, "TTL": 3600,
+            "rdataSOA": "ns.example.com. hostmaster.example.com. 7 3600 600 86400 300"
+        }],
+        "compressedNAME": 12
+    }"#;
+
+    let packet: DnsPacket = serde_json::from_str(json).unwrap();
+    assert_eq!(packet.header.id, 32784);
+    assert!(packet.header.authoritative_answer);
+    assert_eq!(packet.rcode(), ResultCode::NXDOMAIN);
+    assert_eq!(packet.questions[0].name, name("missing.example.com"));
+    assert_eq!(
+        packet.authorities,
+        vec![DnsRecord::SOA {
+            domain: name("example.com"),
+            class: DnsClass::IN,
+            m_name: name("ns.example.com"),
+            r_name: name("hostmaster.example.com"),
+            serial: 7,
+            refresh: 3600,
+            retry: 600,
+            expire: 86400,
+            minimum: 300,
+            ttl: 3600,
+        }]
+    );
+}
+
+#[test]
+fn malformed_rdata_is_rejected() {
+    let json = r#"{"answerRRs": [{"NAME": "a.example.", "TYPE": 1, "CLASS": 1,
+        "TTL": 60, "RDATAHEX": "C0000"}]}"#;
+    assert!(serde_json::from_str::<DnsPacket>(json).is_err());
+
+    let json = r#"{"answerRRs": [{"NAME": "a.example.", "TYPE": 99, "CLASS": 1,
+        "TTL": 60}]}"#;
+    assert!(serde_json::from_str::<DnsPacket>(json).is_err());
+}
@@ -0,0 +1,148 @@
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::thread;
+use std::time::Duration;
+
+use tarnish_dns::buffer::PacketBuffer;
+use tarnish_dns::client::DnsClient;
+use tarnish_dns::metrics::Metrics;
+use tarnish_dns::protocol::{DnsPacket, DnsQuestion, QueryType, ResultCode};
+use tarnish_dns::server::{Catalog, DnsServer};
+
+use common::{name, www_zone};
+
+fn serve(metrics: &Metrics) -> SocketAddr {
+    let metrics = metrics.clone();
+    common::serve(www_zone(), |server| server.metrics = Some(metrics))
+}
+
+fn query(qname: &str) -> DnsPacket {
+    let mut request = DnsPacket::new();
+    request
+        .questions
+        .push(DnsQuestion::new(name(qname), QueryType::A));
+    request
+}
+
+/// Fetches `path` from the HTTP endpoint, returning the status line and the
+/// body.
+fn get(address: SocketAddr, path: &str) -> (String, String) {
+    let mut stream = TcpStream::connect(address).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.lines().next().unwrap().to_string();
+    (status, body.to_string())
+}
+
+fn serve_http(metrics: &Metrics) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let metrics = metrics.clone();
+    thread::spawn(move || metrics.serve_http(&listener));
+    address
+}
+
+#[test]
+fn server_queries_are_counted() {
+    let metrics = Metrics::new();
+    let address = serve(&metrics);
+    let client = DnsClient::new(address);
+    client.send(query("www.example.com")).unwrap();
+    client.send(query("www.example.com")).unwrap();
+    client.send(query("missing.example.com")).unwrap();
+
+    // One byte is too short to even carry an ID, it goes unanswered
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.send_to(&[0], address).unwrap();
+    thread::sleep(Duration::from_millis(100));
+
+    let (status, body) = get(serve_http(&metrics), "/metrics");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    let lines: Vec<&str> = body.lines().collect();
+    for expected in [
+        "# TYPE tarnish_dns_queries_total counter",
+        "tarnish_dns_queries_total{qtype=\"A\",rcode=\"NOERROR\",transport=\"udp\"} 2",
+        "tarnish_dns_queries_total{qtype=\"A\",rcode=\"NXDOMAIN\",transport=\"udp\"} 1",
+        "tarnish_dns_query_duration_seconds_bucket{transport=\"udp\",le=\"+Inf\"} 3",
+        "tarnish_dns_query_duration_seconds_count{transport=\"udp\"} 3",
+        "tarnish_dns_queries_in_flight 0",
+        "tarnish_dns_malformed_packets_total 1",
+        "tarnish_dns_dropped_packets_total 1",
+    ] {
+        assert!(
+            lines.contains(&expected),
+            "missing `{}` in\n{}",
+            expected,
+            body
+        );
+    }
+}
+
+#[test]
+fn malformed_responses_are_not_answered() {
+    let metrics = Metrics::new();
+    let mut server = DnsServer::new(Catalog::new());
+    server.metrics = Some(metrics.clone());
+    let source: SocketAddr = "127.0.0.1:5353".parse().unwrap();
+
+    // A header claiming a question that is not there
+    let mut query = [0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+    let mut buffer = PacketBuffer::from_bytes(&query).unwrap();
+    let response = server.handle_buffer(&mut buffer, source).unwrap();
+    assert_eq!(response.header.id, 0x1234);
+    assert_eq!(response.rcode(), ResultCode::FORMERR);
+
+    // The same with the QR bit set is dropped, so that two servers can not
+    // keep trading FORMERRs
+    query[2] |= 0x80;
+    let mut buffer = PacketBuffer::from_bytes(&query).unwrap();
+    assert!(server.handle_buffer(&mut buffer, source).is_none());
+
+    let body = metrics.render();
+    assert!(body
+        .lines()
+        .any(|line| line == "tarnish_dns_malformed_packets_total 2"));
+}
+
+#[test]
+fn upstream_latency_is_recorded_per_server() {
+    let metrics = Metrics::new();
+    let address = serve(&Metrics::new());
+    let mut client = DnsClient::new(address);
+    client.metrics = Some(metrics.clone());
+    client.send(query("www.example.com")).unwrap();
+
+    // Nothing answers on the port of a closed socket
+    let silent = UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let mut client = DnsClient::new(silent);
+    client.timeout = Duration::from_millis(50);
+    client.metrics = Some(metrics.clone());
+    assert!(client.send(query("www.example.com")).is_err());
+
+    let rendered = metrics.render();
+    let lines: Vec<&str> = rendered.lines().collect();
+    let count = format!(
+        "tarnish_dns_upstream_duration_seconds_count{{server=\"{}\"}} 1",
+        address
+    );
+    let errors = format!(
+        "tarnish_dns_upstream_errors_total{{server=\"{}\"}} 1",
+        silent
+    );
+    assert!(lines.contains(&count.as_str()), "{}", rendered);
+    assert!(lines.contains(&errors.as_str()), "{}", rendered);
+}
+
+#[test]
+fn other_paths_are_not_found() {
+    let (status, _) = get(serve_http(&Metrics::new()), "/");
+    assert_eq!(status, "HTTP/1.1 404 Not Found");
+}
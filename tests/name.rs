@@ -0,0 +1,186 @@
+use std::collections::HashSet;
+
+use tarnish_dns::name::{Name, MAX_LABEL_LENGTH};
+use tarnish_dns::protocol::{DnsQuestion, QueryType};
+use tarnish_dns::DnsError;
+
+fn name(name: &str) -> Name {
+    name.parse().unwrap()
+}
+
+fn labels(name: &Name) -> Vec<Vec<u8>> {
+    name.labels().map(<[u8]>::to_vec).collect()
+}
+
+#[test]
+fn escapes_are_parsed_and_written_back() {
+    let cases: [(&str, &[&[u8]], &str); 5] = [
+        (r"a\.b.example", &[b"a.b", b"example"], r"a\.b.example"),
+        (r"\065bc.example", &[b"Abc", b"example"], "Abc.example"),
+        (r"\\x.example", &[b"\\x", b"example"], r"\\x.example"),
+        (
+            r"\000\255.example",
+            &[&[0, 255], b"example"],
+            r"\000\255.example",
+        ),
+        (
+            r"sp\ ace.example.",
+            &[b"sp ace", b"example"],
+            r"sp\032ace.example",
+        ),
+    ];
+    for (text, expected, written) in cases {
+        let parsed = name(text);
+        assert_eq!(labels(&parsed), expected, "{}", text);
+        assert_eq!(parsed.to_string(), written);
+        assert!(name(written).eq_exact(&parsed));
+    }
+    assert_eq!(name(".").to_string(), ".");
+    assert!(name("").is_root());
+
+    for (text, position) in [
+        (r"\256.example", 0),
+        (r"a.\1.example", 2),
+        (r"a.\12", 2),
+        (r"example\", 7),
+    ] {
+        match text.parse::<Name>() {
+            Err(DnsError::InvalidEscape(at)) => assert_eq!(at, position, "{}", text),
+            other => panic!("expected an invalid escape in {}, got {:?}", text, other),
+        }
+    }
+}
+
+#[test]
+fn length_limits_are_enforced() {
+    let label = "a".repeat(MAX_LABEL_LENGTH);
+    name(&label);
+    assert!(matches!(
+        format!("{}a", label).parse::<Name>(),
+        Err(DnsError::LabelExceedsMaxLengthSize)
+    ));
+
+    // Three full labels and one of 61 bytes make 255 bytes on the wire
+    let longest = format!("{label}.{label}.{label}.{}", "b".repeat(61));
+    assert_eq!(name(&longest).wire_length(), 255);
+    assert!(matches!(
+        format!("c.{}", longest).parse::<Name>(),
+        Err(DnsError::NameTooLong)
+    ));
+    assert!(matches!(
+        name(&longest).prepend_label(b"c"),
+        Err(DnsError::NameTooLong)
+    ));
+}
+
+#[test]
+fn empty_labels_are_rejected() {
+    for text in ["a..example", ".example", "example..", ".."] {
+        assert!(
+            matches!(text.parse::<Name>(), Err(DnsError::EmptyLabel)),
+            "{}",
+            text
+        );
+    }
+    assert!(matches!(
+        Name::from_labels([b"a".to_vec(), Vec::new()]),
+        Err(DnsError::EmptyLabel)
+    ));
+}
+
+#[test]
+fn names_compare_and_hash_without_case() {
+    let lower = name("www.example.com");
+    let mixed = name("WwW.Example.COM");
+    assert_eq!(lower, mixed);
+    assert!(!lower.eq_exact(&mixed));
+    assert_eq!(mixed.to_lowercase().to_string(), "www.example.com");
+    assert_ne!(lower, name("www.example.org"));
+    assert_ne!(lower, name("example.com"));
+
+    let mut set = HashSet::new();
+    set.insert(mixed);
+    assert!(set.contains(&lower));
+    assert!(!set.insert(name("WWW.EXAMPLE.COM")));
+
+    // Only ASCII letters fold, other bytes are compared as they are
+    assert_ne!(name(r"\195.example"), name(r"\227.example"));
+}
+
+#[test]
+fn names_sort_in_canonical_order() {
+    // The example of RFC 4034 section 6.1
+    let sorted: Vec<Name> = [
+        "example",
+        "a.example",
+        "yljkjljk.a.example",
+        "Z.a.example",
+        "zABC.a.EXAMPLE",
+        "z.example",
+        r"\001.z.example",
+        "*.z.example",
+        r"\200.z.example",
+    ]
+    .into_iter()
+    .map(name)
+    .collect();
+
+    let mut shuffled = sorted.clone();
+    shuffled.reverse();
+    shuffled.swap(1, 6);
+    shuffled.sort();
+    assert!(shuffled
+        .iter()
+        .zip(&sorted)
+        .all(|(ours, theirs)| ours.eq_exact(theirs)));
+
+    assert!(Name::root() < name("example"));
+    assert!(name("example.com") < name("a.example.com"));
+}
+
+#[test]
+fn unicode_names_round_trip_through_a_labels() {
+    for (unicode, ascii) in [
+        ("bücher.example", "xn--bcher-kva.example"),
+        ("münchen.de", "xn--mnchen-3ya.de"),
+        ("例え.テスト", "xn--r8jz45g.xn--zckzah"),
+        ("www.ñandú.example.", "www.xn--and-6ma2c.example"),
+    ] {
+        let name = Name::from_unicode(unicode).unwrap();
+        assert_eq!(name.to_string(), ascii);
+        assert_eq!(name.to_unicode(), unicode.trim_end_matches('.'));
+        assert_eq!(Name::from_unicode(&name.to_unicode()).unwrap(), name);
+    }
+
+    // UTS #46 lowercases Unicode names, ASCII ones keep their case
+    assert!(Name::from_unicode("Bücher.Example")
+        .unwrap()
+        .eq_exact(&name("xn--bcher-kva.example")));
+    assert!(Name::from_unicode("WWW.Example.com")
+        .unwrap()
+        .eq_exact(&name("WWW.Example.com")));
+    assert!(Name::from_unicode(r"a\.b.example")
+        .unwrap()
+        .eq_exact(&name(r"a\.b.example")));
+
+    // Labels that are not valid A-labels are shown as they are
+    assert_eq!(name("xn--a.example").to_unicode(), "xn--a.example");
+    assert!(matches!(
+        Name::from_unicode("ab\u{200D}.example"),
+        Err(DnsError::InvalidIdn(_))
+    ));
+    assert!(matches!(
+        Name::from_unicode("a\u{3002}\u{3002}b"),
+        Err(DnsError::EmptyLabel)
+    ));
+}
+
+#[test]
+fn questions_are_written_fully_qualified() {
+    let question = DnsQuestion::new(name("www.example.com"), QueryType::A);
+    assert_eq!(question.to_string(), ";www.example.com.\t\tIN\tA");
+
+    // The root is a single dot, as in the records that follow it
+    let question = DnsQuestion::new(Name::root(), QueryType::NS);
+    assert_eq!(question.to_string(), ";.\t\tIN\tNS");
+}
@@ -0,0 +1,256 @@
+use std::net::Ipv4Addr;
+
+use tarnish_dns::buffer::PacketBuffer;
+use tarnish_dns::name::Name;
+use tarnish_dns::protocol::{
+    DnsClass, DnsPacket, DnsRecord, Location, Opcode, ResultCode, Section,
+};
+use tarnish_dns::server::{Catalog, DnsServer};
+use tarnish_dns::DnsError;
+
+/// A header with the ID 0x1234 and the given section counts.
+fn header(questions: u16, answers: u16) -> Vec<u8> {
+    let mut bytes = vec![0x12, 0x34, 0x81, 0x80];
+    for count in [questions, answers, 0, 0] {
+        bytes.extend_from_slice(&count.to_be_bytes());
+    }
+
+    bytes
+}
+
+/// A question for `name`, given in wire format, of type A and class IN.
+fn question(name: &[u8]) -> Vec<u8> {
+    let mut bytes = name.to_vec();
+    bytes.extend_from_slice(&[0, 1, 0, 1]);
+
+    bytes
+}
+
+/// A record owned by the root, of type `qtype` and class IN, carrying
+/// `length` as its RDATA length whatever the size of `data`.
+fn record(qtype: u16, length: u16, data: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![0];
+    bytes.extend_from_slice(&qtype.to_be_bytes());
+    bytes.extend_from_slice(&[0, 1, 0, 0, 0x0e, 0x10]);
+    bytes.extend_from_slice(&length.to_be_bytes());
+    bytes.extend_from_slice(data);
+
+    bytes
+}
+
+fn parse(bytes: &[u8]) -> tarnish_dns::Result<DnsPacket> {
+    DnsPacket::from_buffer(&mut PacketBuffer::from_bytes(bytes).unwrap())
+}
+
+#[test]
+fn short_headers_are_reported_with_their_length() {
+    match parse(&header(0, 0)[..11]) {
+        Err(DnsError::TruncatedHeader { length }) => assert_eq!(length, 11),
+        other => panic!("expected a truncated header, got {:?}", other),
+    }
+    assert!(matches!(
+        parse(&[]),
+        Err(DnsError::TruncatedHeader { length: 0 })
+    ));
+}
+
+#[test]
+fn counts_past_the_end_of_the_message_are_rejected() {
+    let mut bytes = header(1, 100);
+    bytes.extend(question(b"\x07example\x00"));
+
+    match parse(&bytes) {
+        Err(DnsError::BadSectionCount {
+            section,
+            count,
+            offset,
+        }) => {
+            assert_eq!(section, Section::Answer);
+            assert_eq!(count, 100);
+            assert_eq!(offset, 12);
+        }
+        other => panic!("expected a bad section count, got {:?}", other),
+    }
+}
+
+#[test]
+fn forward_pointers_are_rejected() {
+    // The question name points past itself
+    let mut bytes = header(1, 0);
+    bytes.extend(question(&[0xc0, 14]));
+
+    match parse(&bytes) {
+        Err(DnsError::ForwardPointer {
+            offset,
+            target,
+            location,
+        }) => {
+            assert_eq!(offset, 12);
+            assert_eq!(target, 14);
+            assert_eq!(location, Location::new(Section::Question, 0));
+        }
+        other => panic!("expected a forward pointer, got {:?}", other),
+    }
+
+    // As does a pointer to itself
+    let mut bytes = header(1, 0);
+    bytes.extend(question(&[0xc0, 12]));
+    assert!(matches!(
+        parse(&bytes),
+        Err(DnsError::ForwardPointer {
+            offset: 12,
+            target: 12,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn oversized_names_are_reported_at_the_label_past_the_limit() {
+    let mut name = Vec::new();
+    for _ in 0..4 {
+        name.push(63);
+        name.extend_from_slice(&[b'a'; 63]);
+    }
+    name.push(0);
+    let mut bytes = header(1, 0);
+    bytes.extend(question(&name));
+
+    match parse(&bytes) {
+        Err(DnsError::OversizedName { offset, location }) => {
+            // Three labels of 64 bytes fit, the fourth does not
+            assert_eq!(offset, 12 + 3 * 64);
+            assert_eq!(location, Location::new(Section::Question, 0));
+        }
+        other => panic!("expected an oversized name, got {:?}", other),
+    }
+}
+
+#[test]
+fn records_running_past_the_message_are_truncated() {
+    let mut bytes = header(1, 2);
+    bytes.extend(question(b"\x07example\x00"));
+    bytes.extend(record(1, 4, &[192, 0, 2, 1]));
+    bytes.extend(record(1, 4, &[192, 0]));
+
+    match parse(&bytes) {
+        Err(DnsError::Truncated { offset, location }) => {
+            assert_eq!(offset, bytes.len());
+            assert_eq!(location, Location::new(Section::Answer, 1));
+        }
+        other => panic!("expected a truncated message, got {:?}", other),
+    }
+}
+
+/// A message with a question and the answer `records`, and the offset at
+/// which each record's RDATA starts.
+fn answers(records: &[Vec<u8>]) -> (Vec<u8>, Vec<usize>) {
+    let mut bytes = header(1, records.len() as u16);
+    bytes.extend(question(b"\x07example\x00"));
+    let mut data_starts = Vec::new();
+    for record in records {
+        data_starts.push(bytes.len() + 11);
+        bytes.extend_from_slice(record);
+    }
+
+    (bytes, data_starts)
+}
+
+#[test]
+fn fields_may_not_run_past_the_rdata() {
+    // An NS record claiming 2 bytes of RDATA, with a 5 byte name
+    let (bytes, data_starts) = answers(&[record(2, 2, b"\x03foo\x00")]);
+
+    match parse(&bytes) {
+        Err(DnsError::RdataOverrun { offset, location }) => {
+            assert_eq!(offset, data_starts[0] + 2);
+            assert_eq!(location, Location::new(Section::Answer, 0));
+        }
+        other => panic!("expected an RDATA overrun, got {:?}", other),
+    }
+}
+
+#[test]
+fn rdata_must_be_consumed_exactly() {
+    // An MX record with a byte left over after its exchange name
+    let (bytes, data_starts) = answers(&[record(15, 8, b"\x00\x0a\x03foo\x00\xff")]);
+
+    match parse(&bytes) {
+        Err(DnsError::RdataLengthMismatch {
+            offset,
+            location,
+            expected,
+            actual,
+        }) => {
+            assert_eq!(offset, data_starts[0]);
+            assert_eq!(location, Location::new(Section::Answer, 0));
+            assert_eq!(expected, 8);
+            assert_eq!(actual, 7);
+        }
+        other => panic!("expected an RDATA length mismatch, got {:?}", other),
+    }
+}
+
+#[test]
+fn lenient_parsing_skips_malformed_records() {
+    let (bytes, data_starts) = answers(&[
+        record(1, 4, &[192, 0, 2, 1]),
+        record(15, 8, b"\x00\x0a\x03foo\x00\xff"),
+        // A name running on into the record after it
+        record(2, 2, b"\x03f"),
+        record(1, 4, &[192, 0, 2, 2]),
+    ]);
+    assert!(parse(&bytes).is_err());
+
+    let mut buffer = PacketBuffer::from_bytes(&bytes).unwrap();
+    let (packet, skipped) = DnsPacket::from_buffer_lenient(&mut buffer).unwrap();
+    let address = |last| DnsRecord::A {
+        domain: Name::root(),
+        class: DnsClass::IN,
+        address: Ipv4Addr::new(192, 0, 2, last),
+        ttl: 3600,
+    };
+    assert_eq!(packet.answers, vec![address(1), address(2)]);
+
+    assert_eq!(skipped.len(), 2);
+    assert!(matches!(
+        skipped[0],
+        DnsError::RdataLengthMismatch { offset, location, .. }
+            if offset == data_starts[1] && location == Location::new(Section::Answer, 1)
+    ));
+    assert!(matches!(
+        skipped[1],
+        DnsError::RdataOverrun { offset, location }
+            if offset == data_starts[2] + 2 && location == Location::new(Section::Answer, 2)
+    ));
+
+    // Records running past the message can not be skipped
+    let mut truncated = bytes.clone();
+    truncated.truncate(truncated.len() - 2);
+    let mut buffer = PacketBuffer::from_bytes(&truncated).unwrap();
+    assert!(matches!(
+        DnsPacket::from_buffer_lenient(&mut buffer),
+        Err(DnsError::Truncated { .. })
+    ));
+}
+
+#[test]
+fn unassigned_opcodes_are_kept_and_answered_with_notimp() {
+    let mut bytes = header(1, 0);
+    bytes[2] = 3 << 3;
+    bytes.extend(question(b"\x07example\x00"));
+
+    let mut request = parse(&bytes).unwrap();
+    assert_eq!(request.header.opcode, Opcode::UNKNOWN(3));
+    let mut buffer = PacketBuffer::new();
+    request.write(&mut buffer).unwrap();
+    assert_eq!(buffer.buffer[2], bytes[2]);
+
+    let server = DnsServer::new(Catalog::new());
+    let mut buffer = PacketBuffer::from_bytes(&bytes).unwrap();
+    let response = server
+        .handle_buffer(&mut buffer, "127.0.0.1:5353".parse().unwrap())
+        .unwrap();
+    assert_eq!(response.header.opcode, Opcode::UNKNOWN(3));
+    assert_eq!(response.rcode(), ResultCode::NOTIMP);
+}
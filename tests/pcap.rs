@@ -0,0 +1,258 @@
+use std::io::Write;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tarnish_dns::buffer::PacketBuffer;
+use tarnish_dns::client::DnsClient;
+use tarnish_dns::name::Name;
+use tarnish_dns::pcap::{CaptureReader, CapturedMessage, PcapWriter, Transport};
+use tarnish_dns::protocol::{DnsClass, DnsPacket, DnsQuestion, DnsRecord, QueryType};
+use tarnish_dns::server::{Catalog, DnsServer};
+use tarnish_dns::zone::Zone;
+
+fn name(name: &str) -> Name {
+    name.parse().unwrap()
+}
+
+fn client() -> SocketAddr {
+    "192.0.2.10:40000".parse().unwrap()
+}
+
+fn resolver() -> SocketAddr {
+    "[2001:db8::53]:53".parse().unwrap()
+}
+
+fn query(id: u16) -> DnsPacket {
+    let mut packet = DnsPacket::new();
+    packet.header.id = id;
+    packet
+        .questions
+        .push(DnsQuestion::new(name("example.com"), QueryType::TXT));
+    packet
+}
+
+/// A response large enough to take several TCP segments.
+fn large_response(id: u16) -> DnsPacket {
+    let mut packet = query(id);
+    packet.header.response = true;
+    for index in 0..20 {
+        packet.answers.push(DnsRecord::TXT {
+            domain: name("example.com"),
+            class: DnsClass::IN,
+            data: vec![vec![b'a' + index; 200]],
+            ttl: 300,
+        });
+    }
+    packet
+}
+
+fn wire(packet: &DnsPacket) -> Vec<u8> {
+    let mut buffer = PacketBuffer::with_capacity(65535);
+    packet.clone().write(&mut buffer).unwrap();
+    buffer.buffer[..buffer.position].to_vec()
+}
+
+fn at(micros: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_micros(1_700_000_000_000_000 + micros)
+}
+
+fn read(capture: &[u8]) -> Vec<CapturedMessage> {
+    CaptureReader::new(capture)
+        .unwrap()
+        .collect::<tarnish_dns::Result<_>>()
+        .unwrap()
+}
+
+/// Splits a pcap file written by `PcapWriter` into its file header and its
+/// records.
+fn records(capture: &[u8]) -> (Vec<u8>, Vec<Vec<u8>>) {
+    let mut records = Vec::new();
+    let mut offset = 24;
+    while offset < capture.len() {
+        let length = u32::from_le_bytes(capture[offset + 8..offset + 12].try_into().unwrap());
+        let end = offset + 16 + length as usize;
+        records.push(capture[offset..end].to_vec());
+        offset = end;
+    }
+    (capture[..24].to_vec(), records)
+}
+
+#[test]
+fn written_messages_are_read_back() {
+    let mut writer = PcapWriter::new(Vec::new()).unwrap();
+    let v4_server: SocketAddr = "198.51.100.53:53".parse().unwrap();
+    writer
+        .write_udp(at(1), client(), v4_server, &wire(&query(1)))
+        .unwrap();
+    writer
+        .write_tcp(at(2), client(), resolver(), &wire(&query(2)))
+        .unwrap();
+    writer
+        .write_tcp(at(3), resolver(), client(), &wire(&large_response(2)))
+        .unwrap();
+    let capture = writer.into_inner();
+    assert!(records(&capture).1.len() > 3);
+
+    let messages = read(&capture);
+    assert_eq!(messages.len(), 3);
+
+    assert_eq!(messages[0].timestamp, at(1));
+    assert_eq!(messages[0].transport, Transport::Udp);
+    assert_eq!(
+        (messages[0].source, messages[0].destination),
+        (client(), v4_server)
+    );
+    assert_eq!(messages[0].bytes, wire(&query(1)));
+
+    // IPv4 peers of IPv6 ones are written as mapped addresses
+    let mapped = SocketAddr::new(Ipv4Addr::new(192, 0, 2, 10).to_ipv6_mapped().into(), 40000);
+    assert_eq!(messages[1].transport, Transport::Tcp);
+    assert_eq!(
+        (messages[1].source, messages[1].destination),
+        (mapped, resolver())
+    );
+
+    let response = messages[2].packet.as_ref().unwrap();
+    assert_eq!(response.answers, large_response(2).answers);
+    assert_eq!(messages[2].timestamp, at(3));
+}
+
+#[test]
+fn tcp_segments_are_reassembled_in_order() {
+    let mut writer = PcapWriter::new(Vec::new()).unwrap();
+    writer
+        .write_tcp(at(1), resolver(), client(), &wire(&large_response(7)))
+        .unwrap();
+    writer
+        .write_tcp(at(2), resolver(), client(), &wire(&large_response(8)))
+        .unwrap();
+    let (header, mut segments) = records(&writer.into_inner());
+
+    // Out of order after the SYN, with a retransmission
+    segments[1..].reverse();
+    segments.insert(3, segments[1].clone());
+    let capture: Vec<u8> = header.into_iter().chain(segments.concat()).collect();
+
+    let messages = read(&capture);
+    let ids: Vec<u16> = messages
+        .iter()
+        .map(|message| message.packet.as_ref().unwrap().header.id)
+        .collect();
+    assert_eq!(ids, vec![7, 8]);
+}
+
+#[test]
+fn pcapng_with_ethernet_frames_is_read() {
+    let mut writer = PcapWriter::new(Vec::new()).unwrap();
+    let server: SocketAddr = "198.51.100.53:53".parse().unwrap();
+    writer
+        .write_udp(at(0), client(), server, &wire(&query(9)))
+        .unwrap();
+    let (_, packets) = records(&writer.into_inner());
+    let ip = &packets[0][16..];
+
+    // Ethernet with a VLAN tag
+    let mut frame = vec![0; 12];
+    frame.extend_from_slice(&[0x81, 0x00, 0x00, 0x64, 0x08, 0x00]);
+    frame.extend_from_slice(ip);
+
+    let block = |block_type: u32, body: &[u8]| {
+        let length = (12 + body.len()) as u32;
+        let mut block = block_type.to_le_bytes().to_vec();
+        block.extend_from_slice(&length.to_le_bytes());
+        block.extend_from_slice(body);
+        block.extend_from_slice(&length.to_le_bytes());
+        block
+    };
+
+    let mut section = 0x1A2B_3C4Du32.to_le_bytes().to_vec();
+    section.extend_from_slice(&[1, 0, 0, 0]);
+    section.extend_from_slice(&u64::MAX.to_le_bytes());
+
+    // Ethernet, with nanosecond timestamps
+    let mut interface = vec![1, 0, 0, 0, 0, 0, 0, 0];
+    interface.extend_from_slice(&[9, 0, 1, 0, 9, 0, 0, 0, 0, 0, 0, 0]);
+
+    let nanos: u64 = 1_700_000_000_123_456_789;
+    let mut packet = 0u32.to_le_bytes().to_vec();
+    packet.extend_from_slice(&((nanos >> 32) as u32).to_le_bytes());
+    packet.extend_from_slice(&(nanos as u32).to_le_bytes());
+    packet.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+    packet.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+    packet.extend_from_slice(&frame);
+    packet.resize(packet.len().next_multiple_of(4), 0);
+
+    let mut capture = block(0x0A0D_0D0A, &section);
+    capture.extend(block(1, &interface));
+    capture.extend(block(6, &packet));
+
+    let messages = read(&capture);
+    assert_eq!(messages.len(), 1);
+    assert_eq!(
+        messages[0].timestamp,
+        UNIX_EPOCH + Duration::from_nanos(nanos)
+    );
+    assert_eq!(
+        (messages[0].source, messages[0].destination),
+        (client(), server)
+    );
+    assert_eq!(messages[0].packet.as_ref().unwrap().header.id, 9);
+}
+
+/// A writer whose contents stay readable after it is handed over.
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn server_traffic_is_captured() {
+    let mut zone = Zone::new(name("example.com"), DnsClass::IN);
+    zone.insert(DnsRecord::A {
+        domain: name("www.example.com"),
+        class: DnsClass::IN,
+        address: Ipv4Addr::new(192, 0, 2, 1),
+        ttl: 300,
+    });
+    let mut catalog = Catalog::new();
+    catalog.insert(zone);
+
+    let capture = SharedBuffer::default();
+    let mut server = DnsServer::new(catalog);
+    server.capture_to(capture.clone()).unwrap();
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let address = socket.local_addr().unwrap();
+    thread::spawn(move || server.serve_udp(&socket));
+
+    let mut request = DnsPacket::new();
+    request
+        .questions
+        .push(DnsQuestion::new(name("www.example.com"), QueryType::A));
+    let response = DnsClient::new(address).send(request).unwrap();
+    assert_eq!(response.answers.len(), 1);
+
+    // The response is captured right after it is sent
+    thread::sleep(Duration::from_millis(50));
+    let capture = capture.0.lock().unwrap();
+    let mut reader = CaptureReader::new(capture.as_slice()).unwrap();
+    reader.port = address.port();
+    let messages: Vec<CapturedMessage> = reader.map(Result::unwrap).collect();
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0].destination, address);
+    assert_eq!(messages[1].source, address);
+    assert_eq!(messages[1].destination, messages[0].source);
+    let captured = messages[1].packet.as_ref().unwrap();
+    assert_eq!(captured.header.id, response.header.id);
+    assert_eq!(captured.answers, response.answers);
+}
@@ -0,0 +1,134 @@
+mod common;
+
+use std::io::Write;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+
+use tarnish_dns::client::DnsClient;
+use tarnish_dns::pcap::Transport;
+use tarnish_dns::protocol::{DnsPacket, DnsQuestion, QueryType, ResultCode};
+use tarnish_dns::querylog::{anonymize, LogFormat, QueryLog, QueryLogEntry, Rotation};
+
+use common::{name, serve, www_zone};
+
+fn entry() -> QueryLogEntry {
+    QueryLogEntry {
+        // 2024-02-29T12:34:56.789Z
+        time: UNIX_EPOCH + Duration::from_millis(1_709_210_096_789),
+        client: "2001:db8:1:2::53".parse().unwrap(),
+        transport: Transport::Tcp,
+        question: Some(DnsQuestion::new(name("www.example.com"), QueryType::AAAA)),
+        rcode: ResultCode::NXDOMAIN,
+        answers: 0,
+        latency: Duration::from_micros(1250),
+    }
+}
+
+/// A writer whose contents stay readable after it is handed over.
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn entries_are_formatted() {
+    assert_eq!(
+        entry().to_json(),
+        "{\"time\":\"2024-02-29T12:34:56.789Z\",\"client\":\"2001:db8:1:2::53\",\
+         \"transport\":\"tcp\",\"name\":\"www.example.com\",\"type\":\"AAAA\",\
+         \"rcode\":\"NXDOMAIN\",\"answers\":0,\"latency_ms\":1.250}"
+    );
+    assert_eq!(
+        entry().to_text(),
+        "2024-02-29T12:34:56.789Z 2001:db8:1:2::53 tcp www.example.com AAAA NXDOMAIN 0 1.250ms"
+    );
+}
+
+#[test]
+fn client_addresses_are_anonymized() {
+    let v4: IpAddr = "192.0.2.77".parse().unwrap();
+    assert_eq!(anonymize(v4), "192.0.2.0".parse::<IpAddr>().unwrap());
+    assert_eq!(
+        anonymize(entry().client),
+        "2001:db8:1::".parse::<IpAddr>().unwrap()
+    );
+
+    let buffer = SharedBuffer::default();
+    let mut log = QueryLog::to_writer(buffer.clone());
+    log.format = LogFormat::Text;
+    log.anonymize = true;
+    log.log(&entry());
+    let text = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    assert!(text.contains(" 2001:db8:1:: tcp "), "{}", text);
+}
+
+#[test]
+fn server_answers_are_sampled() {
+    let buffer = SharedBuffer::default();
+    let mut log = QueryLog::to_writer(buffer.clone());
+    log.sample = 2;
+    let address = serve(www_zone(), |server| server.query_log = Some(log));
+
+    let client = DnsClient::new(address);
+    for _ in 0..4 {
+        let mut request = DnsPacket::new();
+        request
+            .questions
+            .push(DnsQuestion::new(name("www.example.com"), QueryType::A));
+        client.send(request).unwrap();
+    }
+    thread::sleep(Duration::from_millis(50));
+
+    let text = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    let lines: Vec<serde_json::Value> = text
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["client"], "127.0.0.1");
+    assert_eq!(lines[0]["transport"], "udp");
+    assert_eq!(lines[0]["name"], "www.example.com");
+    assert_eq!(lines[0]["type"], "A");
+    assert_eq!(lines[0]["rcode"], "NOERROR");
+    assert_eq!(lines[0]["answers"], 1);
+    assert!(lines[0]["latency_ms"].as_f64().unwrap() >= 0.0);
+}
+
+#[test]
+fn files_are_rotated() {
+    let directory = std::env::temp_dir().join(format!("tarnish-querylog-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let path = directory.join("queries.log");
+    let line_length = entry().to_json().len() as u64 + 1;
+
+    let rotation = Rotation {
+        max_bytes: 2 * line_length,
+        keep: 2,
+    };
+    let log = QueryLog::to_file(&path, Some(rotation)).unwrap();
+    for _ in 0..7 {
+        log.log(&entry());
+    }
+
+    let lines = |suffix: &str| {
+        let mut file = path.clone().into_os_string();
+        file.push(suffix);
+        std::fs::read_to_string(file).map(|text| text.lines().count())
+    };
+    assert_eq!(lines("").unwrap(), 1);
+    assert_eq!(lines(".1").unwrap(), 2);
+    assert_eq!(lines(".2").unwrap(), 2);
+    assert!(lines(".3").is_err());
+    std::fs::remove_dir_all(&directory).unwrap();
+}
@@ -0,0 +1,338 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+use tarnish_dns::buffer::PacketBuffer;
+use tarnish_dns::name::{Name, MAX_LABEL_LENGTH};
+use tarnish_dns::protocol::{
+    DnsClass, DnsHeader, DnsPacket, DnsQuestion, DnsRecord, EdnsOption, Opcode, QueryType,
+    ResultCode,
+};
+
+/// Labels of any byte content, biased towards the shapes that break parsers:
+/// single bytes, maximum length labels and bytes needing escapes.
+fn label() -> impl Strategy<Value = Vec<u8>> {
+    prop_oneof![
+        4 => vec(b'a'..=b'z', 1..=12),
+        1 => vec(any::<u8>(), 1..=MAX_LABEL_LENGTH),
+        1 => Just(vec![b'x'; MAX_LABEL_LENGTH]),
+        1 => vec(prop_oneof![Just(b'.'), Just(b'\\'), Just(b' '), Just(0u8)], 1..=4),
+    ]
+}
+
+/// Valid names, from the root up to ones close to the 255 byte limit.
+fn name() -> impl Strategy<Value = Name> {
+    vec(label(), 0..=6).prop_map(|labels| {
+        let mut name = Name::root();
+        for label in labels {
+            // Labels that would overflow the total length end the name
+            match name.prepend_label(&label) {
+                Ok(longer) => name = longer,
+                Err(_) => break,
+            }
+        }
+        name
+    })
+}
+
+/// Any value of the 4 bit header field, assigned or not.
+fn header_result_code() -> impl Strategy<Value = ResultCode> {
+    (0u16..16).prop_map(ResultCode::from_number)
+}
+
+fn opcode() -> impl Strategy<Value = Opcode> {
+    (0u8..16).prop_map(Opcode::from_number)
+}
+
+fn header() -> impl Strategy<Value = DnsHeader> {
+    (
+        any::<u16>(),
+        any::<[bool; 8]>(),
+        opcode(),
+        header_result_code(),
+    )
+        .prop_map(|(id, flags, opcode, rescode)| {
+            let mut header = DnsHeader::new();
+            header.id = id;
+            header.recursion_desired = flags[0];
+            header.truncated_message = flags[1];
+            header.authoritative_answer = flags[2];
+            header.response = flags[3];
+            header.checking_disabled = flags[4];
+            header.authed_data = flags[5];
+            header.z = flags[6];
+            header.recursion_available = flags[7];
+            header.opcode = opcode;
+            header.rescode = rescode;
+            header
+        })
+}
+
+/// Type numbers that the parser does not decode into a dedicated variant.
+fn unknown_type() -> impl Strategy<Value = u16> {
+    any::<u16>().prop_filter("known type", |qtype| {
+        matches!(QueryType::from_number(*qtype), QueryType::UNKNOWN(_))
+    })
+}
+
+fn query_type() -> impl Strategy<Value = QueryType> {
+    prop_oneof![
+        Just(QueryType::A),
+        Just(QueryType::NS),
+        Just(QueryType::CNAME),
+        Just(QueryType::MX),
+        Just(QueryType::TXT),
+        Just(QueryType::AAAA),
+        Just(QueryType::OPT),
+        Just(QueryType::TSIG),
+        Just(QueryType::IXFR),
+        Just(QueryType::AXFR),
+        unknown_type().prop_map(QueryType::UNKNOWN),
+    ]
+}
+
+fn class() -> impl Strategy<Value = DnsClass> {
+    prop_oneof![
+        4 => Just(DnsClass::IN),
+        1 => Just(DnsClass::CH),
+        1 => Just(DnsClass::HS),
+        1 => Just(DnsClass::NONE),
+        1 => Just(DnsClass::ANY),
+        1 => any::<u16>().prop_map(DnsClass::from_number),
+    ]
+}
+
+fn question() -> impl Strategy<Value = DnsQuestion> {
+    (name(), query_type(), class()).prop_map(|(name, qtype, qclass)| {
+        let mut question = DnsQuestion::new(name, qtype);
+        question.qclass = qclass;
+        question
+    })
+}
+
+fn record() -> impl Strategy<Value = DnsRecord> {
+    prop_oneof![
+        (
+            name(),
+            class(),
+            unknown_type(),
+            vec(any::<u8>(), 0..32),
+            any::<u32>()
+        )
+            .prop_map(|(domain, class, qtype, data, ttl)| DnsRecord::UNKNOWN {
+                domain,
+                class,
+                qtype,
+                data,
+                ttl,
+            }),
+        // RRset and name references in UPDATE messages
+        (
+            name(),
+            prop_oneof![Just(DnsClass::ANY), Just(DnsClass::NONE)],
+            query_type().prop_filter("OPT", |qtype| *qtype != QueryType::OPT),
+            any::<u32>()
+        )
+            .prop_map(|(domain, class, qtype, ttl)| DnsRecord::UNKNOWN {
+                domain,
+                class,
+                qtype: qtype.to_number(),
+                data: Vec::new(),
+                ttl,
+            }),
+        (name(), class(), any::<[u8; 4]>(), any::<u32>()).prop_map(
+            |(domain, class, octets, ttl)| DnsRecord::A {
+                domain,
+                class,
+                address: Ipv4Addr::from(octets),
+                ttl,
+            }
+        ),
+        (name(), class(), name(), any::<u32>()).prop_map(|(domain, class, host, ttl)| {
+            DnsRecord::NS {
+                domain,
+                class,
+                host,
+                ttl,
+            }
+        }),
+        (name(), class(), name(), any::<u32>()).prop_map(|(domain, class, host, ttl)| {
+            DnsRecord::CNAME {
+                domain,
+                class,
+                host,
+                ttl,
+            }
+        }),
+        (name(), class(), any::<u16>(), name(), any::<u32>()).prop_map(
+            |(domain, class, priority, host, ttl)| DnsRecord::MX {
+                domain,
+                class,
+                priority,
+                host,
+                ttl,
+            }
+        ),
+        (
+            name(),
+            class(),
+            vec(vec(any::<u8>(), 0..40), 1..4),
+            any::<u32>()
+        )
+            .prop_map(|(domain, class, data, ttl)| DnsRecord::TXT {
+                domain,
+                class,
+                data,
+                ttl,
+            }),
+        (
+            (name(), class(), name(), 0u64..1 << 48, any::<u16>()),
+            (
+                vec(any::<u8>(), 0..64),
+                any::<u16>(),
+                any::<u16>(),
+                vec(any::<u8>(), 0..8),
+                any::<u32>()
+            )
+        )
+            .prop_map(
+                |(
+                    (domain, class, algorithm, time_signed, fudge),
+                    (mac, original_id, error, other, ttl),
+                )| DnsRecord::TSIG {
+                    domain,
+                    class,
+                    algorithm,
+                    time_signed,
+                    fudge,
+                    mac,
+                    original_id,
+                    error: ResultCode::from_number(error),
+                    other,
+                    ttl,
+                }
+            ),
+        (name(), class(), any::<[u8; 16]>(), any::<u32>()).prop_map(
+            |(domain, class, octets, ttl)| DnsRecord::AAAA {
+                domain,
+                class,
+                address: Ipv6Addr::from(octets),
+                ttl,
+            }
+        ),
+        (
+            any::<u16>(),
+            any::<u8>(),
+            any::<u8>(),
+            any::<u16>(),
+            vec(edns_option(), 0..3)
+        )
+            .prop_map(
+                |(udp_payload_size, extended_rcode, version, flags, options)| DnsRecord::OPT {
+                    udp_payload_size,
+                    extended_rcode,
+                    version,
+                    flags,
+                    options,
+                }
+            ),
+    ]
+}
+
+fn edns_option() -> impl Strategy<Value = EdnsOption> {
+    (any::<u16>(), vec(any::<u8>(), 0..16)).prop_map(|(code, data)| EdnsOption { code, data })
+}
+
+fn packet() -> impl Strategy<Value = DnsPacket> {
+    (
+        header(),
+        vec(question(), 0..3),
+        vec(record(), 0..4),
+        vec(record(), 0..3),
+        vec(record(), 0..3),
+    )
+        .prop_map(|(header, questions, answers, authorities, resources)| {
+            let mut packet = DnsPacket::new();
+            packet.header = header;
+            packet.questions = questions;
+            packet.answers = answers;
+            packet.authorities = authorities;
+            packet.resources = resources;
+            packet
+        })
+}
+
+/// Writes `packet`, returning `None` when it does not fit in a message.
+fn write(packet: &mut DnsPacket) -> Option<Vec<u8>> {
+    let mut buffer = PacketBuffer::new();
+    packet.write(&mut buffer).ok()?;
+
+    Some(buffer.buffer[..buffer.position].to_vec())
+}
+
+fn read(bytes: &[u8]) -> DnsPacket {
+    let mut buffer = PacketBuffer::from_bytes(bytes).unwrap();
+    DnsPacket::from_buffer(&mut buffer).unwrap()
+}
+
+proptest! {
+    #[test]
+    fn packet_roundtrip(mut packet in packet()) {
+        if let Some(bytes) = write(&mut packet) {
+            let reread = read(&bytes);
+            prop_assert_eq!(&reread, &packet);
+
+            // Names compare case-insensitively, the wire bytes must match too
+            let mut reread = reread;
+            prop_assert_eq!(write(&mut reread), Some(bytes));
+        }
+    }
+
+    #[test]
+    fn header_wire_roundtrip(mut bytes in any::<[u8; 12]>()) {
+        // Every bit must survive, unassigned opcodes included
+        // Keep the section counts at zero so the header is a whole message
+        bytes[4..].fill(0);
+
+        let mut packet = read(&bytes);
+        prop_assert_eq!(write(&mut packet), Some(bytes.to_vec()));
+    }
+
+    #[test]
+    fn extended_rcode_roundtrip(number in 0u16..4096) {
+        let rcode = ResultCode::from_number(number);
+        let mut packet = DnsPacket::new();
+        packet.set_rcode(rcode);
+
+        let bytes = write(&mut packet).unwrap();
+        prop_assert_eq!(read(&bytes).rcode(), rcode);
+        prop_assert_eq!(rcode.to_number(), number);
+    }
+
+    #[test]
+    fn name_presentation_roundtrip(name in name()) {
+        let parsed: Name = name.to_string().parse().unwrap();
+        prop_assert!(parsed.eq_exact(&name));
+    }
+
+    #[test]
+    fn type_and_class_presentation_roundtrip(qtype in query_type(), class in class()) {
+        prop_assert_eq!(qtype.to_string().parse::<QueryType>().unwrap(), qtype);
+        prop_assert_eq!(qtype.to_string().to_lowercase().parse::<QueryType>().unwrap(), qtype);
+        prop_assert_eq!(class.to_string().parse::<DnsClass>().unwrap(), class);
+    }
+
+    #[test]
+    fn name_canonical_order_is_consistent(first in name(), second in name()) {
+        prop_assert_eq!(first.cmp(&second), second.cmp(&first).reverse());
+        prop_assert_eq!(first == second, first.cmp(&second).is_eq());
+        prop_assert_eq!(first.to_lowercase().cmp(&second), first.cmp(&second));
+    }
+
+    #[test]
+    fn parsing_arbitrary_bytes_does_not_panic(bytes in vec(any::<u8>(), 0..512)) {
+        let mut buffer = PacketBuffer::from_bytes(&bytes).unwrap();
+        let _ = DnsPacket::from_buffer(&mut buffer);
+    }
+}
@@ -0,0 +1,117 @@
+mod common;
+
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tarnish_dns::client::DnsClient;
+use tarnish_dns::protocol::{DnsClass, DnsRecord, ResultCode};
+use tarnish_dns::secondary::{SecondaryManager, SecondaryZone, ZoneState};
+use tarnish_dns::server::{Catalog, DnsServer};
+use tarnish_dns::update::UpdateBuilder;
+
+use common::{name, origin, primary_zone};
+
+/// Serves `server` over UDP and TCP on the same loopback port.
+fn serve(server: &DnsServer) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let socket = UdpSocket::bind(address).unwrap();
+
+    let tcp = server.clone();
+    thread::spawn(move || tcp.serve_tcp(&listener));
+    let udp = server.clone();
+    thread::spawn(move || udp.serve_udp(&socket));
+
+    address
+}
+
+fn wait_for(condition: impl Fn() -> bool) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while Instant::now() < deadline {
+        if condition() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    false
+}
+
+fn secondary_serial(server: &DnsServer) -> Option<u32> {
+    server
+        .catalog
+        .read()
+        .unwrap()
+        .get(&origin(), DnsClass::IN)?
+        .serial()
+}
+
+#[test]
+fn notify_triggers_refresh() {
+    let mut secondary = DnsServer::new(Catalog::new());
+    let manager = Arc::new(SecondaryManager::new(&mut secondary));
+    let secondary_address = serve(&secondary);
+
+    let mut catalog = Catalog::new();
+    catalog.insert(primary_zone());
+    let mut primary = DnsServer::new(catalog);
+    primary.also_notify.push(secondary_address);
+    let primary_address = serve(&primary);
+
+    manager.add_zone(SecondaryZone::new(
+        origin(),
+        DnsClass::IN,
+        primary_address,
+        None,
+    ));
+    assert_eq!(
+        manager.state(&origin(), DnsClass::IN),
+        Some(ZoneState::Expired)
+    );
+
+    let runner = manager.clone();
+    thread::spawn(move || runner.run());
+    assert!(wait_for(
+        || manager.state(&origin(), DnsClass::IN) == Some(ZoneState::Fresh)
+    ));
+    assert_eq!(secondary_serial(&secondary), Some(1));
+
+    // The refresh timer is an hour away, only the NOTIFY sent by the primary
+    // after the update can bring the change over in time
+    let update = UpdateBuilder::new(origin(), DnsClass::IN)
+        .add_record(DnsRecord::A {
+            domain: name("www.example.com"),
+            class: DnsClass::IN,
+            address: Ipv4Addr::new(192, 0, 2, 1),
+            ttl: 300,
+        })
+        .build(0);
+    let response = DnsClient::new(primary_address).send(update).unwrap();
+    assert_eq!(response.rcode(), ResultCode::NOERROR);
+
+    assert!(wait_for(|| secondary_serial(&secondary) == Some(2)));
+    assert!(wait_for(|| manager.zones()[0].serial == Some(2)));
+}
+
+#[test]
+fn unreachable_primary_leaves_zone_expired() {
+    let mut secondary = DnsServer::new(Catalog::new());
+    let manager = SecondaryManager::new(&mut secondary);
+
+    // Nothing listens on the port of a socket that was just closed
+    let unused = UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    manager.add_zone(SecondaryZone::new(origin(), DnsClass::IN, unused, None));
+
+    let now = Instant::now();
+    manager.tick(now);
+
+    let zone = &manager.zones()[0];
+    assert_eq!(zone.state, ZoneState::Expired);
+    assert!(zone.next_refresh > now);
+    assert_eq!(secondary_serial(&secondary), None);
+}
@@ -0,0 +1,142 @@
+mod common;
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::Duration;
+
+use tarnish_dns::protocol::{DnsClass, DnsQuestion, DnsRecord, QueryType, ResultCode};
+use tarnish_dns::trace::Tracer;
+use tarnish_dns::zone::Zone;
+use tarnish_dns::DnsError;
+
+use common::{name, serve_at};
+
+fn zone(origin: &str) -> Zone {
+    let mut zone = Zone::new(name(origin), DnsClass::IN);
+    zone.insert(DnsRecord::SOA {
+        domain: name(origin),
+        class: DnsClass::IN,
+        m_name: name("ns.nic.com"),
+        r_name: name("hostmaster.nic.com"),
+        serial: 1,
+        refresh: 3600,
+        retry: 600,
+        expire: 86400,
+        minimum: 300,
+        ttl: 3600,
+    });
+
+    zone
+}
+
+fn ns(domain: &str, host: &str) -> DnsRecord {
+    DnsRecord::NS {
+        domain: name(domain),
+        class: DnsClass::IN,
+        host: name(host),
+        ttl: 3600,
+    }
+}
+
+fn a(domain: &str, address: Ipv4Addr) -> DnsRecord {
+    DnsRecord::A {
+        domain: name(domain),
+        class: DnsClass::IN,
+        address,
+        ttl: 3600,
+    }
+}
+
+/// A small hierarchy on loopback addresses sharing one port: the root on
+/// 127.0.0.1 delegates com and net to 127.0.0.2, where example.com is
+/// delegated to a server in net, without glue, on 127.0.0.3. The root also
+/// lists a lame server for com that never answers.
+fn hierarchy() -> Tracer {
+    let port = UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let at = |last: u8| SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, last)), port);
+
+    let mut root = zone(".");
+    root.insert(ns(".", "a.root-servers.net"));
+    for tld in ["com", "net"] {
+        root.insert(ns(tld, "ns.nic.com"));
+        root.insert(ns(tld, "ns.lame.com"));
+    }
+    root.insert(a("ns.nic.com", Ipv4Addr::new(127, 0, 0, 2)));
+    root.insert(a("ns.lame.com", Ipv4Addr::new(127, 0, 0, 4)));
+    serve_at(at(1), vec![root], |_| {});
+
+    let mut com = zone("com");
+    com.insert(ns("example.com", "ns.example.net"));
+    let mut net = zone("net");
+    net.insert(a("ns.example.net", Ipv4Addr::new(127, 0, 0, 3)));
+    serve_at(at(2), vec![com, net], |_| {});
+
+    let mut example = zone("example.com");
+    example.insert(a("www.example.com", Ipv4Addr::new(192, 0, 2, 1)));
+    serve_at(at(3), vec![example], |_| {});
+
+    let mut tracer = Tracer::new();
+    tracer.roots = vec![(name("a.root-servers.net"), at(1))];
+    tracer.port = port;
+    tracer.timeout = Duration::from_millis(200);
+    tracer
+}
+
+#[test]
+fn trace_follows_delegations_to_the_answer() {
+    let tracer = hierarchy();
+
+    let mut steps = Vec::new();
+    let question = DnsQuestion::new(name("www.example.com"), QueryType::A);
+    let response = tracer
+        .trace(&question, |step| {
+            steps.push((step.zone.to_string(), step.server, step.result.is_ok()));
+        })
+        .unwrap();
+
+    assert_eq!(
+        response.answers,
+        vec![a("www.example.com", Ipv4Addr::new(192, 0, 2, 1))]
+    );
+
+    // The lame server may be tried before the working one, the glueless
+    // name server is looked up without being reported
+    let answered: Vec<(&str, u8)> = steps
+        .iter()
+        .filter(|(_, _, ok)| *ok)
+        .map(|(zone, server, _)| match server.ip() {
+            IpAddr::V4(address) => (zone.as_str(), address.octets()[3]),
+            IpAddr::V6(_) => unreachable!(),
+        })
+        .collect();
+    assert_eq!(answered, vec![(".", 1), ("com", 2), ("example.com", 3)]);
+    assert!(steps
+        .iter()
+        .all(|(_, server, ok)| *ok || server.ip() == IpAddr::from([127, 0, 0, 4])));
+}
+
+#[test]
+fn trace_ends_at_a_negative_answer() {
+    let tracer = hierarchy();
+
+    let question = DnsQuestion::new(name("missing.example.com"), QueryType::A);
+    let response = tracer.trace(&question, |_| {}).unwrap();
+
+    assert_eq!(response.rcode(), ResultCode::NXDOMAIN);
+    assert!(response.answers.is_empty());
+}
+
+#[test]
+fn trace_fails_without_reachable_servers() {
+    let mut tracer = hierarchy();
+    tracer.roots[0].1.set_ip(IpAddr::from([127, 0, 0, 4]));
+
+    let question = DnsQuestion::new(name("www.example.com"), QueryType::A);
+    assert!(matches!(
+        tracer.trace(&question, |_| {}),
+        Err(DnsError::Timeout)
+    ));
+}
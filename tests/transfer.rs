@@ -0,0 +1,138 @@
+mod common;
+
+use std::net::{SocketAddr, TcpListener};
+use std::thread;
+
+use tarnish_dns::protocol::{DnsClass, DnsRecord, QueryType};
+use tarnish_dns::server::{Catalog, DnsServer};
+use tarnish_dns::transfer::TransferClient;
+use tarnish_dns::tsig::{TsigAlgorithm, TsigKey};
+use tarnish_dns::update::{apply_update, UpdateBuilder};
+use tarnish_dns::zone::Zone;
+use tarnish_dns::DnsError;
+
+use common::{host, name, origin};
+
+/// A zone large enough that its transfer spans several messages.
+fn primary_zone() -> Zone {
+    let mut zone = common::primary_zone();
+    for index in 0..2000 {
+        zone.insert(host(index));
+    }
+
+    zone
+}
+
+/// Starts a primary serving `zone` over TCP on loopback.
+fn start_primary(zone: Zone, keys: Vec<TsigKey>) -> (DnsServer, SocketAddr) {
+    let mut catalog = Catalog::new();
+    catalog.insert(zone);
+    let mut server = DnsServer::new(catalog);
+    server.keys = keys;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let primary = server.clone();
+    thread::spawn(move || primary.serve_tcp(&listener));
+
+    (server, address)
+}
+
+/// Applies an update adding and removing a record on the primary.
+fn update_primary(server: &DnsServer, added: DnsRecord, removed: DnsRecord) {
+    let request = UpdateBuilder::new(origin(), DnsClass::IN)
+        .add_record(added)
+        .delete_record(removed)
+        .build(1);
+
+    let mut catalog = server.catalog.write().unwrap();
+    let zone = catalog.get_mut(&origin(), DnsClass::IN).unwrap();
+    apply_update(zone, &request);
+}
+
+fn primary_copy(server: &DnsServer) -> Zone {
+    server
+        .catalog
+        .read()
+        .unwrap()
+        .get(&origin(), DnsClass::IN)
+        .unwrap()
+        .clone()
+}
+
+fn assert_same_records(secondary: &Zone, primary: &Zone) {
+    assert_eq!(secondary.serial(), primary.serial());
+    assert!(secondary.records().eq(primary.records()));
+}
+
+#[test]
+fn axfr_copies_the_whole_zone() {
+    let (server, address) = start_primary(primary_zone(), Vec::new());
+
+    let secondary = TransferClient::new(address)
+        .axfr(&origin(), DnsClass::IN)
+        .unwrap();
+
+    assert_eq!(secondary.len(), 2002);
+    assert_same_records(&secondary, &primary_copy(&server));
+}
+
+#[test]
+fn ixfr_applies_journaled_changes() {
+    let (server, address) = start_primary(primary_zone(), Vec::new());
+    let client = TransferClient::new(address);
+    let mut secondary = client.axfr(&origin(), DnsClass::IN).unwrap();
+
+    assert!(!client.refresh(&mut secondary).unwrap());
+
+    update_primary(&server, host(5000), host(1));
+    update_primary(&server, host(5001), host(2));
+    assert!(client.refresh(&mut secondary).unwrap());
+
+    assert_eq!(secondary.serial(), Some(3));
+    assert_eq!(secondary.journal().len(), 2);
+    assert!(secondary.rrset(host(1).domain(), QueryType::A).is_empty());
+    assert_same_records(&secondary, &primary_copy(&server));
+}
+
+#[test]
+fn ixfr_falls_back_to_full_transfer_without_journal() {
+    let (server, address) = start_primary(primary_zone(), Vec::new());
+    let client = TransferClient::new(address);
+    let mut secondary = client.axfr(&origin(), DnsClass::IN).unwrap();
+
+    // Replacing the zone on the primary loses its journal
+    update_primary(&server, host(5000), host(1));
+    let mut replaced = primary_zone();
+    replaced.replace_with(primary_copy(&server));
+    replaced.insert(host(6000));
+    replaced.set_serial(10);
+    server.catalog.write().unwrap().insert(replaced);
+
+    assert!(client.refresh(&mut secondary).unwrap());
+    assert_same_records(&secondary, &primary_copy(&server));
+}
+
+#[test]
+fn signed_transfers_require_the_key() {
+    let key = TsigKey::new(
+        name("transfer.key"),
+        TsigAlgorithm::HmacSha256,
+        b"shared secret".to_vec(),
+    );
+    let (server, address) = start_primary(primary_zone(), vec![key.clone()]);
+
+    let mut client = TransferClient::new(address);
+    assert!(matches!(
+        client.axfr(&origin(), DnsClass::IN),
+        Err(DnsError::TransferRefused(_))
+    ));
+
+    client.tsig = Some(key);
+    let mut secondary = client.axfr(&origin(), DnsClass::IN).unwrap();
+    assert_same_records(&secondary, &primary_copy(&server));
+
+    update_primary(&server, host(5000), host(1));
+    assert!(client.refresh(&mut secondary).unwrap());
+    assert_same_records(&secondary, &primary_copy(&server));
+}
@@ -0,0 +1,337 @@
+use std::net::{Ipv4Addr, UdpSocket};
+use std::thread;
+
+use tarnish_dns::buffer::{PacketBuffer, MAX_MESSAGE_LENGTH};
+use tarnish_dns::client::DnsClient;
+use tarnish_dns::name::Name;
+use tarnish_dns::protocol::{DnsClass, DnsPacket, DnsQuestion, DnsRecord, QueryType, ResultCode};
+use tarnish_dns::server::{Catalog, DnsServer};
+use tarnish_dns::tsig::{
+    tsig_record, unix_time, TsigAlgorithm, TsigKey, TsigSigner, TsigVerifier, DEFAULT_FUDGE,
+};
+use tarnish_dns::zone::Zone;
+use tarnish_dns::DnsError;
+
+const NOW: u64 = 1_700_000_000;
+
+fn name(name: &str) -> Name {
+    name.parse().unwrap()
+}
+
+fn key() -> TsigKey {
+    TsigKey::new(
+        name("transfer.key"),
+        TsigAlgorithm::HmacSha256,
+        b"shared secret".to_vec(),
+    )
+}
+
+fn query(id: u16) -> DnsPacket {
+    let mut packet = DnsPacket::new();
+    packet.header.id = id;
+    packet
+        .questions
+        .push(DnsQuestion::new(name("example.com"), QueryType::AXFR));
+
+    packet
+}
+
+fn host(index: u8) -> DnsRecord {
+    DnsRecord::A {
+        domain: name(&format!("host{index}.example.com")),
+        class: DnsClass::IN,
+        address: Ipv4Addr::new(192, 0, 2, index),
+        ttl: 3600,
+    }
+}
+
+fn to_bytes(packet: &mut DnsPacket) -> Vec<u8> {
+    let mut buffer = PacketBuffer::with_capacity(MAX_MESSAGE_LENGTH);
+    packet.write(&mut buffer).unwrap();
+
+    buffer.buffer[..buffer.position].to_vec()
+}
+
+/// `query(id)` signed with `key()` at `NOW`, and the MAC it was signed with.
+fn signed_query(id: u16) -> (Vec<u8>, Vec<u8>) {
+    let mut packet = query(id);
+    let mut signer = TsigSigner::new(key());
+    signer.sign(&mut packet, NOW).unwrap();
+
+    (to_bytes(&mut packet), signer.mac().unwrap().to_vec())
+}
+
+#[test]
+fn signed_messages_verify() {
+    let (bytes, request_mac) = signed_query(1);
+    let mut verifier = TsigVerifier::new(key());
+    let request = verifier.verify(&bytes, NOW + 10).unwrap();
+    assert_eq!(request.questions, query(1).questions);
+    assert!(tsig_record(&request).unwrap().is_some());
+    assert_eq!(verifier.mac(), Some(request_mac.as_slice()));
+    verifier.finish().unwrap();
+
+    // The response chains on the MAC of the request
+    let mut response = query(1);
+    response.header.response = true;
+    response.answers.push(host(1));
+    TsigSigner::for_response(key(), &request_mac)
+        .sign(&mut response, NOW)
+        .unwrap();
+    let bytes = to_bytes(&mut response);
+
+    let mut verifier = TsigVerifier::for_response(key(), &request_mac);
+    assert_eq!(verifier.verify(&bytes, NOW).unwrap().answers, vec![host(1)]);
+    verifier.finish().unwrap();
+
+    // A response to some other request does not
+    let (_, other_mac) = signed_query(2);
+    let mut verifier = TsigVerifier::for_response(key(), &other_mac);
+    assert!(matches!(
+        verifier.verify(&bytes, NOW),
+        Err(DnsError::TsigBadSignature)
+    ));
+}
+
+#[test]
+fn altered_messages_fail_verification() {
+    let (bytes, _) = signed_query(1);
+
+    // The recursion desired flag, covered by the MAC
+    let mut altered = bytes.clone();
+    altered[2] ^= 0x01;
+    assert!(matches!(
+        TsigVerifier::new(key()).verify(&altered, NOW),
+        Err(DnsError::TsigBadSignature)
+    ));
+
+    // The same key name and algorithm with another secret
+    let mut other = key();
+    other.secret = b"another secret".to_vec();
+    assert!(matches!(
+        TsigVerifier::new(other).verify(&bytes, NOW),
+        Err(DnsError::TsigBadSignature)
+    ));
+}
+
+#[test]
+fn messages_signed_outside_the_fudge_are_rejected() {
+    let (bytes, _) = signed_query(1);
+    let fudge = DEFAULT_FUDGE as u64;
+
+    TsigVerifier::new(key())
+        .verify(&bytes, NOW + fudge)
+        .unwrap();
+    TsigVerifier::new(key())
+        .verify(&bytes, NOW - fudge)
+        .unwrap();
+    for now in [NOW + fudge + 1, NOW - fudge - 1] {
+        match TsigVerifier::new(key()).verify(&bytes, now) {
+            Err(DnsError::TsigBadTime {
+                time_signed,
+                now: received,
+            }) => {
+                assert_eq!(time_signed, NOW);
+                assert_eq!(received, now);
+            }
+            other => panic!("expected BADTIME, got {:?}", other.map(|_| ())),
+        }
+    }
+}
+
+#[test]
+fn messages_signed_with_an_unknown_key_are_rejected() {
+    let (bytes, _) = signed_query(1);
+
+    let mut renamed = key();
+    renamed.name = name("other.key");
+    match TsigVerifier::new(renamed).verify(&bytes, NOW) {
+        Err(DnsError::TsigUnknownKey(key_name)) => assert_eq!(key_name, name("transfer.key")),
+        other => panic!("expected an unknown key, got {:?}", other.map(|_| ())),
+    }
+
+    let mut other_algorithm = key();
+    other_algorithm.algorithm = TsigAlgorithm::HmacSha512;
+    assert!(matches!(
+        TsigVerifier::new(other_algorithm).verify(&bytes, NOW),
+        Err(DnsError::TsigUnknownKey(_))
+    ));
+}
+
+#[test]
+fn unsigned_messages_are_limited_in_a_stream() {
+    let (_, request_mac) = signed_query(1);
+    let mut signer = TsigSigner::for_response(key(), &request_mac);
+    let mut verifier = TsigVerifier::for_response(key(), &request_mac);
+
+    let mut first = query(1);
+    first.header.response = true;
+    signer.sign(&mut first, NOW).unwrap();
+    verifier.verify(&to_bytes(&mut first), NOW).unwrap();
+
+    // Up to 99 unsigned messages may follow a signed one, but the stream
+    // may not end on them
+    let mut unsigned = query(1);
+    unsigned.header.response = true;
+    unsigned.answers.push(host(1));
+    let unsigned = to_bytes(&mut unsigned);
+    for _ in 0..99 {
+        verifier.verify(&unsigned, NOW).unwrap();
+    }
+    assert!(matches!(verifier.finish(), Err(DnsError::TsigMissing)));
+    assert!(matches!(
+        verifier.verify(&unsigned, NOW),
+        Err(DnsError::TsigUnsignedRun)
+    ));
+}
+
+#[test]
+fn unsigned_first_messages_are_rejected() {
+    let mut packet = query(1);
+    assert!(matches!(
+        TsigVerifier::new(key()).verify(&to_bytes(&mut packet), NOW),
+        Err(DnsError::TsigMissing)
+    ));
+}
+
+#[test]
+fn tsig_must_be_the_last_record() {
+    let mut packet = query(1);
+    TsigSigner::new(key()).sign(&mut packet, NOW).unwrap();
+    packet.resources.push(host(1));
+
+    assert!(matches!(tsig_record(&packet), Err(DnsError::TsigMisplaced)));
+    assert!(matches!(
+        TsigVerifier::new(key()).verify(&to_bytes(&mut packet), NOW),
+        Err(DnsError::TsigMisplaced)
+    ));
+
+    // Nor may it sit in the answer section
+    let mut packet = query(1);
+    TsigSigner::new(key()).sign(&mut packet, NOW).unwrap();
+    let record = packet.resources.pop().unwrap();
+    packet.answers.push(record);
+    assert!(matches!(tsig_record(&packet), Err(DnsError::TsigMisplaced)));
+}
+
+/// Answers one signed query with `responses`, built from the request and the
+/// MAC it was signed with, each sent as its own datagram.
+fn start_server(responses: fn(&DnsPacket, &[u8]) -> Vec<DnsPacket>) -> std::net::SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let address = socket.local_addr().unwrap();
+    thread::spawn(move || {
+        let mut bytes = [0; 512];
+        let (length, client) = socket.recv_from(&mut bytes).unwrap();
+        let mut verifier = TsigVerifier::new(key());
+        let request = verifier.verify(&bytes[..length], unix_time()).unwrap();
+        for mut response in responses(&request, verifier.mac().unwrap()) {
+            socket.send_to(&to_bytes(&mut response), client).unwrap();
+        }
+    });
+
+    address
+}
+
+fn response_to(request: &DnsPacket) -> DnsPacket {
+    let mut response = DnsPacket::new();
+    response.header.id = request.header.id;
+    response.header.response = true;
+    response.questions = request.questions.clone();
+
+    response
+}
+
+#[test]
+fn client_ignores_unsigned_tsig_errors() {
+    let address = start_server(|request, request_mac| {
+        // What anyone could send: a BADKEY error, without a MAC
+        let mut forged = response_to(request);
+        forged.set_rcode(ResultCode::NOTAUTH);
+        forged.resources.push(DnsRecord::TSIG {
+            domain: key().name,
+            class: DnsClass::ANY,
+            algorithm: key().algorithm.name(),
+            time_signed: unix_time(),
+            fudge: DEFAULT_FUDGE,
+            mac: Vec::new(),
+            original_id: request.header.id,
+            error: ResultCode::BADKEY,
+            other: Vec::new(),
+            ttl: 0,
+        });
+
+        let mut genuine = response_to(request);
+        genuine.answers.push(host(1));
+        TsigSigner::for_response(key(), request_mac)
+            .sign(&mut genuine, unix_time())
+            .unwrap();
+
+        vec![forged, genuine]
+    });
+
+    let mut client = DnsClient::new(address);
+    client.tsig = Some(key());
+    let response = client
+        .query(&name("host1.example.com"), QueryType::A)
+        .unwrap();
+    assert_eq!(response.rcode(), ResultCode::NOERROR);
+    assert_eq!(response.answers, vec![host(1)]);
+}
+
+#[test]
+fn client_reports_signed_tsig_errors() {
+    let address = start_server(|request, request_mac| {
+        let mut rejection = response_to(request);
+        rejection.set_rcode(ResultCode::NOTAUTH);
+        TsigSigner::for_response(key(), request_mac)
+            .sign_with_error(&mut rejection, unix_time(), ResultCode::BADTIME, Vec::new())
+            .unwrap();
+
+        vec![rejection]
+    });
+
+    let mut client = DnsClient::new(address);
+    client.tsig = Some(key());
+    assert!(matches!(
+        client.query(&name("host1.example.com"), QueryType::A),
+        Err(DnsError::TsigRejected(ResultCode::BADTIME))
+    ));
+}
+
+#[test]
+fn oversized_udp_responses_are_truncated_and_signed() {
+    // Sixty addresses for one name are too many for a UDP response
+    let mut zone = Zone::new(name("example.com"), DnsClass::IN);
+    for index in 0..60 {
+        let mut record = host(index);
+        if let DnsRecord::A { domain, .. } = &mut record {
+            *domain = name("pool.example.com");
+        }
+        zone.insert(record);
+    }
+    let mut catalog = Catalog::new();
+    catalog.insert(zone);
+    let mut server = DnsServer::new(catalog);
+    server.keys = vec![key()];
+
+    let mut request = DnsPacket::new();
+    request.header.id = 7;
+    request
+        .questions
+        .push(DnsQuestion::new(name("pool.example.com"), QueryType::A));
+    let mut signer = TsigSigner::new(key());
+    signer.sign(&mut request, unix_time()).unwrap();
+    let mut buffer = PacketBuffer::from_bytes(&to_bytes(&mut request)).unwrap();
+
+    let mut response = server
+        .handle_buffer(&mut buffer, "127.0.0.1:53".parse().unwrap())
+        .unwrap();
+    assert!(response.header.truncated_message);
+    assert!(response.answers.is_empty());
+
+    let bytes = to_bytes(&mut response);
+    assert!(bytes.len() <= 512);
+    let mut verifier = TsigVerifier::for_response(key(), signer.mac().unwrap());
+    verifier.verify(&bytes, unix_time()).unwrap();
+    verifier.finish().unwrap();
+}
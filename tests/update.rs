@@ -0,0 +1,436 @@
+mod common;
+
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::thread;
+
+use tarnish_dns::buffer::PacketBuffer;
+use tarnish_dns::client::DnsClient;
+use tarnish_dns::name::Name;
+use tarnish_dns::protocol::{DnsClass, DnsPacket, DnsQuestion, DnsRecord, QueryType, ResultCode};
+use tarnish_dns::server::{Catalog, DnsServer};
+use tarnish_dns::update::{apply_update, UpdateBuilder};
+use tarnish_dns::zone::Zone;
+
+use common::{host, name, ns, origin, soa};
+
+fn cname(alias: &str, target: &str) -> DnsRecord {
+    DnsRecord::CNAME {
+        domain: name(alias),
+        class: DnsClass::IN,
+        host: name(target),
+        ttl: 3600,
+    }
+}
+
+fn primary_zone() -> Zone {
+    let mut zone = common::primary_zone();
+    zone.insert(host(0));
+    zone.insert(cname("www.example.com", "host0.example.com"));
+
+    zone
+}
+
+fn update() -> UpdateBuilder {
+    UpdateBuilder::new(origin(), DnsClass::IN)
+}
+
+/// Applies `request` to a copy of `zone`, checking that a failure leaves the
+/// zone as it was.
+fn apply(zone: &Zone, request: &DnsPacket) -> (ResultCode, Zone) {
+    let mut updated = zone.clone();
+    let rcode = apply_update(&mut updated, request);
+    if rcode != ResultCode::NOERROR {
+        assert_eq!(&updated, zone, "{:?} changed the zone", rcode);
+    }
+
+    (rcode, updated)
+}
+
+#[test]
+fn prerequisites_are_checked() {
+    let zone = primary_zone();
+    let mut other_address = host(0);
+    if let DnsRecord::A { address, .. } = &mut other_address {
+        *address = Ipv4Addr::new(198, 51, 100, 1);
+    }
+
+    let cases = [
+        (
+            update().require_name_in_use(name("host9.example.com")),
+            ResultCode::NXDOMAIN,
+        ),
+        (
+            update().require_name_absent(name("host0.example.com")),
+            ResultCode::YXDOMAIN,
+        ),
+        (
+            update().require_rrset_exists(name("host0.example.com"), QueryType::MX),
+            ResultCode::NXRRSET,
+        ),
+        (
+            update().require_rrset_absent(name("host0.example.com"), QueryType::A),
+            ResultCode::YXRRSET,
+        ),
+        (
+            update().require_rrset_matches(vec![other_address]),
+            ResultCode::NXRRSET,
+        ),
+        (
+            update().require_rrset_matches(vec![host(0), host(1)]),
+            ResultCode::NXRRSET,
+        ),
+        (
+            update().require_name_in_use(name("host0.example.net")),
+            ResultCode::NOTZONE,
+        ),
+    ];
+    for (builder, expected) in cases {
+        let request = builder.add_record(host(1)).build(1);
+        let (rcode, _) = apply(&zone, &request);
+        assert_eq!(rcode, expected);
+    }
+
+    // Prerequisites that hold let the update through
+    let request = update()
+        .require_name_in_use(name("host0.example.com"))
+        .require_name_absent(name("host1.example.com"))
+        .require_rrset_exists(origin(), QueryType::NS)
+        .require_rrset_absent(name("host0.example.com"), QueryType::MX)
+        .require_rrset_matches(vec![host(0)])
+        .add_record(host(1))
+        .build(1);
+    let (rcode, updated) = apply(&zone, &request);
+    assert_eq!(rcode, ResultCode::NOERROR);
+    assert_eq!(
+        updated
+            .rrset(&name("host1.example.com"), QueryType::A)
+            .len(),
+        1
+    );
+}
+
+#[test]
+fn malformed_updates_are_rejected_before_any_change() {
+    let zone = primary_zone();
+
+    let mut other_class = host(2);
+    other_class.set_class(DnsClass::CH);
+    let meta_type = DnsRecord::UNKNOWN {
+        domain: name("host2.example.com"),
+        class: DnsClass::IN,
+        qtype: QueryType::ANY.to_number(),
+        data: vec![0],
+        ttl: 0,
+    };
+    let mut deletion_with_ttl = update()
+        .delete_rrset(name("host0.example.com"), QueryType::A)
+        .build(1)
+        .authorities
+        .remove(0);
+    deletion_with_ttl.set_ttl(300);
+
+    for invalid in [other_class, meta_type, deletion_with_ttl] {
+        // The valid update before the invalid one is not applied either
+        let mut request = update().add_record(host(1)).build(1);
+        request.authorities.push(invalid.clone());
+        let (rcode, _) = apply(&zone, &request);
+        assert_eq!(rcode, ResultCode::FORMERR, "{:?}", invalid);
+    }
+
+    let request = update().add_record(host(1)).build(1);
+    let mut outside = request.clone();
+    outside.authorities.push(DnsRecord::A {
+        domain: name("host1.example.net"),
+        class: DnsClass::IN,
+        address: Ipv4Addr::new(192, 0, 2, 1),
+        ttl: 300,
+    });
+    assert_eq!(apply(&zone, &outside).0, ResultCode::NOTZONE);
+
+    // The zone section must name the zone, as its SOA
+    let mut other_zone = request.clone();
+    other_zone.questions[0].name = name("example.net");
+    assert_eq!(apply(&zone, &other_zone).0, ResultCode::NOTAUTH);
+    let mut not_soa = request;
+    not_soa.questions[0].qtype = QueryType::A;
+    assert_eq!(apply(&zone, &not_soa).0, ResultCode::FORMERR);
+}
+
+#[test]
+fn cnames_do_not_share_a_name_with_other_data() {
+    let zone = primary_zone();
+
+    // Both are ignored rather than refused, as RFC 2136 asks
+    let request = update()
+        .add_record(cname("host0.example.com", "host1.example.com"))
+        .add_record(DnsRecord::A {
+            domain: name("www.example.com"),
+            class: DnsClass::IN,
+            address: Ipv4Addr::new(192, 0, 2, 80),
+            ttl: 300,
+        })
+        .build(1);
+    let (rcode, updated) = apply(&zone, &request);
+    assert_eq!(rcode, ResultCode::NOERROR);
+    assert_eq!(updated, zone);
+
+    // A CNAME replaces the one already there
+    let request = update()
+        .add_record(cname("www.example.com", "host1.example.com"))
+        .build(1);
+    let (rcode, updated) = apply(&zone, &request);
+    assert_eq!(rcode, ResultCode::NOERROR);
+    assert_eq!(
+        updated.rrset(&name("www.example.com"), QueryType::ANY),
+        vec![&cname("www.example.com", "host1.example.com")]
+    );
+}
+
+#[test]
+fn apex_soa_and_ns_records_are_kept() {
+    let zone = primary_zone();
+    let apex_types = |zone: &Zone| {
+        let mut types: Vec<QueryType> = zone
+            .rrset(&origin(), QueryType::ANY)
+            .iter()
+            .map(|record| record.qtype())
+            .collect();
+        types.sort_by_key(|qtype| qtype.to_number());
+        types
+    };
+
+    let request = update()
+        .add_record(DnsRecord::TXT {
+            domain: origin(),
+            class: DnsClass::IN,
+            data: vec![b"v=spf1 -all".to_vec()],
+            ttl: 300,
+        })
+        .build(1);
+    let (_, with_txt) = apply(&zone, &request);
+    assert_eq!(
+        apex_types(&with_txt),
+        [QueryType::NS, QueryType::SOA, QueryType::TXT]
+    );
+
+    let request = update()
+        .delete_rrset(origin(), QueryType::SOA)
+        .delete_rrset(origin(), QueryType::NS)
+        .delete_record(ns("ns1.example.com"))
+        .delete_record(soa(1))
+        .delete_name(origin())
+        .build(1);
+    let (rcode, updated) = apply(&with_txt, &request);
+    assert_eq!(rcode, ResultCode::NOERROR);
+    assert_eq!(apex_types(&updated), [QueryType::NS, QueryType::SOA]);
+    assert_eq!(
+        updated.rrset(&origin(), QueryType::NS),
+        vec![&ns("ns1.example.com")]
+    );
+
+    // The last NS record can not be deleted, others can
+    let request = update()
+        .add_record(ns("ns2.example.com"))
+        .delete_record(ns("ns1.example.com"))
+        .build(1);
+    let (_, updated) = apply(&zone, &request);
+    assert_eq!(
+        updated.rrset(&origin(), QueryType::NS),
+        vec![&ns("ns2.example.com")]
+    );
+
+    // Nor is the SOA replaced by an older one
+    let request = update().add_record(soa(0)).build(1);
+    let (_, updated) = apply(&zone, &request);
+    assert_eq!(updated, zone);
+}
+
+#[test]
+fn changes_bump_the_serial() {
+    let zone = primary_zone();
+
+    let request = update().add_record(host(1)).build(1);
+    let (_, updated) = apply(&zone, &request);
+    assert_eq!(updated.serial(), Some(2));
+    assert_eq!(updated.diffs_since(1).map(<[_]>::len), Some(1));
+
+    // Updates that change nothing leave the serial alone
+    let request = update()
+        .add_record(host(0))
+        .delete_record(host(5))
+        .delete_rrset(name("host5.example.com"), QueryType::A)
+        .build(1);
+    let (rcode, updated) = apply(&zone, &request);
+    assert_eq!(rcode, ResultCode::NOERROR);
+    assert_eq!(updated, zone);
+
+    // A newer SOA in the update is taken as it is
+    let request = update().add_record(host(1)).add_record(soa(10)).build(1);
+    let (_, updated) = apply(&zone, &request);
+    assert_eq!(updated.serial(), Some(10));
+
+    // Serials wrap around
+    let mut wrapping = zone.clone();
+    wrapping.set_serial(u32::MAX);
+    let request = update().add_record(host(1)).build(1);
+    let (_, updated) = apply(&wrapping, &request);
+    assert_eq!(updated.serial(), Some(0));
+}
+
+#[test]
+fn zones_without_soa_take_no_updates() {
+    let mut zone = primary_zone();
+    zone.remove(&soa(1));
+
+    let request = update().add_record(host(1)).build(1);
+    assert_eq!(apply(&zone, &request).0, ResultCode::SERVFAIL);
+}
+
+#[test]
+fn server_commits_changed_zones() {
+    let source: SocketAddr = "127.0.0.1:53".parse().unwrap();
+    let mut without_soa = Zone::new(name("example.net"), DnsClass::IN);
+    without_soa.insert(DnsRecord::A {
+        domain: name("host0.example.net"),
+        class: DnsClass::IN,
+        address: Ipv4Addr::new(192, 0, 2, 1),
+        ttl: 3600,
+    });
+    let mut catalog = Catalog::new();
+    catalog.insert(primary_zone());
+    catalog.insert(without_soa.clone());
+    let server = DnsServer::new(catalog);
+    let zone = |origin: &Name| {
+        server
+            .catalog
+            .read()
+            .unwrap()
+            .get(origin, DnsClass::IN)
+            .unwrap()
+            .clone()
+    };
+
+    let request = update().add_record(host(1)).build(1);
+    let response = server.handle_request(&request, source);
+    assert_eq!(response.rcode(), ResultCode::NOERROR);
+    let updated = zone(&origin());
+    assert_eq!(updated.serial(), Some(2));
+    assert_eq!(
+        updated
+            .rrset(&name("host1.example.com"), QueryType::A)
+            .len(),
+        1
+    );
+
+    let request = UpdateBuilder::new(name("example.net"), DnsClass::IN)
+        .add_record(DnsRecord::A {
+            domain: name("host1.example.net"),
+            class: DnsClass::IN,
+            address: Ipv4Addr::new(192, 0, 2, 2),
+            ttl: 3600,
+        })
+        .build(2);
+    let response = server.handle_request(&request, source);
+    assert_eq!(response.rcode(), ResultCode::SERVFAIL);
+    assert_eq!(zone(&name("example.net")), without_soa);
+}
+
+#[test]
+fn zones_are_kept_apart_by_class() {
+    let source: SocketAddr = "127.0.0.1:53".parse().unwrap();
+    let mut chaos = Zone::new(origin(), DnsClass::CH);
+    chaos.insert(DnsRecord::TXT {
+        domain: name("version.example.com"),
+        class: DnsClass::CH,
+        data: vec![b"1.0".to_vec()],
+        ttl: 0,
+    });
+    let mut catalog = Catalog::new();
+    catalog.insert(primary_zone());
+    catalog.insert(chaos.clone());
+    let server = DnsServer::new(catalog);
+
+    let request = update().add_record(host(1)).build(1);
+    assert_eq!(
+        server.handle_request(&request, source).rcode(),
+        ResultCode::NOERROR
+    );
+    let catalog = server.catalog.read().unwrap();
+    assert_eq!(
+        catalog.get(&origin(), DnsClass::IN).unwrap().serial(),
+        Some(2)
+    );
+    assert_eq!(catalog.get(&origin(), DnsClass::CH), Some(&chaos));
+    drop(catalog);
+
+    let mut query = DnsPacket::new();
+    query.questions.push(DnsQuestion {
+        qclass: DnsClass::CH,
+        ..DnsQuestion::new(name("version.example.com"), QueryType::TXT)
+    });
+    let response = server.handle_request(&query, source);
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(response.answers[0].class(), DnsClass::CH);
+
+    query.questions[0].qclass = DnsClass::HS;
+    let response = server.handle_request(&query, source);
+    assert_eq!(response.rcode(), ResultCode::REFUSED);
+}
+
+#[test]
+fn large_updates_and_answers_fit_in_edns_datagrams() {
+    let server = DnsServer::new({
+        let mut catalog = Catalog::new();
+        catalog.insert(primary_zone());
+        catalog
+    });
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let client = DnsClient::new(socket.local_addr().unwrap());
+    let serving = server.clone();
+    thread::spawn(move || serving.serve_udp(&socket));
+
+    // Sixty addresses for one name take well over 512 bytes
+    let pool: Vec<_> = (0..60)
+        .map(|index| {
+            let mut record = host(index);
+            if let DnsRecord::A { domain, .. } = &mut record {
+                *domain = name("pool.example.com");
+            }
+            record
+        })
+        .collect();
+    let request = pool
+        .into_iter()
+        .fold(update(), UpdateBuilder::add_record)
+        .build(1);
+    let exchange = client.exchange(request).unwrap();
+    assert_eq!(exchange.response.rcode(), ResultCode::NOERROR);
+    assert!(!exchange.tcp);
+
+    let mut query = DnsPacket::new();
+    query
+        .questions
+        .push(DnsQuestion::new(name("pool.example.com"), QueryType::A));
+    query.resources.push(DnsRecord::OPT {
+        udp_payload_size: 4096,
+        extended_rcode: 0,
+        version: 0,
+        flags: 0,
+        options: Vec::new(),
+    });
+    let exchange = client.exchange(query.clone()).unwrap();
+    assert!(!exchange.tcp);
+    assert!(exchange.message_size > 512);
+    assert_eq!(exchange.response.answers.len(), 60);
+
+    // Without EDNS the client only takes 512 bytes
+    query.resources.clear();
+    let mut buffer = PacketBuffer::new();
+    query.write(&mut buffer).unwrap();
+    let mut buffer = PacketBuffer::from_bytes(&buffer.buffer[..buffer.position]).unwrap();
+    let response = server
+        .handle_buffer(&mut buffer, "127.0.0.1:53".parse().unwrap())
+        .unwrap();
+    assert!(response.header.truncated_message);
+    assert!(response.answers.is_empty());
+}